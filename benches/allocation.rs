@@ -0,0 +1,44 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use rvm::{gc::Allocation, vm::Vm};
+
+// Builds and sums a list of tuples, then drops it, so the heap holds a large
+// structure that becomes garbage all at once.
+const LISTS: &str = r#"
+    let build = fn (n, acc) => {
+        if (n == 0) { acc } else { build(n - 1, (n, acc)) }
+    };
+    let sum = fn (list, acc) => {
+        if (list == 0) { acc } else { sum(second(list), acc + first(list)) }
+    };
+    let repeat = fn (times, acc) => {
+        if (times == 0) { acc } else { repeat(times - 1, acc + sum(build(4000, 0), 0)) }
+    };
+    repeat(50, 0)
+"#;
+
+// Allocates a short-lived tuple per iteration, most of which the collector
+// reclaims long before the run ends.
+const TEMPORARIES: &str = r#"
+    let loop = fn (n, acc) => {
+        if (n == 0) { acc } else { loop(n - 1, acc + second((acc, n % 7))) }
+    };
+    loop(200000, 0)
+"#;
+
+fn run(allocation: Allocation, program: &str) {
+    let mut vm = Vm::new().with_allocation(allocation);
+    vm.interpret("bench", black_box(program)).unwrap();
+}
+
+fn allocation(c: &mut Criterion) {
+    for (name, program) in [("lists", LISTS), ("temporaries", TEMPORARIES)] {
+        let mut group = c.benchmark_group(name);
+        group.bench_function("gc", |b| b.iter(|| run(Allocation::Gc, program)));
+        group.bench_function("arena", |b| b.iter(|| run(Allocation::Arena, program)));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, allocation);
+criterion_main!(benches);
@@ -0,0 +1,31 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use rvm::vm::Vm;
+
+// Two calls passing the same 50,000 distinct integers, so compiling them
+// adds each to the constant table once and finds it there the second time.
+// The callee is a parameter, so the compiler cannot check its arity.
+fn constants() -> String {
+    let arguments: Vec<String> = (0..50_000).map(|i| (i + 100_000).to_string()).collect();
+    let arguments = arguments.join(", ");
+    format!("let call = fn (f) => (f({arguments}), f({arguments}));\ncall")
+}
+
+fn compilation(c: &mut Criterion) {
+    let program = constants();
+
+    let mut group = c.benchmark_group("compilation");
+    // Parsing dominates each run, so take as few samples as criterion allows.
+    group.sample_size(10);
+    group.bench_function("50k constants", |b| {
+        b.iter(|| {
+            Vm::new()
+                .compile_program("bench", black_box(&program))
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, compilation);
+criterion_main!(benches);
@@ -0,0 +1,97 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::env;
+
+use rvm::vm::Vm;
+
+const FIB: &str = r#"
+    let fib = fn (n) => {
+        if (n < 2) { n } else { fib(n - 1) + fib(n - 2) }
+    };
+    fib(30)
+"#;
+
+// A tuple argument keeps the calls out of the memoization table, so every
+// call goes through the dispatch loop.
+const FIB_UNMEMOIZED: &str = r#"
+    let fib = fn (n, unused) => {
+        if (n < 2) { n } else { fib(n - 1, unused) + fib(n - 2, unused) }
+    };
+    fib(20, (0, 0))
+"#;
+
+const COUNT: &str = r#"
+    let count = fn (n, acc) => {
+        if (n == 0) { acc } else { count(n - 1, acc + 1) }
+    };
+    count(100000, 0)
+"#;
+
+const COUNTDOWN: &str = r#"
+    let count = fn (n, acc) => {
+        if (n == 0) { acc } else { count(n - 1, acc + 1) }
+    };
+    count(10000000, 0)
+"#;
+
+// Calling itself through a parameter hides from the compiler that the
+// function calls itself, so every iteration is a `TailCall` rather than a
+// `JumpBack`.
+const COUNTDOWN_TAIL_CALLS: &str = r#"
+    let count = fn (self, n, acc) => {
+        if (n == 0) { acc } else { self(self, n - 1, acc + 1) }
+    };
+    count(count, 10000000, 0)
+"#;
+
+fn run(program: &str) {
+    let mut vm = Vm::new();
+    vm.interpret("bench", black_box(program)).unwrap();
+}
+
+/// Prints which opcodes a program executes, so a change in timings can be
+/// traced to a change in the instructions being run. Set
+/// `RVM_OPCODE_HISTOGRAM=0` to leave it out of the report.
+fn report_opcodes(name: &str, program: &str) {
+    if env::var("RVM_OPCODE_HISTOGRAM").is_ok_and(|value| value == "0") {
+        return;
+    }
+
+    let mut vm = Vm::new().with_opcode_histogram();
+    vm.interpret("bench", program).unwrap();
+
+    let histogram = vm.opcode_histogram().unwrap();
+    let total: u64 = histogram.iter().map(|(_, count)| count).sum();
+
+    println!("{name}: {total} instructions");
+    for (opcode, count) in histogram {
+        let share = count as f64 * 100.0 / total as f64;
+        println!("  {opcode:<10} {count:>12} {share:>6.2}%");
+    }
+}
+
+fn bench(c: &mut Criterion, name: &str, program: &str) {
+    report_opcodes(name, program);
+    c.bench_function(name, |b| b.iter(|| run(program)));
+}
+
+fn dispatch(c: &mut Criterion) {
+    bench(c, "fib(30)", FIB);
+    bench(c, "fib(20) without memoization", FIB_UNMEMOIZED);
+    bench(c, "tail-recursive count to 100000", COUNT);
+}
+
+fn countdown(c: &mut Criterion) {
+    let mut group = c.benchmark_group("countdown from 10000000");
+    group.sample_size(10);
+    for (name, program) in [
+        ("looping in place", COUNTDOWN),
+        ("with tail calls", COUNTDOWN_TAIL_CALLS),
+    ] {
+        report_opcodes(name, program);
+        group.bench_function(name, |b| b.iter(|| run(program)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, dispatch, countdown);
+criterion_main!(benches);
@@ -0,0 +1,43 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use rvm::vm::Vm;
+
+const COMBINATION: &str = r#"
+    let combination = fn (n, k) => {
+        if (k == 0) { 1 } else {
+            if (k == n) { 1 } else { combination(n - 1, k - 1) + combination(n - 1, k) }
+        }
+    };
+    combination(30, 12)
+"#;
+
+// A tuple argument keeps the calls out of the memoization table, so this is
+// the same recursion making all of its 173 million calls.
+const COMBINATION_UNMEMOIZED: &str = r#"
+    let combination = fn (n, k, unused) => {
+        if (k == 0) { 1 } else {
+            if (k == n) { 1 } else {
+                combination(n - 1, k - 1, unused) + combination(n - 1, k, unused)
+            }
+        }
+    };
+    combination(30, 12, (0, 0))
+"#;
+
+fn run(program: &str) {
+    let mut vm = Vm::new();
+    vm.interpret("bench", black_box(program)).unwrap();
+}
+
+fn memoization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("combination(30, 12)");
+    // Each unmemoized run takes seconds, so take as few samples as criterion
+    // allows.
+    group.sample_size(10);
+    group.bench_function("memoized", |b| b.iter(|| run(COMBINATION)));
+    group.bench_function("unmemoized", |b| b.iter(|| run(COMBINATION_UNMEMOIZED)));
+    group.finish();
+}
+
+criterion_group!(benches, memoization);
+criterion_main!(benches);
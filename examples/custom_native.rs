@@ -0,0 +1,75 @@
+//! Exposes Rust functions to programs as natives, and captures what they
+//! print instead of letting it reach standard output.
+//!
+//! ```sh
+//! cargo run --example custom_native
+//! ```
+
+use anyhow::{bail, Result};
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+use rvm::{
+    native::{NativeRegistry, NativeResult},
+    sandbox::SandboxPolicy,
+    value::FinalValue,
+    vm::Vm,
+};
+
+/// A writer whose contents can still be read after the VM takes it.
+#[derive(Clone, Default)]
+struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+const PROGRAM: &str = r#"
+    let greet = fn (name) => { print(text/shout("hello, " + name)) };
+    let _ = greet("world");
+    let _ = greet("rinha");
+    text/repeat("ab", 3)
+"#;
+
+fn main() -> Result<()> {
+    let mut registry = NativeRegistry::new();
+
+    registry.register("text.shout", 1, |arguments| match arguments {
+        [FinalValue::String(text)] => {
+            Ok(NativeResult::Ready(FinalValue::String(text.to_uppercase())))
+        }
+        _ => bail!("text.shout expects a string."),
+    })?;
+
+    registry.register("text.repeat", 2, |arguments| match arguments {
+        [FinalValue::String(text), FinalValue::Integer(times)] if *times >= 0 => Ok(
+            NativeResult::Ready(FinalValue::String(text.repeat(*times as usize))),
+        ),
+        _ => bail!("text.repeat expects a string and a non-negative integer."),
+    })?;
+
+    // Natives are only visible to programs when their namespace is allowed.
+    let policy = SandboxPolicy::new().allow("text");
+    let output = Captured::default();
+
+    let mut vm = Vm::new()
+        .with_natives(&registry, &policy)
+        .with_output(output.clone());
+
+    let result = vm.interpret("natives.rinha", PROGRAM)?;
+
+    let printed = String::from_utf8(output.0.borrow().clone())?;
+    print!("the program printed:\n{printed}");
+    println!("and returned {result:?}");
+
+    Ok(())
+}
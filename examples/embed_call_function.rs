@@ -0,0 +1,44 @@
+//! Runs a program once to define its functions, then calls them from Rust.
+//!
+//! ```sh
+//! cargo run --example embed_call_function
+//! ```
+
+use anyhow::Result;
+use rvm::{value::FinalValue, vm::Vm};
+
+const PROGRAM: &str = r#"
+    let fib = fn (n) => {
+        if (n < 2) { n } else { fib(n - 1) + fib(n - 2) }
+    };
+    let describe = fn (name, value) => { name + " = " + value };
+    "loaded"
+"#;
+
+fn main() -> Result<()> {
+    let mut vm = Vm::new().with_fuel(10_000_000);
+
+    let loaded = vm.interpret("library.rinha", PROGRAM)?;
+    println!("program returned {loaded:?}");
+
+    for n in [10, 20, 25] {
+        let result = vm.call_function("fib", &[FinalValue::Integer(n)])?;
+
+        let FinalValue::Integer(value) = result else {
+            anyhow::bail!("fib returned {result:?}");
+        };
+
+        let line = vm.call_function(
+            "describe",
+            &[
+                FinalValue::String(format!("fib({n})")),
+                FinalValue::Integer(value),
+            ],
+        )?;
+        println!("{line:?}");
+    }
+
+    println!("globals: {:?}", vm.globals());
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+//! A tiny TCP server running one program for every connection, each on a
+//! thread of its own. Each client sends a line with the program's arguments
+//! and gets back what the program printed, followed by its result.
+//!
+//! ```sh
+//! cargo run --example server -- 127.0.0.1:7878
+//! echo 25 | nc 127.0.0.1 7878
+//! ```
+
+use anyhow::{Context, Result};
+use std::{
+    env,
+    io::{BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
+};
+
+use rvm::{artifact::CompiledProgram, vm::Vm};
+
+const PROGRAM: &str = r#"
+    let fib = fn (n) => {
+        if (n < 2) { n } else { fib(n - 1) + fib(n - 2) }
+    };
+    let n = if (argc > 0) { arg0 } else { 10 };
+    let _ = print("computing fib(" + n + ")");
+    fib(n)
+"#;
+
+/// Every request runs on a fresh VM, so a runaway program only costs its
+/// own fuel.
+const FUEL: u64 = 50_000_000;
+
+fn main() -> Result<()> {
+    let address = env::args()
+        .nth(1)
+        .unwrap_or_else(|| "127.0.0.1:7878".to_owned());
+
+    // Compiling once and loading the artifact for each request skips parsing
+    // and optimizing the source every time.
+    let program = Arc::new(Vm::new().compile_program("server.rinha", PROGRAM)?);
+
+    let listener =
+        TcpListener::bind(&address).with_context(|| format!("Could not bind {address}."))?;
+    println!("listening on {address}");
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let program = Arc::clone(&program);
+
+        thread::spawn(move || {
+            if let Err(error) = serve(&program, stream) {
+                eprintln!("request failed: {error:#}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn serve(program: &CompiledProgram, stream: TcpStream) -> Result<()> {
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let arguments: Vec<String> = line.split_whitespace().map(str::to_owned).collect();
+
+    let mut output = stream.try_clone()?;
+    let mut vm = Vm::new()
+        .with_fuel(FUEL)
+        .with_arguments(&arguments)
+        .with_output(stream);
+
+    match vm.interpret_program(program) {
+        Ok(result) => writeln!(output, "{result:?}")?,
+        Err(error) => writeln!(output, "error: {error}")?,
+    }
+
+    Ok(())
+}
@@ -0,0 +1,32 @@
+#![no_main]
+
+//! Runs random programs and checks that the VM reports every problem with
+//! them as an error instead of panicking. Run with `cargo fuzz run ast`.
+
+use std::io;
+
+use libfuzzer_sys::fuzz_target;
+use rvm::{optimizer::Passes, vm::Vm};
+
+mod generator;
+
+/// Enough to get through a few loops, while keeping runs fast.
+const FUEL: u64 = 20_000;
+
+fuzz_target!(|data: &[u8]| {
+    let Some((level, data)) = data.split_first() else {
+        return;
+    };
+
+    let file = generator::Generator::new(data).file();
+    let json = serde_json::to_string(&file).expect("Generated programs serialize.");
+
+    let passes = Passes::level(level % (Passes::MAX_LEVEL + 1)).expect("The level is valid.");
+    let mut vm = Vm::new()
+        .with_passes(passes)
+        .with_fuel(FUEL)
+        .with_output(io::sink())
+        .with_reader(io::empty());
+
+    let _ = vm.interpret_json(&json);
+});
@@ -0,0 +1,74 @@
+#![no_main]
+
+//! Runs random programs on the VM and on the AST interpreter, checking that
+//! they agree on the result and the printed lines. Run with
+//! `cargo fuzz run differential`.
+
+use std::{cell::RefCell, io, io::Write, rc::Rc};
+
+use libfuzzer_sys::fuzz_target;
+use rvm::{
+    interp::{Inconclusive, Interpreter},
+    optimizer::Passes,
+    vm::{RuntimeError, Vm},
+};
+
+mod generator;
+
+/// Enough to get through a few loops, while keeping runs fast.
+const FUEL: u64 = 20_000;
+
+#[derive(Clone, Default)]
+struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let Some((level, data)) = data.split_first() else {
+        return;
+    };
+
+    let file = generator::Generator::new(data).file();
+    let json = serde_json::to_string(&file).expect("Generated programs serialize.");
+
+    let mut interpreter = Interpreter::new().with_steps(FUEL);
+    let expected = interpreter.run(&file);
+    if expected
+        .as_ref()
+        .is_err_and(|error| error.is::<Inconclusive>())
+    {
+        return;
+    }
+
+    let output = Captured::default();
+    let passes = Passes::level(level % (Passes::MAX_LEVEL + 1)).expect("The level is valid.");
+    let mut vm = Vm::new()
+        .with_passes(passes)
+        .with_fuel(FUEL)
+        .with_output(output.clone())
+        .with_reader(io::empty());
+
+    match (expected, vm.interpret_json(&json)) {
+        (_, Err(error)) if !error.is::<RuntimeError>() => {}
+        (_, Err(error)) if format!("{error:#}").contains("Out of fuel") => {}
+        (Ok(expected), Ok(actual)) => {
+            assert_eq!(expected, actual, "Results differ for {json}");
+
+            let printed = String::from_utf8(output.0.take()).expect("Output is UTF-8.");
+            let printed: Vec<&str> = printed.lines().collect();
+            assert_eq!(interpreter.output(), printed, "Output differs for {json}");
+        }
+        (Err(_), Err(_)) => {}
+        (expected, actual) => {
+            panic!("The engines disagree on {json}:\ninterpreter: {expected:?}\nvm: {actual:?}")
+        }
+    }
+});
@@ -0,0 +1,240 @@
+//! Turns fuzzer input into rinha programs. Every byte picks among the
+//! choices available at that point, so similar inputs make similar programs
+//! and the fuzzer can explore by mutating them. Programs are well formed but
+//! not necessarily well typed: variables are mostly bound, but values are
+//! combined and called with no regard for their types.
+
+use rvm::ast::{BinaryOp, File, Location, Parameter, Term};
+
+/// How deeply terms nest.
+const MAX_DEPTH: usize = 12;
+
+const OPERATORS: [BinaryOp; 13] = [
+    BinaryOp::Add,
+    BinaryOp::Sub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::Rem,
+    BinaryOp::Eq,
+    BinaryOp::Neq,
+    BinaryOp::Lt,
+    BinaryOp::Gt,
+    BinaryOp::Lte,
+    BinaryOp::Gte,
+    BinaryOp::And,
+    BinaryOp::Or,
+];
+
+const INTEGERS: [i32; 8] = [0, 1, -1, 2, 7, 100, i32::MAX, i32::MIN];
+
+const STRINGS: [&str; 4] = ["", "a", "short", "a string too long to be inline"];
+
+pub struct Generator<'a> {
+    data: &'a [u8],
+    /// The variables in scope, innermost last.
+    scope: Vec<String>,
+    names: usize,
+}
+
+impl<'a> Generator<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            scope: Vec::new(),
+            names: 0,
+        }
+    }
+
+    pub fn file(&mut self) -> File {
+        File {
+            name: "fuzz.rinha".to_owned(),
+            expression: self.term(MAX_DEPTH),
+            location: location(),
+        }
+    }
+
+    /// The next byte of input, or zero once it runs out, which picks the
+    /// first and simplest choice everywhere.
+    fn byte(&mut self) -> u8 {
+        match self.data.split_first() {
+            Some((byte, rest)) => {
+                self.data = rest;
+                *byte
+            }
+            None => 0,
+        }
+    }
+
+    fn choose(&mut self, choices: usize) -> usize {
+        self.byte() as usize % choices
+    }
+
+    fn term(&mut self, depth: usize) -> Term {
+        if depth == 0 || self.data.is_empty() {
+            return self.leaf();
+        }
+        let depth = depth - 1;
+
+        match self.choose(16) {
+            0..=2 => self.leaf(),
+            3 | 4 => Term::Binary {
+                lhs: Box::new(self.term(depth)),
+                op: OPERATORS[self.choose(OPERATORS.len())],
+                rhs: Box::new(self.term(depth)),
+                location: location(),
+            },
+            5 => Term::If {
+                condition: Box::new(self.term(depth)),
+                then: Box::new(self.term(depth)),
+                otherwise: Box::new(self.term(depth)),
+                location: location(),
+            },
+            6 => Term::Tuple {
+                first: Box::new(self.term(depth)),
+                second: Box::new(self.term(depth)),
+                location: location(),
+            },
+            7 => Term::First {
+                value: Box::new(self.term(depth)),
+                location: location(),
+            },
+            8 => Term::Second {
+                value: Box::new(self.term(depth)),
+                location: location(),
+            },
+            9 => Term::Print {
+                value: Box::new(self.term(depth)),
+                location: location(),
+            },
+            10 | 11 => self.binding(depth),
+            12 => self.function(depth),
+            13 | 14 => self.call(depth),
+            _ => Term::Sequence {
+                value: Box::new(self.term(depth)),
+                next: Box::new(self.term(depth)),
+                location: location(),
+            },
+        }
+    }
+
+    fn leaf(&mut self) -> Term {
+        match self.choose(5) {
+            0 | 1 if !self.scope.is_empty() => self.variable(),
+            0 | 1 => self.integer(),
+            2 => self.integer(),
+            3 => Term::Str {
+                value: STRINGS[self.choose(STRINGS.len())].to_owned(),
+                location: location(),
+            },
+            _ => Term::Bool {
+                value: self.byte().is_multiple_of(2),
+                location: location(),
+            },
+        }
+    }
+
+    fn integer(&mut self) -> Term {
+        let value = match self.choose(4) {
+            0 => i32::from_le_bytes([self.byte(), self.byte(), self.byte(), self.byte()]),
+            _ => INTEGERS[self.choose(INTEGERS.len())],
+        };
+
+        Term::Int {
+            value,
+            location: location(),
+        }
+    }
+
+    /// A variable in scope, or now and then one that is not.
+    fn variable(&mut self) -> Term {
+        let text = match self.scope.len() {
+            0 => "unbound".to_owned(),
+            _ if self.choose(16) == 0 => "unbound".to_owned(),
+            len => {
+                let index = len - 1 - self.choose(len);
+                self.scope[index].clone()
+            }
+        };
+
+        Term::Var {
+            text,
+            location: location(),
+        }
+    }
+
+    /// A fresh name, or now and then one that shadows a variable in scope.
+    fn name(&mut self) -> String {
+        if !self.scope.is_empty() && self.choose(8) == 0 {
+            let index = self.choose(self.scope.len());
+            return self.scope[index].clone();
+        }
+
+        self.names += 1;
+        format!("v{}", self.names)
+    }
+
+    /// A `let`, whose value can refer to its own name so functions can
+    /// recurse.
+    fn binding(&mut self, depth: usize) -> Term {
+        let name = self.name();
+
+        self.scope.push(name.clone());
+        let value = match self.choose(2) {
+            0 => self.function(depth),
+            _ => self.term(depth),
+        };
+        let next = self.term(depth);
+        self.scope.pop();
+
+        Term::Let {
+            name: parameter(name),
+            value: Box::new(value),
+            next: Box::new(next),
+            location: location(),
+        }
+    }
+
+    fn function(&mut self, depth: usize) -> Term {
+        let parameters: Vec<String> = (0..self.choose(4)).map(|_| self.name()).collect();
+
+        let scope = self.scope.len();
+        self.scope.extend(parameters.iter().cloned());
+        let value = self.term(depth);
+        self.scope.truncate(scope);
+
+        Term::Function {
+            parameters: parameters.into_iter().map(parameter).collect(),
+            value: Box::new(value),
+            location: location(),
+        }
+    }
+
+    fn call(&mut self, depth: usize) -> Term {
+        let callee = match self.choose(3) {
+            0 => self.function(depth),
+            _ => self.leaf(),
+        };
+        let arguments = (0..self.choose(4)).map(|_| self.term(depth)).collect();
+
+        Term::Call {
+            callee: Box::new(callee),
+            arguments,
+            location: location(),
+        }
+    }
+}
+
+fn location() -> Location {
+    Location {
+        start: 0,
+        end: 0,
+        filename: "fuzz.rinha".to_owned(),
+    }
+}
+
+fn parameter(text: String) -> Parameter {
+    Parameter {
+        text,
+        location: location(),
+    }
+}
@@ -0,0 +1,44 @@
+/* The C API of rvm, implemented in src/capi.rs. Link against the cdylib
+   built by `cargo build --release` (librvm.so, librvm.dylib or rvm.dll). */
+
+#ifndef RVM_H
+#define RVM_H
+
+#include <stdbool.h>
+#include <stddef.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct Rvm Rvm;
+
+typedef struct RvmResult {
+    /* Whether the program ran to completion. */
+    bool ok;
+    /* The program's result as JSON, or NULL when it failed. */
+    char *value;
+    /* What the program printed, output_length bytes long and NUL
+       terminated. */
+    char *output;
+    size_t output_length;
+} RvmResult;
+
+Rvm *rvm_new(void);
+void rvm_free(Rvm *rvm);
+
+/* Runs a program without natives or input, in a fresh VM each time. Free
+   the result with rvm_result_free. */
+RvmResult rvm_interpret(Rvm *rvm, const char *source);
+
+/* Why the last rvm_interpret failed, or NULL when it did not. Valid until
+   the next call on rvm. */
+const char *rvm_last_error(const Rvm *rvm);
+
+void rvm_result_free(RvmResult result);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
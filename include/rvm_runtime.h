@@ -0,0 +1,537 @@
+/* The runtime of the C programs `rvm build --emit c` writes, which they
+   include verbatim so they build on their own. It expects RT_CONSTANTS,
+   RT_IDENTIFIERS, RT_MAX_ARITY and RT_MAX_TUPLE_DEPTH to be defined and
+   rt_identifiers to be declared before it. */
+
+#ifndef RVM_RUNTIME_H
+#define RVM_RUNTIME_H
+
+#include <ctype.h>
+#include <inttypes.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+enum Tag { TAG_BOOL, TAG_INTEGER, TAG_STRING, TAG_TUPLE, TAG_CLOSURE, TAG_TAIL_CALL };
+
+typedef struct Value {
+    uint8_t tag;
+    union {
+        bool boolean;
+        int32_t integer;
+        struct String *string;
+        struct Tuple *tuple;
+        struct Closure *closure;
+    } as;
+} Value;
+
+typedef struct String {
+    size_t length;
+    char bytes[];
+} String;
+
+typedef struct Tuple {
+    Value first;
+    Value second;
+    uint32_t depth;
+} Tuple;
+
+typedef Value (*Code)(struct Closure *self, const Value *arguments);
+
+typedef struct Closure {
+    Code code;
+    const char *name;
+    uint16_t arity;
+    Value environment[];
+} Closure;
+
+static Value rt_constants[RT_CONSTANTS + 1];
+static Value rt_globals[RT_IDENTIFIERS + 1];
+static bool rt_defined[RT_IDENTIFIERS + 1];
+
+/* The call a tail call asked for, which the nearest rt_call makes. */
+static struct {
+    Closure *closure;
+    Value arguments[RT_MAX_ARITY + 1];
+} rt_pending;
+
+static inline _Noreturn void rt_fail(const char *message) {
+    fflush(stdout);
+    fprintf(stderr, "Error: %s\n", message);
+    exit(1);
+}
+
+/* Nothing is ever freed: programs run to completion and exit. */
+static inline void *rt_alloc(size_t size) {
+    void *memory = malloc(size);
+    if (memory == NULL) {
+        rt_fail("Out of memory.");
+    }
+    return memory;
+}
+
+static inline Value rt_bool(bool boolean) {
+    Value value;
+    value.tag = TAG_BOOL;
+    value.as.boolean = boolean;
+    return value;
+}
+
+static inline Value rt_integer(int32_t integer) {
+    Value value;
+    value.tag = TAG_INTEGER;
+    value.as.integer = integer;
+    return value;
+}
+
+static inline Value rt_string(const char *bytes, size_t length) {
+    String *string = rt_alloc(sizeof(String) + length);
+    string->length = length;
+    memcpy(string->bytes, bytes, length);
+
+    Value value;
+    value.tag = TAG_STRING;
+    value.as.string = string;
+    return value;
+}
+
+static inline Value rt_concat(const char *lhs, size_t lhs_length, const char *rhs, size_t rhs_length) {
+    String *string = rt_alloc(sizeof(String) + lhs_length + rhs_length);
+    string->length = lhs_length + rhs_length;
+    memcpy(string->bytes, lhs, lhs_length);
+    memcpy(string->bytes + lhs_length, rhs, rhs_length);
+
+    Value value;
+    value.tag = TAG_STRING;
+    value.as.string = string;
+    return value;
+}
+
+static inline Value rt_add(Value lhs, Value rhs) {
+    char digits[16];
+
+    if (lhs.tag == TAG_INTEGER && rhs.tag == TAG_INTEGER) {
+        return rt_integer((int32_t)((uint32_t)lhs.as.integer + (uint32_t)rhs.as.integer));
+    }
+    if (lhs.tag == TAG_STRING && rhs.tag == TAG_INTEGER) {
+        int length = snprintf(digits, sizeof digits, "%" PRId32, rhs.as.integer);
+        return rt_concat(lhs.as.string->bytes, lhs.as.string->length, digits, (size_t)length);
+    }
+    if (lhs.tag == TAG_INTEGER && rhs.tag == TAG_STRING) {
+        int length = snprintf(digits, sizeof digits, "%" PRId32, lhs.as.integer);
+        return rt_concat(digits, (size_t)length, rhs.as.string->bytes, rhs.as.string->length);
+    }
+    if (lhs.tag == TAG_STRING && rhs.tag == TAG_STRING) {
+        return rt_concat(lhs.as.string->bytes, lhs.as.string->length, rhs.as.string->bytes,
+                         rhs.as.string->length);
+    }
+    rt_fail("Wrong types for add.");
+}
+
+static inline void rt_integers(Value lhs, Value rhs) {
+    if (lhs.tag != TAG_INTEGER || rhs.tag != TAG_INTEGER) {
+        rt_fail("Operands must be both integers.");
+    }
+}
+
+static inline Value rt_sub(Value lhs, Value rhs) {
+    rt_integers(lhs, rhs);
+    return rt_integer((int32_t)((uint32_t)lhs.as.integer - (uint32_t)rhs.as.integer));
+}
+
+static inline Value rt_neg(Value value) {
+    if (value.tag != TAG_INTEGER) {
+        rt_fail("Operand must be an integer.");
+    }
+    return rt_integer((int32_t)(0u - (uint32_t)value.as.integer));
+}
+
+static inline Value rt_mul(Value lhs, Value rhs) {
+    rt_integers(lhs, rhs);
+    return rt_integer((int32_t)((uint32_t)lhs.as.integer * (uint32_t)rhs.as.integer));
+}
+
+static inline Value rt_div(Value lhs, Value rhs) {
+    rt_integers(lhs, rhs);
+    if (rhs.as.integer == 0) {
+        rt_fail("Attempted to divide by zero");
+    }
+    /* Dividing by -1 negates, wrapping around for the smallest integer. */
+    if (rhs.as.integer == -1) {
+        return rt_integer((int32_t)(0u - (uint32_t)lhs.as.integer));
+    }
+    return rt_integer(lhs.as.integer / rhs.as.integer);
+}
+
+static inline Value rt_rem(Value lhs, Value rhs) {
+    rt_integers(lhs, rhs);
+    if (rhs.as.integer == 0) {
+        rt_fail("Attempted to take remainder by zero");
+    }
+    if (rhs.as.integer == -1) {
+        return rt_integer(0);
+    }
+    return rt_integer(lhs.as.integer % rhs.as.integer);
+}
+
+static inline Value rt_band(Value lhs, Value rhs) {
+    rt_integers(lhs, rhs);
+    return rt_integer(lhs.as.integer & rhs.as.integer);
+}
+
+static inline Value rt_bor(Value lhs, Value rhs) {
+    rt_integers(lhs, rhs);
+    return rt_integer(lhs.as.integer | rhs.as.integer);
+}
+
+static inline Value rt_bxor(Value lhs, Value rhs) {
+    rt_integers(lhs, rhs);
+    return rt_integer(lhs.as.integer ^ rhs.as.integer);
+}
+
+static inline Value rt_shl(Value lhs, Value rhs) {
+    rt_integers(lhs, rhs);
+    return rt_integer((int32_t)((uint32_t)lhs.as.integer << (rhs.as.integer & 31)));
+}
+
+static inline Value rt_shr(Value lhs, Value rhs) {
+    rt_integers(lhs, rhs);
+    return rt_integer(lhs.as.integer >> (rhs.as.integer & 31));
+}
+
+/* Orders integers by value and strings by their bytes, which is the order
+   of their code points for UTF-8. Returns a negative, zero or positive number
+   like memcmp. */
+static inline int rt_compare(Value lhs, Value rhs) {
+    if (lhs.tag == TAG_INTEGER && rhs.tag == TAG_INTEGER) {
+        return (lhs.as.integer > rhs.as.integer) - (lhs.as.integer < rhs.as.integer);
+    }
+    if (lhs.tag == TAG_STRING && rhs.tag == TAG_STRING) {
+        size_t lhs_length = lhs.as.string->length;
+        size_t rhs_length = rhs.as.string->length;
+        int order = memcmp(lhs.as.string->bytes, rhs.as.string->bytes,
+                           lhs_length < rhs_length ? lhs_length : rhs_length);
+        return order != 0 ? order : (lhs_length > rhs_length) - (lhs_length < rhs_length);
+    }
+    rt_fail("Operands must be both integers or both strings.");
+}
+
+static inline Value rt_lt(Value lhs, Value rhs) {
+    return rt_bool(rt_compare(lhs, rhs) < 0);
+}
+
+static inline Value rt_gt(Value lhs, Value rhs) {
+    return rt_bool(rt_compare(lhs, rhs) > 0);
+}
+
+static inline Value rt_lte(Value lhs, Value rhs) {
+    return rt_bool(rt_compare(lhs, rhs) <= 0);
+}
+
+static inline Value rt_gte(Value lhs, Value rhs) {
+    return rt_bool(rt_compare(lhs, rhs) >= 0);
+}
+
+static inline void rt_bools(Value lhs, Value rhs) {
+    if (lhs.tag != TAG_BOOL || rhs.tag != TAG_BOOL) {
+        rt_fail("Operands must be both integers.");
+    }
+}
+
+static inline Value rt_and(Value lhs, Value rhs) {
+    rt_bools(lhs, rhs);
+    return rt_bool(lhs.as.boolean && rhs.as.boolean);
+}
+
+static inline Value rt_or(Value lhs, Value rhs) {
+    rt_bools(lhs, rhs);
+    return rt_bool(lhs.as.boolean || rhs.as.boolean);
+}
+
+/* Structural, except that closures are only equal to themselves. */
+static inline bool rt_equals(Value lhs, Value rhs) {
+    if (lhs.tag != rhs.tag) {
+        return false;
+    }
+    switch (lhs.tag) {
+    case TAG_BOOL:
+        return lhs.as.boolean == rhs.as.boolean;
+    case TAG_INTEGER:
+        return lhs.as.integer == rhs.as.integer;
+    case TAG_STRING:
+        return lhs.as.string->length == rhs.as.string->length &&
+               memcmp(lhs.as.string->bytes, rhs.as.string->bytes, lhs.as.string->length) == 0;
+    case TAG_TUPLE:
+        return rt_equals(lhs.as.tuple->first, rhs.as.tuple->first) &&
+               rt_equals(lhs.as.tuple->second, rhs.as.tuple->second);
+    default:
+        return lhs.as.closure == rhs.as.closure;
+    }
+}
+
+static inline uint32_t rt_depth(Value value) {
+    return value.tag == TAG_TUPLE ? value.as.tuple->depth : 0;
+}
+
+static inline Value rt_tuple(Value first, Value second) {
+    uint32_t depth = 1 + (rt_depth(first) > rt_depth(second) ? rt_depth(first) : rt_depth(second));
+    if (depth > RT_MAX_TUPLE_DEPTH) {
+        char message[96];
+        snprintf(message, sizeof message,
+                 "Tuple nesting depth %" PRIu32 " exceeds the limit of %d.", depth,
+                 RT_MAX_TUPLE_DEPTH);
+        rt_fail(message);
+    }
+
+    Tuple *tuple = rt_alloc(sizeof(Tuple));
+    tuple->first = first;
+    tuple->second = second;
+    tuple->depth = depth;
+
+    Value value;
+    value.tag = TAG_TUPLE;
+    value.as.tuple = tuple;
+    return value;
+}
+
+static inline Value rt_first(Value value) {
+    if (value.tag != TAG_TUPLE) {
+        rt_fail("Tried to compute `first` of a non tuple type.");
+    }
+    return value.as.tuple->first;
+}
+
+static inline Value rt_second(Value value) {
+    if (value.tag != TAG_TUPLE) {
+        rt_fail("Tried to compute `second` of a non tuple type.");
+    }
+    return value.as.tuple->second;
+}
+
+/* Text being built by rt_format, which grows as needed. */
+typedef struct Buffer {
+    char *bytes;
+    size_t length;
+    size_t capacity;
+} Buffer;
+
+static inline void rt_append(Buffer *buffer, const char *bytes, size_t length) {
+    if (length == 0) {
+        return;
+    }
+    if (buffer->length + length > buffer->capacity) {
+        buffer->capacity = 2 * (buffer->length + length);
+        buffer->bytes = realloc(buffer->bytes, buffer->capacity);
+        if (buffer->bytes == NULL) {
+            rt_fail("Out of memory.");
+        }
+    }
+    memcpy(buffer->bytes + buffer->length, bytes, length);
+    buffer->length += length;
+}
+
+/* Appends the text print shows for value. */
+static void rt_format(Buffer *buffer, Value value) {
+    char digits[16];
+
+    switch (value.tag) {
+    case TAG_BOOL:
+        if (value.as.boolean) {
+            rt_append(buffer, "true", 4);
+        } else {
+            rt_append(buffer, "false", 5);
+        }
+        break;
+    case TAG_INTEGER:
+        rt_append(buffer, digits,
+                  (size_t)snprintf(digits, sizeof digits, "%" PRId32, value.as.integer));
+        break;
+    case TAG_STRING:
+        rt_append(buffer, value.as.string->bytes, value.as.string->length);
+        break;
+    case TAG_TUPLE:
+        rt_append(buffer, "(", 1);
+        rt_format(buffer, value.as.tuple->first);
+        rt_append(buffer, ", ", 2);
+        rt_format(buffer, value.as.tuple->second);
+        rt_append(buffer, ")", 1);
+        break;
+    default:
+        rt_append(buffer, "<#closure", 9);
+        if (value.as.closure->name != NULL) {
+            rt_append(buffer, " ", 1);
+            rt_append(buffer, value.as.closure->name, strlen(value.as.closure->name));
+        }
+        rt_append(buffer, ">", 1);
+    }
+}
+
+static inline void rt_write(Value value) {
+    Buffer buffer = {NULL, 0, 0};
+    rt_format(&buffer, value);
+    fwrite(buffer.bytes, 1, buffer.length, stdout);
+    free(buffer.bytes);
+}
+
+static inline Value rt_show(Value value) {
+    Buffer buffer = {NULL, 0, 0};
+    rt_format(&buffer, value);
+    Value string = rt_string(buffer.bytes, buffer.length);
+    free(buffer.bytes);
+    return string;
+}
+
+static inline void rt_print(Value value) {
+    rt_write(value);
+    putchar('\n');
+}
+
+static inline bool rt_condition(Value value) {
+    if (value.tag != TAG_BOOL) {
+        rt_fail("Type error: if condition must evaluate to a boolean.");
+    }
+    return value.as.boolean;
+}
+
+static inline Value rt_not(Value value) {
+    if (value.tag != TAG_BOOL) {
+        rt_fail("Operand must be a boolean.");
+    }
+    return rt_bool(!value.as.boolean);
+}
+
+static inline Value rt_global(uint16_t index) {
+    if (!rt_defined[index]) {
+        char message[256];
+        snprintf(message, sizeof message, "Unknown variable %s.", rt_identifiers[index]);
+        rt_fail(message);
+    }
+    return rt_globals[index];
+}
+
+static inline void rt_set_global(uint16_t index, Value value) {
+    rt_globals[index] = value;
+    rt_defined[index] = true;
+}
+
+static inline Value rt_closure(Code code, const char *name, uint16_t arity, uint16_t count,
+                        const Value *environment) {
+    Closure *closure = rt_alloc(sizeof(Closure) + count * sizeof(Value));
+    closure->code = code;
+    closure->name = name;
+    closure->arity = arity;
+    if (count > 0) {
+        memcpy(closure->environment, environment, count * sizeof(Value));
+    }
+
+    Value value;
+    value.tag = TAG_CLOSURE;
+    value.as.closure = closure;
+    return value;
+}
+
+static inline Closure *rt_callee(Value callee, uint16_t arity) {
+    if (callee.tag != TAG_CLOSURE) {
+        rt_fail("Attempted to call value that is not a function!");
+    }
+    if (callee.as.closure->arity != arity) {
+        rt_fail("Attempted to call function with wrong number of arguments.");
+    }
+    return callee.as.closure;
+}
+
+/* Makes the calls tail calls left pending until one returns a value. Callees
+   copy their arguments before doing anything else, so the next tail call can
+   reuse rt_pending. */
+static inline Value rt_finish(Value result) {
+    while (result.tag == TAG_TAIL_CALL) {
+        Closure *closure = rt_pending.closure;
+        result = closure->code(closure, rt_pending.arguments);
+    }
+    return result;
+}
+
+static inline Value rt_call(Value callee, uint16_t arity, const Value *arguments) {
+    Closure *closure = rt_callee(callee, arity);
+    return rt_finish(closure->code(closure, arguments));
+}
+
+static inline Value rt_tail_call(Value callee, uint16_t arity, const Value *arguments) {
+    rt_pending.closure = rt_callee(callee, arity);
+    if (arity > 0) {
+        memcpy(rt_pending.arguments, arguments, arity * sizeof(Value));
+    }
+
+    Value value;
+    value.tag = TAG_TAIL_CALL;
+    return value;
+}
+
+/* The next line of input without its line terminator. Once the input is
+   exhausted, every line is empty. */
+static inline Value rt_read_line(void) {
+    size_t length = 0;
+    size_t capacity = 64;
+    char *line = rt_alloc(capacity);
+    int c;
+
+    while ((c = getchar()) != EOF && c != '\n') {
+        if (length == capacity) {
+            capacity *= 2;
+            line = realloc(line, capacity);
+            if (line == NULL) {
+                rt_fail("Out of memory.");
+            }
+        }
+        line[length++] = (char)c;
+    }
+    if (length > 0 && line[length - 1] == '\r') {
+        length--;
+    }
+
+    Value value = rt_string(line, length);
+    free(line);
+    return value;
+}
+
+static inline Value rt_read_int(void) {
+    Value line = rt_read_line();
+    const char *start = line.as.string->bytes;
+    const char *end = start + line.as.string->length;
+
+    while (start < end && isspace((unsigned char)*start)) {
+        start++;
+    }
+    while (end > start && isspace((unsigned char)end[-1])) {
+        end--;
+    }
+
+    const char *digits = start;
+    bool negative = false;
+    if (digits < end && (*digits == '-' || *digits == '+')) {
+        negative = *digits == '-';
+        digits++;
+    }
+
+    int64_t integer = 0;
+    bool valid = digits < end;
+    for (const char *digit = digits; valid && digit < end; digit++) {
+        valid = isdigit((unsigned char)*digit) && integer <= (int64_t)INT32_MAX + 1;
+        integer = integer * 10 + (*digit - '0');
+    }
+    if (negative) {
+        integer = -integer;
+    }
+
+    if (!valid || integer < INT32_MIN || integer > INT32_MAX) {
+        char message[256];
+        snprintf(message, sizeof message, "Expected an integer as input, but read \"%.*s\".",
+                 (int)line.as.string->length, line.as.string->bytes);
+        rt_fail(message);
+    }
+    return rt_integer((int32_t)integer);
+}
+
+#endif
@@ -0,0 +1,117 @@
+use anyhow::{anyhow, bail, Result};
+use num_bigint::BigInt;
+use std::fmt;
+
+/// What the VM does when `+`, `-`, `*`, `/` or `%` produce an integer that
+/// does not fit in 32 bits. Dividing `-2147483648` by `-1` is the only way
+/// `/` and `%` overflow.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Overflow {
+    /// Results wrap around, so `2147483647 + 1` is `-2147483648`. This is
+    /// also what the reference interpreter and the compiled targets do.
+    #[default]
+    Wrapping,
+    /// Results are clamped to the nearest integer that fits.
+    Saturating,
+    /// Overflowing stops the program with an error.
+    Checked,
+    /// Results that do not fit become big integers, which arithmetic,
+    /// comparisons and `print` take like any other integer. Bitwise
+    /// operators and builtins only take integers that fit in 32 bits.
+    Promoting,
+}
+
+/// An arithmetic operator on integers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Operator {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+/// The result of arithmetic on integers: one that fits in 32 bits, or a big
+/// one when the VM promotes those that do not.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Integer {
+    Small(i32),
+    Big(BigInt),
+}
+
+impl From<BigInt> for Integer {
+    /// Keeps integers that fit in 32 bits small.
+    fn from(integer: BigInt) -> Self {
+        match i32::try_from(&integer) {
+            Ok(small) => Integer::Small(small),
+            Err(_) => Integer::Big(integer),
+        }
+    }
+}
+
+impl Overflow {
+    /// Applies `operator` to integers that fit in 32 bits, failing when
+    /// dividing by zero whatever the mode.
+    pub fn apply(self, operator: Operator, lhs: i32, rhs: i32) -> Result<Integer> {
+        let (wrapped, overflowed) = match operator {
+            Operator::Add => lhs.overflowing_add(rhs),
+            Operator::Sub => lhs.overflowing_sub(rhs),
+            Operator::Mul => lhs.overflowing_mul(rhs),
+            Operator::Div | Operator::Rem if rhs == 0 => bail!(operator.division_by_zero()),
+            Operator::Div => lhs.overflowing_div(rhs),
+            Operator::Rem => lhs.overflowing_rem(rhs),
+        };
+
+        match self {
+            _ if !overflowed => Ok(Integer::Small(wrapped)),
+            Overflow::Wrapping => Ok(Integer::Small(wrapped)),
+            Overflow::Saturating => Ok(Integer::Small(match operator {
+                Operator::Add => lhs.saturating_add(rhs),
+                Operator::Sub => lhs.saturating_sub(rhs),
+                Operator::Mul => lhs.saturating_mul(rhs),
+                Operator::Div => lhs.saturating_div(rhs),
+                // The remainder itself always fits, only the quotient
+                // computing it overflows.
+                Operator::Rem => wrapped,
+            })),
+            Overflow::Checked => Err(anyhow!("Integer overflow in {lhs} {operator} {rhs}.")),
+            Overflow::Promoting => operator.apply_big(&lhs.into(), &rhs.into()),
+        }
+    }
+}
+
+impl Operator {
+    /// Applies the operator to integers of any size.
+    pub fn apply_big(self, lhs: &BigInt, rhs: &BigInt) -> Result<Integer> {
+        let zero = BigInt::from(0);
+        Ok(match self {
+            Operator::Add => lhs + rhs,
+            Operator::Sub => lhs - rhs,
+            Operator::Mul => lhs * rhs,
+            Operator::Div | Operator::Rem if *rhs == zero => bail!(self.division_by_zero()),
+            Operator::Div => lhs / rhs,
+            Operator::Rem => lhs % rhs,
+        }
+        .into())
+    }
+
+    fn division_by_zero(self) -> &'static str {
+        match self {
+            Operator::Rem => "Attempted to take remainder by zero",
+            _ => "Attempted to divide by zero",
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Operator::Add => '+',
+            Operator::Sub => '-',
+            Operator::Mul => '*',
+            Operator::Div => '/',
+            Operator::Rem => '%',
+        };
+        write!(f, "{symbol}")
+    }
+}
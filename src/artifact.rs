@@ -0,0 +1,706 @@
+use anyhow::{anyhow, bail, Context, Result};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeSet, path::PathBuf, sync::Arc};
+
+use crate::{bytecode::Instruction, value::Value};
+
+/// The first bytes of every `.rvmc` file.
+pub const MAGIC: &[u8; 4] = b"RVMC";
+
+/// Version of the `.rvmc` format written by this build.
+pub const VERSION: u8 = 7;
+
+/// Header flag marking a body framed with zstd.
+const ZSTD: u8 = 1;
+
+/// A constant of a [`CompiledProgram`]. Unlike [`Value`], it holds nothing
+/// tied to the thread that made it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Constant {
+    Bool(bool),
+    Integer(i32),
+    String(Arc<str>),
+}
+
+impl TryFrom<&Value> for Constant {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Value) -> Result<Self> {
+        Ok(match value {
+            Value::Bool(b) => Constant::Bool(*b),
+            Value::Integer(i) => Constant::Integer(*i),
+            Value::String(s) => Constant::String(Arc::from(&**s)),
+            _ => bail!("Only booleans, integers and strings can be constants."),
+        })
+    }
+}
+
+impl From<&Constant> for Value {
+    fn from(constant: &Constant) -> Self {
+        match constant {
+            Constant::Bool(b) => Value::Bool(*b),
+            Constant::Integer(i) => Value::Integer(*i),
+            Constant::String(s) => Value::String(s.as_ref().into()),
+        }
+    }
+}
+
+/// A function of a [`CompiledProgram`]. Its index in the function table is
+/// its position in [`CompiledProgram::functions`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CompiledFunction {
+    pub arity: u16,
+    pub bytecode: Vec<Instruction>,
+    pub captured: Vec<String>,
+    pub locals: Vec<String>,
+    pub name: Option<String>,
+}
+
+/// Where a compiled program came from and how it was compiled.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Metadata {
+    pub filename: String,
+    pub source_sha256: [u8; 32],
+    pub compiler_version: String,
+    /// The optimizations and semantics the program was compiled with.
+    /// Programs compiled from the same source with different options are
+    /// not interchangeable.
+    pub options: Vec<String>,
+}
+
+/// The start of an `.rvmc` file, which is never compressed so the metadata
+/// can be read without decoding the program.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Header {
+    pub version: u8,
+    pub compressed: bool,
+    pub metadata: Metadata,
+}
+
+/// Everything needed to run a program without its source: the top-level
+/// bytecode and the tables it indexes into. It is only read when loaded, so
+/// an `Arc` of it can be shared by VMs running on different threads.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CompiledProgram {
+    pub constants: Vec<Constant>,
+    pub functions: Vec<CompiledFunction>,
+    pub identifiers: Vec<String>,
+    pub metadata: Metadata,
+    pub script: Vec<Instruction>,
+}
+
+const _: () = {
+    const fn shareable<T: Send + Sync>() {}
+    shareable::<CompiledProgram>()
+};
+
+impl Metadata {
+    /// Describes a program compiled by this build from `source`.
+    pub fn new(filename: &str, source: &str, options: &[&str]) -> Self {
+        Self {
+            filename: filename.to_owned(),
+            source_sha256: Sha256::digest(source.as_bytes()).into(),
+            compiler_version: env!("CARGO_PKG_VERSION").to_owned(),
+            options: options.iter().map(|&option| option.to_owned()).collect(),
+        }
+    }
+
+    /// The SHA-256 of the source, in hexadecimal.
+    pub fn source_digest(&self) -> String {
+        hex(&self.source_sha256)
+    }
+
+    /// Identifies the bytecode compiled from a source: programs share a key
+    /// only when they were compiled from the same source, by the same
+    /// compiler version, with the same options. The filename is left out, so
+    /// copies of a program share their compiled bytecode.
+    pub fn cache_key(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.source_sha256);
+        hasher.update(self.compiler_version.as_bytes());
+        for option in &self.options {
+            hasher.update([0]);
+            hasher.update(option.as_bytes());
+        }
+
+        hex(&hasher.finalize())
+    }
+
+    /// Like [`Metadata::cache_key`], but also identifying the files the
+    /// program imports by their resolved path and contents, as listed by
+    /// [`imported_files`](crate::imports::imported_files). Programs importing
+    /// nothing keep their [`Metadata::cache_key`].
+    pub fn cache_key_with_imports(&self, imports: &[(PathBuf, String)]) -> String {
+        if imports.is_empty() {
+            return self.cache_key();
+        }
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.cache_key().as_bytes());
+        for (path, contents) in imports {
+            hasher.update([0]);
+            hasher.update(path.to_string_lossy().as_bytes());
+            hasher.update([0]);
+            hasher.update(Sha256::digest(contents.as_bytes()));
+        }
+
+        hex(&hasher.finalize())
+    }
+}
+
+impl CompiledProgram {
+    /// Encodes the program in the `.rvmc` format.
+    ///
+    /// The constant pool is written deduplicated and sorted, with integers
+    /// delta encoded and strings front coded, so the constant indices in the
+    /// bytecode are renumbered on the way. Loading the artifact back yields an
+    /// equivalent program, but not necessarily an identical one.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = self.encode_header(0);
+        bytes.extend(self.encode_body()?);
+        Ok(bytes)
+    }
+
+    /// Like [`CompiledProgram::to_bytes`], but with the body compressed with
+    /// zstd at `level`. The header stays uncompressed.
+    #[cfg(feature = "zstd")]
+    pub fn to_compressed_bytes(&self, level: i32) -> Result<Vec<u8>> {
+        let body = self.encode_body()?;
+
+        let mut bytes = self.encode_header(ZSTD);
+        bytes.extend(zstd::encode_all(&body[..], level).context("Could not compress artifact.")?);
+        Ok(bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let (header, body) = split_header(bytes)?;
+
+        let body = if header.compressed {
+            decompress(body)?
+        } else {
+            body.to_vec()
+        };
+
+        Self::decode_body(&body, header.metadata)
+    }
+
+    /// Reads the header of an `.rvmc` file without decoding the program.
+    pub fn read_header(bytes: &[u8]) -> Result<Header> {
+        split_header(bytes).map(|(header, _)| header)
+    }
+
+    fn encode_header(&self, flags: u8) -> Vec<u8> {
+        let mut writer = Writer::default();
+
+        writer.bytes.extend(MAGIC);
+        writer.bytes.extend([VERSION, flags]);
+
+        writer.string(&self.metadata.filename);
+        writer.bytes.extend(self.metadata.source_sha256);
+        writer.string(&self.metadata.compiler_version);
+        writer.strings(&self.metadata.options);
+
+        writer.bytes
+    }
+
+    fn encode_body(&self) -> Result<Vec<u8>> {
+        let pool = Pool::new(&self.constants);
+        let mut writer = Writer::default();
+
+        pool.write(&mut writer);
+
+        writer.strings(&self.identifiers);
+
+        writer.varint(self.functions.len() as u64);
+        for function in &self.functions {
+            writer.varint(function.arity as u64);
+            writer.strings(&function.captured);
+            writer.strings(&function.locals);
+            writer.optional_string(function.name.as_deref());
+            writer.bytecode(&function.bytecode, &pool.renumbered)?;
+        }
+
+        writer.bytecode(&self.script, &pool.renumbered)?;
+
+        Ok(writer.bytes)
+    }
+
+    fn decode_body(bytes: &[u8], metadata: Metadata) -> Result<Self> {
+        let mut reader = Reader { bytes, position: 0 };
+
+        let constants = Pool::read(&mut reader)?;
+        let identifiers = reader.strings()?;
+
+        let mut functions = Vec::new();
+        for _ in 0..reader.varint()? {
+            functions.push(CompiledFunction {
+                arity: reader.operand()?,
+                captured: reader.strings()?,
+                locals: reader.strings()?,
+                name: reader.optional_string()?,
+                bytecode: reader.bytecode()?,
+            });
+        }
+
+        let script = reader.bytecode()?;
+
+        if reader.position != bytes.len() {
+            bail!("Unexpected trailing bytes at offset {}.", reader.position);
+        }
+
+        Ok(Self {
+            constants,
+            functions,
+            identifiers,
+            metadata,
+            script,
+        })
+    }
+}
+
+fn split_header(bytes: &[u8]) -> Result<(Header, &[u8])> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    let magic = reader
+        .take(MAGIC.len())
+        .map_err(|_| anyhow!("Not an .rvmc file."))?;
+    if magic != MAGIC {
+        bail!("Not an .rvmc file.");
+    }
+
+    let version = reader.byte()?;
+    if version != VERSION {
+        bail!("Unsupported .rvmc version {version}, expected {VERSION}.");
+    }
+
+    let compressed = match reader.byte()? {
+        0 => false,
+        ZSTD => true,
+        flags => bail!("Unknown .rvmc flags {flags:#04x}."),
+    };
+
+    let metadata = Metadata {
+        filename: reader.string()?,
+        source_sha256: reader
+            .take(32)?
+            .try_into()
+            .expect("Exactly 32 bytes were taken."),
+        compiler_version: reader.string()?,
+        options: reader.strings()?,
+    };
+
+    let header = Header {
+        version,
+        compressed,
+        metadata,
+    };
+
+    Ok((header, &bytes[reader.position..]))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(body: &[u8]) -> Result<Vec<u8>> {
+    zstd::decode_all(body).context("Could not decompress artifact.")
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_body: &[u8]) -> Result<Vec<u8>> {
+    bail!("Artifact is compressed with zstd, but rvm was built without the zstd feature.")
+}
+
+/// The constants in the order they are written: integers ascending, then
+/// strings in lexicographic order, then booleans.
+struct Pool<'a> {
+    integers: Vec<i32>,
+    strings: Vec<&'a str>,
+    booleans: Vec<bool>,
+    /// The new index of each of the program's constants.
+    renumbered: Vec<u32>,
+}
+
+impl<'a> Pool<'a> {
+    fn new(constants: &'a [Constant]) -> Self {
+        let mut integers = BTreeSet::new();
+        let mut strings = BTreeSet::new();
+        let mut booleans = BTreeSet::new();
+
+        for constant in constants {
+            match constant {
+                Constant::Integer(i) => integers.insert(*i),
+                Constant::String(s) => strings.insert(&**s),
+                Constant::Bool(b) => booleans.insert(*b),
+            };
+        }
+
+        let integers: Vec<i32> = integers.into_iter().collect();
+        let strings: Vec<&str> = strings.into_iter().collect();
+        let booleans: Vec<bool> = booleans.into_iter().collect();
+
+        let renumbered = constants
+            .iter()
+            .map(|constant| {
+                let index = match constant {
+                    Constant::Integer(i) => integers.binary_search(i),
+                    Constant::String(s) => strings
+                        .binary_search(&&**s)
+                        .map(|index| integers.len() + index),
+                    Constant::Bool(b) => booleans
+                        .binary_search(b)
+                        .map(|index| integers.len() + strings.len() + index),
+                };
+
+                index.expect("Every constant is in the pool.") as u32
+            })
+            .collect();
+
+        Self {
+            integers,
+            strings,
+            booleans,
+            renumbered,
+        }
+    }
+
+    fn write(&self, writer: &mut Writer) {
+        writer.varint(self.integers.len() as u64);
+        let mut previous = None;
+        for &integer in &self.integers {
+            match previous {
+                None => writer.varint(zigzag(integer)),
+                Some(previous) => writer.varint((integer as i64 - previous as i64) as u64),
+            }
+            previous = Some(integer);
+        }
+
+        writer.varint(self.strings.len() as u64);
+        let mut previous = "";
+        for string in &self.strings {
+            let shared = shared_prefix(previous, string);
+            writer.varint(shared as u64);
+            writer.string(&string[shared..]);
+            previous = string;
+        }
+
+        writer.varint(self.booleans.len() as u64);
+        for &boolean in &self.booleans {
+            writer.bytes.push(boolean as u8);
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Vec<Constant>> {
+        let mut constants = Vec::new();
+
+        let mut previous: Option<i32> = None;
+        for _ in 0..reader.varint()? {
+            let integer = match previous {
+                None => unzigzag(reader.varint()?)?,
+                Some(previous) => i64::from(previous)
+                    .checked_add_unsigned(reader.varint()?)
+                    .and_then(|integer| i32::try_from(integer).ok())
+                    .ok_or_else(|| anyhow!("Integer constant out of range."))?,
+            };
+            constants.push(Constant::Integer(integer));
+            previous = Some(integer);
+        }
+
+        let mut previous = String::new();
+        for _ in 0..reader.varint()? {
+            let shared = reader.varint()? as usize;
+            let suffix = reader.string()?;
+
+            if !previous.is_char_boundary(shared) {
+                bail!("Invalid prefix length {shared} in string constant.");
+            }
+
+            previous.truncate(shared);
+            previous.push_str(&suffix);
+            constants.push(Constant::String(Arc::from(previous.as_str())));
+        }
+
+        for _ in 0..reader.varint()? {
+            let boolean = match reader.byte()? {
+                0 => false,
+                1 => true,
+                byte => bail!("Invalid boolean constant {byte}."),
+            };
+            constants.push(Constant::Bool(boolean));
+        }
+
+        if constants.len() > u32::MAX as usize {
+            bail!("Cannot create more than {} constants.", u32::MAX);
+        }
+
+        Ok(constants)
+    }
+}
+
+/// The length in bytes of the longest common prefix of `lhs` and `rhs` that
+/// ends at a character boundary.
+fn shared_prefix(lhs: &str, rhs: &str) -> usize {
+    let mut shared = lhs
+        .bytes()
+        .zip(rhs.bytes())
+        .take_while(|(l, r)| l == r)
+        .count();
+
+    while !rhs.is_char_boundary(shared) {
+        shared -= 1;
+    }
+
+    shared
+}
+
+pub(crate) fn zigzag(integer: i32) -> u64 {
+    ((integer << 1) ^ (integer >> 31)) as u32 as u64
+}
+
+pub(crate) fn unzigzag(encoded: u64) -> Result<i32> {
+    let encoded = u32::try_from(encoded).map_err(|_| anyhow!("Integer constant out of range."))?;
+    Ok(((encoded >> 1) as i32) ^ -((encoded & 1) as i32))
+}
+
+#[derive(Default)]
+pub(crate) struct Writer {
+    pub(crate) bytes: Vec<u8>,
+}
+
+impl Writer {
+    /// LEB128: seven bits per byte, least significant first, with the high
+    /// bit set on every byte but the last.
+    pub(crate) fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    pub(crate) fn string(&mut self, string: &str) {
+        self.varint(string.len() as u64);
+        self.bytes.extend(string.as_bytes());
+    }
+
+    pub(crate) fn optional_string(&mut self, string: Option<&str>) {
+        match string {
+            Some(string) => {
+                self.bytes.push(1);
+                self.string(string);
+            }
+            None => self.bytes.push(0),
+        }
+    }
+
+    pub(crate) fn strings(&mut self, strings: &[String]) {
+        self.varint(strings.len() as u64);
+        for string in strings {
+            self.string(string);
+        }
+    }
+
+    fn bytecode(&mut self, bytecode: &[Instruction], constants: &[u32]) -> Result<()> {
+        self.varint(bytecode.len() as u64);
+
+        for instruction in bytecode {
+            self.bytes.push(instruction.opcode());
+
+            match *instruction {
+                Instruction::Constant(index) => {
+                    let index = constants
+                        .get(index as usize)
+                        .ok_or_else(|| anyhow!("Constant {index} does not exist."))?;
+                    self.varint(*index as u64);
+                }
+                Instruction::GlobalGet(operand)
+                | Instruction::GlobalSet(operand)
+                | Instruction::Closure(operand) => self.varint(operand as u64),
+                Instruction::Call(operand)
+                | Instruction::Return(operand)
+                | Instruction::TailCall(operand)
+                | Instruction::Slide(operand) => self.varint(operand as u64),
+                Instruction::LocalGet(index, identifier) => {
+                    self.varint(index as u64);
+                    self.varint(identifier as u64);
+                }
+                Instruction::If(jump) | Instruction::Jump(jump) | Instruction::JumpBack(jump) => {
+                    self.varint(jump as u64)
+                }
+                Instruction::LocalGetConstantAdd(index, identifier, constant)
+                | Instruction::LocalGetConstantSub(index, identifier, constant) => {
+                    let constant = constants
+                        .get(constant as usize)
+                        .ok_or_else(|| anyhow!("Constant {constant} does not exist."))?;
+                    self.varint(index as u64);
+                    self.varint(identifier as u64);
+                    self.varint(*constant as u64);
+                }
+                Instruction::ConstantLt(index) | Instruction::ConstantEq(index) => {
+                    let index = constants
+                        .get(index as usize)
+                        .ok_or_else(|| anyhow!("Constant {index} does not exist."))?;
+                    self.varint(*index as u64);
+                }
+                Instruction::LocalGetTailCall(index, identifier, arity) => {
+                    self.varint(index as u64);
+                    self.varint(identifier as u64);
+                    self.varint(arity as u64);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes, position: 0 }
+    }
+
+    /// Whether every byte has been read.
+    pub(crate) fn is_finished(&self) -> bool {
+        self.position == self.bytes.len()
+    }
+
+    pub(crate) fn byte(&mut self) -> Result<u8> {
+        let byte = *self
+            .bytes
+            .get(self.position)
+            .ok_or_else(|| anyhow!("Artifact ends unexpectedly."))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    pub(crate) fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or_else(|| anyhow!("Artifact ends unexpectedly."))?;
+
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    pub(crate) fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        bail!("Varint at offset {} is too long.", self.position)
+    }
+
+    pub(crate) fn operand<T: TryFrom<u64>>(&mut self) -> Result<T> {
+        let value = self.varint()?;
+        T::try_from(value).map_err(|_| anyhow!("Operand {value} out of range."))
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String> {
+        let length = self.varint()? as usize;
+        let bytes = self.take(length)?;
+
+        let string = std::str::from_utf8(bytes).context("Invalid UTF-8 in string.")?;
+        Ok(string.to_owned())
+    }
+
+    pub(crate) fn optional_string(&mut self) -> Result<Option<String>> {
+        match self.byte()? {
+            0 => Ok(None),
+            1 => self.string().map(Some),
+            byte => bail!("Invalid optional string marker {byte}."),
+        }
+    }
+
+    pub(crate) fn strings(&mut self) -> Result<Vec<String>> {
+        (0..self.varint()?).map(|_| self.string()).collect()
+    }
+
+    fn bytecode(&mut self) -> Result<Vec<Instruction>> {
+        (0..self.varint()?)
+            .map(|_| {
+                let instruction = match self.byte()? {
+                    0 => Instruction::Constant(self.operand()?),
+                    1 => Instruction::True,
+                    2 => Instruction::False,
+                    3 => Instruction::Add,
+                    4 => Instruction::Sub,
+                    5 => Instruction::Mul,
+                    6 => Instruction::Div,
+                    7 => Instruction::Rem,
+                    8 => Instruction::Eq,
+                    9 => Instruction::Neq,
+                    10 => Instruction::Gt,
+                    11 => Instruction::Lt,
+                    12 => Instruction::Gte,
+                    13 => Instruction::Lte,
+                    14 => Instruction::And,
+                    15 => Instruction::Or,
+                    16 => Instruction::Tuple,
+                    17 => Instruction::First,
+                    18 => Instruction::Second,
+                    19 => Instruction::Print,
+                    20 => Instruction::Dup,
+                    21 => Instruction::GlobalGet(self.operand()?),
+                    22 => Instruction::GlobalSet(self.operand()?),
+                    23 => Instruction::LocalGet(self.operand()?, self.operand()?),
+                    24 => Instruction::If(self.operand()?),
+                    25 => Instruction::Jump(self.operand()?),
+                    26 => Instruction::Closure(self.operand()?),
+                    27 => Instruction::Call(self.operand()?),
+                    28 => Instruction::Return(self.operand()?),
+                    29 => Instruction::TailCall(self.operand()?),
+                    30 => Instruction::Slide(self.operand()?),
+                    31 => Instruction::ReadLine,
+                    32 => Instruction::ReadInt,
+                    33 => Instruction::Pop,
+                    34 => Instruction::LocalGetConstantAdd(
+                        self.operand()?,
+                        self.operand()?,
+                        self.operand()?,
+                    ),
+                    35 => Instruction::LocalGetConstantSub(
+                        self.operand()?,
+                        self.operand()?,
+                        self.operand()?,
+                    ),
+                    36 => Instruction::ConstantLt(self.operand()?),
+                    37 => Instruction::ConstantEq(self.operand()?),
+                    38 => Instruction::LocalGetTailCall(
+                        self.operand()?,
+                        self.operand()?,
+                        self.operand()?,
+                    ),
+                    39 => Instruction::Neg,
+                    40 => Instruction::Not,
+                    41 => Instruction::Show,
+                    42 => Instruction::BitAnd,
+                    43 => Instruction::BitOr,
+                    44 => Instruction::BitXor,
+                    45 => Instruction::Shl,
+                    46 => Instruction::Shr,
+                    47 => Instruction::JumpBack(self.operand()?),
+                    opcode => bail!("Unknown opcode {opcode}."),
+                };
+
+                Ok(instruction)
+            })
+            .collect()
+    }
+}
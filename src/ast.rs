@@ -0,0 +1,676 @@
+use anyhow::{bail, Result};
+use rinha::{
+    ast::{self as rinha_ast, Element},
+    parser::{parse_or_report, Var},
+};
+use serde::{Deserialize, Serialize};
+
+/// A program in the JSON format the rinha specification distributes
+/// pre-parsed programs in.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct File {
+    pub name: String,
+    pub expression: Term,
+    pub location: Location,
+}
+
+impl File {
+    /// Parses rinha source code into the JSON model.
+    pub fn parse(filename: &str, contents: &str) -> Result<Self> {
+        let file = parse_program(filename, contents)?;
+
+        Ok(Self {
+            name: file.name,
+            expression: file.expression.try_into()?,
+            location: file.location.into(),
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Location {
+    pub start: usize,
+    pub end: usize,
+    pub filename: String,
+}
+
+/// A name introduced by `let` or by a function parameter.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Parameter {
+    pub text: String,
+    pub location: Location,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Eq,
+    Neq,
+    Lt,
+    Gt,
+    Lte,
+    Gte,
+    And,
+    Or,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub enum UnaryOp {
+    /// Integer negation, `-x`.
+    Neg,
+    /// Boolean negation, `!x`.
+    Not,
+}
+
+impl UnaryOp {
+    /// The parser's AST has no unary terms, so they are represented as a
+    /// call of one argument to this name, which no program can spell.
+    pub fn callee(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "-",
+            UnaryOp::Not => "!",
+        }
+    }
+
+    /// The operator [`UnaryOp::callee`] stands for `name`, if any.
+    pub fn from_callee(name: &str) -> Option<Self> {
+        [UnaryOp::Neg, UnaryOp::Not]
+            .into_iter()
+            .find(|op| op.callee() == name)
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "kind")]
+pub enum Term {
+    Int {
+        value: i32,
+        location: Location,
+    },
+    Str {
+        value: String,
+        location: Location,
+    },
+    Bool {
+        value: bool,
+        location: Location,
+    },
+    Call {
+        callee: Box<Term>,
+        arguments: Vec<Term>,
+        location: Location,
+    },
+    Binary {
+        lhs: Box<Term>,
+        op: BinaryOp,
+        rhs: Box<Term>,
+        location: Location,
+    },
+    /// Not part of the rinha specification, which has no unary operators,
+    /// but accepted in JSON ASTs written by hand or by other front-ends.
+    Unary {
+        op: UnaryOp,
+        value: Box<Term>,
+        location: Location,
+    },
+    Function {
+        parameters: Vec<Parameter>,
+        value: Box<Term>,
+        location: Location,
+    },
+    Let {
+        name: Parameter,
+        value: Box<Term>,
+        next: Box<Term>,
+        location: Location,
+    },
+    /// JSON ASTs may leave out `otherwise`, which the rinha grammar always
+    /// has. An `if` without it evaluates to `false` when the condition is.
+    If {
+        condition: Box<Term>,
+        then: Box<Term>,
+        #[serde(default = "Term::missing_otherwise")]
+        otherwise: Box<Term>,
+        location: Location,
+    },
+    Print {
+        value: Box<Term>,
+        location: Location,
+    },
+    First {
+        value: Box<Term>,
+        location: Location,
+    },
+    Second {
+        value: Box<Term>,
+        location: Location,
+    },
+    Tuple {
+        first: Box<Term>,
+        second: Box<Term>,
+        location: Location,
+    },
+    Var {
+        text: String,
+        location: Location,
+    },
+    /// Evaluates `value` for its effects and discards it, then evaluates
+    /// `next`. Written `value; next` at the top level of a program.
+    Sequence {
+        value: Box<Term>,
+        next: Box<Term>,
+        location: Location,
+    },
+}
+
+impl Term {
+    fn missing_otherwise() -> Box<Term> {
+        Box::new(Term::Bool {
+            value: false,
+            location: Location::default(),
+        })
+    }
+}
+
+/// The parser's AST has no sequences, so `value; next` is represented as a
+/// `let` binding this name, which no program can spell. The compiler pops
+/// the value instead of binding it.
+pub const SEQUENCE_BINDING: &str = "";
+
+/// Parses a program, also accepting a sequence of top-level expressions
+/// separated by `;`, such as `print(1); print(2); 3`, which the rinha grammar
+/// only allows after `let`s. Each expression but the last is evaluated for
+/// its effects, and the last is the result. The last may be followed by a
+/// `;` too, and statements on lines of their own need no `;` between them,
+/// as in a file of `let`s and `print`s one per line.
+///
+/// Programs the grammar accepts are parsed as they are. Others are split
+/// into their top-level statements, which are parsed one by one in a copy
+/// of the source with everything else blanked out, so locations still point
+/// into the original.
+pub fn parse_program(filename: &str, contents: &str) -> Result<rinha_ast::File> {
+    let error = match parse_or_report(filename, contents) {
+        Ok(file) => return Ok(file),
+        Err(error) => error,
+    };
+
+    let mut statements = split_statements(contents);
+    let trailing_semicolon = statements.last().is_some_and(|&(start, end)| start == end);
+    if trailing_semicolon {
+        statements.pop();
+    }
+
+    let is_let = |&(start, end): &(usize, usize)| starts_with_keyword(&contents[start..end], "let");
+    let Some((last, init)) = statements.split_last() else {
+        return Err(error.into());
+    };
+    // Without anything the grammar lacks, the error is the program's own.
+    let ends_with_semicolon = |&(_, end): &(usize, usize)| contents.as_bytes()[end] == b';';
+    if is_let(last)
+        || (!trailing_semicolon && init.iter().all(|s| is_let(s) && ends_with_semicolon(s)))
+    {
+        return Err(error.into());
+    }
+    let &(last_start, last_end) = last;
+
+    let parse = |start: usize, end: usize, suffix: &str| -> Result<rinha_ast::File> {
+        let mut padded: Vec<u8> = contents
+            .bytes()
+            .enumerate()
+            .map(|(index, byte)| match byte {
+                _ if (start..end).contains(&index) => byte,
+                b'\n' => b'\n',
+                _ => b' ',
+            })
+            .collect();
+        padded.extend(suffix.bytes());
+
+        let padded = String::from_utf8(padded).expect("Statements end at ASCII characters.");
+        Ok(parse_or_report(filename, &padded)?)
+    };
+
+    let mut file = parse(last_start, last_end, "")?;
+    for &(start, end) in init.iter().rev() {
+        let next = Box::new(file.expression);
+        let location = rinha_ast::Location::new(start, file.location.end, filename);
+
+        file.expression = if is_let(&(start, end)) {
+            // Parse the `let` with a placeholder to stand for what follows.
+            let rinha_ast::Term::Let(binding) = parse(start, end, ";0")?.expression else {
+                bail!("Expected a let at {filename}:{start}..{end}.");
+            };
+            rinha_ast::Term::Let(rinha_ast::Let {
+                next,
+                location,
+                ..binding
+            })
+        } else {
+            let value = parse(start, end, "")?.expression;
+            rinha_ast::Term::Let(rinha_ast::Let {
+                name: Var {
+                    text: SEQUENCE_BINDING.to_owned(),
+                    location: value.location().clone(),
+                },
+                value: Box::new(value),
+                next,
+                location,
+            })
+        };
+        file.location.start = start;
+    }
+
+    Ok(file)
+}
+
+/// Parses a program the parser rejects as far as it recovers from its syntax
+/// errors, leaving an error node in place of each, or `None` if it cannot.
+/// Only the grammar is followed, without the statement sequences
+/// [`parse_program`] also accepts.
+pub fn parse_recovering(filename: &str, contents: &str) -> Option<rinha_ast::File> {
+    let mut errors = Vec::new();
+    rinha::rinha::FileParser::new()
+        .parse(&mut errors, filename, contents)
+        .ok()
+}
+
+/// Whether `byte` may be the last of a term: a name, a number, a string or
+/// something in parentheses or braces.
+fn ends_term(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || matches!(byte, b'_' | b'"' | b')' | b'}')
+}
+
+/// Whether `text` starts with the keyword, and not with a longer name.
+fn starts_with_keyword(text: &str, keyword: &str) -> bool {
+    text.strip_prefix(keyword)
+        .is_some_and(|rest| !rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_'))
+}
+
+/// The byte ranges of the statements making up a program, which are
+/// separated by the `;`s outside of any parentheses, braces, strings or
+/// comments. A line starting with a name, a number or a string also starts
+/// a statement when the line before ends a term, since the expression
+/// there cannot go on, unless it is an `else`. Each range starts at its
+/// statement's first token and ends after its last.
+pub(crate) fn split_statements(contents: &str) -> Vec<(usize, usize)> {
+    let bytes = contents.as_bytes();
+    let mut statements = Vec::new();
+    let mut start = None;
+    let mut depth = 0usize;
+    let mut index = 0;
+    // The end of the last token, and whether a line ended after it.
+    let mut token_end = 0;
+    let mut line_ended = false;
+
+    while index < bytes.len() {
+        let byte = bytes[index];
+        let comment = byte == b'/' && matches!(bytes.get(index + 1), Some(b'/' | b'*'));
+        let token = !byte.is_ascii_whitespace() && !comment;
+        if token {
+            // Only a call, with `(`, or an operator continue an expression.
+            let follows_expression = token_end > 0 && ends_term(bytes[token_end - 1]);
+            let starts_statement = ends_term(byte)
+                && !matches!(byte, b')' | b'}')
+                && !starts_with_keyword(&contents[index..], "else");
+            if let Some(current) = start.filter(|_| depth == 0 && line_ended) {
+                if follows_expression && starts_statement {
+                    statements.push((current, token_end));
+                    start = None;
+                }
+            }
+            line_ended = false;
+        }
+        if start.is_none() && token && byte != b';' {
+            start = Some(index);
+        }
+
+        match byte {
+            b'(' | b'{' => depth += 1,
+            b')' | b'}' => depth = depth.saturating_sub(1),
+            b';' if depth == 0 => {
+                statements.push((start.unwrap_or(index), index));
+                start = None;
+            }
+            b'"' => {
+                index += 1;
+                while index < bytes.len() && bytes[index] != b'"' {
+                    if bytes[index] == b'\\' {
+                        index += 1;
+                    }
+                    index += 1;
+                }
+            }
+            b'/' if bytes.get(index + 1) == Some(&b'/') => {
+                while index < bytes.len() && bytes[index] != b'\n' {
+                    index += 1;
+                }
+                line_ended = true;
+            }
+            b'/' if bytes.get(index + 1) == Some(&b'*') => {
+                index += 2;
+                while index < bytes.len() && !bytes[index..].starts_with(b"*/") {
+                    line_ended |= bytes[index] == b'\n';
+                    index += 1;
+                }
+                index += 1;
+            }
+            b'\n' => line_ended = true,
+            _ => {}
+        }
+        index += 1;
+        if token {
+            token_end = index.min(bytes.len());
+        }
+    }
+
+    statements.push((start.unwrap_or(contents.len()), contents.len()));
+    statements
+}
+
+impl From<Location> for rinha_ast::Location {
+    fn from(location: Location) -> Self {
+        rinha_ast::Location::new(location.start, location.end, &location.filename)
+    }
+}
+
+impl From<Parameter> for Var {
+    fn from(parameter: Parameter) -> Self {
+        Var {
+            text: parameter.text,
+            location: parameter.location.into(),
+        }
+    }
+}
+
+impl From<BinaryOp> for rinha_ast::BinaryOp {
+    fn from(op: BinaryOp) -> Self {
+        match op {
+            BinaryOp::Add => rinha_ast::BinaryOp::Add,
+            BinaryOp::Sub => rinha_ast::BinaryOp::Sub,
+            BinaryOp::Mul => rinha_ast::BinaryOp::Mul,
+            BinaryOp::Div => rinha_ast::BinaryOp::Div,
+            BinaryOp::Rem => rinha_ast::BinaryOp::Rem,
+            BinaryOp::Eq => rinha_ast::BinaryOp::Eq,
+            BinaryOp::Neq => rinha_ast::BinaryOp::Neq,
+            BinaryOp::Lt => rinha_ast::BinaryOp::Lt,
+            BinaryOp::Gt => rinha_ast::BinaryOp::Gt,
+            BinaryOp::Lte => rinha_ast::BinaryOp::Lte,
+            BinaryOp::Gte => rinha_ast::BinaryOp::Gte,
+            BinaryOp::And => rinha_ast::BinaryOp::And,
+            BinaryOp::Or => rinha_ast::BinaryOp::Or,
+        }
+    }
+}
+
+/// Converts to the parser's AST, which is what the compiler works on.
+impl From<Term> for rinha_ast::Term {
+    fn from(term: Term) -> Self {
+        let boxed = |term: Box<Term>| Box::new(rinha_ast::Term::from(*term));
+
+        match term {
+            Term::Int { value, location } => rinha_ast::Term::Int(rinha_ast::Int {
+                value,
+                location: location.into(),
+            }),
+            Term::Str { value, location } => rinha_ast::Term::Str(rinha_ast::Str {
+                value,
+                location: location.into(),
+            }),
+            Term::Bool { value, location } => rinha_ast::Term::Bool(rinha_ast::Bool {
+                value,
+                location: location.into(),
+            }),
+            Term::Call {
+                callee,
+                arguments,
+                location,
+            } => rinha_ast::Term::Call(rinha_ast::Call {
+                callee: boxed(callee),
+                arguments: arguments.into_iter().map(Into::into).collect(),
+                location: location.into(),
+            }),
+            Term::Binary {
+                lhs,
+                op,
+                rhs,
+                location,
+            } => rinha_ast::Term::Binary(rinha_ast::Binary {
+                lhs: boxed(lhs),
+                op: op.into(),
+                rhs: boxed(rhs),
+                location: location.into(),
+            }),
+            Term::Unary {
+                op,
+                value,
+                location,
+            } => {
+                let value = boxed(value);
+                rinha_ast::Term::Call(rinha_ast::Call {
+                    callee: Box::new(rinha_ast::Term::Var(Var {
+                        text: op.callee().to_owned(),
+                        location: location.clone().into(),
+                    })),
+                    arguments: vec![*value],
+                    location: location.into(),
+                })
+            }
+            Term::Function {
+                parameters,
+                value,
+                location,
+            } => rinha_ast::Term::Function(rinha_ast::Function {
+                parameters: parameters.into_iter().map(Into::into).collect(),
+                value: boxed(value),
+                location: location.into(),
+            }),
+            Term::Let {
+                name,
+                value,
+                next,
+                location,
+            } => rinha_ast::Term::Let(rinha_ast::Let {
+                name: name.into(),
+                value: boxed(value),
+                next: boxed(next),
+                location: location.into(),
+            }),
+            Term::If {
+                condition,
+                then,
+                otherwise,
+                location,
+            } => rinha_ast::Term::If(rinha_ast::If {
+                condition: boxed(condition),
+                then: boxed(then),
+                otherwise: boxed(otherwise),
+                location: location.into(),
+            }),
+            Term::Print { value, location } => rinha_ast::Term::Print(rinha_ast::Print {
+                value: boxed(value),
+                location: location.into(),
+            }),
+            Term::First { value, location } => rinha_ast::Term::First(rinha_ast::First {
+                value: boxed(value),
+                location: location.into(),
+            }),
+            Term::Second { value, location } => rinha_ast::Term::Second(rinha_ast::Second {
+                value: boxed(value),
+                location: location.into(),
+            }),
+            Term::Tuple {
+                first,
+                second,
+                location,
+            } => rinha_ast::Term::Tuple(rinha_ast::Tuple {
+                first: boxed(first),
+                second: boxed(second),
+                location: location.into(),
+            }),
+            Term::Var { text, location } => rinha_ast::Term::Var(Var {
+                text,
+                location: location.into(),
+            }),
+            Term::Sequence {
+                value,
+                next,
+                location,
+            } => {
+                let value = boxed(value);
+                rinha_ast::Term::Let(rinha_ast::Let {
+                    name: Var {
+                        text: SEQUENCE_BINDING.to_owned(),
+                        location: value.location().clone(),
+                    },
+                    value,
+                    next: boxed(next),
+                    location: location.into(),
+                })
+            }
+        }
+    }
+}
+
+impl From<rinha_ast::Location> for Location {
+    fn from(location: rinha_ast::Location) -> Self {
+        Location {
+            start: location.start,
+            end: location.end,
+            filename: location.filename,
+        }
+    }
+}
+
+impl From<Var> for Parameter {
+    fn from(var: Var) -> Self {
+        Parameter {
+            text: var.text,
+            location: var.location.into(),
+        }
+    }
+}
+
+impl From<rinha_ast::BinaryOp> for BinaryOp {
+    fn from(op: rinha_ast::BinaryOp) -> Self {
+        match op {
+            rinha_ast::BinaryOp::Add => BinaryOp::Add,
+            rinha_ast::BinaryOp::Sub => BinaryOp::Sub,
+            rinha_ast::BinaryOp::Mul => BinaryOp::Mul,
+            rinha_ast::BinaryOp::Div => BinaryOp::Div,
+            rinha_ast::BinaryOp::Rem => BinaryOp::Rem,
+            rinha_ast::BinaryOp::Eq => BinaryOp::Eq,
+            rinha_ast::BinaryOp::Neq => BinaryOp::Neq,
+            rinha_ast::BinaryOp::Lt => BinaryOp::Lt,
+            rinha_ast::BinaryOp::Gt => BinaryOp::Gt,
+            rinha_ast::BinaryOp::Lte => BinaryOp::Lte,
+            rinha_ast::BinaryOp::Gte => BinaryOp::Gte,
+            rinha_ast::BinaryOp::And => BinaryOp::And,
+            rinha_ast::BinaryOp::Or => BinaryOp::Or,
+        }
+    }
+}
+
+/// Converts from the parser's AST, which fails only on the error nodes the
+/// parser leaves behind when recovering from syntax errors.
+impl TryFrom<rinha_ast::Term> for Term {
+    type Error = anyhow::Error;
+
+    fn try_from(term: rinha_ast::Term) -> Result<Self> {
+        let boxed = |term: Box<rinha_ast::Term>| Term::try_from(*term).map(Box::new);
+
+        let term = match term {
+            rinha_ast::Term::Error(error) => bail!("Syntax error: {}", error.message),
+            rinha_ast::Term::Int(int) => Term::Int {
+                value: int.value,
+                location: int.location.into(),
+            },
+            rinha_ast::Term::Str(string) => Term::Str {
+                value: string.value,
+                location: string.location.into(),
+            },
+            rinha_ast::Term::Bool(bool) => Term::Bool {
+                value: bool.value,
+                location: bool.location.into(),
+            },
+            rinha_ast::Term::Call(mut call) => {
+                let op = match (&*call.callee, call.arguments.len()) {
+                    (rinha_ast::Term::Var(var), 1) => UnaryOp::from_callee(&var.text),
+                    _ => None,
+                };
+
+                match op {
+                    Some(op) => Term::Unary {
+                        op,
+                        value: Box::new(Term::try_from(call.arguments.remove(0))?),
+                        location: call.location.into(),
+                    },
+                    None => Term::Call {
+                        callee: boxed(call.callee)?,
+                        arguments: call
+                            .arguments
+                            .into_iter()
+                            .map(Term::try_from)
+                            .collect::<Result<_>>()?,
+                        location: call.location.into(),
+                    },
+                }
+            }
+            rinha_ast::Term::Binary(binary) => Term::Binary {
+                lhs: boxed(binary.lhs)?,
+                op: binary.op.into(),
+                rhs: boxed(binary.rhs)?,
+                location: binary.location.into(),
+            },
+            rinha_ast::Term::Function(function) => Term::Function {
+                parameters: function.parameters.into_iter().map(Into::into).collect(),
+                value: boxed(function.value)?,
+                location: function.location.into(),
+            },
+            rinha_ast::Term::Let(binding) if binding.name.text == SEQUENCE_BINDING => {
+                Term::Sequence {
+                    value: boxed(binding.value)?,
+                    next: boxed(binding.next)?,
+                    location: binding.location.into(),
+                }
+            }
+            rinha_ast::Term::Let(binding) => Term::Let {
+                name: binding.name.into(),
+                value: boxed(binding.value)?,
+                next: boxed(binding.next)?,
+                location: binding.location.into(),
+            },
+            rinha_ast::Term::If(branch) => Term::If {
+                condition: boxed(branch.condition)?,
+                then: boxed(branch.then)?,
+                otherwise: boxed(branch.otherwise)?,
+                location: branch.location.into(),
+            },
+            rinha_ast::Term::Print(print) => Term::Print {
+                value: boxed(print.value)?,
+                location: print.location.into(),
+            },
+            rinha_ast::Term::First(first) => Term::First {
+                value: boxed(first.value)?,
+                location: first.location.into(),
+            },
+            rinha_ast::Term::Second(second) => Term::Second {
+                value: boxed(second.value)?,
+                location: second.location.into(),
+            },
+            rinha_ast::Term::Tuple(tuple) => Term::Tuple {
+                first: boxed(tuple.first)?,
+                second: boxed(tuple.second)?,
+                location: tuple.location.into(),
+            },
+            rinha_ast::Term::Var(var) => Term::Var {
+                text: var.text,
+                location: var.location.into(),
+            },
+        };
+
+        Ok(term)
+    }
+}
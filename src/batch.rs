@@ -0,0 +1,139 @@
+use anyhow::{anyhow, Context, Result};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::{
+    fmt, fs, io,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    compare::{files, normalize},
+    optimizer::Passes,
+    printing::Captured,
+    value::FinalValue,
+    vm::Vm,
+};
+
+/// How [`run_all`] runs each program.
+#[derive(Clone, Debug, Default)]
+pub struct BatchOptions {
+    /// How many instructions each program may execute.
+    pub fuel: Option<u64>,
+    /// How long each program may take. The clock starts before compiling
+    /// the program but is only checked while it runs, so compiling it is
+    /// never cut short.
+    pub timeout: Option<Duration>,
+    /// Roughly how many bytes of values each program may keep alive.
+    pub memory_limit: Option<usize>,
+    pub passes: Passes,
+    /// How many programs run at once, or `None` for one per core.
+    pub threads: Option<usize>,
+}
+
+/// What running one program produced.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RunReport {
+    pub program: PathBuf,
+    pub result: Option<FinalValue>,
+    pub error: Option<String>,
+    /// The lines the program printed, normalized like [`normalize`] does.
+    pub stdout: Vec<String>,
+    pub instructions_executed: u64,
+    pub elapsed: Duration,
+}
+
+impl fmt::Display for RunReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let program = self.program.display();
+        let milliseconds = self.elapsed.as_millis();
+
+        match (&self.result, &self.error) {
+            (_, Some(error)) => write!(f, "error    {program} ({milliseconds} ms): {error}"),
+            (result, None) => {
+                let result = serde_json::to_string(result).map_err(|_| fmt::Error)?;
+                write!(f, "ok       {program} ({milliseconds} ms): {result}")
+            }
+        }
+    }
+}
+
+/// Lists the `.rinha` programs and `.json` ASTs in `directory`, sorted so
+/// reports are stable.
+pub fn programs(directory: &Path) -> Result<Vec<PathBuf>> {
+    files(directory, &["rinha", "json"])
+}
+
+/// Runs every program in `paths` on a thread pool, each in a VM of its own
+/// with the limits in `options`, reporting on them in the order given.
+///
+/// Each VM is created, run and dropped on the thread that picks its program
+/// up, so only paths and reports cross threads.
+pub fn run_all(paths: &[PathBuf], options: &BatchOptions) -> Result<Vec<RunReport>> {
+    let mut pool = rayon::ThreadPoolBuilder::new();
+    if let Some(threads) = options.threads {
+        pool = pool.num_threads(threads);
+    }
+    let pool = pool.build().context("Could not start the thread pool.")?;
+
+    Ok(pool.install(|| {
+        paths
+            .par_iter()
+            .map(|path| run_one(path, options))
+            .collect()
+    }))
+}
+
+/// Runs the program at `path` like [`run_all`] runs each of its programs.
+/// Programs read no input and only print into their report.
+pub fn run_one(path: &Path, options: &BatchOptions) -> RunReport {
+    let start = Instant::now();
+    let printed = Captured::default();
+
+    let mut vm = Vm::new()
+        .with_passes(options.passes.clone())
+        .with_reader(io::empty())
+        .with_output(printed.clone());
+    if let Some(fuel) = options.fuel {
+        vm = vm.with_fuel(fuel);
+    }
+    if let Some(timeout) = options.timeout {
+        vm = vm.with_timeout(timeout);
+    }
+    if let Some(bytes) = options.memory_limit {
+        vm = vm.with_memory_limit(bytes);
+    }
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| execute(&mut vm, path)))
+        .unwrap_or_else(|_| Err(anyhow!("The VM panicked.")));
+
+    let stdout = normalize(&printed.text());
+    let (result, error) = match result {
+        Ok(value) => (Some(value), None),
+        Err(error) => (None, Some(error.to_string())),
+    };
+
+    RunReport {
+        program: path.to_owned(),
+        result,
+        error,
+        stdout,
+        instructions_executed: vm.instructions_executed(),
+        elapsed: start.elapsed(),
+    }
+}
+
+fn execute(vm: &mut Vm, path: &Path) -> Result<FinalValue> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("Could not read {}.", path.display()))?;
+
+    if path
+        .extension()
+        .is_some_and(|extension| extension == "json")
+    {
+        vm.interpret_json(&contents)
+    } else {
+        vm.interpret(&path.to_string_lossy(), &contents)
+    }
+}
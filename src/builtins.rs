@@ -0,0 +1,165 @@
+use anyhow::{bail, Result};
+use std::collections::BTreeMap;
+
+use crate::{
+    native::{Native, NativeResult},
+    value::FinalValue,
+};
+
+/// The functions every program can call without enabling any natives:
+/// `str_length(s)`, `str_slice(s, start, end)`, `int_to_str(n)`,
+/// `str_to_int(s)`, `min(a, b)`, `max(a, b)`, `abs(n)`, and for lists
+/// `list(...)`, `get(l, index)`, `push(l, value)` and `length(l)`, and for
+/// records `record()`, `set(r, key, value)`, `get(r, key)` and `has(r, key)`,
+/// and `random(seed)`. Strings are measured and sliced in characters, and
+/// `push` and `set` return a new list or record rather than changing the one
+/// given. They have no side effects, so calling them does not keep a call
+/// from being memoized.
+///
+/// `random` is deterministic for the same reason: it returns a tuple of a
+/// non-negative pseudo-random integer and the seed to pass to the next call,
+/// and programs thread the seed through themselves.
+pub fn builtins() -> Vec<Native> {
+    vec![
+        builtin("str_length", 1, |arguments| match arguments {
+            [FinalValue::String(s)] => Ok(FinalValue::Integer(length(s)?)),
+            _ => bail!("str_length expects a string."),
+        }),
+        builtin("str_slice", 3, |arguments| match arguments {
+            [FinalValue::String(s), FinalValue::Integer(start), FinalValue::Integer(end)] => {
+                let length = length(s)?;
+                if *start < 0 || start > end || *end > length {
+                    bail!(
+                        "str_slice expects 0 <= start <= end <= {length}, got {start} and {end}."
+                    );
+                }
+
+                let slice = s
+                    .chars()
+                    .skip(*start as usize)
+                    .take((end - start) as usize)
+                    .collect();
+                Ok(FinalValue::String(slice))
+            }
+            _ => bail!("str_slice expects a string and two integers."),
+        }),
+        builtin("int_to_str", 1, |arguments| match arguments {
+            [FinalValue::Integer(n)] => Ok(FinalValue::String(n.to_string())),
+            _ => bail!("int_to_str expects an integer."),
+        }),
+        builtin("str_to_int", 1, |arguments| match arguments {
+            [FinalValue::String(s)] => match s.trim().parse() {
+                Ok(n) => Ok(FinalValue::Integer(n)),
+                Err(_) => bail!("str_to_int expects an integer, but got \"{s}\"."),
+            },
+            _ => bail!("str_to_int expects a string."),
+        }),
+        builtin("min", 2, |arguments| match arguments {
+            [FinalValue::Integer(a), FinalValue::Integer(b)] => Ok(FinalValue::Integer(*a.min(b))),
+            _ => bail!("min expects two integers."),
+        }),
+        builtin("max", 2, |arguments| match arguments {
+            [FinalValue::Integer(a), FinalValue::Integer(b)] => Ok(FinalValue::Integer(*a.max(b))),
+            _ => bail!("max expects two integers."),
+        }),
+        // Wraps like the arithmetic operators do by default, so abs(-2147483648) is itself.
+        builtin("abs", 1, |arguments| match arguments {
+            [FinalValue::Integer(n)] => Ok(FinalValue::Integer(n.wrapping_abs())),
+            _ => bail!("abs expects an integer."),
+        }),
+        Native {
+            arity: None,
+            ..builtin("list", 0, |arguments| {
+                Ok(FinalValue::List(arguments.to_vec()))
+            })
+        },
+        builtin("get", 2, |arguments| match arguments {
+            [FinalValue::List(elements), FinalValue::Integer(index)] => {
+                match usize::try_from(*index).ok().and_then(|i| elements.get(i)) {
+                    Some(element) => Ok(element.clone()),
+                    None => bail!(
+                        "get expects an index below {}, got {index}.",
+                        elements.len()
+                    ),
+                }
+            }
+            [FinalValue::Record(fields), FinalValue::String(key)] => match fields.get(key) {
+                Some(value) => Ok(value.clone()),
+                None => bail!("get expects a key of the record, got \"{key}\"."),
+            },
+            _ => bail!("get expects a list and an integer or a record and a string."),
+        }),
+        builtin("push", 2, |arguments| match arguments {
+            [FinalValue::List(elements), value] => {
+                let mut elements = elements.clone();
+                elements.push(value.clone());
+                Ok(FinalValue::List(elements))
+            }
+            _ => bail!("push expects a list."),
+        }),
+        builtin("length", 1, |arguments| match arguments {
+            [FinalValue::List(elements)] => match i32::try_from(elements.len()) {
+                Ok(length) => Ok(FinalValue::Integer(length)),
+                Err(_) => bail!("List is too long to measure."),
+            },
+            _ => bail!("length expects a list."),
+        }),
+        builtin("record", 0, |_| Ok(FinalValue::Record(BTreeMap::new()))),
+        builtin("set", 3, |arguments| match arguments {
+            [FinalValue::Record(fields), FinalValue::String(key), value] => {
+                let mut fields = fields.clone();
+                fields.insert(key.clone(), value.clone());
+                Ok(FinalValue::Record(fields))
+            }
+            _ => bail!("set expects a record and a string."),
+        }),
+        builtin("has", 2, |arguments| match arguments {
+            [FinalValue::Record(fields), FinalValue::String(key)] => {
+                Ok(FinalValue::Bool(fields.contains_key(key)))
+            }
+            _ => bail!("has expects a record and a string."),
+        }),
+        builtin("random", 1, |arguments| match arguments {
+            [FinalValue::Integer(seed)] => {
+                let (value, seed) = random(*seed);
+                Ok(FinalValue::Tuple(
+                    Box::new(FinalValue::Integer(value)),
+                    Box::new(FinalValue::Integer(seed)),
+                ))
+            }
+            _ => bail!("random expects an integer seed."),
+        }),
+    ]
+}
+
+/// One step of a 32-bit PCG generator: advances `seed` as a linear
+/// congruential generator and scrambles the new state into the value, whose
+/// low bits are then as good as its high ones.
+fn random(seed: i32) -> (i32, i32) {
+    let state = (seed as u32)
+        .wrapping_mul(747_796_405)
+        .wrapping_add(2_891_336_453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277_803_737);
+    let value = (word >> 22) ^ word;
+    ((value >> 1) as i32, state as i32)
+}
+
+fn builtin(
+    name: &str,
+    arity: u16,
+    function: impl Fn(&[FinalValue]) -> Result<FinalValue> + 'static,
+) -> Native {
+    Native {
+        name: name.to_owned(),
+        arity: Some(arity),
+        function: Box::new(move |arguments| function(arguments).map(NativeResult::Ready)),
+        pure: true,
+    }
+}
+
+fn length(s: &str) -> Result<i32> {
+    match i32::try_from(s.chars().count()) {
+        Ok(length) => Ok(length),
+        Err(_) => bail!("String is too long to measure."),
+    }
+}
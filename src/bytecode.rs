@@ -1,6 +1,6 @@
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub enum Instruction {
-    Constant(u16),
+    Constant(u32),
     True,
     False,
     Add,
@@ -20,13 +20,231 @@ pub enum Instruction {
     First,
     Second,
     Print,
-    GlobalGet(u16),
-    GlobalSet(u16),
-    LocalGet(u16, u16),
+    Dup,
+    GlobalGet(u32),
+    GlobalSet(u32),
+    LocalGet(u16, u32),
+    /// Pops a boolean and, when it is false, skips the given number of
+    /// instructions after this one. The compiler backpatches the count once
+    /// the branch is compiled.
     If(u32),
+    /// Skips the given number of instructions after this one.
     Jump(u32),
-    Closure(u16),
+    Closure(u32),
     Call(u16),
     Return(u16),
     TailCall(u16),
+    /// Drops the given number of values from under the top of the stack,
+    /// which is how `let` bindings go out of scope.
+    Slide(u16),
+    /// Pushes the next line of input, without its line terminator.
+    ReadLine,
+    /// Pushes the next line of input, parsed as an integer.
+    ReadInt,
+    /// Discards the value on top of the stack, which is how expressions in a
+    /// sequence are evaluated for their effects.
+    Pop,
+    /// `LocalGet(local, identifier); Constant(constant); Add`, as fused by
+    /// [`crate::optimizer::fuse`].
+    LocalGetConstantAdd(u16, u32, u32),
+    /// `LocalGet(local, identifier); Constant(constant); Sub`.
+    LocalGetConstantSub(u16, u32, u32),
+    /// `Constant(constant); Lt`.
+    ConstantLt(u32),
+    /// `Constant(constant); Eq`.
+    ConstantEq(u32),
+    /// `LocalGet(local, identifier); TailCall(arity)`.
+    LocalGetTailCall(u16, u32, u16),
+    /// Negates the integer on top of the stack.
+    Neg,
+    /// Negates the boolean on top of the stack.
+    Not,
+    /// Replaces the value on top of the stack with the string `Print` shows
+    /// for it.
+    Show,
+    /// The bitwise operators of the `band`, `bor` and `bxor` builtins.
+    BitAnd,
+    BitOr,
+    BitXor,
+    /// Shifts the integer under the top of the stack left by the top of the
+    /// stack, modulo 32, as `shl` does.
+    Shl,
+    /// Shifts right like [`Instruction::Shl`] shifts left, keeping the sign
+    /// as `shr` does.
+    Shr,
+    /// Replaces the arguments of the frame with as many values from the top
+    /// of the stack, drops everything else above them and jumps back the
+    /// given number of instructions, to the start of the function. This is
+    /// what a function calling itself in tail position compiles to.
+    JumpBack(u32),
+}
+
+/// The names of the opcodes, indexed by [`Instruction::opcode`].
+pub const OPCODE_NAMES: [&str; Instruction::OPCODES] = [
+    "Constant",
+    "True",
+    "False",
+    "Add",
+    "Sub",
+    "Mul",
+    "Div",
+    "Rem",
+    "Eq",
+    "Neq",
+    "Gt",
+    "Lt",
+    "Gte",
+    "Lte",
+    "And",
+    "Or",
+    "Tuple",
+    "First",
+    "Second",
+    "Print",
+    "Dup",
+    "GlobalGet",
+    "GlobalSet",
+    "LocalGet",
+    "If",
+    "Jump",
+    "Closure",
+    "Call",
+    "Return",
+    "TailCall",
+    "Slide",
+    "ReadLine",
+    "ReadInt",
+    "Pop",
+    "LocalGetConstantAdd",
+    "LocalGetConstantSub",
+    "ConstantLt",
+    "ConstantEq",
+    "LocalGetTailCall",
+    "Neg",
+    "Not",
+    "Show",
+    "BitAnd",
+    "BitOr",
+    "BitXor",
+    "Shl",
+    "Shr",
+    "JumpBack",
+];
+
+impl Instruction {
+    pub const OPCODES: usize = 48;
+
+    /// A number identifying the kind of instruction, regardless of its
+    /// operands. Compiled artifacts store instructions under these numbers.
+    pub fn opcode(&self) -> u8 {
+        match self {
+            Instruction::Constant(_) => 0,
+            Instruction::True => 1,
+            Instruction::False => 2,
+            Instruction::Add => 3,
+            Instruction::Sub => 4,
+            Instruction::Mul => 5,
+            Instruction::Div => 6,
+            Instruction::Rem => 7,
+            Instruction::Eq => 8,
+            Instruction::Neq => 9,
+            Instruction::Gt => 10,
+            Instruction::Lt => 11,
+            Instruction::Gte => 12,
+            Instruction::Lte => 13,
+            Instruction::And => 14,
+            Instruction::Or => 15,
+            Instruction::Tuple => 16,
+            Instruction::First => 17,
+            Instruction::Second => 18,
+            Instruction::Print => 19,
+            Instruction::Dup => 20,
+            Instruction::GlobalGet(_) => 21,
+            Instruction::GlobalSet(_) => 22,
+            Instruction::LocalGet(_, _) => 23,
+            Instruction::If(_) => 24,
+            Instruction::Jump(_) => 25,
+            Instruction::Closure(_) => 26,
+            Instruction::Call(_) => 27,
+            Instruction::Return(_) => 28,
+            Instruction::TailCall(_) => 29,
+            Instruction::Slide(_) => 30,
+            Instruction::ReadLine => 31,
+            Instruction::ReadInt => 32,
+            Instruction::Pop => 33,
+            Instruction::LocalGetConstantAdd(_, _, _) => 34,
+            Instruction::LocalGetConstantSub(_, _, _) => 35,
+            Instruction::ConstantLt(_) => 36,
+            Instruction::ConstantEq(_) => 37,
+            Instruction::LocalGetTailCall(_, _, _) => 38,
+            Instruction::Neg => 39,
+            Instruction::Not => 40,
+            Instruction::Show => 41,
+            Instruction::BitAnd => 42,
+            Instruction::BitOr => 43,
+            Instruction::BitXor => 44,
+            Instruction::Shl => 45,
+            Instruction::Shr => 46,
+            Instruction::JumpBack(_) => 47,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        OPCODE_NAMES[self.opcode() as usize]
+    }
+
+    /// Returns how many values an instruction pops and pushes. Calls are seen
+    /// from the caller, which gets a single result in place of the callee and
+    /// its arguments. `Return` is treated as leaving the stack untouched, since
+    /// it ends the frame, and so is `JumpBack`, which starts it over. A
+    /// `Closure` also pops the values it captures, which
+    /// depends on the function and is not counted here.
+    pub fn stack_effect(&self) -> (usize, usize) {
+        match self {
+            Instruction::Constant(_)
+            | Instruction::True
+            | Instruction::False
+            | Instruction::GlobalGet(_)
+            | Instruction::LocalGet(_, _)
+            | Instruction::LocalGetConstantAdd(_, _, _)
+            | Instruction::LocalGetConstantSub(_, _, _)
+            | Instruction::Closure(_)
+            | Instruction::ReadLine
+            | Instruction::ReadInt => (0, 1),
+            Instruction::Dup => (1, 2),
+            Instruction::Add
+            | Instruction::Sub
+            | Instruction::Mul
+            | Instruction::Div
+            | Instruction::Rem
+            | Instruction::BitAnd
+            | Instruction::BitOr
+            | Instruction::BitXor
+            | Instruction::Shl
+            | Instruction::Shr
+            | Instruction::Eq
+            | Instruction::Neq
+            | Instruction::Gt
+            | Instruction::Lt
+            | Instruction::Gte
+            | Instruction::Lte
+            | Instruction::And
+            | Instruction::Or
+            | Instruction::Tuple => (2, 1),
+            Instruction::First
+            | Instruction::Second
+            | Instruction::Print
+            | Instruction::Neg
+            | Instruction::Not
+            | Instruction::Show
+            | Instruction::ConstantLt(_)
+            | Instruction::ConstantEq(_) => (1, 1),
+            Instruction::GlobalSet(_) | Instruction::If(_) | Instruction::Pop => (1, 0),
+            Instruction::Jump(_) | Instruction::JumpBack(_) | Instruction::Return(_) => (0, 0),
+            Instruction::Call(arity) | Instruction::TailCall(arity) => (*arity as usize + 1, 1),
+            // The local is the last argument, or the callee when there are none.
+            Instruction::LocalGetTailCall(_, _, arity) => (*arity as usize, 1),
+            Instruction::Slide(count) => (*count as usize + 1, 1),
+        }
+    }
 }
@@ -0,0 +1,84 @@
+use anyhow::{Context, Result};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use crate::{
+    artifact::{CompiledProgram, Metadata},
+    imports::imported_files,
+    optimizer::Passes,
+    vm::Vm,
+};
+
+/// A directory of compiled artifacts, each named after the
+/// [`Metadata::cache_key_with_imports`] of the program it holds, so programs
+/// are only compiled the first time a given source is seen along with the
+/// files it imports.
+pub struct CompilationCache {
+    directory: PathBuf,
+    passes: Passes,
+}
+
+impl CompilationCache {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+            passes: Passes::default(),
+        }
+    }
+
+    /// Compiles programs with only the given optimizer passes. Their
+    /// artifacts are kept apart from those compiled with other passes.
+    pub fn with_passes(mut self, passes: Passes) -> Self {
+        self.passes = passes;
+        self
+    }
+
+    /// Where the artifact for the program with `key` is stored.
+    pub fn path(&self, key: &str) -> PathBuf {
+        self.directory.join(format!("{key}.rvmc"))
+    }
+
+    /// Returns the compiled program for `source`, compiling it and storing
+    /// the artifact when the cache has no usable one. Artifacts that cannot
+    /// be read or belong to another program are replaced. Editing or moving
+    /// a file the program imports compiles it again.
+    pub fn compile(&self, filename: &str, source: &str) -> Result<CompiledProgram> {
+        let metadata = Metadata::new(filename, source, &self.passes.names());
+        let key = metadata.cache_key_with_imports(&imported_files(filename, source)?);
+        let path = self.path(&key);
+
+        if let Some(program) = load(&path, &metadata.cache_key()) {
+            return Ok(program);
+        }
+
+        let program = Vm::new()
+            .with_passes(self.passes.clone())
+            .compile_program(filename, source)?;
+        self.store(&path, &program)
+            .with_context(|| format!("Could not cache {}.", path.display()))?;
+
+        Ok(program)
+    }
+
+    /// Writes the artifact under a temporary name first, so concurrent runs
+    /// never see it half written.
+    fn store(&self, path: &Path, program: &CompiledProgram) -> Result<()> {
+        fs::create_dir_all(&self.directory)?;
+
+        let temporary = path.with_extension(format!("{}.tmp", process::id()));
+        fs::write(&temporary, program.to_bytes()?)?;
+        fs::rename(&temporary, path)?;
+
+        Ok(())
+    }
+}
+
+fn load(path: &Path, key: &str) -> Option<CompiledProgram> {
+    let bytes = fs::read(path).ok()?;
+    let program = CompiledProgram::from_bytes(&bytes).ok()?;
+
+    (program.metadata.cache_key() == key).then_some(program)
+}
@@ -1,10 +1,121 @@
-use crate::{bytecode::Instruction, value::Value};
-use std::rc::Rc;
+use crate::{
+    function::Function,
+    gc::Gc,
+    memo::MemoKey,
+    value::{Environment, Tagged},
+};
+use std::{fmt, rc::Rc};
+
+/// How many of the calls a frame's tail calls replaced are remembered.
+pub const ELIDED_CALLS: usize = 8;
 
 #[derive(Debug)]
-pub struct CallFrame<'a> {
-    pub bytecode: &'a [Instruction],
-    pub closure: Rc<Value<'a>>,
+pub struct CallFrame {
+    pub function: Rc<Function>,
+    /// The closure being run, or `None` for the top-level script.
+    pub closure: Option<Gc>,
+    /// The variables the closure captured, or none for the script.
+    pub environment: Rc<Environment>,
     pub instruction_pointer: usize,
     pub frame_index: usize,
+    /// The frames this one replaced through tail calls.
+    pub elided: ElidedCalls,
+    /// The key and memoized result of a call re-executed to check its
+    /// memoization table entry; see [`crate::vm::Vm::with_memo_verification`].
+    /// Few calls have one, so it is boxed to keep frames small.
+    pub memo_check: Option<Box<(MemoKey, Tagged)>>,
+    /// The function and arguments the frame's result is memoized under, if
+    /// any.
+    pub memo_key: Option<MemoKey>,
+    /// Whether the frame, and every call it made, has run without side
+    /// effects so far.
+    pub pure: bool,
+}
+
+impl CallFrame {
+    /// Where the frame starts on the stack: at its closure, or at its first
+    /// local for the top-level script, which has no closure slot. Everything
+    /// from here up is discarded when the frame is left.
+    pub fn base(&self) -> usize {
+        match self.closure {
+            Some(_) => self.frame_index - 1,
+            None => self.frame_index,
+        }
+    }
+}
+
+/// A ring buffer of the functions whose frames were replaced by tail calls,
+/// identified by their index in the VM's function table. Only the most
+/// recent [`ELIDED_CALLS`] are kept, but all of them are counted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ElidedCalls {
+    pub(crate) count: u64,
+    pub(crate) recent: [u32; ELIDED_CALLS],
+}
+
+impl ElidedCalls {
+    pub fn record(&mut self, function: u32) {
+        self.recent[(self.count % ELIDED_CALLS as u64) as usize] = function;
+        self.count += 1;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// The most recent elided functions, oldest first.
+    pub fn recent(&self) -> impl Iterator<Item = u32> + '_ {
+        let start = self.count.saturating_sub(ELIDED_CALLS as u64);
+
+        (start..self.count).map(|call| self.recent[(call % ELIDED_CALLS as u64) as usize])
+    }
+}
+
+/// The call frames active when a runtime error happened, innermost first.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct StackTrace {
+    pub frames: Vec<TraceFrame>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceFrame {
+    /// The name of the function, `<anonymous>` or `<script>`.
+    pub function: String,
+    /// The instruction the frame was running: the failing one in the
+    /// innermost frame and the call being made in the others.
+    pub address: usize,
+    /// Where in the source that instruction comes from, when known.
+    pub offset: Option<usize>,
+    /// How many calls tail calls into this frame replaced.
+    pub elided_count: u64,
+    /// The names of the most recent of those, oldest first.
+    pub elided: Vec<String>,
+}
+
+impl fmt::Display for StackTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Stack trace, innermost call first:")?;
+
+        for frame in &self.frames {
+            write!(f, "\n  at {}", frame.function)?;
+
+            if frame.elided_count > 0 {
+                let plural = if frame.elided_count == 1 { "" } else { "s" };
+                let skipped = if frame.elided_count > frame.elided.len() as u64 {
+                    "… → "
+                } else {
+                    ""
+                };
+
+                write!(
+                    f,
+                    "\n  … {} tail call{plural} elided via {skipped}{} …",
+                    frame.elided_count,
+                    frame.elided.join(" → ")
+                )?;
+            }
+        }
+
+        Ok(())
+    }
 }
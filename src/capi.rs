@@ -0,0 +1,138 @@
+use std::{
+    ffi::{c_char, CStr, CString},
+    io,
+    panic::{self, AssertUnwindSafe},
+    ptr,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{printing::Captured, value::FinalValue, vm::Vm};
+
+/// A VM embedded through the C API, as declared in `include/rvm.h`.
+#[derive(Default)]
+pub struct Rvm {
+    /// Why the last call to [`rvm_interpret`] failed.
+    error: Option<CString>,
+}
+
+/// What [`rvm_interpret`] produced. The strings belong to the caller, who
+/// frees them with [`rvm_result_free`].
+#[repr(C)]
+pub struct RvmResult {
+    /// Whether the program ran to completion.
+    pub ok: bool,
+    /// The program's result as JSON, or null when it failed.
+    pub value: *mut c_char,
+    /// What the program printed, NUL terminated. It is `output_length`
+    /// bytes long, since a program may print NULs.
+    pub output: *mut c_char,
+    pub output_length: usize,
+}
+
+/// Creates a VM, which is freed with [`rvm_free`].
+#[no_mangle]
+pub extern "C" fn rvm_new() -> *mut Rvm {
+    Box::into_raw(Box::default())
+}
+
+/// Frees a VM created by [`rvm_new`]. Does nothing when `rvm` is null.
+///
+/// # Safety
+///
+/// `rvm` must be null or come from [`rvm_new`], and must not be used again.
+#[no_mangle]
+pub unsafe extern "C" fn rvm_free(rvm: *mut Rvm) {
+    if !rvm.is_null() {
+        drop(Box::from_raw(rvm));
+    }
+}
+
+/// Runs a program without natives or input. Each program runs in a fresh
+/// VM, so nothing carries over between calls.
+///
+/// # Safety
+///
+/// `rvm` must come from [`rvm_new`] and `source` must be a NUL terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn rvm_interpret(rvm: *mut Rvm, source: *const c_char) -> RvmResult {
+    let rvm = &mut *rvm;
+    let printed = Captured::default();
+
+    let value = CStr::from_ptr(source)
+        .to_str()
+        .map_err(|_| anyhow!("Source is not valid UTF-8."))
+        .and_then(|source| interpret(source, printed.clone()));
+    let value = value.and_then(|value| Ok(serde_json::to_string(&value)?));
+
+    let mut output = printed.take();
+    let output_length = output.len();
+    output.push(0);
+    let output = Box::into_raw(output.into_boxed_slice()) as *mut c_char;
+
+    match value {
+        Ok(value) => {
+            rvm.error = None;
+            RvmResult {
+                ok: true,
+                value: CString::new(value).expect("JSON escapes NULs.").into_raw(),
+                output,
+                output_length,
+            }
+        }
+        Err(error) => {
+            rvm.error = Some(
+                CString::new(error.to_string().replace('\0', "\\0")).expect("NULs were escaped."),
+            );
+            RvmResult {
+                ok: false,
+                value: ptr::null_mut(),
+                output,
+                output_length,
+            }
+        }
+    }
+}
+
+fn interpret(source: &str, printed: Captured) -> Result<FinalValue> {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        Vm::new()
+            .with_reader(io::empty())
+            .with_output(printed)
+            .interpret("source.rinha", source)
+    }))
+    .unwrap_or_else(|_| Err(anyhow!("The VM panicked.")))
+}
+
+/// Why the last call to [`rvm_interpret`] failed, or null when it did not.
+/// The string belongs to the VM and lives until its next call.
+///
+/// # Safety
+///
+/// `rvm` must come from [`rvm_new`].
+#[no_mangle]
+pub unsafe extern "C" fn rvm_last_error(rvm: *const Rvm) -> *const c_char {
+    (*rvm)
+        .error
+        .as_ref()
+        .map_or(ptr::null(), |error| error.as_ptr())
+}
+
+/// Frees the strings of a result.
+///
+/// # Safety
+///
+/// `result` must come from [`rvm_interpret`] and not have been freed.
+#[no_mangle]
+pub unsafe extern "C" fn rvm_result_free(result: RvmResult) {
+    if !result.value.is_null() {
+        drop(CString::from_raw(result.value));
+    }
+    if !result.output.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+            result.output as *mut u8,
+            result.output_length + 1,
+        )));
+    }
+}
@@ -0,0 +1,41 @@
+use crate::bytecode::Instruction;
+
+/// Bytecode along with the source offset each instruction was compiled
+/// from. Every function owns one, and so does the top-level script.
+#[derive(Clone, Debug, Default)]
+pub struct Chunk {
+    pub bytecode: Vec<Instruction>,
+    /// One per instruction, or none when the bytecode was loaded without
+    /// its source.
+    pub offsets: Vec<usize>,
+}
+
+impl Chunk {
+    /// Bytecode with no source to point back to.
+    pub fn new(bytecode: Vec<Instruction>) -> Self {
+        Self {
+            bytecode,
+            offsets: Vec::new(),
+        }
+    }
+
+    /// Appends `instruction`, compiled from the source at `offset`.
+    pub fn push(&mut self, instruction: Instruction, offset: usize) {
+        self.bytecode.push(instruction);
+        self.offsets.push(offset);
+    }
+
+    /// Where in the source the instruction at `address` was compiled from,
+    /// if that is known.
+    pub fn offset(&self, address: usize) -> Option<usize> {
+        self.offsets.get(address).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytecode.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytecode.is_empty()
+    }
+}
@@ -0,0 +1,169 @@
+use anyhow::{Context, Result};
+use std::{
+    fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// What running a program through a command produced, after normalization.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RunOutput {
+    pub success: bool,
+    pub lines: Vec<String>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Outcome {
+    Same,
+    /// Exactly one of the implementations exited with an error.
+    StatusMismatch {
+        ours: bool,
+        theirs: bool,
+    },
+    /// The outputs first disagree at the zero-based `line`.
+    Different {
+        line: usize,
+        ours: Option<String>,
+        theirs: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug)]
+pub struct Comparison {
+    pub program: PathBuf,
+    pub outcome: Outcome,
+}
+
+impl fmt::Display for Comparison {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let program = self.program.display();
+
+        match &self.outcome {
+            Outcome::Same => write!(f, "ok       {program}"),
+            Outcome::StatusMismatch { ours, theirs } => {
+                let status = |success: &bool| if *success { "succeeded" } else { "failed" };
+                write!(
+                    f,
+                    "status   {program}: rvm {}, other implementation {}",
+                    status(ours),
+                    status(theirs)
+                )
+            }
+            Outcome::Different { line, ours, theirs } => {
+                let show = |line: &Option<String>| match line {
+                    Some(line) => format!("{line:?}"),
+                    None => "<end of output>".to_owned(),
+                };
+                write!(
+                    f,
+                    "differs  {program}, line {}: rvm printed {}, other implementation printed {}",
+                    line + 1,
+                    show(ours),
+                    show(theirs)
+                )
+            }
+        }
+    }
+}
+
+/// Runs every `.rinha` program in `directory` with both shell commands, which
+/// receive the program path as their last argument, and compares what they print.
+pub fn compare_directory(ours: &str, theirs: &str, directory: &Path) -> Result<Vec<Comparison>> {
+    corpus(directory)?
+        .into_iter()
+        .map(|program| {
+            let our_output = run(ours, &program)?;
+            let their_output = run(theirs, &program)?;
+
+            Ok(Comparison {
+                outcome: compare(&our_output, &their_output),
+                program,
+            })
+        })
+        .collect()
+}
+
+/// Lists the `.rinha` files in `directory`, sorted so reports are stable.
+pub fn corpus(directory: &Path) -> Result<Vec<PathBuf>> {
+    files(directory, &["rinha"])
+}
+
+/// Lists the files in `directory` with one of `extensions`, sorted.
+pub fn files(directory: &Path, extensions: &[&str]) -> Result<Vec<PathBuf>> {
+    let mut programs = Vec::new();
+
+    let entries = fs::read_dir(directory)
+        .with_context(|| format!("Could not read directory {}.", directory.display()))?;
+
+    for entry in entries {
+        let path = entry?.path();
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        if path.is_file() && extension.is_some_and(|e| extensions.contains(&e)) {
+            programs.push(path);
+        }
+    }
+
+    programs.sort();
+    Ok(programs)
+}
+
+pub fn run(command: &str, program: &Path) -> Result<RunOutput> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{command} \"$1\""))
+        .arg("sh")
+        .arg(program)
+        .output()
+        .with_context(|| format!("Could not run `{command}`."))?;
+
+    Ok(RunOutput {
+        success: output.status.success(),
+        lines: normalize(&String::from_utf8_lossy(&output.stdout)),
+    })
+}
+
+/// Makes outputs comparable across implementations: line endings are unified,
+/// trailing whitespace is dropped from every line and trailing blank lines are
+/// ignored.
+pub fn normalize(output: &str) -> Vec<String> {
+    let mut lines: Vec<String> = output
+        .lines()
+        .map(|line| line.trim_end().to_owned())
+        .collect();
+
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+
+    lines
+}
+
+pub fn compare(ours: &RunOutput, theirs: &RunOutput) -> Outcome {
+    if ours.success != theirs.success {
+        return Outcome::StatusMismatch {
+            ours: ours.success,
+            theirs: theirs.success,
+        };
+    }
+
+    let length = ours.lines.len().max(theirs.lines.len());
+    for line in 0..length {
+        let our_line = ours.lines.get(line);
+        let their_line = theirs.lines.get(line);
+
+        if our_line != their_line {
+            return Outcome::Different {
+                line,
+                ours: our_line.cloned(),
+                theirs: their_line.cloned(),
+            };
+        }
+    }
+
+    Outcome::Same
+}
+
+/// Quotes `text` so it is passed verbatim as a single shell word.
+pub fn shell_quote(text: &str) -> String {
+    format!("'{}'", text.replace('\'', r"'\''"))
+}
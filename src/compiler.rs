@@ -1,46 +1,380 @@
 use anyhow::{anyhow, bail, Result};
-use rinha::ast::{BinaryOp, Term};
-use std::collections::HashSet;
+use rinha::ast::{BinaryOp, Element, Location, Term};
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use crate::{
+    ast::{UnaryOp, SEQUENCE_BINDING},
     bytecode::Instruction,
+    chunk::Chunk,
+    diagnostics::{CompileError, CompileErrors, CompileReport, Warning, WarningKind},
     function::{Function, Local},
     value::Value,
     vm::Vm,
 };
 
-pub struct Compiler<'a> {
-    parent: Option<&'a Compiler<'a>>,
-    bytecode: Vec<Instruction>,
+/// Compiles terms with an explicit work stack rather than by recursing on the
+/// AST, so generated programs with very deep nesting cannot overflow the host
+/// stack.
+///
+/// `let` bindings are lexically scoped. Inside functions, and inside blocks of
+/// the top-level script, they are locals kept on the stack until their scope
+/// ends. Only the chain of `let`s making up the script itself defines
+/// globals, so functions can refer to each other and to themselves, and a
+/// name bound twice in that chain is a local from the second binding on.
+pub struct Compiler {
+    /// The function being compiled and the ones enclosing it. The outermost
+    /// scope is the top-level script.
+    scopes: Vec<Scope>,
+    /// Addresses of `If`s and `Jump`s waiting for their offsets, innermost
+    /// last, with the stack height their branches start at.
+    branches: Vec<(u32, usize)>,
+    /// The globals the program defines, with the arity of the function each
+    /// one is bound to, if it is bound to a function literal.
+    global_arities: HashMap<String, Option<u16>>,
+    /// The index of the function literal each global is bound to, for the
+    /// ones that are.
+    global_functions: HashMap<String, u32>,
+    /// Whether calls in tail position become `TailCall`s, which reuse the
+    /// caller's frame.
+    tail_calls: bool,
+    /// Whether calls to small leaf functions are inlined.
+    inlining: bool,
+    /// Whether errors in terms are collected in `errors` rather than ending
+    /// compilation.
+    recovering: bool,
+    errors: Vec<CompileError>,
+    /// Where the term the next instruction is emitted for starts in the
+    /// source.
+    offset: usize,
+    /// The file offsets point into, which is the one the first term compiled
+    /// comes from. Terms imported from other files are attributed to the
+    /// term they were spliced into.
+    filename: Option<String>,
+    warnings: Vec<Warning>,
+    /// Where each global is bound, in binding order.
+    global_bindings: Vec<(String, Location)>,
+    /// The globals some code refers to.
+    used_globals: HashSet<String>,
+    /// Calls from inside functions to names that are not locals, with the
+    /// number of arguments they pass. Their arities are checked once every
+    /// global is known, since the functions may be called before the
+    /// globals they refer to are bound.
+    global_calls: Vec<(String, u16, Location)>,
+    /// Where functions read names that are not locals, which must be
+    /// globals once every global is known.
+    global_reads: Vec<(String, Location)>,
+}
+
+struct Scope {
+    /// The bytecode emitted so far, with the source offset of the term each
+    /// instruction was emitted for.
+    chunk: Chunk,
+    /// Every name bound in the function, in binding order.
     locals: Vec<Local>,
+    /// The locals in scope, innermost last.
+    bindings: Vec<Binding>,
+    /// How many values the function has on the stack at this point.
+    height: usize,
+    arity: u16,
+    captured: Vec<String>,
+    name: Option<String>,
+    /// Whether `name` is a global, so calls to it from the function call the
+    /// function itself.
+    global: bool,
+    /// How many instructions calls in the function were inlined with so far.
+    inlined: usize,
+    /// Entered while compiling the function's own code, so it is timed apart
+    /// from the functions nested in it.
+    span: tracing::Span,
 }
 
+struct Binding {
+    name: String,
+    slot: u16,
+    /// The arity of the function the local is bound to, if it is bound to a
+    /// function literal.
+    arity: Option<u16>,
+    /// The index of that function literal.
+    function: Option<u32>,
+    /// Where the `let` introducing the local names it, or `None` for
+    /// parameters.
+    location: Option<Location>,
+    used: bool,
+}
+
+/// The most instructions a function may have, counting its `Return`, for
+/// calls to it to be inlined.
+pub const INLINE_SIZE: usize = 16;
+
+/// The most instructions inlining may add to a single function, so helpers
+/// called many times over do not blow up its size.
+pub const INLINE_BUDGET: usize = 256;
+
 #[derive(Clone, Copy, Debug)]
 pub enum CallPosition {
     NonTail,
     Unknown,
 }
 
-impl<'a> Compiler<'a> {
-    pub fn new(parent: Option<&'a Compiler<'a>>) -> Self {
+enum Task {
+    Compile(Term, CallPosition),
+    /// Compiles a term of the `let` chain making up the top-level script.
+    Statement(Term, CallPosition),
+    /// Compiles a function literal, along with the variable it is bound to
+    /// when it appears directly in a `let` and whether that is a global.
+    Function(rinha::ast::Function, Option<String>, bool),
+    /// Emits an instruction for the term starting at the given offset.
+    Emit(Instruction, usize),
+    /// Binds the value on top of the stack to a `let` name, along with the
+    /// arity of the function literal it is, if any. Globals are set, while
+    /// locals stay on the stack.
+    Bind {
+        name: String,
+        arity: Option<u16>,
+        global: bool,
+        location: Location,
+    },
+    /// Ends the scope of the innermost local, dropping it from under the
+    /// value of its `let`.
+    Unbind,
+    /// Emits the `If` after its condition.
+    Then,
+    /// Emits the `Jump` over the else branch and patches the `If`.
+    Otherwise,
+    /// Patches the `Jump` once the else branch is compiled.
+    EndIf,
+    /// Emits the body of the function with the given index in place of a
+    /// call to it, once its arguments are on the stack.
+    Inline(u32, usize),
+    /// Emits the `JumpBack` a function calling itself in tail position
+    /// compiles to, once the arguments of the call are on the stack.
+    JumpBack(usize),
+    /// Finishes the innermost function and emits its closure, attributed to
+    /// the literal starting at the given offset.
+    EndFunction(usize),
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Self {
         Self {
-            parent,
-            bytecode: Vec::new(),
-            locals: Vec::new(),
+            scopes: vec![Scope {
+                chunk: Chunk::default(),
+                locals: Vec::new(),
+                bindings: Vec::new(),
+                height: 0,
+                arity: 0,
+                captured: Vec::new(),
+                name: None,
+                global: false,
+                inlined: 0,
+                span: tracing::Span::none(),
+            }],
+            branches: Vec::new(),
+            global_arities: HashMap::new(),
+            global_functions: HashMap::new(),
+            tail_calls: true,
+            inlining: false,
+            recovering: false,
+            errors: Vec::new(),
+            offset: 0,
+            filename: None,
+            warnings: Vec::new(),
+            global_bindings: Vec::new(),
+            used_globals: HashSet::new(),
+            global_calls: Vec::new(),
+            global_reads: Vec::new(),
         }
     }
+
+    pub fn with_tail_calls(mut self, enabled: bool) -> Self {
+        self.tail_calls = enabled;
+        self
+    }
+
+    /// Compiles calls to small leaf functions, which do nothing but compute
+    /// a value from their parameters, to a copy of their body, sparing the
+    /// call. Only functions bound with `let` to a literal that captures
+    /// nothing qualify, and only when the call is known to reach them: a
+    /// local in scope, or a global defined earlier in the same program.
+    /// Inlined code keeps using the global as it was when the caller was
+    /// compiled, even if a later program on the same VM redefines it.
+    pub fn with_inlining(mut self, enabled: bool) -> Self {
+        self.inlining = enabled;
+        self
+    }
+
+    /// Keeps compiling past errors in terms, such as the error nodes the
+    /// parser leaves where it recovered from a syntax error or calls with the
+    /// wrong number of arguments, so compiling fails with all of them at
+    /// once, as [`CompileErrors`]. A value stands in for each term in error,
+    /// and the code is never run.
+    pub fn with_error_recovery(mut self, enabled: bool) -> Self {
+        self.recovering = enabled;
+        self
+    }
+
+    /// Compiles `term` as top-level code, returning its bytecode and where
+    /// each instruction came from. Functions are added to the VM's function
+    /// table.
     pub fn compile(
         &mut self,
         term: Term,
         vm: &mut Vm,
         call_position: CallPosition,
-    ) -> Result<Vec<Instruction>> {
+    ) -> Result<Chunk> {
+        let mut tasks = vec![Task::Statement(term, call_position)];
+
+        while let Some(task) = tasks.pop() {
+            let span = self.scope().span.clone();
+            let _compiling = span.enter();
+
+            match task {
+                Task::Compile(term, call_position) => {
+                    self.compile_term(term, vm, call_position, false, &mut tasks)?
+                }
+                Task::Statement(term, call_position) => {
+                    let statement = self.scopes.len() == 1;
+                    self.compile_term(term, vm, call_position, statement, &mut tasks)?
+                }
+                Task::Function(f, name, global) => self.enter_function(f, name, global, &mut tasks),
+                Task::Emit(instruction, offset) => {
+                    self.offset = offset;
+                    self.emit(instruction);
+                }
+                Task::Bind {
+                    name,
+                    arity,
+                    global,
+                    location,
+                } => {
+                    let index = vm.create_identifier(name.clone())?;
+                    let function = match self.scope().chunk.bytecode.last() {
+                        Some(Instruction::Closure(function)) if arity.is_some() => Some(*function),
+                        _ => None,
+                    };
+
+                    if global {
+                        if let Some(function) = function {
+                            self.global_functions.insert(name.clone(), function);
+                        }
+                        self.global_bindings.push((name.clone(), location));
+                        self.global_arities.insert(name, arity);
+                        self.emit(Instruction::GlobalSet(index));
+                        continue;
+                    }
+
+                    let scope = self.scope();
+                    let slot = scope.height - 1;
+                    if slot > u16::MAX as usize {
+                        bail!("Too many values on the stack to bind {name}.");
+                    }
+
+                    scope.locals.push(Local { name: name.clone() });
+                    self.warn_if_shadowing(&name, &location);
+                    self.scope().bindings.push(Binding {
+                        name,
+                        slot: slot as u16,
+                        arity,
+                        function,
+                        location: Some(location),
+                        used: false,
+                    });
+                }
+                Task::Unbind => {
+                    let binding = self
+                        .scope()
+                        .bindings
+                        .pop()
+                        .expect("Every local is unbound after it is bound.");
+                    if let Some(location) = binding.location.filter(|_| !binding.used) {
+                        self.warn_unused(&binding.name, location);
+                    }
+                    self.emit(Instruction::Slide(1));
+                }
+                Task::Then => {
+                    self.emit(Instruction::If(0));
+                    let if_address = self.last_address()?;
+                    let height = self.scope().height;
+                    self.branches.push((if_address, height));
+                }
+                Task::Otherwise => {
+                    self.emit(Instruction::Jump(0));
+                    let jump_address = self.last_address()?;
+                    let (if_address, height) = self.branch();
+
+                    let scope = self.scope();
+                    scope.chunk.bytecode[if_address as usize] =
+                        Instruction::If(jump_address - if_address);
+                    scope.height = height;
+                    self.branches.push((jump_address, height));
+                }
+                Task::EndIf => {
+                    let after_address = self.last_address()?;
+                    let (jump_address, _) = self.branch();
+
+                    self.scope().chunk.bytecode[jump_address as usize] =
+                        Instruction::Jump(after_address - jump_address);
+                }
+                Task::Inline(function, offset) => self.inline(function, offset, vm)?,
+                Task::JumpBack(offset) => {
+                    self.offset = offset;
+                    let address = self.scope().chunk.bytecode.len();
+                    self.emit(Instruction::JumpBack(address as u32));
+
+                    // What follows is compiled as if the call returned.
+                    let scope = self.scope();
+                    scope.height = scope.height - scope.arity as usize + 1;
+                }
+                Task::EndFunction(offset) => self.exit_function(offset, vm)?,
+            }
+        }
+
+        self.finish_warnings(vm);
+
+        if !self.errors.is_empty() {
+            return Err(CompileErrors(self.errors.clone()).into());
+        }
+
+        Ok(std::mem::take(&mut self.scope().chunk))
+    }
+
+    /// The warnings and, when recovering, the errors compiling found,
+    /// leaving none behind.
+    pub fn take_report(&mut self) -> CompileReport {
+        CompileReport {
+            warnings: std::mem::take(&mut self.warnings),
+            errors: std::mem::take(&mut self.errors),
+        }
+    }
+
+    /// Compiles a single node, scheduling its children as further tasks.
+    /// Tasks run last in, first out, so they are pushed in reverse order.
+    fn compile_term(
+        &mut self,
+        term: Term,
+        vm: &mut Vm,
+        call_position: CallPosition,
+        statement: bool,
+        tasks: &mut Vec<Task>,
+    ) -> Result<()> {
+        let offset = self.offset_of(term.location());
+        self.offset = offset;
+
         match term {
             Term::Int(i) => {
                 let value = Value::Integer(i.value);
                 let index = vm.create_constant(value)?;
 
-                self.bytecode.push(Instruction::Constant(index));
+                self.emit(Instruction::Constant(index));
             }
             Term::Bool(b) => {
                 let instruction = if b.value {
@@ -48,18 +382,15 @@ impl<'a> Compiler<'a> {
                 } else {
                     Instruction::False
                 };
-                self.bytecode.push(instruction);
+                self.emit(instruction);
             }
             Term::Str(s) => {
-                let value = Value::String(s.value);
+                let value = Value::String(s.value.into());
                 let index = vm.create_constant(value)?;
 
-                self.bytecode.push(Instruction::Constant(index));
+                self.emit(Instruction::Constant(index));
             }
             Term::Binary(b) => {
-                self.compile(*b.lhs, vm, CallPosition::NonTail)?;
-                self.compile(*b.rhs, vm, CallPosition::NonTail)?;
-
                 let instruction = match b.op {
                     BinaryOp::Add => Instruction::Add,
                     BinaryOp::Sub => Instruction::Sub,
@@ -75,230 +406,749 @@ impl<'a> Compiler<'a> {
                     BinaryOp::And => Instruction::And,
                     BinaryOp::Or => Instruction::Or,
                 };
-                self.bytecode.push(instruction);
+
+                tasks.push(Task::Emit(instruction, offset));
+                tasks.push(Task::Compile(*b.rhs, CallPosition::NonTail));
+                tasks.push(Task::Compile(*b.lhs, CallPosition::NonTail));
             }
             Term::Tuple(t) => {
-                self.compile(*t.first, vm, CallPosition::NonTail)?;
-                self.compile(*t.second, vm, CallPosition::NonTail)?;
-
-                self.bytecode.push(Instruction::Tuple);
+                tasks.push(Task::Emit(Instruction::Tuple, offset));
+                tasks.push(Task::Compile(*t.second, CallPosition::NonTail));
+                tasks.push(Task::Compile(*t.first, CallPosition::NonTail));
             }
             Term::First(t) => {
-                self.compile(*t.value, vm, call_position)?;
-
-                self.bytecode.push(Instruction::First);
+                tasks.push(Task::Emit(Instruction::First, offset));
+                tasks.push(Task::Compile(*t.value, CallPosition::NonTail));
             }
             Term::Second(t) => {
-                self.compile(*t.value, vm, call_position)?;
-
-                self.bytecode.push(Instruction::Second);
+                tasks.push(Task::Emit(Instruction::Second, offset));
+                tasks.push(Task::Compile(*t.value, CallPosition::NonTail));
+            }
+            Term::Let(t) if t.name.text == SEQUENCE_BINDING => {
+                if statement {
+                    tasks.push(Task::Statement(*t.next, call_position));
+                } else {
+                    tasks.push(Task::Compile(*t.next, call_position));
+                }
+                tasks.push(Task::Emit(Instruction::Pop, offset));
+                tasks.push(Task::Compile(*t.value, CallPosition::NonTail));
             }
             Term::Let(t) => {
-                self.compile(*t.value, vm, CallPosition::NonTail)?;
-
-                let index = vm.create_identifier(t.name.text.clone())?;
+                let name = t.name.text;
+                let location = t.name.location;
+                let global = statement && !self.global_arities.contains_key(&name);
 
-                if self.parent.is_some() {
-                    self.locals.push(Local { name: t.name.text });
+                if global {
+                    tasks.push(Task::Statement(*t.next, call_position));
                 } else {
-                    self.bytecode.push(Instruction::GlobalSet(index));
+                    tasks.push(Task::Unbind);
+                    tasks.push(Task::Compile(*t.next, call_position));
                 }
 
-                self.compile(*t.next, vm, call_position)?;
+                match *t.value {
+                    Term::Function(f) => {
+                        tasks.push(Task::Bind {
+                            name: name.clone(),
+                            arity: Some(arity(&f)?),
+                            global,
+                            location,
+                        });
+                        tasks.push(Task::Function(f, Some(name), global));
+                    }
+                    value => {
+                        tasks.push(Task::Bind {
+                            name,
+                            arity: None,
+                            global,
+                            location,
+                        });
+                        tasks.push(Task::Compile(value, CallPosition::NonTail));
+                    }
+                }
             }
             Term::Var(t) => {
-                let identifier_index = vm.create_identifier(t.text.clone())?;
-
-                let local_index = self.resolve_local(&t.text);
-                if let Some(index) = local_index {
-                    self.bytecode
-                        .push(Instruction::LocalGet(index as u16, identifier_index));
-                } else {
-                    self.bytecode.push(Instruction::GlobalGet(identifier_index));
+                if self.scopes.len() == 1 {
+                    if self.is_global(&t.text) {
+                        self.warn_if_unbound(&t.text, t.location.clone(), vm);
+                    }
+                } else if self.refers_to_global(&t.text) {
+                    self.global_reads.push((t.text.clone(), t.location.clone()));
                 }
+
+                self.load(&t.text, vm)?
             }
             Term::Print(t) => {
-                self.compile(*t.value, vm, CallPosition::NonTail)?;
-                self.bytecode.push(Instruction::Print);
+                tasks.push(Task::Emit(Instruction::Print, offset));
+                tasks.push(Task::Compile(*t.value, CallPosition::NonTail));
             }
             Term::If(t) => {
-                self.compile(*t.condition, vm, CallPosition::NonTail)?;
-                self.bytecode.push(Instruction::If(0));
+                if let Term::Bool(condition) = &*t.condition {
+                    let skipped = if condition.value {
+                        &t.otherwise
+                    } else {
+                        &t.then
+                    };
+                    self.warn_unreachable(condition.value, skipped.location().clone());
+                }
 
-                let if_address = self.bytecode.len() - 1;
-                let if_address = if if_address > i32::MAX as usize {
-                    bail!("Instruction too long.");
-                } else {
-                    if_address as u32
-                };
+                tasks.push(Task::EndIf);
+                tasks.push(Task::Compile(*t.otherwise, call_position));
+                tasks.push(Task::Otherwise);
+                tasks.push(Task::Compile(*t.then, call_position));
+                tasks.push(Task::Then);
+                tasks.push(Task::Compile(*t.condition, CallPosition::NonTail));
+            }
+            Term::Function(f) => {
+                arity(&f)?;
+                tasks.push(Task::Function(f, None, false))
+            }
+            Term::Call(c) => {
+                let arity = u16::try_from(c.arguments.len()).map_err(|_| {
+                    anyhow!(
+                        "Cannot call a function with more than {} arguments.",
+                        u16::MAX
+                    )
+                })?;
 
-                self.compile(*t.then, vm, call_position)?;
-                self.bytecode.push(Instruction::Jump(0));
+                if let Term::Var(callee) = &*c.callee {
+                    self.mark_used(&callee.text);
 
-                let jump_address = self.bytecode.len() - 1;
-                let jump_address = if jump_address > i32::MAX as usize {
-                    bail!("Instruction too long.");
-                } else {
-                    jump_address as u32
-                };
+                    if let Some(op) = UnaryOp::from_callee(&callee.text).filter(|_| arity == 1) {
+                        let instruction = match op {
+                            UnaryOp::Neg => Instruction::Neg,
+                            UnaryOp::Not => Instruction::Not,
+                        };
+                        tasks.push(Task::Emit(instruction, offset));
+                        tasks.extend(
+                            c.arguments
+                                .into_iter()
+                                .map(|argument| Task::Compile(argument, CallPosition::NonTail)),
+                        );
+                        return Ok(());
+                    }
 
-                self.bytecode[if_address as usize] = Instruction::If(jump_address - if_address);
+                    if let Some((instruction, expected)) = self.intrinsic(&callee.text) {
+                        if arity != expected {
+                            self.fail(arity_error(&callee.text, expected, arity, &c.location))?;
 
-                self.compile(*t.otherwise, vm, call_position)?;
-                let after_address = self.bytecode.len() - 1;
-                let after_address = if after_address > i32::MAX as usize {
-                    bail!("Instruction too long.");
-                } else {
-                    after_address as u32
+                            // Drop the arguments from under a stand-in value.
+                            tasks.push(Task::Emit(Instruction::Slide(arity), offset));
+                            tasks.push(Task::Emit(Instruction::False, offset));
+                        } else {
+                            tasks.push(Task::Emit(instruction, offset));
+                        }
+                        for argument in c.arguments.into_iter().rev() {
+                            tasks.push(Task::Compile(argument, CallPosition::NonTail));
+                        }
+                        return Ok(());
+                    }
+
+                    if let Some(expected) = self.known_arity(&callee.text) {
+                        if expected != arity {
+                            self.fail(arity_error(&callee.text, expected, arity, &c.location))?;
+                        }
+                    } else if self.refers_to_global(&callee.text) {
+                        self.global_calls
+                            .push((callee.text.clone(), arity, c.location.clone()));
+                    }
+
+                    if let Some(function) = self.inlinable(&callee.text, arity, vm) {
+                        tasks.push(Task::Inline(function, offset));
+                        for argument in c.arguments.into_iter().rev() {
+                            tasks.push(Task::Compile(argument, CallPosition::NonTail));
+                        }
+                        return Ok(());
+                    }
+
+                    if matches!(call_position, CallPosition::Unknown)
+                        && self.tail_calls
+                        && self.calls_itself(&callee.text, arity)
+                    {
+                        tasks.push(Task::JumpBack(offset));
+                        for argument in c.arguments.into_iter().rev() {
+                            tasks.push(Task::Compile(argument, CallPosition::NonTail));
+                        }
+                        return Ok(());
+                    }
+                }
+
+                let instruction = match call_position {
+                    CallPosition::Unknown if self.tail_calls => Instruction::TailCall(arity),
+                    _ => Instruction::Call(arity),
                 };
 
-                self.bytecode[jump_address as usize] =
-                    Instruction::Jump(after_address - jump_address);
+                tasks.push(Task::Emit(instruction, offset));
+                for argument in c.arguments.into_iter().rev() {
+                    tasks.push(Task::Compile(argument, CallPosition::NonTail));
+                }
+                tasks.push(Task::Compile(*c.callee, CallPosition::NonTail));
             }
-            Term::Function(f) => {
-                let captured = compute_captured_parameters(
-                    &f.value,
-                    f.parameters.iter().map(|p| p.text.clone()).collect(),
-                );
+            Term::Error(e) => {
+                self.fail(CompileError {
+                    message: e.message,
+                    location: e.location.into(),
+                })?;
+                self.emit(Instruction::False);
+            }
+        };
 
-                let mut compiler = Compiler::new(Some(self));
+        Ok(())
+    }
 
-                let arity = f.parameters.len() as u16;
+    /// Fails with `error`, unless recovering, when it is collected instead.
+    fn fail(&mut self, error: CompileError) -> Result<()> {
+        if !self.recovering {
+            return Err(error.into());
+        }
 
-                for parameter in f.parameters {
-                    compiler.locals.push(Local {
-                        name: parameter.text,
-                    });
-                }
+        self.errors.push(error);
+        Ok(())
+    }
 
-                let mut bytecode = compiler.compile(*f.value, vm, CallPosition::Unknown)?;
-                bytecode.push(Instruction::Return(compiler.locals.len() as u16));
+    /// Starts compiling a function literal. `name` is the variable it is
+    /// bound to when it appears directly in a `let`, which is used to show it
+    /// and, when `global`, to recognize calls to itself.
+    ///
+    /// The function captures the variables it uses that are visible where it
+    /// is defined, other than globals, which are looked up when it runs.
+    fn enter_function(
+        &mut self,
+        f: rinha::ast::Function,
+        name: Option<String>,
+        global: bool,
+        tasks: &mut Vec<Task>,
+    ) {
+        let enclosing = self.scope();
+        let mut captured: Vec<String> =
+            compute_captured_parameters(&f.value, f.parameters.iter().map(|p| p.text.as_str()))
+                .into_iter()
+                .filter(|name| {
+                    enclosing.bindings.iter().any(|b| b.name == *name)
+                        || enclosing.captured.contains(name)
+                })
+                .collect();
+        captured.sort();
 
-                let index = vm.functions.len() as u16;
+        let arity = f.parameters.len() as u16;
+        let bindings = f
+            .parameters
+            .iter()
+            .enumerate()
+            .map(|(slot, parameter)| Binding {
+                name: parameter.text.clone(),
+                slot: slot as u16,
+                arity: None,
+                function: None,
+                location: None,
+                used: false,
+            })
+            .collect();
 
-                let function = Function {
-                    arity,
-                    bytecode,
-                    captured,
-                    locals: compiler.locals.clone(),
-                    index,
-                };
-                vm.functions.push(function);
+        let span = tracing::debug_span!(
+            "compile_function",
+            function = name.as_deref().unwrap_or("<anonymous>")
+        );
 
-                self.bytecode.push(Instruction::Closure(index));
-            }
-            Term::Call(c) => {
-                self.compile(*c.callee, vm, CallPosition::NonTail)?;
+        self.scopes.push(Scope {
+            chunk: Chunk::default(),
+            locals: f
+                .parameters
+                .into_iter()
+                .map(|parameter| Local {
+                    name: parameter.text,
+                })
+                .collect(),
+            bindings,
+            height: arity as usize,
+            arity,
+            captured,
+            name,
+            global,
+            inlined: 0,
+            span,
+        });
 
-                let arity = c.arguments.len() as u16;
+        tasks.push(Task::EndFunction(self.offset_of(&f.location)));
+        tasks.push(Task::Compile(*f.value, CallPosition::Unknown));
+    }
 
-                for argument in c.arguments {
-                    self.compile(argument, vm, CallPosition::NonTail)?;
-                }
+    /// Finishes the innermost function and emits its closure, along with the
+    /// values it captures.
+    fn exit_function(&mut self, offset: usize, vm: &mut Vm) -> Result<()> {
+        let mut scope = self
+            .scopes
+            .pop()
+            .expect("Every function ends after it starts.");
+        scope
+            .chunk
+            .push(Instruction::Return(scope.arity), self.offset);
 
-                let instruction = match call_position {
-                    CallPosition::NonTail => Instruction::Call(arity),
-                    CallPosition::Unknown => Instruction::TailCall(arity),
-                };
+        let index = vm.functions.len() as u32;
 
-                self.bytecode.push(instruction);
-            }
-            Term::Error(e) => bail!(anyhow!(e.message)),
+        let function = Function {
+            arity: scope.arity,
+            chunk: scope.chunk,
+            captured: scope.captured.iter().map(|name| vm.intern(name)).collect(),
+            index,
+            locals: scope.locals,
+            name: scope.name,
+            // Measured once the VM has optimized the bytecode.
+            max_stack: 0,
         };
+        vm.functions.push(Rc::new(function));
+
+        self.offset = offset;
+        for name in &scope.captured {
+            self.load(name, vm)?;
+        }
 
-        Ok(self.bytecode.clone())
+        self.emit(Instruction::Closure(index));
+        self.scope().height -= scope.captured.len();
+
+        Ok(())
     }
 
-    fn resolve_local(&self, name: &str) -> Option<usize> {
-        self.locals.iter().position(|l| l.name == name)
+    /// Pushes the value of a variable: a local of the function being compiled
+    /// when one is in scope, or otherwise a captured variable or a global,
+    /// which are told apart when the code runs.
+    fn load(&mut self, name: &str, vm: &mut Vm) -> Result<()> {
+        let identifier = vm.create_identifier(name.to_owned())?;
+        self.mark_used(name);
+
+        match self.resolve_local(name) {
+            Some(binding) => {
+                let slot = binding.slot;
+                self.emit(Instruction::LocalGet(slot, identifier));
+            }
+            None => self.emit(Instruction::GlobalGet(identifier)),
+        }
+
+        Ok(())
     }
-}
 
-fn compute_captured_parameters(term: &Term, mut environment: HashSet<String>) -> HashSet<String> {
-    match term {
-        Term::Bool(_) | Term::Int(_) | Term::Str(_) | Term::Error(_) => HashSet::new(),
-        Term::First(f) => compute_captured_parameters(&f.value, environment),
-        Term::Second(f) => compute_captured_parameters(&f.value, environment),
-        Term::Tuple(t) => {
-            let mut result = HashSet::new();
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes
+            .last_mut()
+            .expect("The top-level scope is never popped.")
+    }
 
-            let captured_in_first = compute_captured_parameters(&t.first, environment.clone());
-            result.extend(captured_in_first);
+    /// The offset a term at `location` is recorded at; see
+    /// [`Compiler::filename`].
+    fn offset_of(&mut self, location: &Location) -> usize {
+        let filename = self
+            .filename
+            .get_or_insert_with(|| location.filename.clone());
+        if location.filename == *filename {
+            location.start
+        } else {
+            self.offset
+        }
+    }
 
-            let captured_in_second = compute_captured_parameters(&t.second, environment);
-            result.extend(captured_in_second);
+    fn emit(&mut self, instruction: Instruction) {
+        let offset = self.offset;
+        let scope = self.scope();
+        let (popped, pushed) = instruction.stack_effect();
 
-            result
+        scope.height = scope.height - popped + pushed;
+        scope.chunk.push(instruction, offset);
+    }
+
+    fn branch(&mut self) -> (u32, usize) {
+        self.branches
+            .pop()
+            .expect("Every branch is patched after it is emitted.")
+    }
+
+    fn last_address(&mut self) -> Result<u32> {
+        let address = self.scope().chunk.bytecode.len() - 1;
+
+        if address > i32::MAX as usize {
+            bail!("Instruction too long.");
+        }
+
+        Ok(address as u32)
+    }
+
+    /// The arity of the function `name` refers to, when it is let-bound to a
+    /// function literal in the scope being compiled. Captured variables and,
+    /// inside functions, globals are looked up when the call runs, so they
+    /// are not checked here.
+    fn known_arity(&mut self, name: &str) -> Option<u16> {
+        if let Some(binding) = self.resolve_local(name) {
+            return binding.arity;
         }
-        Term::Binary(b) => {
-            let mut result = HashSet::new();
 
-            let captured_in_lhs = compute_captured_parameters(&b.lhs, environment.clone());
-            result.extend(captured_in_lhs);
+        if self.scopes.len() > 1 {
+            return None;
+        }
+
+        self.global_arities.get(name).copied().flatten()
+    }
 
-            let captured_in_rhs = compute_captured_parameters(&b.rhs, environment);
-            result.extend(captured_in_rhs);
+    /// The instruction a call to `name` compiles to and the number of
+    /// arguments it takes, when it names a builtin that no variable in scope
+    /// shadows.
+    fn intrinsic(&mut self, name: &str) -> Option<(Instruction, u16)> {
+        let intrinsic = match name {
+            "read_line" => (Instruction::ReadLine, 0),
+            "read_int" => (Instruction::ReadInt, 0),
+            "show" => (Instruction::Show, 1),
+            "band" => (Instruction::BitAnd, 2),
+            "bor" => (Instruction::BitOr, 2),
+            "bxor" => (Instruction::BitXor, 2),
+            "shl" => (Instruction::Shl, 2),
+            "shr" => (Instruction::Shr, 2),
+            _ => return None,
+        };
 
-            result
+        if self.resolve_local(name).is_some()
+            || self
+                .scope()
+                .captured
+                .iter()
+                .any(|captured| captured == name)
+            || self.global_arities.contains_key(name)
+        {
+            return None;
         }
-        Term::If(i) => {
-            let mut result = HashSet::new();
 
-            let captured_in_condition =
-                compute_captured_parameters(&i.condition, environment.clone());
-            result.extend(captured_in_condition);
+        Some(intrinsic)
+    }
 
-            let captured_in_then = compute_captured_parameters(&i.then, environment.clone());
-            result.extend(captured_in_then);
+    /// The function a call to `name` with `arity` arguments can be replaced
+    /// with the body of, charging its size to the inlining budget of the
+    /// function being compiled.
+    fn inlinable(&mut self, name: &str, arity: u16, vm: &Vm) -> Option<u32> {
+        if !self.inlining {
+            return None;
+        }
 
-            let captured_in_otherwise =
-                compute_captured_parameters(&i.otherwise, environment.clone());
-            result.extend(captured_in_otherwise);
+        let local = self.resolve_local(name).map(|binding| binding.function);
+        let index = match local {
+            Some(function) => function?,
+            None if self.scope().captured.iter().any(|c| c == name) => return None,
+            None => *self.global_functions.get(name)?,
+        };
 
-            result
+        let function = &vm.functions[index as usize];
+        let size = function.chunk.len();
+        if function.arity != arity
+            || !function.captured.is_empty()
+            || size > INLINE_SIZE
+            || self.scope().inlined + size > INLINE_BUDGET
+            || !is_leaf(&function.chunk.bytecode)
+        {
+            return None;
         }
-        Term::Print(p) => compute_captured_parameters(&p.value, environment),
-        Term::Let(l) => {
-            let mut result = HashSet::new();
 
-            let captured_in_value = compute_captured_parameters(&l.value, environment.clone());
-            result.extend(captured_in_value);
+        self.scope().inlined += size;
+        Some(index)
+    }
 
-            environment.insert(l.name.text.clone());
+    /// Emits the body of `function` over the arguments on top of the stack.
+    /// Its locals are found above them rather than at the start of a frame,
+    /// and its `Return` becomes a `Slide` dropping the arguments.
+    fn inline(&mut self, function: u32, offset: usize, vm: &Vm) -> Result<()> {
+        let function = vm.functions[function as usize].clone();
+        let arity = function.arity as usize;
+        let (_, body) = function
+            .chunk
+            .bytecode
+            .split_last()
+            .expect("Leaf functions end with a return.");
 
-            let captured_in_next = compute_captured_parameters(&l.next, environment);
-            result.extend(captured_in_next);
+        let scope = self.scope();
+        let base = scope.height - arity;
+        for instruction in body {
+            let instruction = match *instruction {
+                Instruction::LocalGet(slot, identifier) => {
+                    let Ok(slot) = u16::try_from(base + slot as usize) else {
+                        bail!("Too many values on the stack to inline a call.");
+                    };
+                    Instruction::LocalGet(slot, identifier)
+                }
+                ref instruction => instruction.clone(),
+            };
+            scope.chunk.push(instruction, offset);
+        }
 
-            result
+        if arity > 0 {
+            scope.chunk.push(Instruction::Slide(arity as u16), offset);
         }
-        Term::Call(c) => {
-            let mut result = HashSet::new();
+        scope.height = base + 1;
 
-            let captured_in_callee = compute_captured_parameters(&c.callee, environment.clone());
-            result.extend(captured_in_callee);
+        Ok(())
+    }
+
+    /// Whether a call to `name` with `arity` arguments calls the function
+    /// being compiled, because `name` is the global it is bound to and
+    /// nothing in the function shadows it.
+    fn calls_itself(&mut self, name: &str, arity: u16) -> bool {
+        if self.scopes.len() == 1 || self.resolve_local(name).is_some() {
+            return false;
+        }
 
-            for argument in c.arguments.clone() {
-                let captured_in_argument =
-                    compute_captured_parameters(&argument, environment.clone());
-                result.extend(captured_in_argument);
+        let scope = self.scope();
+        scope.global
+            && scope.name.as_deref() == Some(name)
+            && scope.arity == arity
+            && !scope.captured.iter().any(|captured| captured == name)
+    }
+
+    /// The innermost local named `name` in scope.
+    fn resolve_local(&mut self, name: &str) -> Option<&Binding> {
+        self.scope().bindings.iter().rev().find(|b| b.name == name)
+    }
+
+    /// Whether `name` refers to a global from inside a function, where
+    /// globals bound later in the script are not known yet.
+    fn refers_to_global(&mut self, name: &str) -> bool {
+        self.scopes.len() > 1 && self.is_global(name)
+    }
+
+    /// Whether `name` is neither a local nor a captured variable here.
+    fn is_global(&mut self, name: &str) -> bool {
+        self.resolve_local(name).is_none() && !self.scope().captured.iter().any(|c| c == name)
+    }
+
+    /// Records that the variable `name` refers to in the current scope is
+    /// used.
+    fn mark_used(&mut self, name: &str) {
+        let scope = self.scope();
+        match scope.bindings.iter_mut().rev().find(|b| b.name == name) {
+            Some(binding) => binding.used = true,
+            None if scope.captured.iter().any(|c| c == name) => {}
+            None => {
+                self.used_globals.insert(name.to_owned());
             }
+        }
+    }
+
+    fn warn_if_shadowing(&mut self, name: &str, location: &Location) {
+        let shadowed = self.global_arities.contains_key(name)
+            || self
+                .scopes
+                .iter()
+                .any(|scope| scope.bindings.iter().any(|b| b.name == name));
+
+        if shadowed && name != "_" {
+            self.warnings.push(
+                Warning::new(
+                    WarningKind::ShadowedBinding,
+                    format!("Variable {name} shadows another variable of the same name."),
+                    location.clone().into(),
+                    "shadows an earlier binding",
+                )
+                .with_help("Rename one of them if they are meant to be different."),
+            );
+        }
+    }
+
+    fn warn_unused(&mut self, name: &str, location: Location) {
+        if name == "_" {
+            return;
+        }
+
+        self.warnings.push(
+            Warning::new(
+                WarningKind::UnusedBinding,
+                format!("Variable {name} is never used."),
+                location.into(),
+                "bound here",
+            )
+            .with_help("Bind it to _ if it is only there for its effects."),
+        );
+    }
 
-            result
+    fn warn_unreachable(&mut self, condition: bool, location: Location) {
+        self.warnings.push(Warning::new(
+            WarningKind::UnreachableBranch,
+            format!("This branch never runs, since the condition is always {condition}."),
+            location.into(),
+            "never runs",
+        ));
+    }
+
+    /// Warns about reading `name` as a global if the program has not bound it
+    /// so far and the VM does not provide it either.
+    fn warn_if_unbound(&mut self, name: &str, location: Location, vm: &Vm) {
+        if self.global_arities.contains_key(name) || vm.defines(name) {
+            return;
         }
-        Term::Function(f) => {
-            for parameter in f.parameters.clone() {
-                environment.insert(parameter.text);
+
+        self.warnings.push(Warning::new(
+            WarningKind::UnboundVariable,
+            format!("Variable {name} is not defined."),
+            location.into(),
+            "fails if it runs",
+        ));
+    }
+
+    /// Reports the globals nothing refers to, the calls from functions to
+    /// globals with the wrong number of arguments and the names functions
+    /// read that are not bound anywhere, once every global is known.
+    fn finish_warnings(&mut self, vm: &Vm) {
+        for (name, location) in std::mem::take(&mut self.global_reads) {
+            self.warn_if_unbound(&name, location, vm);
+        }
+
+        for (name, location) in std::mem::take(&mut self.global_bindings) {
+            if !self.used_globals.contains(&name) {
+                self.warn_unused(&name, location);
+            }
+        }
+
+        for (name, arity, location) in std::mem::take(&mut self.global_calls) {
+            let Some(Some(expected)) = self.global_arities.get(&name).copied() else {
+                continue;
+            };
+            if expected != arity {
+                self.warnings.push(Warning::new(
+                    WarningKind::WrongArity,
+                    format!(
+                        "Function {name} takes {} but is called with {arity}.",
+                        arguments(expected)
+                    ),
+                    location.into(),
+                    "fails if it runs",
+                ));
             }
-            compute_captured_parameters(&f.value, environment)
         }
-        Term::Var(v) => {
-            let mut result = HashSet::new();
+    }
+}
+
+/// How many parameters `f` takes, which must fit the operand of a `Call`.
+fn arity(f: &rinha::ast::Function) -> Result<u16> {
+    u16::try_from(f.parameters.len())
+        .map_err(|_| anyhow!("Functions cannot take more than {} parameters.", u16::MAX))
+}
+
+fn arity_error(name: &str, expected: u16, arity: u16, location: &Location) -> CompileError {
+    CompileError {
+        message: format!(
+            "Function {name} takes {} but is called with {arity}",
+            arguments(expected)
+        ),
+        location: location.clone().into(),
+    }
+}
+
+/// `count` arguments, in words that agree with it.
+fn arguments(count: u16) -> String {
+    match count {
+        1 => "1 argument".to_string(),
+        _ => format!("{count} arguments"),
+    }
+}
+
+/// Finds the variables `term` uses that are neither in `parameters` nor
+/// bound inside it. Walks the term with an explicit stack, like the compiler,
+/// keeping a count of the bindings in scope for each name.
+fn compute_captured_parameters<'t>(
+    term: &'t Term,
+    parameters: impl Iterator<Item = &'t str>,
+) -> HashSet<String> {
+    enum Visit<'t> {
+        Term(&'t Term),
+        Bind(&'t str),
+        Unbind(&'t str),
+    }
 
-            if !environment.contains(&v.text) {
-                result.insert(v.text.clone());
+    let mut environment: HashMap<&str, usize> = HashMap::new();
+    for parameter in parameters {
+        *environment.entry(parameter).or_default() += 1;
+    }
+
+    let mut result = HashSet::new();
+    let mut stack = vec![Visit::Term(term)];
+
+    while let Some(visit) = stack.pop() {
+        let term = match visit {
+            Visit::Term(term) => term,
+            Visit::Bind(name) => {
+                *environment.entry(name).or_default() += 1;
+                continue;
+            }
+            Visit::Unbind(name) => {
+                *environment
+                    .get_mut(name)
+                    .expect("Names are bound before unbound.") -= 1;
+                continue;
             }
+        };
 
-            result
+        match term {
+            Term::Bool(_) | Term::Int(_) | Term::Str(_) | Term::Error(_) => {}
+            Term::First(f) => stack.push(Visit::Term(&f.value)),
+            Term::Second(f) => stack.push(Visit::Term(&f.value)),
+            Term::Print(p) => stack.push(Visit::Term(&p.value)),
+            Term::Tuple(t) => {
+                stack.push(Visit::Term(&t.second));
+                stack.push(Visit::Term(&t.first));
+            }
+            Term::Binary(b) => {
+                stack.push(Visit::Term(&b.rhs));
+                stack.push(Visit::Term(&b.lhs));
+            }
+            Term::If(i) => {
+                stack.push(Visit::Term(&i.otherwise));
+                stack.push(Visit::Term(&i.then));
+                stack.push(Visit::Term(&i.condition));
+            }
+            Term::Let(l) => {
+                stack.push(Visit::Unbind(&l.name.text));
+                stack.push(Visit::Term(&l.next));
+                stack.push(Visit::Bind(&l.name.text));
+                stack.push(Visit::Term(&l.value));
+            }
+            Term::Call(c) => {
+                for argument in c.arguments.iter().rev() {
+                    stack.push(Visit::Term(argument));
+                }
+                stack.push(Visit::Term(&c.callee));
+            }
+            Term::Function(f) => {
+                for parameter in &f.parameters {
+                    stack.push(Visit::Unbind(&parameter.text));
+                }
+                stack.push(Visit::Term(&f.value));
+                for parameter in &f.parameters {
+                    *environment.entry(&parameter.text).or_default() += 1;
+                }
+            }
+            Term::Var(v) => {
+                if environment.get(v.text.as_str()).copied().unwrap_or(0) == 0 {
+                    result.insert(v.text.clone());
+                }
+            }
         }
     }
+
+    result
+}
+
+/// Whether `bytecode` computes its result from its frame alone: it calls
+/// nothing, reads no globals, has no effects and only returns at its end.
+fn is_leaf(bytecode: &[Instruction]) -> bool {
+    let Some((Instruction::Return(_), body)) = bytecode.split_last() else {
+        return false;
+    };
+
+    body.iter().all(|instruction| {
+        !matches!(
+            instruction,
+            Instruction::GlobalGet(_)
+                | Instruction::GlobalSet(_)
+                | Instruction::Closure(_)
+                | Instruction::Call(_)
+                | Instruction::TailCall(_)
+                | Instruction::LocalGetTailCall(_, _, _)
+                | Instruction::JumpBack(_)
+                | Instruction::Return(_)
+                | Instruction::Print
+                | Instruction::ReadLine
+                | Instruction::ReadInt
+        )
+    })
 }
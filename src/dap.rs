@@ -0,0 +1,438 @@
+use std::{
+    fs, io,
+    io::{BufRead, Write},
+    path::Path,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+
+use crate::{
+    debugger::{self, Breakpoints, Frame, Granularity, Step},
+    optimizer::Passes,
+    printing::Captured,
+    value::FinalValue,
+    vm::{Execution, Vm},
+};
+
+/// Programs run on a single thread, which the protocol still wants named.
+const THREAD_ID: u64 = 1;
+
+/// Serves the Debug Adapter Protocol, which editors such as VS Code use to
+/// drive debuggers: messages are JSON objects, each preceded by a
+/// `Content-Length` header. Breakpoints are set by line in the launched
+/// program or by function name, and every paused frame has two scopes, its
+/// stack slots and the variables its closure captured. Steps go by line
+/// unless the client asks for `statement` granularity, which steps by
+/// expression, or `instruction`.
+pub struct DapServer<W: Write> {
+    output: W,
+    next_seq: u64,
+    /// Events that follow the response of the request being handled.
+    events: Vec<Value>,
+    program: Option<Program>,
+    lines: Vec<usize>,
+    functions: Vec<String>,
+    /// Whether the client sent all its breakpoints, so the program may start.
+    configured: bool,
+}
+
+struct Program {
+    vm: Vm,
+    path: String,
+    source: String,
+    /// What the program printed since the last output event. Standard
+    /// output carries the protocol, so it is forwarded in output events.
+    printed: Captured,
+    state: State,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    NotStarted,
+    Paused,
+    Ended,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            next_seq: 1,
+            events: Vec::new(),
+            program: None,
+            lines: Vec::new(),
+            functions: Vec::new(),
+            configured: false,
+        }
+    }
+
+    /// Answers requests from `input` until it ends or the client
+    /// disconnects.
+    pub fn serve(&mut self, mut input: impl BufRead) -> Result<()> {
+        while let Some(request) = read_message(&mut input)? {
+            let command = request["command"].as_str().unwrap_or_default().to_owned();
+
+            let mut response = json!({
+                "type": "response",
+                "request_seq": request["seq"],
+                "command": command,
+            });
+            match self.handle(&command, &request["arguments"]) {
+                Ok(body) => {
+                    response["success"] = json!(true);
+                    response["body"] = body;
+                }
+                Err(error) => {
+                    response["success"] = json!(false);
+                    response["message"] = json!(error.to_string());
+                }
+            }
+            self.send(response)?;
+
+            for event in std::mem::take(&mut self.events) {
+                self.send(event)?;
+            }
+
+            if command == "disconnect" {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles a request, returning the body of its response.
+    fn handle(&mut self, command: &str, arguments: &Value) -> Result<Value> {
+        match command {
+            "initialize" => {
+                self.event("initialized", json!({}));
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsFunctionBreakpoints": true,
+                    "supportsSteppingGranularity": true,
+                }))
+            }
+            "launch" => {
+                let path = arguments["program"]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("Launching needs the path of a program."))?;
+                self.launch(path)?;
+                Ok(json!({}))
+            }
+            "setBreakpoints" => {
+                self.lines = arguments["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|breakpoint| breakpoint["line"].as_u64())
+                    .map(|line| line as usize)
+                    .collect();
+                self.apply_breakpoints();
+
+                let breakpoints: Vec<Value> = self
+                    .lines
+                    .iter()
+                    .map(|line| json!({ "verified": true, "line": line }))
+                    .collect();
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "setFunctionBreakpoints" => {
+                self.functions = arguments["breakpoints"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|breakpoint| breakpoint["name"].as_str())
+                    .map(str::to_owned)
+                    .collect();
+                self.apply_breakpoints();
+
+                let breakpoints: Vec<Value> = self
+                    .functions
+                    .iter()
+                    .map(|_| json!({ "verified": true }))
+                    .collect();
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "configurationDone" => {
+                self.configured = true;
+                self.start()?;
+                Ok(json!({}))
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => {
+                let program = self.paused()?;
+                let name = Path::new(&program.path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned());
+
+                let frames: Vec<Value> = program
+                    .vm
+                    .frames()
+                    .iter()
+                    .enumerate()
+                    .map(|(id, frame)| {
+                        let (line, column) = program.position(frame);
+                        json!({
+                            "id": id,
+                            "name": frame.function,
+                            "line": line,
+                            "column": column,
+                            "source": { "name": name, "path": program.path },
+                        })
+                    })
+                    .collect();
+                Ok(json!({ "stackFrames": frames, "totalFrames": frames.len() }))
+            }
+            "scopes" => {
+                self.paused()?;
+                let frame = arguments["frameId"].as_u64().unwrap_or_default();
+                Ok(json!({ "scopes": [
+                    { "name": "Locals", "variablesReference": 2 * frame + 1, "expensive": false },
+                    { "name": "Closure", "variablesReference": 2 * frame + 2, "expensive": false },
+                ]}))
+            }
+            "variables" => {
+                let program = self.paused()?;
+                let reference = arguments["variablesReference"]
+                    .as_u64()
+                    .filter(|reference| *reference > 0)
+                    .ok_or_else(|| anyhow!("Unknown variables reference."))?;
+                let frames = program.vm.frames();
+                let frame = frames
+                    .get(((reference - 1) / 2) as usize)
+                    .ok_or_else(|| anyhow!("Unknown variables reference {reference}."))?;
+
+                let variables: Vec<Value> = if reference % 2 == 1 {
+                    frame
+                        .slots
+                        .iter()
+                        .enumerate()
+                        .map(|(slot, (name, value))| {
+                            let name = name.clone().unwrap_or_else(|| format!("[{slot}]"));
+                            variable(&name, value)
+                        })
+                        .collect()
+                } else {
+                    frame
+                        .environment
+                        .iter()
+                        .map(|(name, value)| variable(name, value))
+                        .collect()
+                };
+                Ok(json!({ "variables": variables }))
+            }
+            "continue" => {
+                let execution = self.paused()?.vm.resume();
+                self.report(execution, "breakpoint");
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                self.step(command, arguments["granularity"].as_str())?;
+                Ok(json!({}))
+            }
+            "disconnect" => Ok(json!({})),
+            _ => bail!("Unsupported request {command}."),
+        }
+    }
+
+    fn launch(&mut self, path: &str) -> Result<()> {
+        let source = fs::read_to_string(path).context("Could not read file.")?;
+
+        let printed = Captured::default();
+        let vm = Vm::new()
+            .with_passes(Passes::level(0)?)
+            .with_reader(io::empty())
+            .with_output(printed.clone())
+            .with_breakpoints(Breakpoints::new(&source));
+
+        self.program = Some(Program {
+            vm,
+            path: path.to_owned(),
+            source,
+            printed,
+            state: State::NotStarted,
+        });
+        self.apply_breakpoints();
+        self.start()
+    }
+
+    /// Starts the program once it is launched and the client has set its
+    /// breakpoints, whichever comes last.
+    fn start(&mut self) -> Result<()> {
+        if !self.configured {
+            return Ok(());
+        }
+        let Some(program) = &mut self.program else {
+            return Ok(());
+        };
+        if program.state != State::NotStarted {
+            return Ok(());
+        }
+
+        let execution = program.vm.start(&program.path, &program.source);
+        self.report(execution, "breakpoint");
+        Ok(())
+    }
+
+    /// Steps until the line or expression changes: in any frame for
+    /// `stepIn`, in the current frame or a caller for `next`, and in a caller
+    /// for `stepOut`. Steps by `instruction` run a single one.
+    fn step(&mut self, command: &str, granularity: Option<&str>) -> Result<()> {
+        let program = self.paused()?;
+        let step = match command {
+            "stepIn" => Step::Into,
+            "next" => Step::Over,
+            _ => Step::Out,
+        };
+
+        let execution = match granularity {
+            Some("instruction") if step != Step::Out => program.vm.step(),
+            Some("statement") => {
+                debugger::step_source(&mut program.vm, step, Granularity::Expression)
+            }
+            _ => debugger::step_source(&mut program.vm, step, Granularity::Line),
+        };
+
+        self.report(execution, "step");
+        Ok(())
+    }
+
+    /// Queues the events describing how a run stopped.
+    fn report(&mut self, execution: Result<Execution>, reason: &str) {
+        let program = self.program.as_mut().expect("Only launched programs run.");
+
+        let printed = String::from_utf8_lossy(&program.printed.take()).into_owned();
+
+        let (state, failure) = match execution {
+            Ok(Execution::Paused) => (State::Paused, None),
+            Ok(Execution::Finished(_)) => (State::Ended, None),
+            Ok(Execution::Suspended(token)) => (
+                State::Ended,
+                Some(format!(
+                    "Suspended waiting for native function {}.",
+                    token.native
+                )),
+            ),
+            Err(error) => (State::Ended, Some(error.to_string())),
+        };
+        program.state = state;
+
+        if !printed.is_empty() {
+            self.event("output", json!({ "category": "stdout", "output": printed }));
+        }
+        if let Some(failure) = &failure {
+            self.event(
+                "output",
+                json!({ "category": "stderr", "output": format!("{failure}\n") }),
+            );
+        }
+
+        if state == State::Paused {
+            self.event(
+                "stopped",
+                json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true }),
+            );
+        } else {
+            let exit_code = if failure.is_some() { 1 } else { 0 };
+            self.event("exited", json!({ "exitCode": exit_code }));
+            self.event("terminated", json!({}));
+        }
+    }
+
+    /// Replaces the launched program's breakpoints with the client's.
+    fn apply_breakpoints(&mut self) {
+        let Some(program) = &mut self.program else {
+            return;
+        };
+        let breakpoints = program
+            .vm
+            .breakpoints_mut()
+            .expect("Launched programs always have breakpoints.");
+
+        breakpoints.clear();
+        for line in &self.lines {
+            breakpoints.add_line(*line);
+        }
+        for function in &self.functions {
+            breakpoints.add_function(function);
+        }
+    }
+
+    fn paused(&mut self) -> Result<&mut Program> {
+        match &mut self.program {
+            Some(program) if program.state == State::Paused => Ok(program),
+            _ => bail!("The program is not paused."),
+        }
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.events
+            .push(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn send(&mut self, mut message: Value) -> Result<()> {
+        message["seq"] = json!(self.next_seq);
+        self.next_seq += 1;
+
+        let body = serde_json::to_string(&message)?;
+        write!(self.output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+impl Program {
+    fn breakpoints(&self) -> &Breakpoints {
+        self.vm
+            .breakpoints()
+            .expect("Launched programs always have breakpoints.")
+    }
+
+    /// The line and column a frame is at, or zeros when it is unknown, as
+    /// the protocol has it.
+    fn position(&self, frame: &Frame) -> (usize, usize) {
+        match frame.offset {
+            Some(offset) => {
+                let breakpoints = self.breakpoints();
+                (breakpoints.line_of(offset), breakpoints.column_of(offset))
+            }
+            None => (0, 0),
+        }
+    }
+}
+
+fn variable(name: &str, value: &FinalValue) -> Value {
+    json!({
+        "name": name,
+        "value": serde_json::to_string(value).expect("Values always serialize."),
+        "variablesReference": 0,
+    })
+}
+
+/// Reads a message, or `None` once the input ends. The Language Server
+/// Protocol frames its messages the same way.
+pub(crate) fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let length = length.ok_or_else(|| anyhow!("Message without a Content-Length header."))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+
+    Ok(Some(serde_json::from_slice(&body)?))
+}
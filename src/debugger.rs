@@ -0,0 +1,427 @@
+use std::{
+    collections::BTreeSet,
+    io::{self, BufRead, Write},
+};
+
+use anyhow::{bail, Result};
+
+use crate::{
+    bytecode::Instruction,
+    function::Function,
+    optimizer::Passes,
+    source_map::LineIndex,
+    value::FinalValue,
+    vm::{Execution, Vm},
+};
+
+/// Decides where a run pauses: before the first instruction of a line with a
+/// breakpoint, on entry to a function with one, and before every
+/// instruction while stepping. Lines are counted from 1.
+pub struct Breakpoints {
+    lines: BTreeSet<usize>,
+    functions: BTreeSet<String>,
+    source: LineIndex,
+    stepping: bool,
+    /// Set when a paused run continues, so it does not pause again at the
+    /// instruction it paused at.
+    resuming: bool,
+    /// The line of the instruction each active frame checked last, so a run
+    /// only pauses at a line when it enters it, and not again when a call
+    /// made from the line returns.
+    previous_lines: Vec<Option<usize>>,
+}
+
+impl Breakpoints {
+    pub fn new(source: &str) -> Self {
+        Self {
+            lines: BTreeSet::new(),
+            functions: BTreeSet::new(),
+            source: LineIndex::new(source),
+            stepping: false,
+            resuming: false,
+            previous_lines: Vec::new(),
+        }
+    }
+
+    pub fn add_line(&mut self, line: usize) {
+        self.lines.insert(line);
+    }
+
+    pub fn add_function(&mut self, name: &str) {
+        self.functions.insert(name.to_owned());
+    }
+
+    /// Removes the breakpoint at a line or function, returning whether there
+    /// was one.
+    pub fn remove(&mut self, location: &str) -> bool {
+        match location.parse() {
+            Ok(line) => self.lines.remove(&line),
+            Err(_) => self.functions.remove(location),
+        }
+    }
+
+    /// The line a source offset is on.
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.source.line_of(offset)
+    }
+
+    /// The column a source offset is at.
+    pub fn column_of(&self, offset: usize) -> usize {
+        self.source.column_of(offset)
+    }
+
+    pub fn clear(&mut self) {
+        self.lines.clear();
+        self.functions.clear();
+    }
+
+    /// Makes the next run pause before each instruction, or stop doing so.
+    pub(crate) fn resume(&mut self, stepping: bool) {
+        self.stepping = stepping;
+        self.resuming = true;
+    }
+
+    /// Whether to pause before the instruction at `address` in `function`,
+    /// run by the frame `depth` frames deep.
+    pub(crate) fn should_pause(
+        &mut self,
+        function: &Function,
+        address: usize,
+        depth: usize,
+    ) -> bool {
+        // A frame starting over runs a new call, reached through a tail call.
+        if address == 0 {
+            self.previous_lines.truncate(depth - 1);
+        }
+        self.previous_lines.resize(depth, None);
+
+        let line = function
+            .chunk
+            .offset(address)
+            .map(|offset| self.line_of(offset));
+        let previous = std::mem::replace(&mut self.previous_lines[depth - 1], line);
+        let entered = line.is_some() && line != previous;
+
+        if std::mem::take(&mut self.resuming) {
+            return false;
+        }
+
+        self.stepping
+            || (entered && line.is_some_and(|line| self.lines.contains(&line)))
+            || (address == 0
+                && function
+                    .name
+                    .as_ref()
+                    .is_some_and(|name| self.functions.contains(name)))
+    }
+}
+
+/// A call frame of a paused run.
+#[derive(Debug)]
+pub struct Frame {
+    /// The name of the function the frame runs, `<anonymous>` or
+    /// `<script>`.
+    pub function: String,
+    /// The instruction the frame is at: the next one to run in the innermost
+    /// frame and the call being made in the others.
+    pub address: usize,
+    pub instruction: Option<Instruction>,
+    /// Where in the source the instruction comes from, when known.
+    pub offset: Option<usize>,
+    /// The values the frame has on the stack, bottom first. Parameters are
+    /// named; other slots hold `let` bindings and temporaries.
+    pub slots: Vec<(Option<String>, FinalValue)>,
+    /// The variables the frame's closure captured.
+    pub environment: Vec<(String, FinalValue)>,
+}
+
+/// How a source-level step treats calls: it may stop inside them, only once
+/// they return, or only once the current frame itself returns.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Step {
+    Into,
+    Over,
+    Out,
+}
+
+/// What a source-level step counts as moving on: reaching an instruction
+/// compiled from another expression, or from another line.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Granularity {
+    Expression,
+    Line,
+}
+
+/// Runs a paused program instruction by instruction until it reaches another
+/// expression or line as `step` allows, it ends or it fails. `vm` must have
+/// breakpoints, which know the lines of its source.
+pub fn step_source(vm: &mut Vm, step: Step, granularity: Granularity) -> Result<Execution> {
+    let location = |vm: &Vm| {
+        let position = vm.paused_offset().map(|offset| match granularity {
+            Granularity::Expression => offset,
+            Granularity::Line => vm
+                .breakpoints()
+                .expect("Stepping by source needs breakpoints.")
+                .line_of(offset),
+        });
+        (vm.frame_depth(), position)
+    };
+    let (depth, position) = location(vm);
+
+    loop {
+        let execution = vm.step();
+        if !matches!(execution, Ok(Execution::Paused)) {
+            return execution;
+        }
+
+        let (new_depth, new_position) = location(vm);
+        let done = match step {
+            Step::Into => new_depth != depth || new_position != position,
+            Step::Over => new_depth < depth || (new_depth == depth && new_position != position),
+            Step::Out => new_depth < depth,
+        };
+        if done {
+            return execution;
+        }
+    }
+}
+
+/// Runs a program under a line-oriented protocol, reading one command per
+/// line and answering each with one or more lines:
+///
+/// - `break LINE|FUNCTION` and `clear LINE|FUNCTION` set and remove
+///   breakpoints.
+/// - `run` starts the program, `step` runs a single instruction and
+///   `continue` runs up to the next breakpoint.
+/// - `stepexpr` runs up to the next expression, stepping into calls, and
+///   `nextline` up to the next line, stepping over them. Both also show the
+///   source line paused at, with a caret under the expression.
+/// - `stack` lists the call frames, innermost first, numbered from 0.
+/// - `locals [FRAME]` and `env [FRAME]` show a frame's stack slots and the
+///   variables its closure captured.
+/// - `quit` ends the session.
+///
+/// Pauses are reported as `paused at line LINE in FUNCTION: INSTRUCTION`,
+/// the end of the program as `finished: VALUE` and failures as
+/// `error: MESSAGE`. Values are shown as JSON.
+pub struct Debugger {
+    vm: Vm,
+    filename: String,
+    source: String,
+    state: State,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    NotStarted,
+    Paused,
+    Ended,
+}
+
+impl Debugger {
+    /// Programs are compiled without optimizations, so stepping goes
+    /// through the code as written. They read no input, as standard input
+    /// carries the debugger's commands.
+    pub fn new(filename: &str, source: &str) -> Result<Self> {
+        let vm = Vm::new()
+            .with_passes(Passes::level(0)?)
+            .with_reader(io::empty())
+            .with_breakpoints(Breakpoints::new(source));
+
+        Ok(Self {
+            vm,
+            filename: filename.to_owned(),
+            source: source.to_owned(),
+            state: State::NotStarted,
+        })
+    }
+
+    /// Answers commands from `input` until it ends or one of them is `quit`.
+    pub fn serve(&mut self, input: impl BufRead, output: &mut impl Write) -> Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim() == "quit" {
+                break;
+            }
+
+            let response = self
+                .command(&line)
+                .unwrap_or_else(|error| format!("error: {error}"));
+            writeln!(output, "{response}")?;
+            output.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs a single command, returning its response.
+    pub fn command(&mut self, line: &str) -> Result<String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or_default();
+        let argument = words.next();
+
+        match (command, argument) {
+            ("break", Some(location)) => {
+                let breakpoints = self.breakpoints_mut();
+                match location.parse() {
+                    Ok(line) => breakpoints.add_line(line),
+                    Err(_) => breakpoints.add_function(location),
+                }
+                Ok(format!("breakpoint at {location}"))
+            }
+            ("clear", Some(location)) => {
+                if !self.breakpoints_mut().remove(location) {
+                    bail!("There is no breakpoint at {location}.");
+                }
+                Ok(format!("cleared {location}"))
+            }
+            ("run", None) => {
+                if self.state != State::NotStarted {
+                    bail!("The program has already been started.");
+                }
+                let execution = self.vm.start(&self.filename, &self.source);
+                self.report(execution)
+            }
+            ("step" | "continue", None) => {
+                if self.state != State::Paused {
+                    bail!("The program is not paused.");
+                }
+                let execution = if command == "step" {
+                    self.vm.step()
+                } else {
+                    self.vm.resume()
+                };
+                self.report(execution)
+            }
+            ("stepexpr" | "nextline", None) => {
+                if self.state != State::Paused {
+                    bail!("The program is not paused.");
+                }
+                let execution = if command == "stepexpr" {
+                    step_source(&mut self.vm, Step::Into, Granularity::Expression)
+                } else {
+                    step_source(&mut self.vm, Step::Over, Granularity::Line)
+                };
+                let report = self.report(execution)?;
+
+                match self.vm.paused_offset() {
+                    Some(offset) if self.state == State::Paused => {
+                        Ok(format!("{report}\n{}", self.excerpt(offset)))
+                    }
+                    _ => Ok(report),
+                }
+            }
+            ("stack", None) => {
+                let lines: Vec<String> = self
+                    .frames()?
+                    .iter()
+                    .enumerate()
+                    .map(|(index, frame)| format!("#{index} {}", self.describe(frame)))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            ("locals" | "env", frame) => {
+                let frame = match frame {
+                    Some(frame) => frame.parse()?,
+                    None => 0,
+                };
+                let frames = self.frames()?;
+                let Some(frame) = frames.get(frame) else {
+                    bail!("There is no frame {frame}.");
+                };
+
+                let lines: Vec<String> = if command == "locals" {
+                    frame
+                        .slots
+                        .iter()
+                        .enumerate()
+                        .map(|(slot, (name, value))| match name {
+                            Some(name) => format!("{slot} {name} = {}", show(value)),
+                            None => format!("{slot} = {}", show(value)),
+                        })
+                        .collect()
+                } else {
+                    frame
+                        .environment
+                        .iter()
+                        .map(|(name, value)| format!("{name} = {}", show(value)))
+                        .collect()
+                };
+                Ok(lines.join("\n"))
+            }
+            _ => bail!("Unknown command {line:?}."),
+        }
+    }
+
+    fn breakpoints_mut(&mut self) -> &mut Breakpoints {
+        self.vm
+            .breakpoints_mut()
+            .expect("The debugger's VM always has breakpoints.")
+    }
+
+    fn frames(&self) -> Result<Vec<Frame>> {
+        if self.state != State::Paused {
+            bail!("The program is not paused.");
+        }
+        Ok(self.vm.frames())
+    }
+
+    fn report(&mut self, execution: Result<Execution>) -> Result<String> {
+        match execution {
+            Ok(Execution::Paused) => {
+                self.state = State::Paused;
+                let frames = self.vm.frames();
+                let frame = frames.first().expect("A paused run has a frame.");
+                Ok(format!("paused at {}", self.describe(frame)))
+            }
+            Ok(Execution::Finished(value)) => {
+                self.state = State::Ended;
+                Ok(format!("finished: {}", show(&value)))
+            }
+            Ok(Execution::Suspended(token)) => {
+                self.state = State::Ended;
+                bail!("Suspended waiting for native function {}.", token.native)
+            }
+            Err(error) => {
+                self.state = State::Ended;
+                Err(error)
+            }
+        }
+    }
+
+    fn describe(&self, frame: &Frame) -> String {
+        let line = match frame.offset {
+            Some(offset) => self.breakpoints().line_of(offset).to_string(),
+            None => "?".to_owned(),
+        };
+        let instruction = match &frame.instruction {
+            Some(instruction) => format!("{instruction:?}"),
+            None => "-".to_owned(),
+        };
+
+        format!("line {line} in {}: {instruction}", frame.function)
+    }
+
+    fn breakpoints(&self) -> &Breakpoints {
+        self.vm
+            .breakpoints()
+            .expect("The debugger's VM always has breakpoints.")
+    }
+
+    /// The source line `offset` is on, followed by a caret under it.
+    fn excerpt(&self, offset: usize) -> String {
+        let start = offset + 1 - self.breakpoints().column_of(offset);
+        let line = self.source[start..].lines().next().unwrap_or_default();
+        let indent: String = self.source[start..offset]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+
+        format!("{line}\n{indent}^")
+    }
+}
+
+/// Shows values as in the JSON output of `rvm --output json`.
+fn show(value: &FinalValue) -> String {
+    serde_json::to_string(value).expect("Values always serialize.")
+}
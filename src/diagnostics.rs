@@ -0,0 +1,345 @@
+use miette::{
+    Diagnostic, GraphicalReportHandler, GraphicalTheme, LabeledSpan, NamedSource, Report, Severity,
+    SourceCode, SourceSpan,
+};
+use rinha::{
+    ast::{Element, Term},
+    parser::ParseError,
+};
+use serde::Serialize;
+use std::{collections::HashSet, fmt::Display, iter};
+use thiserror::Error;
+
+use crate::{ast::Location, imports, vm::RuntimeError};
+
+/// The kinds of [`Warning`] the compiler reports.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    /// A `let` binding nothing refers to.
+    UnusedBinding,
+    /// A `let` binding hiding a variable of the same name.
+    ShadowedBinding,
+    /// A branch of an `if` whose condition is a literal, so it never runs.
+    UnreachableBranch,
+    /// A call to a function with a different number of arguments than it
+    /// takes, where it is only an error if the call runs.
+    WrongArity,
+    /// A variable that is neither bound anywhere before it is read nor
+    /// provided by the VM, which is only an error if it is read.
+    UnboundVariable,
+}
+
+impl WarningKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            WarningKind::UnusedBinding => "unused_binding",
+            WarningKind::ShadowedBinding => "shadowed_binding",
+            WarningKind::UnreachableBranch => "unreachable_branch",
+            WarningKind::WrongArity => "wrong_arity",
+            WarningKind::UnboundVariable => "unbound_variable",
+        }
+    }
+}
+
+/// Something suspicious about a program that does not stop it from
+/// compiling.
+#[derive(Clone, Debug, Eq, Error, PartialEq, Serialize)]
+#[error("{message}")]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+    /// The term the warning is about.
+    pub location: Location,
+    /// What is wrong with that term, shown under it.
+    pub label: String,
+    pub help: Option<String>,
+}
+
+impl Warning {
+    pub fn new(kind: WarningKind, message: String, location: Location, label: &str) -> Self {
+        Self {
+            kind,
+            message,
+            location,
+            label: label.to_owned(),
+            help: None,
+        }
+    }
+
+    pub fn with_help(mut self, help: &str) -> Self {
+        self.help = Some(help.to_owned());
+        self
+    }
+}
+
+impl Diagnostic for Warning {
+    fn code<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        Some(Box::new(self.kind.name()))
+    }
+
+    fn severity(&self) -> Option<Severity> {
+        Some(Severity::Warning)
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.help
+            .as_ref()
+            .map(|help| Box::new(help) as Box<dyn Display>)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        let span = SourceSpan::from(self.location.start..self.location.end);
+
+        Some(Box::new(iter::once(LabeledSpan::new_with_span(
+            Some(self.label.clone()),
+            span,
+        ))))
+    }
+}
+
+/// What compiling a program found worth pointing out, in the order the
+/// compiler came across it.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct CompileReport {
+    pub warnings: Vec<Warning>,
+    /// The errors compiling collected, which are only collected with
+    /// [`crate::compiler::Compiler::with_error_recovery`].
+    pub errors: Vec<CompileError>,
+}
+
+impl CompileReport {
+    pub fn is_empty(&self) -> bool {
+        self.warnings.is_empty() && self.errors.is_empty()
+    }
+
+    /// Renders every error and then every warning with an excerpt of
+    /// `source` underlining the term it is about. Those about terms imported
+    /// from other files are rendered without one.
+    pub fn render(&self, filename: &str, source: &str, colors: bool) -> String {
+        let handler = handler(colors);
+
+        let mut rendered = String::new();
+        for error in &self.errors {
+            let diagnostic = ErrorDiagnostic::new(&error.clone().into(), filename, source);
+
+            // Writing to a string cannot fail.
+            let _ = handler.render_report(&mut rendered, &diagnostic);
+        }
+        for warning in &self.warnings {
+            let mut report = Report::new(warning.clone());
+            if warning.location.filename == filename {
+                report = report.with_source_code(NamedSource::new(filename, source.to_owned()));
+            }
+
+            // Writing to a string cannot fail.
+            let _ = handler.render_report(&mut rendered, report.as_ref());
+        }
+
+        rendered
+    }
+}
+
+/// An error in a term of a program, found while compiling it.
+#[derive(Clone, Debug, Eq, Error, PartialEq, Serialize)]
+#[error("{message} at {}:{}..{}.", .location.filename, .location.start, .location.end)]
+pub struct CompileError {
+    pub message: String,
+    pub location: Location,
+}
+
+/// Every error compiling a program found, in the order it came across them,
+/// when compiling with [`crate::compiler::Compiler::with_error_recovery`].
+#[derive(Clone, Debug, Eq, Error, PartialEq)]
+#[error("{}", summarize(.0))]
+pub struct CompileErrors(pub Vec<CompileError>);
+
+fn summarize(errors: &[CompileError]) -> String {
+    match errors {
+        [error] => error.to_string(),
+        _ => format!("Found {} errors compiling the program.", errors.len()),
+    }
+}
+
+/// An error from compiling or running a program, along with the parts of
+/// its source it is about, as far as they are known.
+#[derive(Debug, Error)]
+#[error("{message}")]
+pub struct ErrorDiagnostic {
+    message: String,
+    /// The term that failed, then the calls that led to it, innermost first.
+    labels: Vec<LabeledSpan>,
+    help: Option<String>,
+    source_code: NamedSource,
+}
+
+impl ErrorDiagnostic {
+    /// Describes `error`, which compiling or running `source` failed with.
+    /// Runtime errors point at the term that failed and at the calls that
+    /// led to it.
+    pub fn new(error: &anyhow::Error, filename: &str, source: &str) -> Self {
+        let mut diagnostic = Self {
+            message: error.to_string(),
+            labels: Vec::new(),
+            help: None,
+            source_code: NamedSource::new(filename, source.to_owned()),
+        };
+
+        if let Some(error) = error.downcast_ref::<CompileError>() {
+            diagnostic.message = error.message.clone();
+            if error.location.filename == filename {
+                diagnostic.labels.push(LabeledSpan::new_with_span(
+                    Some("here".to_owned()),
+                    error.location.start..error.location.end,
+                ));
+            }
+        } else if let Some(error) = error.downcast_ref::<RuntimeError>() {
+            diagnostic.message = error.error.to_string();
+
+            let ends = TermEnds::new(filename, source);
+            let mut seen = HashSet::new();
+            for (depth, frame) in error.trace.frames.iter().enumerate() {
+                let Some(offset) = frame.offset.filter(|offset| seen.insert(*offset)) else {
+                    continue;
+                };
+
+                // Outer frames are running calls, which may start a larger
+                // term, as in `f(x) + 1`.
+                let (label, end) = match depth {
+                    0 => (
+                        format!("failed in {}", frame.function),
+                        ends.end_of(offset, false),
+                    ),
+                    _ => (
+                        format!("called from {}", frame.function),
+                        ends.end_of(offset, true),
+                    ),
+                };
+                let end = end.unwrap_or(offset);
+                diagnostic
+                    .labels
+                    .push(LabeledSpan::new_with_span(Some(label), offset..end));
+            }
+
+            let elided: u64 = error.trace.frames.iter().map(|f| f.elided_count).sum();
+            if elided > 0 {
+                diagnostic.help = Some(format!(
+                    "{elided} tail calls along the way replaced their callers' frames."
+                ));
+            }
+        }
+
+        diagnostic
+    }
+}
+
+impl Diagnostic for ErrorDiagnostic {
+    fn severity(&self) -> Option<Severity> {
+        Some(Severity::Error)
+    }
+
+    fn help<'a>(&'a self) -> Option<Box<dyn Display + 'a>> {
+        self.help
+            .as_ref()
+            .map(|help| Box::new(help) as Box<dyn Display>)
+    }
+
+    fn source_code(&self) -> Option<&dyn SourceCode> {
+        Some(&self.source_code)
+    }
+
+    fn labels(&self) -> Option<Box<dyn Iterator<Item = LabeledSpan> + '_>> {
+        Some(Box::new(self.labels.iter().cloned()))
+    }
+}
+
+/// Renders `error`, which compiling or running `source` failed with, with
+/// excerpts of the source; see [`ErrorDiagnostic`]. Syntax errors are
+/// rendered as the parser reports them, and [`CompileErrors`] one by one.
+pub fn render_error(error: &anyhow::Error, filename: &str, source: &str, colors: bool) -> String {
+    if let Some(CompileErrors(errors)) = error.downcast_ref() {
+        let report = CompileReport {
+            warnings: Vec::new(),
+            errors: errors.clone(),
+        };
+        return report.render(filename, source, colors);
+    }
+
+    let handler = handler(colors);
+    let mut rendered = String::new();
+
+    // Writing to a string cannot fail.
+    let _ = match error.downcast_ref::<ParseError>() {
+        Some(error) => handler.render_report(&mut rendered, error),
+        None => handler.render_report(
+            &mut rendered,
+            &ErrorDiagnostic::new(error, filename, source),
+        ),
+    };
+
+    rendered
+}
+
+fn handler(colors: bool) -> GraphicalReportHandler {
+    let theme = if colors {
+        GraphicalTheme::unicode()
+    } else {
+        GraphicalTheme::unicode_nocolor()
+    };
+
+    GraphicalReportHandler::new_themed(theme).with_links(false)
+}
+
+/// Where the terms of a program end, by where they start. The compiler only
+/// records where the term each instruction comes from starts, so errors
+/// find the rest by parsing the program again.
+struct TermEnds {
+    /// The start and end of each term, and whether it is a call.
+    ends: Vec<(usize, usize, bool)>,
+}
+
+impl TermEnds {
+    fn new(filename: &str, source: &str) -> Self {
+        let mut ends = Vec::new();
+        let Ok(file) = imports::parse_with_imports(filename, source) else {
+            return Self { ends };
+        };
+
+        let mut stack = vec![&file.expression];
+        while let Some(term) = stack.pop() {
+            // A `let` chain starts where the statement it begins with does.
+            let location = term.location();
+            if location.filename == filename && !matches!(term, Term::Let(_)) {
+                ends.push((location.start, location.end, matches!(term, Term::Call(_))));
+            }
+
+            match term {
+                Term::Bool(_) | Term::Int(_) | Term::Str(_) | Term::Error(_) | Term::Var(_) => {}
+                Term::First(t) => stack.push(&t.value),
+                Term::Second(t) => stack.push(&t.value),
+                Term::Print(t) => stack.push(&t.value),
+                Term::Tuple(t) => stack.extend([&*t.first, &*t.second]),
+                Term::Binary(b) => stack.extend([&*b.lhs, &*b.rhs]),
+                Term::If(i) => stack.extend([&*i.condition, &*i.then, &*i.otherwise]),
+                Term::Let(l) => stack.extend([&*l.value, &*l.next]),
+                Term::Function(f) => stack.push(&f.value),
+                Term::Call(c) => {
+                    stack.push(&c.callee);
+                    stack.extend(&c.arguments);
+                }
+            }
+        }
+
+        Self { ends }
+    }
+
+    /// Where the outermost term starting at `start` ends, other than a `let`,
+    /// or the outermost call if `call`.
+    fn end_of(&self, start: usize, call: bool) -> Option<usize> {
+        self.ends
+            .iter()
+            .filter(|(term_start, _, is_call)| *term_start == start && (*is_call || !call))
+            .map(|(_, end, _)| *end)
+            .max()
+    }
+}
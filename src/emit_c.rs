@@ -0,0 +1,341 @@
+use anyhow::Result;
+
+use crate::{
+    artifact::{CompiledProgram, Constant},
+    bytecode::Instruction,
+    optimizer::jump_target,
+    verifier::{stack_heights, Tables},
+};
+
+/// The values, allocation, operators and calls the emitted code relies on,
+/// shipped as a header of its own so it can be read apart from any program.
+/// Errors print the same messages as the VM and exit with status 1.
+const RUNTIME: &str = include_str!("../include/rvm_runtime.h");
+
+/// Lowers a compiled program to a standalone C11 program that runs it, so
+/// it can be built with any C compiler and compared with the VM. Values are
+/// tagged structs, each function becomes a C function whose stack slots are
+/// C variables, and tail calls go through a trampoline so they never grow
+/// the C stack.
+///
+/// The emitted program never frees memory and does not memoize calls. It
+/// has no natives, so using one is an unknown variable.
+pub fn emit_c(program: &CompiledProgram) -> Result<String> {
+    let captured: Vec<usize> = program
+        .functions
+        .iter()
+        .map(|function| function.captured.len())
+        .collect();
+    let tables = Tables {
+        constants: program.constants.len(),
+        functions: &captured,
+        identifiers: program.identifiers.len(),
+    };
+
+    let max_arity = program
+        .functions
+        .iter()
+        .map(|function| function.arity)
+        .max()
+        .unwrap_or(0);
+
+    let mut c = String::new();
+    c += &format!(
+        "/* Compiled from {} by rvm {}. */\n\n",
+        program.metadata.filename.replace("*/", "* /"),
+        program.metadata.compiler_version
+    );
+    c += "#include <stddef.h>\n\n";
+    c += &format!("#define RT_CONSTANTS {}\n", program.constants.len());
+    c += &format!("#define RT_IDENTIFIERS {}\n", program.identifiers.len());
+    c += &format!("#define RT_MAX_ARITY {max_arity}\n");
+    c += &format!(
+        "#define RT_MAX_TUPLE_DEPTH {}\n\n",
+        crate::vm::DEFAULT_MAX_TUPLE_DEPTH
+    );
+
+    c += "static const char *const rt_identifiers[RT_IDENTIFIERS + 1] = {";
+    for identifier in &program.identifiers {
+        c += &format!("{}, ", literal(identifier.as_bytes()));
+    }
+    c += "NULL};\n";
+    c += RUNTIME;
+
+    c += "\nstatic Value script(Closure *self, const Value *arguments);\n";
+    for index in 0..program.functions.len() {
+        c += &format!("static Value f{index}(Closure *self, const Value *arguments);\n");
+    }
+
+    for (index, function) in program.functions.iter().enumerate() {
+        let heights = stack_heights(&function.bytecode, function.arity as usize, tables)?;
+        c += &format!(
+            "\n/* {} */\nstatic Value f{index}(Closure *self, const Value *arguments) {{\n",
+            function
+                .name
+                .as_deref()
+                .unwrap_or("<anonymous>")
+                .replace("*/", "* /")
+        );
+        c += &body(
+            program,
+            &function.bytecode,
+            &heights,
+            function.arity,
+            &function.captured,
+        )?;
+        c += "}\n";
+    }
+
+    let heights = stack_heights(&program.script, 0, tables)?;
+    c += "\nstatic Value script(Closure *self, const Value *arguments) {\n";
+    c += &body(program, &program.script, &heights, 0, &[])?;
+    c += "}\n";
+
+    c += "\nint main(void) {\n";
+    for (index, constant) in program.constants.iter().enumerate() {
+        let value = match constant {
+            Constant::Bool(b) => format!("rt_bool({b})"),
+            Constant::Integer(i) => integer(*i),
+            Constant::String(s) => format!("rt_string({}, {})", literal(s.as_bytes()), s.len()),
+        };
+        c += &format!("    rt_constants[{index}] = {value};\n");
+    }
+    c += "    rt_finish(script(NULL, NULL));\n    return 0;\n}\n";
+
+    Ok(c)
+}
+
+/// The statements of a C function running `bytecode`, where `heights` are
+/// the stack heights the verifier found. Stack slot `n` is the variable
+/// `sn`, and the arguments are the first slots.
+fn body(
+    program: &CompiledProgram,
+    bytecode: &[Instruction],
+    heights: &[Option<usize>],
+    arity: u16,
+    captured: &[String],
+) -> Result<String> {
+    let slots = bytecode
+        .iter()
+        .zip(heights)
+        .filter_map(|(instruction, height)| {
+            let (popped, pushed) = match instruction {
+                Instruction::Closure(index) => {
+                    (program.functions[*index as usize].captured.len(), 1)
+                }
+                _ => instruction.stack_effect(),
+            };
+            Some((*height)? - popped + pushed)
+        })
+        .max()
+        .unwrap_or(0)
+        .max(arity as usize);
+
+    let mut targets = vec![false; bytecode.len() + 1];
+    for (address, instruction) in bytecode.iter().enumerate() {
+        if let (Some(_), Some(target)) = (heights[address], jump_target(address, instruction)) {
+            targets[target] = true;
+        }
+    }
+
+    let mut c = String::from("    (void)self;\n    (void)arguments;\n");
+    for slot in 0..slots {
+        if slot < arity as usize {
+            c += &format!("    Value s{slot} = arguments[{slot}];\n");
+        } else {
+            c += &format!("    Value s{slot};\n");
+        }
+    }
+
+    for (address, instruction) in bytecode.iter().enumerate() {
+        let Some(h) = heights[address] else {
+            continue;
+        };
+        if targets[address] {
+            c += &format!("L{address}:;\n");
+        }
+
+        let binary = |function: &str| format!("s{} = {function}(s{}, s{});", h - 2, h - 2, h - 1);
+        let statement = match instruction {
+            Instruction::Constant(index) => format!("s{h} = rt_constants[{index}];"),
+            Instruction::True => format!("s{h} = rt_bool(true);"),
+            Instruction::False => format!("s{h} = rt_bool(false);"),
+            Instruction::Add => binary("rt_add"),
+            Instruction::Sub => binary("rt_sub"),
+            Instruction::Mul => binary("rt_mul"),
+            Instruction::Div => binary("rt_div"),
+            Instruction::Rem => binary("rt_rem"),
+            Instruction::BitAnd => binary("rt_band"),
+            Instruction::BitOr => binary("rt_bor"),
+            Instruction::BitXor => binary("rt_bxor"),
+            Instruction::Shl => binary("rt_shl"),
+            Instruction::Shr => binary("rt_shr"),
+            Instruction::Gt => binary("rt_gt"),
+            Instruction::Lt => binary("rt_lt"),
+            Instruction::Gte => binary("rt_gte"),
+            Instruction::Lte => binary("rt_lte"),
+            Instruction::And => binary("rt_and"),
+            Instruction::Or => binary("rt_or"),
+            Instruction::Tuple => binary("rt_tuple"),
+            Instruction::Eq => format!("s{} = rt_bool(rt_equals(s{}, s{}));", h - 2, h - 2, h - 1),
+            Instruction::Neq => {
+                format!("s{} = rt_bool(!rt_equals(s{}, s{}));", h - 2, h - 2, h - 1)
+            }
+            Instruction::First => format!("s{} = rt_first(s{});", h - 1, h - 1),
+            Instruction::Second => format!("s{} = rt_second(s{});", h - 1, h - 1),
+            Instruction::Print => format!("rt_print(s{});", h - 1),
+            Instruction::Neg => format!("s{} = rt_neg(s{});", h - 1, h - 1),
+            Instruction::Not => format!("s{} = rt_not(s{});", h - 1, h - 1),
+            Instruction::Show => format!("s{} = rt_show(s{});", h - 1, h - 1),
+            Instruction::Dup => format!("s{h} = s{};", h - 1),
+            Instruction::GlobalGet(index) => {
+                let name = &program.identifiers[*index as usize];
+                match captured.iter().position(|captured| captured == name) {
+                    Some(slot) => format!("s{h} = self->environment[{slot}];"),
+                    None => format!("s{h} = rt_global({index});"),
+                }
+            }
+            Instruction::GlobalSet(index) => format!("rt_set_global({index}, s{});", h - 1),
+            Instruction::LocalGet(slot, _) => format!("s{h} = s{slot};"),
+            Instruction::If(_) => format!(
+                "if (!rt_condition(s{})) goto L{};",
+                h - 1,
+                jump_target(address, instruction).expect("An If always has a target.")
+            ),
+            Instruction::Jump(_) => format!(
+                "goto L{};",
+                jump_target(address, instruction).expect("A Jump always has a target.")
+            ),
+            Instruction::JumpBack(_) => {
+                let mut statement = String::new();
+                for slot in 0..arity as usize {
+                    statement += &format!("s{slot} = s{}; ", h - arity as usize + slot);
+                }
+                statement
+                    + &format!(
+                        "goto L{};",
+                        jump_target(address, instruction).expect("A JumpBack always has a target.")
+                    )
+            }
+            Instruction::Closure(index) => {
+                let function = &program.functions[*index as usize];
+                let count = function.captured.len();
+                let name = match &function.name {
+                    Some(name) => literal(name.as_bytes()),
+                    None => "NULL".to_owned(),
+                };
+                let closure = |environment: &str| {
+                    format!(
+                        "s{} = rt_closure(f{index}, {name}, {}, {count}, {environment});",
+                        h - count,
+                        function.arity
+                    )
+                };
+
+                match count {
+                    0 => closure("NULL"),
+                    _ => format!(
+                        "{{ const Value environment[] = {{{}}}; {} }}",
+                        slot_list(h - count, h),
+                        closure("environment")
+                    ),
+                }
+            }
+            Instruction::Call(arity) | Instruction::TailCall(arity) => {
+                let arity = *arity as usize;
+                let callee = h - arity - 1;
+                let call = |arguments: &str| match instruction {
+                    Instruction::Call(_) => {
+                        format!("s{callee} = rt_call(s{callee}, {arity}, {arguments});")
+                    }
+                    _ => format!("return rt_tail_call(s{callee}, {arity}, {arguments});"),
+                };
+
+                match arity {
+                    0 => call("NULL"),
+                    _ => format!(
+                        "{{ const Value arguments[] = {{{}}}; {} }}",
+                        slot_list(h - arity, h),
+                        call("arguments")
+                    ),
+                }
+            }
+            Instruction::LocalGetTailCall(slot, _, arity) => {
+                // The local is the last argument, or the callee when there
+                // are none.
+                let arity = *arity as usize;
+                match arity {
+                    0 => format!("return rt_tail_call(s{slot}, 0, NULL);"),
+                    _ => {
+                        let mut arguments: Vec<String> =
+                            (h + 1 - arity..h).map(|slot| format!("s{slot}")).collect();
+                        arguments.push(format!("s{slot}"));
+                        format!(
+                            "{{ const Value arguments[] = {{{}}}; return rt_tail_call(s{}, {arity}, arguments); }}",
+                            arguments.join(", "),
+                            h - arity
+                        )
+                    }
+                }
+            }
+            Instruction::LocalGetConstantAdd(slot, _, index) => {
+                format!("s{h} = rt_add(s{slot}, rt_constants[{index}]);")
+            }
+            Instruction::LocalGetConstantSub(slot, _, index) => {
+                format!("s{h} = rt_sub(s{slot}, rt_constants[{index}]);")
+            }
+            Instruction::ConstantLt(index) => {
+                format!("s{} = rt_lt(s{}, rt_constants[{index}]);", h - 1, h - 1)
+            }
+            Instruction::ConstantEq(index) => format!(
+                "s{} = rt_bool(rt_equals(s{}, rt_constants[{index}]));",
+                h - 1,
+                h - 1
+            ),
+            Instruction::Return(_) => format!("return s{};", h - 1),
+            Instruction::Slide(count) => format!("s{} = s{};", h - *count as usize - 1, h - 1),
+            Instruction::ReadLine => format!("s{h} = rt_read_line();"),
+            Instruction::ReadInt => format!("s{h} = rt_read_int();"),
+            Instruction::Pop => continue,
+        };
+
+        c += &format!("    {statement}\n");
+    }
+
+    Ok(c)
+}
+
+/// The variables of the stack slots from `start` up to `end`, separated by
+/// commas.
+fn slot_list(start: usize, end: usize) -> String {
+    (start..end)
+        .map(|slot| format!("s{slot}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// `i32::MIN` has no literal in C, since `-2147483648` negates a value too
+/// large for an `int`.
+fn integer(i: i32) -> String {
+    match i {
+        i32::MIN => "rt_integer(INT32_MIN)".to_owned(),
+        i => format!("rt_integer({i})"),
+    }
+}
+
+/// A C string literal for `bytes`. Anything but printable ASCII is written
+/// as a three digit octal escape, which never runs into what follows.
+fn literal(bytes: &[u8]) -> String {
+    let mut literal = String::from("\"");
+
+    for &byte in bytes {
+        match byte {
+            b'"' | b'\\' | b'?' => literal += &format!("\\{}", byte as char),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => literal += &format!("\\{byte:03o}"),
+        }
+    }
+
+    literal.push('"');
+    literal
+}
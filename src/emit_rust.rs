@@ -0,0 +1,652 @@
+use anyhow::Result;
+
+use crate::{
+    artifact::{CompiledProgram, Constant},
+    bytecode::Instruction,
+    optimizer::jump_target,
+    verifier::{stack_heights, Tables},
+};
+
+/// The values, operators and calls the emitted code relies on. Errors print
+/// the same messages as the VM and exit with status 1.
+const RUNTIME: &str = r#"
+#[derive(Clone, Copy)]
+enum Value {
+    Bool(bool),
+    Integer(i32),
+    Str(&'static str),
+    Tuple(&'static Tuple),
+    Closure(&'static Closure),
+}
+
+struct Tuple {
+    first: Value,
+    second: Value,
+    depth: u32,
+}
+
+type Code = fn(&mut Runtime, &'static Closure, &[Value]) -> Next;
+
+struct Closure {
+    code: Code,
+    name: Option<&'static str>,
+    arity: u16,
+    environment: &'static [Value],
+}
+
+/// What a function returned, or the call a tail call asked for, which the
+/// nearest `finish` makes.
+enum Next {
+    Value(Value),
+    TailCall(&'static Closure, [Value; MAX_ARITY]),
+}
+
+struct Runtime {
+    globals: Vec<Option<Value>>,
+}
+
+fn fail(message: &str) -> ! {
+    let _ = io::stdout().flush();
+    eprintln!("Error: {message}");
+    process::exit(1);
+}
+
+/// Nothing is ever freed: programs run to completion and exit.
+fn leak<T>(value: T) -> &'static T {
+    Box::leak(Box::new(value))
+}
+
+fn leak_str(string: String) -> Value {
+    Value::Str(Box::leak(string.into_boxed_str()))
+}
+
+fn integers(lhs: Value, rhs: Value) -> (i32, i32) {
+    match (lhs, rhs) {
+        (Value::Integer(lhs), Value::Integer(rhs)) => (lhs, rhs),
+        _ => fail("Operands must be both integers."),
+    }
+}
+
+fn bools(lhs: Value, rhs: Value) -> (bool, bool) {
+    match (lhs, rhs) {
+        (Value::Bool(lhs), Value::Bool(rhs)) => (lhs, rhs),
+        _ => fail("Operands must be both integers."),
+    }
+}
+
+fn add(lhs: Value, rhs: Value) -> Value {
+    match (lhs, rhs) {
+        (Value::Integer(lhs), Value::Integer(rhs)) => Value::Integer(lhs.wrapping_add(rhs)),
+        (Value::Str(lhs), Value::Integer(rhs)) => leak_str(format!("{lhs}{rhs}")),
+        (Value::Integer(lhs), Value::Str(rhs)) => leak_str(format!("{lhs}{rhs}")),
+        (Value::Str(lhs), Value::Str(rhs)) => leak_str(format!("{lhs}{rhs}")),
+        _ => fail("Wrong types for add."),
+    }
+}
+
+fn sub(lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = integers(lhs, rhs);
+    Value::Integer(lhs.wrapping_sub(rhs))
+}
+
+fn mul(lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = integers(lhs, rhs);
+    Value::Integer(lhs.wrapping_mul(rhs))
+}
+
+fn div(lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = integers(lhs, rhs);
+    if rhs == 0 {
+        fail("Attempted to divide by zero");
+    }
+    Value::Integer(lhs.wrapping_div(rhs))
+}
+
+fn rem(lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = integers(lhs, rhs);
+    if rhs == 0 {
+        fail("Attempted to take remainder by zero");
+    }
+    Value::Integer(lhs.wrapping_rem(rhs))
+}
+
+fn band(lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = integers(lhs, rhs);
+    Value::Integer(lhs & rhs)
+}
+
+fn bor(lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = integers(lhs, rhs);
+    Value::Integer(lhs | rhs)
+}
+
+fn bxor(lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = integers(lhs, rhs);
+    Value::Integer(lhs ^ rhs)
+}
+
+fn shl(lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = integers(lhs, rhs);
+    Value::Integer(lhs.wrapping_shl((rhs & 31) as u32))
+}
+
+fn shr(lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = integers(lhs, rhs);
+    Value::Integer(lhs >> (rhs & 31))
+}
+
+/// Orders integers by value and strings by their bytes, which is the order
+/// of their code points.
+fn compare(lhs: Value, rhs: Value) -> Ordering {
+    match (lhs, rhs) {
+        (Value::Integer(lhs), Value::Integer(rhs)) => lhs.cmp(&rhs),
+        (Value::Str(lhs), Value::Str(rhs)) => lhs.cmp(rhs),
+        _ => fail("Operands must be both integers or both strings."),
+    }
+}
+
+fn lt(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare(lhs, rhs).is_lt())
+}
+
+fn gt(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare(lhs, rhs).is_gt())
+}
+
+fn lte(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare(lhs, rhs).is_le())
+}
+
+fn gte(lhs: Value, rhs: Value) -> Value {
+    Value::Bool(compare(lhs, rhs).is_ge())
+}
+
+fn and(lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = bools(lhs, rhs);
+    Value::Bool(lhs && rhs)
+}
+
+fn or(lhs: Value, rhs: Value) -> Value {
+    let (lhs, rhs) = bools(lhs, rhs);
+    Value::Bool(lhs || rhs)
+}
+
+fn neg(value: Value) -> Value {
+    match value {
+        Value::Integer(integer) => Value::Integer(integer.wrapping_neg()),
+        _ => fail("Operand must be an integer."),
+    }
+}
+
+fn not(value: Value) -> Value {
+    match value {
+        Value::Bool(boolean) => Value::Bool(!boolean),
+        _ => fail("Operand must be a boolean."),
+    }
+}
+
+/// Structural, except that closures are only equal to themselves.
+fn equals(lhs: Value, rhs: Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Bool(lhs), Value::Bool(rhs)) => lhs == rhs,
+        (Value::Integer(lhs), Value::Integer(rhs)) => lhs == rhs,
+        (Value::Str(lhs), Value::Str(rhs)) => lhs == rhs,
+        (Value::Tuple(lhs), Value::Tuple(rhs)) => {
+            equals(lhs.first, rhs.first) && equals(lhs.second, rhs.second)
+        }
+        (Value::Closure(lhs), Value::Closure(rhs)) => std::ptr::eq(lhs, rhs),
+        _ => false,
+    }
+}
+
+fn depth(value: Value) -> u32 {
+    match value {
+        Value::Tuple(tuple) => tuple.depth,
+        _ => 0,
+    }
+}
+
+fn tuple(first: Value, second: Value) -> Value {
+    let depth = 1 + depth(first).max(depth(second));
+    if depth > MAX_TUPLE_DEPTH {
+        fail(&format!(
+            "Tuple nesting depth {depth} exceeds the limit of {MAX_TUPLE_DEPTH}."
+        ));
+    }
+
+    Value::Tuple(leak(Tuple {
+        first,
+        second,
+        depth,
+    }))
+}
+
+fn first(value: Value) -> Value {
+    match value {
+        Value::Tuple(tuple) => tuple.first,
+        _ => fail("Tried to compute `first` of a non tuple type."),
+    }
+}
+
+fn second(value: Value) -> Value {
+    match value {
+        Value::Tuple(tuple) => tuple.second,
+        _ => fail("Tried to compute `second` of a non tuple type."),
+    }
+}
+
+/// The text print shows for a value.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Bool(boolean) => write!(f, "{boolean}"),
+            Value::Integer(integer) => write!(f, "{integer}"),
+            Value::Str(string) => write!(f, "{string}"),
+            Value::Tuple(tuple) => write!(f, "({}, {})", tuple.first, tuple.second),
+            Value::Closure(closure) => match closure.name {
+                Some(name) => write!(f, "<#closure {name}>"),
+                None => write!(f, "<#closure>"),
+            },
+        }
+    }
+}
+
+fn show(value: Value) -> Value {
+    leak_str(value.to_string())
+}
+
+fn print(value: Value) {
+    let _ = writeln!(io::stdout().lock(), "{value}");
+}
+
+fn condition(value: Value) -> bool {
+    match value {
+        Value::Bool(boolean) => boolean,
+        _ => fail("Type error: if condition must evaluate to a boolean."),
+    }
+}
+
+fn global(rt: &Runtime, index: usize) -> Value {
+    match rt.globals[index] {
+        Some(value) => value,
+        None => fail(&format!("Unknown variable {}.", IDENTIFIERS[index])),
+    }
+}
+
+fn closure(code: Code, name: Option<&'static str>, arity: u16, environment: &[Value]) -> Value {
+    Value::Closure(leak(Closure {
+        code,
+        name,
+        arity,
+        environment: Box::leak(environment.to_vec().into_boxed_slice()),
+    }))
+}
+
+fn callee(value: Value, arity: usize) -> &'static Closure {
+    match value {
+        Value::Closure(closure) if closure.arity as usize == arity => closure,
+        Value::Closure(_) => fail("Attempted to call function with wrong number of arguments."),
+        _ => fail("Attempted to call value that is not a function!"),
+    }
+}
+
+/// Makes the calls tail calls left pending until one returns a value.
+fn finish(rt: &mut Runtime, mut next: Next) -> Value {
+    loop {
+        match next {
+            Next::Value(value) => return value,
+            Next::TailCall(closure, arguments) => next = (closure.code)(rt, closure, &arguments),
+        }
+    }
+}
+
+fn call(rt: &mut Runtime, value: Value, arguments: &[Value]) -> Value {
+    let closure = callee(value, arguments.len());
+    let next = (closure.code)(rt, closure, arguments);
+    finish(rt, next)
+}
+
+fn tail_call(value: Value, arguments: &[Value]) -> Next {
+    let closure = callee(value, arguments.len());
+    let mut pending = [Value::Bool(false); MAX_ARITY];
+    pending[..arguments.len()].copy_from_slice(arguments);
+    Next::TailCall(closure, pending)
+}
+
+/// The next line of input without its line terminator. Once the input is
+/// exhausted, every line is empty.
+fn read_line() -> Value {
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line).is_err() {
+        line.clear();
+    }
+    if line.ends_with('\n') {
+        line.pop();
+    }
+    if line.ends_with('\r') {
+        line.pop();
+    }
+    leak_str(line)
+}
+
+fn read_int() -> Value {
+    let Value::Str(line) = read_line() else {
+        unreachable!()
+    };
+    match line.trim().parse() {
+        Ok(integer) => Value::Integer(integer),
+        Err(_) => fail(&format!("Expected an integer as input, but read \"{line}\".")),
+    }
+}
+"#;
+
+/// Lowers a compiled program to a standalone Rust program that runs it, so
+/// `rustc -O` can turn it into a native binary without the VM's dispatch
+/// loop. Like [`emit_c`](crate::emit_c::emit_c), values are tagged, each
+/// function becomes a Rust function whose stack slots are variables, and
+/// tail calls go through a trampoline. Jumps only go forward, so each
+/// target ends a labeled block opened at the start of the function, and a
+/// `JumpBack` continues a loop around the whole body.
+///
+/// The emitted program never frees memory and does not memoize calls. It
+/// has no natives, so using one is an unknown variable.
+pub fn emit_rust(program: &CompiledProgram) -> Result<String> {
+    let captured: Vec<usize> = program
+        .functions
+        .iter()
+        .map(|function| function.captured.len())
+        .collect();
+    let tables = Tables {
+        constants: program.constants.len(),
+        functions: &captured,
+        identifiers: program.identifiers.len(),
+    };
+
+    let max_arity = program
+        .functions
+        .iter()
+        .map(|function| function.arity)
+        .max()
+        .unwrap_or(0);
+
+    let mut rust = String::new();
+    rust += &format!(
+        "// Compiled from {} by rvm {}.\n\n",
+        program.metadata.filename.replace('\n', " "),
+        program.metadata.compiler_version
+    );
+    rust += "#![allow(unreachable_code, unused)]\n\nuse std::{\n    cmp::Ordering,\n    fmt,\n    io::{self, Write},\n    process,\n};\n\n";
+    rust += &format!("const MAX_ARITY: usize = {max_arity};\n");
+    rust += &format!(
+        "const MAX_TUPLE_DEPTH: u32 = {};\n\n",
+        crate::vm::DEFAULT_MAX_TUPLE_DEPTH
+    );
+
+    rust += &format!(
+        "static IDENTIFIERS: [&str; {}] = [",
+        program.identifiers.len()
+    );
+    for identifier in &program.identifiers {
+        rust += &format!("{identifier:?}, ");
+    }
+    rust += "];\n";
+
+    rust += &format!("static CONSTANTS: [Value; {}] = [", program.constants.len());
+    for constant in &program.constants {
+        let value = match constant {
+            Constant::Bool(b) => format!("Value::Bool({b})"),
+            Constant::Integer(i) => format!("Value::Integer({i})"),
+            Constant::String(s) => format!("Value::Str({:?})", &**s),
+        };
+        rust += &format!("{value}, ");
+    }
+    rust += "];\n";
+    rust += RUNTIME;
+
+    for (index, function) in program.functions.iter().enumerate() {
+        let heights = stack_heights(&function.bytecode, function.arity as usize, tables)?;
+        rust += &format!(
+            "\n// {}\nfn f{index}(rt: &mut Runtime, this: &'static Closure, arguments: &[Value]) -> Next {{\n",
+            function
+                .name
+                .as_deref()
+                .unwrap_or("<anonymous>")
+                .replace('\n', " ")
+        );
+        rust += &body(
+            program,
+            &function.bytecode,
+            &heights,
+            function.arity,
+            &function.captured,
+        )?;
+        rust += "}\n";
+    }
+
+    let heights = stack_heights(&program.script, 0, tables)?;
+    rust += "\nfn script(rt: &mut Runtime) -> Next {\n";
+    rust += &body(program, &program.script, &heights, 0, &[])?;
+    rust += "}\n";
+
+    rust += "\nfn main() {\n";
+    rust += "    let mut rt = Runtime {\n        globals: vec![None; IDENTIFIERS.len()],\n    };\n";
+    rust += "    let next = script(&mut rt);\n    finish(&mut rt, next);\n}\n";
+
+    Ok(rust)
+}
+
+/// The statements of a Rust function running `bytecode`, where `heights`
+/// are the stack heights the verifier found. Stack slot `n` is the variable
+/// `sn`, and the arguments are the first slots.
+fn body(
+    program: &CompiledProgram,
+    bytecode: &[Instruction],
+    heights: &[Option<usize>],
+    arity: u16,
+    captured: &[String],
+) -> Result<String> {
+    let slots = bytecode
+        .iter()
+        .zip(heights)
+        .filter_map(|(instruction, height)| {
+            let (popped, pushed) = match instruction {
+                Instruction::Closure(index) => {
+                    (program.functions[*index as usize].captured.len(), 1)
+                }
+                _ => instruction.stack_effect(),
+            };
+            Some((*height)? - popped + pushed)
+        })
+        .max()
+        .unwrap_or(0)
+        .max(arity as usize);
+
+    let reachable = |(address, _): &(usize, &Instruction)| heights[*address].is_some();
+    let looping = bytecode
+        .iter()
+        .enumerate()
+        .filter(reachable)
+        .any(|(_, instruction)| matches!(instruction, Instruction::JumpBack(_)));
+    let mut open: Vec<usize> = bytecode
+        .iter()
+        .enumerate()
+        .filter(reachable)
+        .filter(|(_, instruction)| !matches!(instruction, Instruction::JumpBack(_)))
+        .filter_map(|(address, instruction)| jump_target(address, instruction))
+        .collect();
+    open.sort_unstable();
+    open.dedup();
+
+    let mut rust = String::new();
+    for slot in 0..slots {
+        if slot < arity as usize {
+            rust += &format!("    let mut s{slot} = arguments[{slot}];\n");
+        } else {
+            rust += &format!("    let mut s{slot} = Value::Bool(false);\n");
+        }
+    }
+    if looping {
+        rust += "    'start: loop {\n";
+    }
+    // The block ending first is the innermost.
+    for target in open.iter().rev() {
+        rust += &format!("    'l{target}: {{\n");
+    }
+
+    for (address, instruction) in bytecode.iter().enumerate() {
+        if open.first() == Some(&address) {
+            rust += &format!("    }} // 'l{address}\n");
+            open.remove(0);
+        }
+        let Some(h) = heights[address] else {
+            continue;
+        };
+
+        let binary = |function: &str| format!("s{} = {function}(s{}, s{});", h - 2, h - 2, h - 1);
+        let unary = |function: &str| format!("s{} = {function}(s{});", h - 1, h - 1);
+        let statement = match instruction {
+            Instruction::Constant(index) => format!("s{h} = CONSTANTS[{index}];"),
+            Instruction::True => format!("s{h} = Value::Bool(true);"),
+            Instruction::False => format!("s{h} = Value::Bool(false);"),
+            Instruction::Add => binary("add"),
+            Instruction::Sub => binary("sub"),
+            Instruction::Mul => binary("mul"),
+            Instruction::Div => binary("div"),
+            Instruction::Rem => binary("rem"),
+            Instruction::BitAnd => binary("band"),
+            Instruction::BitOr => binary("bor"),
+            Instruction::BitXor => binary("bxor"),
+            Instruction::Shl => binary("shl"),
+            Instruction::Shr => binary("shr"),
+            Instruction::Gt => binary("gt"),
+            Instruction::Lt => binary("lt"),
+            Instruction::Gte => binary("gte"),
+            Instruction::Lte => binary("lte"),
+            Instruction::And => binary("and"),
+            Instruction::Or => binary("or"),
+            Instruction::Tuple => binary("tuple"),
+            Instruction::Eq => format!("s{} = Value::Bool(equals(s{}, s{}));", h - 2, h - 2, h - 1),
+            Instruction::Neq => {
+                format!("s{} = Value::Bool(!equals(s{}, s{}));", h - 2, h - 2, h - 1)
+            }
+            Instruction::First => unary("first"),
+            Instruction::Second => unary("second"),
+            Instruction::Print => format!("print(s{});", h - 1),
+            Instruction::Neg => unary("neg"),
+            Instruction::Not => unary("not"),
+            Instruction::Show => unary("show"),
+            Instruction::Dup => format!("s{h} = s{};", h - 1),
+            Instruction::GlobalGet(index) => {
+                let name = &program.identifiers[*index as usize];
+                match captured.iter().position(|captured| captured == name) {
+                    Some(slot) => format!("s{h} = this.environment[{slot}];"),
+                    None => format!("s{h} = global(rt, {index});"),
+                }
+            }
+            Instruction::GlobalSet(index) => {
+                format!("rt.globals[{index}] = Some(s{});", h - 1)
+            }
+            Instruction::LocalGet(slot, _) => format!("s{h} = s{slot};"),
+            Instruction::If(_) => format!(
+                "if !condition(s{}) {{ break 'l{}; }}",
+                h - 1,
+                jump_target(address, instruction).expect("An If always has a target.")
+            ),
+            Instruction::Jump(_) => format!(
+                "break 'l{};",
+                jump_target(address, instruction).expect("A Jump always has a target.")
+            ),
+            Instruction::JumpBack(_) => {
+                let mut statement = String::new();
+                for slot in 0..arity as usize {
+                    statement += &format!("s{slot} = s{}; ", h - arity as usize + slot);
+                }
+                statement + "continue 'start;"
+            }
+            Instruction::Closure(index) => {
+                let function = &program.functions[*index as usize];
+                let count = function.captured.len();
+                let name = match &function.name {
+                    Some(name) => format!("Some({name:?})"),
+                    None => "None".to_owned(),
+                };
+                format!(
+                    "s{} = closure(f{index}, {name}, {}, &[{}]);",
+                    h - count,
+                    function.arity,
+                    slot_list(h - count, h)
+                )
+            }
+            Instruction::Call(arity) => {
+                let callee = h - *arity as usize - 1;
+                format!(
+                    "s{callee} = call(rt, s{callee}, &[{}]);",
+                    slot_list(h - *arity as usize, h)
+                )
+            }
+            Instruction::TailCall(arity) => format!(
+                "return tail_call(s{}, &[{}]);",
+                h - *arity as usize - 1,
+                slot_list(h - *arity as usize, h)
+            ),
+            Instruction::LocalGetTailCall(slot, _, arity) => {
+                // The local is the last argument, or the callee when there
+                // are none.
+                let arity = *arity as usize;
+                match arity {
+                    0 => format!("return tail_call(s{slot}, &[]);"),
+                    _ => {
+                        let mut arguments: Vec<String> =
+                            (h + 1 - arity..h).map(|slot| format!("s{slot}")).collect();
+                        arguments.push(format!("s{slot}"));
+                        format!(
+                            "return tail_call(s{}, &[{}]);",
+                            h - arity,
+                            arguments.join(", ")
+                        )
+                    }
+                }
+            }
+            Instruction::LocalGetConstantAdd(slot, _, index) => {
+                format!("s{h} = add(s{slot}, CONSTANTS[{index}]);")
+            }
+            Instruction::LocalGetConstantSub(slot, _, index) => {
+                format!("s{h} = sub(s{slot}, CONSTANTS[{index}]);")
+            }
+            Instruction::ConstantLt(index) => {
+                format!("s{} = lt(s{}, CONSTANTS[{index}]);", h - 1, h - 1)
+            }
+            Instruction::ConstantEq(index) => format!(
+                "s{} = Value::Bool(equals(s{}, CONSTANTS[{index}]));",
+                h - 1,
+                h - 1
+            ),
+            Instruction::Return(_) => format!("return Next::Value(s{});", h - 1),
+            Instruction::Slide(count) => format!("s{} = s{};", h - *count as usize - 1, h - 1),
+            Instruction::ReadLine => format!("s{h} = read_line();"),
+            Instruction::ReadInt => format!("s{h} = read_int();"),
+            Instruction::Pop => continue,
+        };
+
+        rust += &format!("    {statement}\n");
+    }
+
+    for target in open {
+        rust += &format!("    }} // 'l{target}\n");
+    }
+    if looping {
+        rust += "    }\n";
+    }
+    rust += "    unreachable!()\n";
+
+    Ok(rust)
+}
+
+/// The variables of the stack slots from `start` up to `end`, separated by
+/// commas.
+fn slot_list(start: usize, end: usize) -> String {
+    (start..end)
+        .map(|slot| format!("s{slot}"))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,1705 @@
+use std::{borrow::Cow, collections::HashMap};
+
+use anyhow::Result;
+use wasm_encoder::{
+    BlockType, CodeSection, ConstExpr, DataSection, ElementSection, Elements, EntityType,
+    ExportKind, ExportSection, Function, FunctionSection, GlobalSection, GlobalType, ImportSection,
+    InstructionSink, MemArg, MemorySection, MemoryType, Module, RefType, TableSection, TableType,
+    TypeSection, ValType,
+};
+
+use crate::{
+    artifact::{CompiledProgram, Constant},
+    bytecode::Instruction,
+    optimizer::jump_target,
+    verifier::{stack_heights, Tables},
+    vm::DEFAULT_MAX_TUPLE_DEPTH,
+};
+
+// A value is an i64 holding one of these tags in its upper half and an
+// integer, a boolean or the address of an object in its lower half.
+const BOOL: i64 = 0;
+const INTEGER: i64 = 1;
+const STRING: i64 = 2;
+const TUPLE: i64 = 3;
+const CLOSURE: i64 = 4;
+
+// The functions imported from the host's `rinha` module, in import order.
+// `print` and `fail` take the address and length of UTF-8 text; `fail`
+// reports an error and must not return. `read_line` reads the next line of
+// input without its line terminator and returns its length, which
+// `take_line` then copies to the given address.
+const HOST_PRINT: u32 = 0;
+const HOST_FAIL: u32 = 1;
+const HOST_READ_LINE: u32 = 2;
+const HOST_TAKE_LINE: u32 = 3;
+const HOST_FUNCTIONS: u32 = 4;
+
+/// The global holding the address of the first free byte of the heap.
+const HEAP_TOP: u32 = 0;
+
+/// Where the values of global variables start, followed by a byte per
+/// global telling whether it was defined.
+const GLOBALS: u32 = 8;
+
+const WASM_PAGE: u32 = 65536;
+
+/// The functions of the emitted module that implement values, allocation
+/// and operators, after the imports and in this order.
+#[derive(Clone, Copy, Debug)]
+enum Runtime {
+    Reserve,
+    Alloc,
+    Fail,
+    EmitByte,
+    EmitBytes,
+    EmitString,
+    EmitInteger,
+    Write,
+    Print,
+    BeginString,
+    EndString,
+    Integers,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Lt,
+    Gt,
+    Lte,
+    Gte,
+    And,
+    Or,
+    Equals,
+    Compare,
+    Tuple,
+    First,
+    Second,
+    Condition,
+    Callee,
+    ReadLine,
+    ReadInt,
+    Neg,
+    Not,
+    Show,
+}
+
+impl Runtime {
+    const ALL: [Self; 40] = [
+        Self::Reserve,
+        Self::Alloc,
+        Self::Fail,
+        Self::EmitByte,
+        Self::EmitBytes,
+        Self::EmitString,
+        Self::EmitInteger,
+        Self::Write,
+        Self::Print,
+        Self::BeginString,
+        Self::EndString,
+        Self::Integers,
+        Self::Add,
+        Self::Sub,
+        Self::Mul,
+        Self::Div,
+        Self::Rem,
+        Self::BitAnd,
+        Self::BitOr,
+        Self::BitXor,
+        Self::Shl,
+        Self::Shr,
+        Self::Lt,
+        Self::Gt,
+        Self::Lte,
+        Self::Gte,
+        Self::And,
+        Self::Or,
+        Self::Equals,
+        Self::Compare,
+        Self::Tuple,
+        Self::First,
+        Self::Second,
+        Self::Condition,
+        Self::Callee,
+        Self::ReadLine,
+        Self::ReadInt,
+        Self::Neg,
+        Self::Not,
+        Self::Show,
+    ];
+
+    fn index(self) -> u32 {
+        HOST_FUNCTIONS + self as u32
+    }
+
+    fn signature(self) -> (&'static [ValType], &'static [ValType]) {
+        use ValType::{I32, I64};
+
+        match self {
+            Self::Reserve | Self::Fail | Self::EmitByte | Self::EmitString | Self::EmitInteger => {
+                (&[I32], &[])
+            }
+            Self::Alloc | Self::EndString => (&[I32], &[I32]),
+            Self::EmitBytes => (&[I32, I32], &[]),
+            Self::Write | Self::Print => (&[I64], &[]),
+            Self::BeginString => (&[], &[I32]),
+            Self::Integers => (&[I64, I64], &[]),
+            Self::Add
+            | Self::Sub
+            | Self::Mul
+            | Self::Div
+            | Self::Rem
+            | Self::BitAnd
+            | Self::BitOr
+            | Self::BitXor
+            | Self::Shl
+            | Self::Shr
+            | Self::Lt
+            | Self::Gt
+            | Self::Lte
+            | Self::Gte
+            | Self::And
+            | Self::Or
+            | Self::Tuple => (&[I64, I64], &[I64]),
+            Self::Equals | Self::Compare => (&[I64, I64], &[I32]),
+            Self::First | Self::Second | Self::Neg | Self::Not | Self::Show => (&[I64], &[I64]),
+            Self::Condition => (&[I64], &[I32]),
+            Self::Callee => (&[I64, I32], &[I32]),
+            Self::ReadLine | Self::ReadInt => (&[], &[I64]),
+        }
+    }
+}
+
+/// The function types of the module, each added once.
+#[derive(Default)]
+struct Types {
+    types: Vec<(Vec<ValType>, Vec<ValType>)>,
+}
+
+impl Types {
+    fn index(&mut self, params: &[ValType], results: &[ValType]) -> u32 {
+        let position = self
+            .types
+            .iter()
+            .position(|(p, r)| p == params && r == results)
+            .unwrap_or_else(|| {
+                self.types.push((params.to_vec(), results.to_vec()));
+                self.types.len() - 1
+            });
+        position as u32
+    }
+
+    /// The type of a compiled function, which takes its closure and
+    /// arguments and returns its result.
+    fn function(&mut self, arity: u16) -> u32 {
+        let mut params = vec![ValType::I32];
+        params.extend((0..arity).map(|_| ValType::I64));
+        self.index(&params, &[ValType::I64])
+    }
+}
+
+/// The initial contents of memory: the globals, then strings.
+struct Data {
+    bytes: Vec<u8>,
+    strings: HashMap<Vec<u8>, u32>,
+}
+
+impl Data {
+    fn new(identifiers: usize) -> Self {
+        Self {
+            bytes: vec![0; GLOBALS as usize + identifiers * 9],
+            strings: HashMap::new(),
+        }
+    }
+
+    /// The address of a string object holding `bytes`: its length as an
+    /// i32, followed by the bytes.
+    fn string(&mut self, bytes: &[u8]) -> u32 {
+        if let Some(&address) = self.strings.get(bytes) {
+            return address;
+        }
+
+        self.bytes.resize(self.bytes.len().next_multiple_of(4), 0);
+        let address = self.bytes.len() as u32;
+        self.bytes
+            .extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+        self.bytes.extend_from_slice(bytes);
+        self.strings.insert(bytes.to_vec(), address);
+        address
+    }
+}
+
+/// Lowers a compiled program to a WebAssembly module, so it can run in
+/// browsers and in runtimes such as wasmtime. Values are i64s tagged in
+/// their upper half, objects are bump allocated in linear memory, each
+/// function becomes a wasm function whose stack slots are locals, and tail
+/// calls use `return_call_indirect`.
+///
+/// The module imports `print`, `fail`, `read_line` and `take_line` from
+/// the host module `rinha`, and exports its `memory` and a `run` function
+/// running the program. Like [`crate::emit_c::emit_c`], it never frees
+/// memory, does not memoize calls and has no natives.
+pub fn emit_wasm(program: &CompiledProgram) -> Result<Vec<u8>> {
+    let captured: Vec<usize> = program
+        .functions
+        .iter()
+        .map(|function| function.captured.len())
+        .collect();
+    let tables = Tables {
+        constants: program.constants.len(),
+        functions: &captured,
+        identifiers: program.identifiers.len(),
+    };
+
+    let mut data = Data::new(program.identifiers.len());
+    let mut types = Types::default();
+
+    let constants = program
+        .constants
+        .iter()
+        .map(|constant| match constant {
+            Constant::Bool(b) => *b as i64,
+            Constant::Integer(i) => INTEGER << 32 | *i as u32 as i64,
+            Constant::String(s) => STRING << 32 | data.string(s.as_bytes()) as i64,
+        })
+        .collect::<Vec<_>>();
+
+    let mut imports = ImportSection::new();
+    for (name, params, results) in [
+        ("print", &[ValType::I32, ValType::I32][..], &[][..]),
+        ("fail", &[ValType::I32, ValType::I32], &[]),
+        ("read_line", &[], &[ValType::I32]),
+        ("take_line", &[ValType::I32], &[]),
+    ] {
+        let ty = types.index(params, results);
+        imports.import("rinha", name, EntityType::Function(ty));
+    }
+
+    let mut functions = FunctionSection::new();
+    let mut code = CodeSection::new();
+
+    for runtime in Runtime::ALL {
+        let (params, results) = runtime.signature();
+        functions.function(types.index(params, results));
+        code.function(&runtime_function(runtime, &mut data));
+    }
+
+    let first = HOST_FUNCTIONS + Runtime::ALL.len() as u32;
+    let lowering = Lowering {
+        program,
+        constants: &constants,
+    };
+
+    for function in &program.functions {
+        let heights = stack_heights(&function.bytecode, function.arity as usize, tables)?;
+        functions.function(types.function(function.arity));
+        code.function(&lowering.body(
+            &function.bytecode,
+            &heights,
+            function.arity,
+            &function.captured,
+            &mut data,
+            &mut types,
+        ));
+    }
+
+    let heights = stack_heights(&program.script, 0, tables)?;
+    let script = first + program.functions.len() as u32;
+    functions.function(types.function(0));
+    code.function(&lowering.body(&program.script, &heights, 0, &[], &mut data, &mut types));
+
+    let mut run = Function::new([]);
+    run.instructions().i32_const(0).call(script).drop().end();
+    functions.function(types.index(&[], &[]));
+    code.function(&run);
+
+    let heap = (data.bytes.len() as u32).next_multiple_of(8);
+
+    let mut type_section = TypeSection::new();
+    for (params, results) in &types.types {
+        type_section
+            .ty()
+            .function(params.iter().copied(), results.iter().copied());
+    }
+
+    let count = program.functions.len() as u64;
+    let mut table = TableSection::new();
+    table.table(TableType {
+        element_type: RefType::FUNCREF,
+        table64: false,
+        minimum: count,
+        maximum: Some(count),
+        shared: false,
+    });
+
+    let mut memory = MemorySection::new();
+    memory.memory(MemoryType {
+        minimum: (heap / WASM_PAGE + 1) as u64,
+        maximum: None,
+        memory64: false,
+        shared: false,
+        page_size_log2: None,
+    });
+
+    let mut globals = GlobalSection::new();
+    globals.global(
+        GlobalType {
+            val_type: ValType::I32,
+            mutable: true,
+            shared: false,
+        },
+        &ConstExpr::i32_const(heap as i32),
+    );
+
+    let mut exports = ExportSection::new();
+    exports.export("memory", ExportKind::Memory, 0);
+    exports.export("run", ExportKind::Func, script + 1);
+
+    let indices: Vec<u32> = (first..script).collect();
+    let mut elements = ElementSection::new();
+    elements.active(
+        Some(0),
+        &ConstExpr::i32_const(0),
+        Elements::Functions(Cow::Borrowed(&indices)),
+    );
+
+    let mut data_section = DataSection::new();
+    data_section.active(0, &ConstExpr::i32_const(0), data.bytes.iter().copied());
+
+    let mut module = Module::new();
+    module
+        .section(&type_section)
+        .section(&imports)
+        .section(&functions)
+        .section(&table)
+        .section(&memory)
+        .section(&globals)
+        .section(&exports)
+        .section(&elements)
+        .section(&code)
+        .section(&data_section);
+
+    Ok(module.finish())
+}
+
+fn memory(offset: u32, align: u32) -> MemArg {
+    MemArg {
+        offset: offset as u64,
+        align,
+        memory_index: 0,
+    }
+}
+
+/// Replaces the value on top of the wasm stack with its tag.
+fn tag(i: &mut InstructionSink) {
+    i.i64_const(32).i64_shr_u().i32_wrap_i64();
+}
+
+/// Replaces the i32 on top of the wasm stack with a value tagged `tag`.
+fn tagged(i: &mut InstructionSink, tag: i64) {
+    i.i64_extend_i32_u();
+    if tag != BOOL {
+        i.i64_const(tag << 32).i64_or();
+    }
+}
+
+/// Fails with the string object at `message`.
+fn fail(i: &mut InstructionSink, message: u32) {
+    i.i32_const(message as i32)
+        .call(Runtime::Fail.index())
+        .unreachable();
+}
+
+/// Pushes the tuple depth of the value in `local`, which is zero unless it
+/// is a tuple.
+fn depth(i: &mut InstructionSink, local: u32) {
+    i.local_get(local);
+    tag(i);
+    i.i32_const(TUPLE as i32)
+        .i32_eq()
+        .if_(BlockType::Result(ValType::I32))
+        .local_get(local)
+        .i32_wrap_i64()
+        .i32_load(memory(16, 2))
+        .else_()
+        .i32_const(0)
+        .end();
+}
+
+/// Leaves whether the byte on top of the wasm stack is ASCII whitespace,
+/// using `scratch` as a temporary.
+fn is_space(i: &mut InstructionSink, scratch: u32) {
+    i.local_tee(scratch)
+        .i32_const(b' ' as i32)
+        .i32_eq()
+        .local_get(scratch)
+        .i32_const(b'\t' as i32)
+        .i32_sub()
+        .i32_const(4)
+        .i32_le_u()
+        .i32_or();
+}
+
+/// The body of a runtime function. Errors report the same messages as the
+/// VM.
+fn runtime_function(runtime: Runtime, data: &mut Data) -> Function {
+    use ValType::{I32, I64};
+
+    let locals: &[(u32, ValType)] = match runtime {
+        Runtime::EmitInteger => &[(1, I64), (3, I32)],
+        Runtime::Write | Runtime::Print | Runtime::Tuple => &[(2, I32)],
+        Runtime::Show => &[(1, I32)],
+        Runtime::Add => &[(2, I32)],
+        Runtime::Equals => &[(5, I32)],
+        Runtime::Compare => &[(7, I32)],
+        Runtime::ReadLine => &[(2, I32)],
+        Runtime::ReadInt => &[(6, I32), (1, I64)],
+        _ => &[],
+    };
+    let mut function = Function::new(locals.iter().copied());
+    let mut i = function.instructions();
+
+    match runtime {
+        // Grows memory to fit `size` more bytes past the top of the heap.
+        Runtime::Reserve => {
+            let out_of_memory = data.string(b"Out of memory.");
+            i.global_get(HEAP_TOP)
+                .local_get(0)
+                .i32_add()
+                .memory_size(0)
+                .i32_const(16)
+                .i32_shl()
+                .i32_gt_u()
+                .if_(BlockType::Empty)
+                .global_get(HEAP_TOP)
+                .local_get(0)
+                .i32_add()
+                .i32_const(16)
+                .i32_shr_u()
+                .i32_const(1)
+                .i32_add()
+                .memory_size(0)
+                .i32_sub()
+                .memory_grow(0)
+                .i32_const(-1)
+                .i32_eq()
+                .if_(BlockType::Empty);
+            fail(&mut i, out_of_memory);
+            i.end().end();
+        }
+        Runtime::Alloc => {
+            i.global_get(HEAP_TOP)
+                .i32_const(7)
+                .i32_add()
+                .i32_const(-8)
+                .i32_and()
+                .global_set(HEAP_TOP)
+                .local_get(0)
+                .call(Runtime::Reserve.index())
+                .global_get(HEAP_TOP)
+                .global_get(HEAP_TOP)
+                .local_get(0)
+                .i32_add()
+                .global_set(HEAP_TOP);
+        }
+        Runtime::Fail => {
+            i.local_get(0)
+                .i32_const(4)
+                .i32_add()
+                .local_get(0)
+                .i32_load(memory(0, 2))
+                .call(HOST_FAIL)
+                .unreachable();
+        }
+        // Text is written at the top of the heap, where `Print` and
+        // `EndString` pick it up.
+        Runtime::EmitByte => {
+            i.i32_const(1)
+                .call(Runtime::Reserve.index())
+                .global_get(HEAP_TOP)
+                .local_get(0)
+                .i32_store8(memory(0, 0))
+                .global_get(HEAP_TOP)
+                .i32_const(1)
+                .i32_add()
+                .global_set(HEAP_TOP);
+        }
+        Runtime::EmitBytes => {
+            i.local_get(1)
+                .call(Runtime::Reserve.index())
+                .global_get(HEAP_TOP)
+                .local_get(0)
+                .local_get(1)
+                .memory_copy(0, 0)
+                .global_get(HEAP_TOP)
+                .local_get(1)
+                .i32_add()
+                .global_set(HEAP_TOP);
+        }
+        Runtime::EmitString => {
+            i.local_get(0)
+                .i32_const(4)
+                .i32_add()
+                .local_get(0)
+                .i32_load(memory(0, 2))
+                .call(Runtime::EmitBytes.index());
+        }
+        // Writes the digits from the least significant one and reverses
+        // them in place.
+        Runtime::EmitInteger => {
+            let (magnitude, start, end, byte) = (1, 2, 3, 4);
+            i.local_get(0)
+                .i32_const(0)
+                .i32_lt_s()
+                .if_(BlockType::Empty)
+                .i32_const(b'-' as i32)
+                .call(Runtime::EmitByte.index())
+                .end()
+                .local_get(0)
+                .i64_extend_i32_s()
+                .local_set(magnitude)
+                .local_get(magnitude)
+                .i64_const(0)
+                .i64_lt_s()
+                .if_(BlockType::Empty)
+                .i64_const(0)
+                .local_get(magnitude)
+                .i64_sub()
+                .local_set(magnitude)
+                .end()
+                .global_get(HEAP_TOP)
+                .local_set(start)
+                .loop_(BlockType::Empty)
+                .local_get(magnitude)
+                .i64_const(10)
+                .i64_rem_u()
+                .i32_wrap_i64()
+                .i32_const(b'0' as i32)
+                .i32_add()
+                .call(Runtime::EmitByte.index())
+                .local_get(magnitude)
+                .i64_const(10)
+                .i64_div_u()
+                .local_tee(magnitude)
+                .i64_const(0)
+                .i64_ne()
+                .br_if(0)
+                .end()
+                .global_get(HEAP_TOP)
+                .i32_const(1)
+                .i32_sub()
+                .local_set(end)
+                .block(BlockType::Empty)
+                .loop_(BlockType::Empty)
+                .local_get(start)
+                .local_get(end)
+                .i32_ge_u()
+                .br_if(1)
+                .local_get(start)
+                .i32_load8_u(memory(0, 0))
+                .local_set(byte)
+                .local_get(start)
+                .local_get(end)
+                .i32_load8_u(memory(0, 0))
+                .i32_store8(memory(0, 0))
+                .local_get(end)
+                .local_get(byte)
+                .i32_store8(memory(0, 0))
+                .local_get(start)
+                .i32_const(1)
+                .i32_add()
+                .local_set(start)
+                .local_get(end)
+                .i32_const(1)
+                .i32_sub()
+                .local_set(end)
+                .br(0)
+                .end()
+                .end();
+        }
+        Runtime::Write => {
+            let (payload, value_tag) = (1, 2);
+            let truth = data.string(b"true");
+            let falsehood = data.string(b"false");
+            let separator = data.string(b", ");
+            let closure = data.string(b"<#closure");
+
+            i.local_get(0)
+                .i32_wrap_i64()
+                .local_set(payload)
+                .local_get(0);
+            tag(&mut i);
+            i.local_set(value_tag)
+                .local_get(value_tag)
+                .i32_eqz()
+                .if_(BlockType::Empty)
+                .i32_const(truth as i32)
+                .i32_const(falsehood as i32)
+                .local_get(payload)
+                .select()
+                .call(Runtime::EmitString.index())
+                .return_()
+                .end()
+                .local_get(value_tag)
+                .i32_const(INTEGER as i32)
+                .i32_eq()
+                .if_(BlockType::Empty)
+                .local_get(payload)
+                .call(Runtime::EmitInteger.index())
+                .return_()
+                .end()
+                .local_get(value_tag)
+                .i32_const(STRING as i32)
+                .i32_eq()
+                .if_(BlockType::Empty)
+                .local_get(payload)
+                .call(Runtime::EmitString.index())
+                .return_()
+                .end()
+                .local_get(value_tag)
+                .i32_const(TUPLE as i32)
+                .i32_eq()
+                .if_(BlockType::Empty)
+                .i32_const(b'(' as i32)
+                .call(Runtime::EmitByte.index())
+                .local_get(payload)
+                .i64_load(memory(0, 3))
+                .call(Runtime::Write.index())
+                .i32_const(separator as i32)
+                .call(Runtime::EmitString.index())
+                .local_get(payload)
+                .i64_load(memory(8, 3))
+                .call(Runtime::Write.index())
+                .i32_const(b')' as i32)
+                .call(Runtime::EmitByte.index())
+                .return_()
+                .end()
+                .i32_const(closure as i32)
+                .call(Runtime::EmitString.index())
+                .local_get(payload)
+                .i32_load(memory(8, 2))
+                .if_(BlockType::Empty)
+                .i32_const(b' ' as i32)
+                .call(Runtime::EmitByte.index())
+                .local_get(payload)
+                .i32_load(memory(8, 2))
+                .call(Runtime::EmitString.index())
+                .end()
+                .i32_const(b'>' as i32)
+                .call(Runtime::EmitByte.index());
+        }
+        // Writes the value at the top of the heap, hands it to the host
+        // and frees it again.
+        Runtime::Print => {
+            let start = 1;
+            i.global_get(HEAP_TOP)
+                .local_set(start)
+                .local_get(0)
+                .call(Runtime::Write.index())
+                .local_get(start)
+                .global_get(HEAP_TOP)
+                .local_get(start)
+                .i32_sub()
+                .call(HOST_PRINT)
+                .local_get(start)
+                .global_set(HEAP_TOP);
+        }
+        // Starts a string object at the top of the heap, which the text
+        // emitted until `EndString` becomes the contents of.
+        Runtime::BeginString => {
+            i.global_get(HEAP_TOP)
+                .i32_const(7)
+                .i32_add()
+                .i32_const(-8)
+                .i32_and()
+                .global_set(HEAP_TOP)
+                .i32_const(4)
+                .call(Runtime::Reserve.index())
+                .global_get(HEAP_TOP)
+                .global_get(HEAP_TOP)
+                .i32_const(4)
+                .i32_add()
+                .global_set(HEAP_TOP);
+        }
+        Runtime::EndString => {
+            i.local_get(0)
+                .global_get(HEAP_TOP)
+                .local_get(0)
+                .i32_sub()
+                .i32_const(4)
+                .i32_sub()
+                .i32_store(memory(0, 2))
+                .local_get(0);
+        }
+        Runtime::Integers => {
+            let message = data.string(b"Operands must be both integers.");
+            i.local_get(0);
+            tag(&mut i);
+            i.i32_const(INTEGER as i32).i32_ne().local_get(1);
+            tag(&mut i);
+            i.i32_const(INTEGER as i32)
+                .i32_ne()
+                .i32_or()
+                .if_(BlockType::Empty);
+            fail(&mut i, message);
+            i.end();
+        }
+        // Adds integers, or concatenates when either side is a string.
+        Runtime::Add => {
+            let (lhs_tag, rhs_tag) = (2, 3);
+            let message = data.string(b"Wrong types for add.");
+            i.local_get(0);
+            tag(&mut i);
+            i.local_set(lhs_tag).local_get(1);
+            tag(&mut i);
+            i.local_set(rhs_tag)
+                .local_get(lhs_tag)
+                .i32_const(INTEGER as i32)
+                .i32_eq()
+                .local_get(rhs_tag)
+                .i32_const(INTEGER as i32)
+                .i32_eq()
+                .i32_and()
+                .if_(BlockType::Empty)
+                .local_get(0)
+                .i32_wrap_i64()
+                .local_get(1)
+                .i32_wrap_i64()
+                .i32_add();
+            tagged(&mut i, INTEGER);
+            i.return_()
+                .end()
+                // Only integers and strings have a tag of 1 or 2.
+                .local_get(lhs_tag)
+                .i32_const(1)
+                .i32_sub()
+                .i32_const(1)
+                .i32_gt_u()
+                .local_get(rhs_tag)
+                .i32_const(1)
+                .i32_sub()
+                .i32_const(1)
+                .i32_gt_u()
+                .i32_or()
+                .if_(BlockType::Empty);
+            fail(&mut i, message);
+            i.end()
+                .call(Runtime::BeginString.index())
+                .local_set(lhs_tag)
+                .local_get(0)
+                .call(Runtime::Write.index())
+                .local_get(1)
+                .call(Runtime::Write.index())
+                .local_get(lhs_tag)
+                .call(Runtime::EndString.index());
+            tagged(&mut i, STRING);
+        }
+        Runtime::Sub
+        | Runtime::Mul
+        | Runtime::BitAnd
+        | Runtime::BitOr
+        | Runtime::BitXor
+        | Runtime::Shl
+        | Runtime::Shr => {
+            i.local_get(0)
+                .local_get(1)
+                .call(Runtime::Integers.index())
+                .local_get(0)
+                .i32_wrap_i64()
+                .local_get(1)
+                .i32_wrap_i64();
+            match runtime {
+                Runtime::Sub => tagged(i.i32_sub(), INTEGER),
+                Runtime::Mul => tagged(i.i32_mul(), INTEGER),
+                Runtime::BitAnd => tagged(i.i32_and(), INTEGER),
+                Runtime::BitOr => tagged(i.i32_or(), INTEGER),
+                Runtime::BitXor => tagged(i.i32_xor(), INTEGER),
+                Runtime::Shl => tagged(i.i32_shl(), INTEGER),
+                _ => tagged(i.i32_shr_s(), INTEGER),
+            }
+        }
+        Runtime::Lt | Runtime::Gt | Runtime::Lte | Runtime::Gte => {
+            i.local_get(0)
+                .local_get(1)
+                .call(Runtime::Compare.index())
+                .i32_const(0);
+            match runtime {
+                Runtime::Lt => tagged(i.i32_lt_s(), BOOL),
+                Runtime::Gt => tagged(i.i32_gt_s(), BOOL),
+                Runtime::Lte => tagged(i.i32_le_s(), BOOL),
+                _ => tagged(i.i32_ge_s(), BOOL),
+            }
+        }
+        Runtime::Div | Runtime::Rem => {
+            let message = match runtime {
+                Runtime::Div => data.string(b"Attempted to divide by zero"),
+                _ => data.string(b"Attempted to take remainder by zero"),
+            };
+            i.local_get(0)
+                .local_get(1)
+                .call(Runtime::Integers.index())
+                .local_get(1)
+                .i32_wrap_i64()
+                .i32_eqz()
+                .if_(BlockType::Empty);
+            fail(&mut i, message);
+            // Dividing by -1 negates, wrapping around for the smallest
+            // integer, where `i32.div_s` would trap.
+            i.end()
+                .local_get(1)
+                .i32_wrap_i64()
+                .i32_const(-1)
+                .i32_eq()
+                .if_(BlockType::Result(ValType::I32));
+            match runtime {
+                Runtime::Div => i.i32_const(0).local_get(0).i32_wrap_i64().i32_sub(),
+                _ => i.i32_const(0),
+            };
+            i.else_()
+                .local_get(0)
+                .i32_wrap_i64()
+                .local_get(1)
+                .i32_wrap_i64();
+            match runtime {
+                Runtime::Div => i.i32_div_s(),
+                _ => i.i32_rem_s(),
+            };
+            tagged(i.end(), INTEGER);
+        }
+        // Booleans are their own payload with a tag of zero.
+        Runtime::And | Runtime::Or => {
+            let message = data.string(b"Operands must be both integers.");
+            i.local_get(0).local_get(1).i64_or();
+            tag(&mut i);
+            i.if_(BlockType::Empty);
+            fail(&mut i, message);
+            i.end().local_get(0).local_get(1);
+            match runtime {
+                Runtime::And => i.i64_and(),
+                _ => i.i64_or(),
+            };
+        }
+        // Structural, except that closures are only equal to themselves.
+        Runtime::Equals => {
+            let (value_tag, lhs, rhs, length, index) = (2, 3, 4, 5, 6);
+            i.local_get(0);
+            tag(&mut i);
+            i.local_tee(value_tag).local_get(1);
+            tag(&mut i);
+            i.i32_ne()
+                .if_(BlockType::Empty)
+                .i32_const(0)
+                .return_()
+                .end()
+                .local_get(value_tag)
+                .i32_const(STRING as i32)
+                .i32_eq()
+                .if_(BlockType::Empty)
+                .local_get(0)
+                .i32_wrap_i64()
+                .local_set(lhs)
+                .local_get(1)
+                .i32_wrap_i64()
+                .local_set(rhs)
+                .local_get(lhs)
+                .i32_load(memory(0, 2))
+                .local_tee(length)
+                .local_get(rhs)
+                .i32_load(memory(0, 2))
+                .i32_ne()
+                .if_(BlockType::Empty)
+                .i32_const(0)
+                .return_()
+                .end()
+                .loop_(BlockType::Empty)
+                .local_get(index)
+                .local_get(length)
+                .i32_ge_u()
+                .if_(BlockType::Empty)
+                .i32_const(1)
+                .return_()
+                .end()
+                .local_get(lhs)
+                .local_get(index)
+                .i32_add()
+                .i32_load8_u(memory(4, 0))
+                .local_get(rhs)
+                .local_get(index)
+                .i32_add()
+                .i32_load8_u(memory(4, 0))
+                .i32_ne()
+                .if_(BlockType::Empty)
+                .i32_const(0)
+                .return_()
+                .end()
+                .local_get(index)
+                .i32_const(1)
+                .i32_add()
+                .local_set(index)
+                .br(0)
+                .end()
+                .unreachable()
+                .end()
+                .local_get(value_tag)
+                .i32_const(TUPLE as i32)
+                .i32_eq()
+                .if_(BlockType::Empty)
+                .local_get(0)
+                .i32_wrap_i64()
+                .i64_load(memory(0, 3))
+                .local_get(1)
+                .i32_wrap_i64()
+                .i64_load(memory(0, 3))
+                .call(Runtime::Equals.index())
+                .i32_eqz()
+                .if_(BlockType::Empty)
+                .i32_const(0)
+                .return_()
+                .end()
+                .local_get(0)
+                .i32_wrap_i64()
+                .i64_load(memory(8, 3))
+                .local_get(1)
+                .i32_wrap_i64()
+                .i64_load(memory(8, 3))
+                .call(Runtime::Equals.index())
+                .return_()
+                .end()
+                .local_get(0)
+                .local_get(1)
+                .i64_eq();
+        }
+        // Orders integers by value and strings by their bytes, leaving -1, 0
+        // or 1.
+        Runtime::Compare => {
+            let (value_tag, lhs, rhs, length, index, lhs_byte, rhs_byte) = (2, 3, 4, 5, 6, 7, 8);
+            let message = data.string(b"Operands must be both integers or both strings.");
+            i.local_get(0);
+            tag(&mut i);
+            i.local_tee(value_tag).local_get(1);
+            tag(&mut i);
+            i.i32_ne().if_(BlockType::Empty);
+            fail(&mut i, message);
+            i.end()
+                .local_get(0)
+                .i32_wrap_i64()
+                .local_set(lhs)
+                .local_get(1)
+                .i32_wrap_i64()
+                .local_set(rhs)
+                .local_get(value_tag)
+                .i32_const(INTEGER as i32)
+                .i32_eq()
+                .if_(BlockType::Empty)
+                .local_get(lhs)
+                .local_get(rhs)
+                .i32_gt_s()
+                .local_get(lhs)
+                .local_get(rhs)
+                .i32_lt_s()
+                .i32_sub()
+                .return_()
+                .end()
+                .local_get(value_tag)
+                .i32_const(STRING as i32)
+                .i32_ne()
+                .if_(BlockType::Empty);
+            fail(&mut i, message);
+            i.end()
+                // The bytes both strings have, then their lengths.
+                .local_get(lhs)
+                .i32_load(memory(0, 2))
+                .local_get(rhs)
+                .i32_load(memory(0, 2))
+                .local_get(lhs)
+                .i32_load(memory(0, 2))
+                .local_get(rhs)
+                .i32_load(memory(0, 2))
+                .i32_lt_u()
+                .select()
+                .local_set(length)
+                .loop_(BlockType::Empty)
+                .local_get(index)
+                .local_get(length)
+                .i32_ge_u()
+                .if_(BlockType::Empty)
+                .local_get(lhs)
+                .i32_load(memory(0, 2))
+                .local_get(rhs)
+                .i32_load(memory(0, 2))
+                .i32_gt_u()
+                .local_get(lhs)
+                .i32_load(memory(0, 2))
+                .local_get(rhs)
+                .i32_load(memory(0, 2))
+                .i32_lt_u()
+                .i32_sub()
+                .return_()
+                .end()
+                .local_get(lhs)
+                .local_get(index)
+                .i32_add()
+                .i32_load8_u(memory(4, 0))
+                .local_set(lhs_byte)
+                .local_get(rhs)
+                .local_get(index)
+                .i32_add()
+                .i32_load8_u(memory(4, 0))
+                .local_set(rhs_byte)
+                .local_get(lhs_byte)
+                .local_get(rhs_byte)
+                .i32_ne()
+                .if_(BlockType::Empty)
+                .local_get(lhs_byte)
+                .local_get(rhs_byte)
+                .i32_gt_u()
+                .local_get(lhs_byte)
+                .local_get(rhs_byte)
+                .i32_lt_u()
+                .i32_sub()
+                .return_()
+                .end()
+                .local_get(index)
+                .i32_const(1)
+                .i32_add()
+                .local_set(index)
+                .br(0)
+                .end()
+                .unreachable();
+        }
+        // A tuple is its first and second values followed by its depth.
+        Runtime::Tuple => {
+            let (first, second) = (2, 3);
+            let message = data.string(
+                format!(
+                    "Tuple nesting depth {} exceeds the limit of {DEFAULT_MAX_TUPLE_DEPTH}.",
+                    DEFAULT_MAX_TUPLE_DEPTH + 1
+                )
+                .as_bytes(),
+            );
+            depth(&mut i, 0);
+            i.local_set(first);
+            depth(&mut i, 1);
+            i.local_set(second)
+                .local_get(second)
+                .local_get(first)
+                .local_get(second)
+                .local_get(first)
+                .i32_gt_u()
+                .select()
+                .i32_const(1)
+                .i32_add()
+                .local_tee(first)
+                .i32_const(DEFAULT_MAX_TUPLE_DEPTH as i32)
+                .i32_gt_u()
+                .if_(BlockType::Empty);
+            fail(&mut i, message);
+            i.end()
+                .i32_const(24)
+                .call(Runtime::Alloc.index())
+                .local_tee(second)
+                .local_get(0)
+                .i64_store(memory(0, 3))
+                .local_get(second)
+                .local_get(1)
+                .i64_store(memory(8, 3))
+                .local_get(second)
+                .local_get(first)
+                .i32_store(memory(16, 2))
+                .local_get(second);
+            tagged(&mut i, TUPLE);
+        }
+        Runtime::First | Runtime::Second => {
+            let (message, offset) = match runtime {
+                Runtime::First => (
+                    data.string(b"Tried to compute `first` of a non tuple type."),
+                    0,
+                ),
+                _ => (
+                    data.string(b"Tried to compute `second` of a non tuple type."),
+                    8,
+                ),
+            };
+            i.local_get(0);
+            tag(&mut i);
+            i.i32_const(TUPLE as i32).i32_ne().if_(BlockType::Empty);
+            fail(&mut i, message);
+            i.end()
+                .local_get(0)
+                .i32_wrap_i64()
+                .i64_load(memory(offset, 3));
+        }
+        Runtime::Show => {
+            let start = 1;
+            i.call(Runtime::BeginString.index())
+                .local_set(start)
+                .local_get(0)
+                .call(Runtime::Write.index())
+                .local_get(start)
+                .call(Runtime::EndString.index());
+            tagged(&mut i, STRING);
+        }
+        Runtime::Neg => {
+            let message = data.string(b"Operand must be an integer.");
+            i.local_get(0);
+            tag(&mut i);
+            i.i32_const(INTEGER as i32).i32_ne().if_(BlockType::Empty);
+            fail(&mut i, message);
+            i.end().i32_const(0).local_get(0).i32_wrap_i64().i32_sub();
+            tagged(&mut i, INTEGER);
+        }
+        Runtime::Not => {
+            let message = data.string(b"Operand must be a boolean.");
+            i.local_get(0);
+            tag(&mut i);
+            i.if_(BlockType::Empty);
+            fail(&mut i, message);
+            i.end().local_get(0).i64_const(1).i64_xor();
+        }
+        Runtime::Condition => {
+            let message = data.string(b"Type error: if condition must evaluate to a boolean.");
+            i.local_get(0);
+            tag(&mut i);
+            i.if_(BlockType::Empty);
+            fail(&mut i, message);
+            i.end().local_get(0).i32_wrap_i64();
+        }
+        // A closure is its function's index in the table, its arity, the
+        // address of its name or zero, how many values it captured and
+        // those values.
+        Runtime::Callee => {
+            let not_function = data.string(b"Attempted to call value that is not a function!");
+            let wrong_arity =
+                data.string(b"Attempted to call function with wrong number of arguments.");
+            i.local_get(0);
+            tag(&mut i);
+            i.i32_const(CLOSURE as i32).i32_ne().if_(BlockType::Empty);
+            fail(&mut i, not_function);
+            i.end()
+                .local_get(0)
+                .i32_wrap_i64()
+                .i32_load(memory(4, 2))
+                .local_get(1)
+                .i32_ne()
+                .if_(BlockType::Empty);
+            fail(&mut i, wrong_arity);
+            i.end().local_get(0).i32_wrap_i64();
+        }
+        Runtime::ReadLine => {
+            let (length, string) = (0, 1);
+            i.call(HOST_READ_LINE)
+                .local_set(length)
+                .local_get(length)
+                .i32_const(4)
+                .i32_add()
+                .call(Runtime::Alloc.index())
+                .local_tee(string)
+                .local_get(length)
+                .i32_store(memory(0, 2))
+                .local_get(string)
+                .i32_const(4)
+                .i32_add()
+                .call(HOST_TAKE_LINE)
+                .local_get(string);
+            tagged(&mut i, STRING);
+        }
+        Runtime::ReadInt => {
+            let (line, start, end, negative, valid, byte, integer) = (0, 1, 2, 3, 4, 5, 6);
+            let expected = data.string(b"Expected an integer as input, but read \"");
+            let quote = data.string(b"\".");
+            i.call(Runtime::ReadLine.index())
+                .i32_wrap_i64()
+                .local_tee(line)
+                .i32_const(4)
+                .i32_add()
+                .local_tee(start)
+                .local_get(line)
+                .i32_load(memory(0, 2))
+                .i32_add()
+                .local_set(end)
+                .block(BlockType::Empty)
+                .loop_(BlockType::Empty)
+                .local_get(start)
+                .local_get(end)
+                .i32_ge_u()
+                .br_if(1)
+                .local_get(start)
+                .i32_load8_u(memory(0, 0));
+            is_space(&mut i, byte);
+            i.i32_eqz()
+                .br_if(1)
+                .local_get(start)
+                .i32_const(1)
+                .i32_add()
+                .local_set(start)
+                .br(0)
+                .end()
+                .end()
+                .block(BlockType::Empty)
+                .loop_(BlockType::Empty)
+                .local_get(end)
+                .local_get(start)
+                .i32_le_u()
+                .br_if(1)
+                .local_get(end)
+                .i32_const(1)
+                .i32_sub()
+                .i32_load8_u(memory(0, 0));
+            is_space(&mut i, byte);
+            i.i32_eqz()
+                .br_if(1)
+                .local_get(end)
+                .i32_const(1)
+                .i32_sub()
+                .local_set(end)
+                .br(0)
+                .end()
+                .end()
+                .local_get(start)
+                .local_get(end)
+                .i32_lt_u()
+                .if_(BlockType::Empty)
+                .local_get(start)
+                .i32_load8_u(memory(0, 0))
+                .local_tee(byte)
+                .i32_const(b'-' as i32)
+                .i32_eq()
+                .local_get(byte)
+                .i32_const(b'+' as i32)
+                .i32_eq()
+                .i32_or()
+                .if_(BlockType::Empty)
+                .local_get(byte)
+                .i32_const(b'-' as i32)
+                .i32_eq()
+                .local_set(negative)
+                .local_get(start)
+                .i32_const(1)
+                .i32_add()
+                .local_set(start)
+                .end()
+                .end()
+                .local_get(start)
+                .local_get(end)
+                .i32_lt_u()
+                .local_set(valid)
+                .block(BlockType::Empty)
+                .loop_(BlockType::Empty)
+                .local_get(valid)
+                .i32_eqz()
+                .br_if(1)
+                .local_get(start)
+                .local_get(end)
+                .i32_ge_u()
+                .br_if(1)
+                .local_get(start)
+                .i32_load8_u(memory(0, 0))
+                .i32_const(b'0' as i32)
+                .i32_sub()
+                .local_tee(byte)
+                .i32_const(9)
+                .i32_le_u()
+                .local_get(integer)
+                .i64_const(i32::MAX as i64 + 1)
+                .i64_le_s()
+                .i32_and()
+                .local_set(valid)
+                .local_get(integer)
+                .i64_const(10)
+                .i64_mul()
+                .local_get(byte)
+                .i64_extend_i32_u()
+                .i64_add()
+                .local_set(integer)
+                .local_get(start)
+                .i32_const(1)
+                .i32_add()
+                .local_set(start)
+                .br(0)
+                .end()
+                .end()
+                .local_get(negative)
+                .if_(BlockType::Empty)
+                .i64_const(0)
+                .local_get(integer)
+                .i64_sub()
+                .local_set(integer)
+                .end()
+                .local_get(valid)
+                .i32_eqz()
+                .local_get(integer)
+                .i64_const(i32::MIN as i64)
+                .i64_lt_s()
+                .i32_or()
+                .local_get(integer)
+                .i64_const(i32::MAX as i64)
+                .i64_gt_s()
+                .i32_or()
+                .if_(BlockType::Empty)
+                .call(Runtime::BeginString.index())
+                .local_set(start)
+                .i32_const(expected as i32)
+                .call(Runtime::EmitString.index())
+                .local_get(line)
+                .call(Runtime::EmitString.index())
+                .i32_const(quote as i32)
+                .call(Runtime::EmitString.index())
+                .local_get(start)
+                .call(Runtime::EndString.index())
+                .call(Runtime::Fail.index())
+                .unreachable()
+                .end()
+                .local_get(integer)
+                .i32_wrap_i64();
+            tagged(&mut i, INTEGER);
+        }
+    }
+
+    i.end();
+    function
+}
+
+/// What lowering a function's bytecode needs to know about the module.
+struct Lowering<'a> {
+    program: &'a CompiledProgram,
+    /// The value of each constant.
+    constants: &'a [i64],
+}
+
+impl Lowering<'_> {
+    /// A wasm function running `bytecode`, where `heights` are the stack
+    /// heights the verifier found. Its first local is the closure being
+    /// run, and stack slot `n` is local `n + 1`, so the arguments are the
+    /// first slots. Jumps only go forward, so each target ends a block that
+    /// is opened at the start of the function.
+    fn body(
+        &self,
+        bytecode: &[Instruction],
+        heights: &[Option<usize>],
+        arity: u16,
+        captured: &[String],
+        data: &mut Data,
+        types: &mut Types,
+    ) -> Function {
+        let program = self.program;
+        let slots = bytecode
+            .iter()
+            .zip(heights)
+            .filter_map(|(instruction, height)| {
+                let (popped, pushed) = match instruction {
+                    Instruction::Closure(index) => {
+                        (program.functions[*index as usize].captured.len(), 1)
+                    }
+                    _ => instruction.stack_effect(),
+                };
+                Some((*height)? - popped + pushed)
+            })
+            .max()
+            .unwrap_or(0)
+            .max(arity as usize);
+
+        let slot = |n: usize| n as u32 + 1;
+        let scratch = slot(slots);
+
+        let reachable = |(address, _): &(usize, &Instruction)| heights[*address].is_some();
+        // A `JumpBack` starts the function over, so a loop around the whole
+        // body takes it there instead of a block.
+        let looping = bytecode
+            .iter()
+            .enumerate()
+            .filter(reachable)
+            .any(|(_, instruction)| matches!(instruction, Instruction::JumpBack(_)));
+        let mut open: Vec<usize> = bytecode
+            .iter()
+            .enumerate()
+            .filter(reachable)
+            .filter(|(_, instruction)| !matches!(instruction, Instruction::JumpBack(_)))
+            .filter_map(|(address, instruction)| jump_target(address, instruction))
+            .collect();
+        open.sort_unstable();
+        open.dedup();
+        let depth = |open: &[usize], target: usize| {
+            open.iter()
+                .position(|&open| open == target)
+                .expect("Every jump target has a block.") as u32
+        };
+
+        let mut function = Function::new([
+            ((slots - arity as usize) as u32, ValType::I64),
+            (1, ValType::I32),
+        ]);
+        let mut i = function.instructions();
+        if looping {
+            i.loop_(BlockType::Empty);
+        }
+        for _ in &open {
+            i.block(BlockType::Empty);
+        }
+
+        for (address, instruction) in bytecode.iter().enumerate() {
+            if open.first() == Some(&address) {
+                i.end();
+                open.remove(0);
+            }
+            let Some(h) = heights[address] else {
+                continue;
+            };
+
+            let mut binary = |runtime: Runtime| {
+                i.local_get(slot(h - 2))
+                    .local_get(slot(h - 1))
+                    .call(runtime.index())
+                    .local_set(slot(h - 2));
+            };
+
+            match instruction {
+                Instruction::Constant(index) => {
+                    i.i64_const(self.constants[*index as usize])
+                        .local_set(slot(h));
+                }
+                Instruction::True => {
+                    i.i64_const(1).local_set(slot(h));
+                }
+                Instruction::False => {
+                    i.i64_const(0).local_set(slot(h));
+                }
+                Instruction::Add => binary(Runtime::Add),
+                Instruction::Sub => binary(Runtime::Sub),
+                Instruction::Mul => binary(Runtime::Mul),
+                Instruction::Div => binary(Runtime::Div),
+                Instruction::Rem => binary(Runtime::Rem),
+                Instruction::BitAnd => binary(Runtime::BitAnd),
+                Instruction::BitOr => binary(Runtime::BitOr),
+                Instruction::BitXor => binary(Runtime::BitXor),
+                Instruction::Shl => binary(Runtime::Shl),
+                Instruction::Shr => binary(Runtime::Shr),
+                Instruction::Gt => binary(Runtime::Gt),
+                Instruction::Lt => binary(Runtime::Lt),
+                Instruction::Gte => binary(Runtime::Gte),
+                Instruction::Lte => binary(Runtime::Lte),
+                Instruction::And => binary(Runtime::And),
+                Instruction::Or => binary(Runtime::Or),
+                Instruction::Tuple => binary(Runtime::Tuple),
+                Instruction::Eq | Instruction::Neq => {
+                    i.local_get(slot(h - 2))
+                        .local_get(slot(h - 1))
+                        .call(Runtime::Equals.index());
+                    if let Instruction::Neq = instruction {
+                        i.i32_eqz();
+                    }
+                    i.i64_extend_i32_u().local_set(slot(h - 2));
+                }
+                Instruction::First | Instruction::Second => {
+                    let runtime = match instruction {
+                        Instruction::First => Runtime::First,
+                        _ => Runtime::Second,
+                    };
+                    i.local_get(slot(h - 1))
+                        .call(runtime.index())
+                        .local_set(slot(h - 1));
+                }
+                Instruction::Print => {
+                    i.local_get(slot(h - 1)).call(Runtime::Print.index());
+                }
+                Instruction::Neg | Instruction::Not | Instruction::Show => {
+                    let runtime = match instruction {
+                        Instruction::Neg => Runtime::Neg,
+                        Instruction::Not => Runtime::Not,
+                        _ => Runtime::Show,
+                    };
+                    i.local_get(slot(h - 1))
+                        .call(runtime.index())
+                        .local_set(slot(h - 1));
+                }
+                Instruction::Dup => {
+                    i.local_get(slot(h - 1)).local_set(slot(h));
+                }
+                Instruction::GlobalGet(index) => {
+                    let name = &program.identifiers[*index as usize];
+                    match captured.iter().position(|captured| captured == name) {
+                        Some(position) => {
+                            i.local_get(0)
+                                .i64_load(memory(16 + 8 * position as u32, 3))
+                                .local_set(slot(h));
+                        }
+                        None => {
+                            let message =
+                                data.string(format!("Unknown variable {name}.").as_bytes());
+                            i.i32_const(0)
+                                .i32_load8_u(memory(self.defined(*index), 0))
+                                .i32_eqz()
+                                .if_(BlockType::Empty);
+                            fail(&mut i, message);
+                            i.end()
+                                .i32_const(0)
+                                .i64_load(memory(GLOBALS + 8 * *index, 3))
+                                .local_set(slot(h));
+                        }
+                    }
+                }
+                Instruction::GlobalSet(index) => {
+                    i.i32_const(0)
+                        .local_get(slot(h - 1))
+                        .i64_store(memory(GLOBALS + 8 * *index, 3))
+                        .i32_const(0)
+                        .i32_const(1)
+                        .i32_store8(memory(self.defined(*index), 0));
+                }
+                Instruction::LocalGet(local, _) => {
+                    i.local_get(slot(*local as usize)).local_set(slot(h));
+                }
+                Instruction::If(_) => {
+                    let target =
+                        jump_target(address, instruction).expect("An If always has a target.");
+                    i.local_get(slot(h - 1))
+                        .call(Runtime::Condition.index())
+                        .i32_eqz()
+                        .br_if(depth(&open, target));
+                }
+                Instruction::Jump(_) => {
+                    let target =
+                        jump_target(address, instruction).expect("A Jump always has a target.");
+                    i.br(depth(&open, target));
+                }
+                Instruction::JumpBack(_) => {
+                    for parameter in 0..arity as usize {
+                        i.local_get(slot(h - arity as usize + parameter))
+                            .local_set(slot(parameter));
+                    }
+                    i.br(open.len() as u32);
+                }
+                Instruction::Closure(index) => {
+                    let function = &program.functions[*index as usize];
+                    let count = function.captured.len();
+                    let name = match &function.name {
+                        Some(name) => data.string(name.as_bytes()),
+                        None => 0,
+                    };
+
+                    i.i32_const(16 + 8 * count as i32)
+                        .call(Runtime::Alloc.index())
+                        .local_tee(scratch)
+                        .i32_const(*index as i32)
+                        .i32_store(memory(0, 2))
+                        .local_get(scratch)
+                        .i32_const(function.arity as i32)
+                        .i32_store(memory(4, 2))
+                        .local_get(scratch)
+                        .i32_const(name as i32)
+                        .i32_store(memory(8, 2))
+                        .local_get(scratch)
+                        .i32_const(count as i32)
+                        .i32_store(memory(12, 2));
+                    for k in 0..count {
+                        i.local_get(scratch)
+                            .local_get(slot(h - count + k))
+                            .i64_store(memory(16 + 8 * k as u32, 3));
+                    }
+                    i.local_get(scratch);
+                    tagged(&mut i, CLOSURE);
+                    i.local_set(slot(h - count));
+                }
+                Instruction::Call(arity) | Instruction::TailCall(arity) => {
+                    let arity = *arity as usize;
+                    let callee = h - arity - 1;
+                    let arguments: Vec<usize> = (h - arity..h).collect();
+                    Self::call(&mut i, scratch, callee, &arguments);
+                    match instruction {
+                        Instruction::Call(_) => {
+                            i.call_indirect(0, types.function(arity as u16))
+                                .local_set(slot(callee));
+                        }
+                        _ => {
+                            i.return_call_indirect(0, types.function(arity as u16));
+                        }
+                    }
+                }
+                Instruction::LocalGetTailCall(local, _, arity) => {
+                    // The local is the last argument, or the callee when
+                    // there are none.
+                    let arity = *arity as usize;
+                    let local = *local as usize;
+                    let (callee, arguments) = match arity {
+                        0 => (local, vec![]),
+                        _ => {
+                            let mut arguments: Vec<usize> = (h + 1 - arity..h).collect();
+                            arguments.push(local);
+                            (h - arity, arguments)
+                        }
+                    };
+                    Self::call(&mut i, scratch, callee, &arguments);
+                    i.return_call_indirect(0, types.function(arity as u16));
+                }
+                Instruction::LocalGetConstantAdd(local, _, index)
+                | Instruction::LocalGetConstantSub(local, _, index) => {
+                    let runtime = match instruction {
+                        Instruction::LocalGetConstantAdd(..) => Runtime::Add,
+                        _ => Runtime::Sub,
+                    };
+                    i.local_get(slot(*local as usize))
+                        .i64_const(self.constants[*index as usize])
+                        .call(runtime.index())
+                        .local_set(slot(h));
+                }
+                Instruction::ConstantLt(index) => {
+                    i.local_get(slot(h - 1))
+                        .i64_const(self.constants[*index as usize])
+                        .call(Runtime::Lt.index())
+                        .local_set(slot(h - 1));
+                }
+                Instruction::ConstantEq(index) => {
+                    i.local_get(slot(h - 1))
+                        .i64_const(self.constants[*index as usize])
+                        .call(Runtime::Equals.index())
+                        .i64_extend_i32_u()
+                        .local_set(slot(h - 1));
+                }
+                Instruction::Return(_) => {
+                    i.local_get(slot(h - 1)).return_();
+                }
+                Instruction::Slide(count) => {
+                    i.local_get(slot(h - 1))
+                        .local_set(slot(h - *count as usize - 1));
+                }
+                Instruction::ReadLine => {
+                    i.call(Runtime::ReadLine.index()).local_set(slot(h));
+                }
+                Instruction::ReadInt => {
+                    i.call(Runtime::ReadInt.index()).local_set(slot(h));
+                }
+                Instruction::Pop => {}
+            }
+        }
+
+        if looping {
+            i.end();
+        }
+        i.unreachable().end();
+        function
+    }
+
+    /// The address of the byte telling whether global `index` is defined.
+    fn defined(&self, index: u32) -> u32 {
+        GLOBALS + 8 * self.program.identifiers.len() as u32 + index
+    }
+
+    /// Pushes the closure in slot `callee` and the `arguments` a call to it
+    /// passes, followed by its function's index in the table, after
+    /// checking it can be called with that many arguments.
+    fn call(i: &mut InstructionSink, scratch: u32, callee: usize, arguments: &[usize]) {
+        i.local_get(callee as u32 + 1)
+            .i32_const(arguments.len() as i32)
+            .call(Runtime::Callee.index())
+            .local_tee(scratch);
+        for &argument in arguments {
+            i.local_get(argument as u32 + 1);
+        }
+        i.local_get(scratch).i32_load(memory(0, 2));
+    }
+}
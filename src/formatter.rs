@@ -0,0 +1,305 @@
+use anyhow::Result;
+use rinha::ast::{BinaryOp, Element, Term};
+
+use crate::{
+    ast::{parse_program, SEQUENCE_BINDING},
+    imports::take_imports,
+};
+
+/// How deep each level of a block is indented.
+const INDENT: &str = "  ";
+
+/// Formats a program canonically: one statement per line, blocks of `if`s,
+/// `else`s and function literals on lines of their own, indented by two
+/// spaces, and only the parentheses the grammar needs. Formatting a
+/// formatted program changes nothing.
+///
+/// Comments and single blank lines between statements are kept. A comment
+/// on the line a statement ends stays at its end, and a comment inside an
+/// expression moves before the statement following it.
+pub fn format_program(filename: &str, contents: &str) -> Result<String> {
+    let (imports, remainder) = take_imports(filename, contents)?;
+    let file = parse_program(filename, &remainder)?;
+
+    let mut printer = Printer {
+        source: contents,
+        comments: comments(&remainder),
+        next_comment: 0,
+        output: String::new(),
+        indent: 0,
+    };
+
+    if let Some((_, location)) = imports.first() {
+        printer.leading(location.start, None);
+        for (path, _) in &imports {
+            printer.output.push_str(&format!("import \"{path}\";\n"));
+        }
+        printer.output.push('\n');
+    }
+    printer.statements(&file.expression);
+    while let Some(&(start, end)) = printer.comments.get(printer.next_comment) {
+        printer.newline();
+        printer.output.push_str(contents[start..end].trim_end());
+        printer.next_comment += 1;
+    }
+    printer.output.push('\n');
+
+    Ok(printer.output)
+}
+
+struct Printer<'s> {
+    source: &'s str,
+    /// Where each comment in the program starts and ends, in order.
+    comments: Vec<(usize, usize)>,
+    /// The first comment not printed yet.
+    next_comment: usize,
+    output: String,
+    indent: usize,
+}
+
+impl Printer<'_> {
+    /// Prints a chain of `let`s and expressions evaluated for their effects,
+    /// one per line, ending with the expression it evaluates to.
+    fn statements(&mut self, mut term: &Term) {
+        let mut previous_end = None;
+
+        loop {
+            let Term::Let(binding) = term else {
+                self.leading(term.location().start, previous_end);
+                self.expression(term, 0);
+                self.trailing(term.location().end);
+                return;
+            };
+
+            if binding.name.text == SEQUENCE_BINDING {
+                self.leading(binding.value.location().start, previous_end);
+                self.expression(&binding.value, 0);
+            } else {
+                self.leading(binding.location.start, previous_end);
+                self.output
+                    .push_str(&format!("let {} = ", binding.name.text));
+                self.expression(&binding.value, 0);
+            }
+            self.output.push(';');
+
+            let end = binding.value.location().end;
+            self.trailing(end);
+            self.newline();
+
+            previous_end = Some(end);
+            term = &binding.next;
+        }
+    }
+
+    /// Prints `term`, in parentheses unless it binds at least as tightly as
+    /// `level` asks for.
+    fn expression(&mut self, term: &Term, level: u8) {
+        let parenthesized = precedence(term) < level;
+        if parenthesized {
+            self.output.push('(');
+        }
+
+        match term {
+            Term::Let(_) => self.block(term),
+            Term::If(i) => {
+                self.output.push_str("if (");
+                self.expression(&i.condition, 0);
+                self.output.push_str(") ");
+                self.block(&i.then);
+                self.output.push_str(" else ");
+                self.block(&i.otherwise);
+            }
+            Term::Function(f) => {
+                let parameters: Vec<&str> = f.parameters.iter().map(|p| p.text.as_str()).collect();
+                self.output
+                    .push_str(&format!("fn ({}) => ", parameters.join(", ")));
+                self.block(&f.value);
+            }
+            Term::Tuple(t) => {
+                self.output.push('(');
+                self.expression(&t.first, 0);
+                self.output.push_str(", ");
+                self.expression(&t.second, 0);
+                self.output.push(')');
+            }
+            Term::Binary(b) => {
+                // Every level of the grammar nests to the right, so only the
+                // left operand must bind more tightly.
+                let level = precedence(term);
+                self.expression(&b.lhs, level + 1);
+                self.output.push_str(&format!(" {} ", operator(&b.op)));
+                self.expression(&b.rhs, level);
+            }
+            Term::Call(c) => {
+                self.expression(&c.callee, APPLY);
+                self.output.push('(');
+                for (index, argument) in c.arguments.iter().enumerate() {
+                    if index > 0 {
+                        self.output.push_str(", ");
+                    }
+                    self.expression(argument, 0);
+                }
+                self.output.push(')');
+            }
+            Term::Print(p) => self.builtin("print", &p.value),
+            Term::First(f) => self.builtin("first", &f.value),
+            Term::Second(s) => self.builtin("second", &s.value),
+            Term::Var(v) => self.output.push_str(&v.text),
+            Term::Int(i) => self.output.push_str(&i.value.to_string()),
+            Term::Bool(b) => self.output.push_str(&b.value.to_string()),
+            // Strings are kept as written, escapes included.
+            Term::Str(s) => self.output.push_str(&format!("\"{}\"", s.value)),
+            Term::Error(e) => self.output.push_str(&e.full_text),
+        }
+
+        if parenthesized {
+            self.output.push(')');
+        }
+    }
+
+    fn builtin(&mut self, name: &str, value: &Term) {
+        self.output.push_str(name);
+        self.output.push('(');
+        self.expression(value, 0);
+        self.output.push(')');
+    }
+
+    /// Prints `term` between braces, indented on lines of its own.
+    fn block(&mut self, term: &Term) {
+        self.output.push('{');
+        self.indent += 1;
+        self.newline();
+        self.statements(term);
+        self.indent -= 1;
+        self.newline();
+        self.output.push('}');
+    }
+
+    /// Prints what comes before a statement starting at `start`: a blank
+    /// line if there was one since the statement before, which ended at
+    /// `previous_end`, and the comments not printed yet, one per line.
+    fn leading(&mut self, start: usize, previous_end: Option<usize>) {
+        let comment_start = self
+            .comments
+            .get(self.next_comment)
+            .map_or(start, |&(comment_start, _)| comment_start.min(start));
+        let blank = previous_end.is_some_and(|end| {
+            end < comment_start && self.source[end..comment_start].matches('\n').count() > 1
+        });
+        if blank {
+            self.blank_line();
+        }
+
+        while let Some(&(comment_start, comment_end)) = self.comments.get(self.next_comment) {
+            if comment_start >= start {
+                break;
+            }
+            self.output
+                .push_str(self.source[comment_start..comment_end].trim_end());
+            self.newline();
+            self.next_comment += 1;
+        }
+    }
+
+    /// Prints the comments on the line a statement ends, at `end`, after it.
+    fn trailing(&mut self, end: usize) {
+        while let Some(&(start, comment_end)) = self.comments.get(self.next_comment) {
+            let between = self.source.get(end..start).unwrap_or("\n");
+            if between.contains('\n')
+                || !between
+                    .chars()
+                    .all(|c| c.is_whitespace() || c == ';' || c == ')')
+            {
+                return;
+            }
+            self.output.push(' ');
+            self.output
+                .push_str(self.source[start..comment_end].trim_end());
+            self.next_comment += 1;
+        }
+    }
+
+    fn newline(&mut self) {
+        self.output.push('\n');
+        self.output.push_str(&INDENT.repeat(self.indent));
+    }
+
+    /// Turns the indentation the output ends with into a blank line.
+    fn blank_line(&mut self) {
+        let indented = self.output.trim_end_matches(' ').len();
+        self.output.truncate(indented);
+        self.newline();
+    }
+}
+
+/// The precedence of calls and of what they may call, the highest.
+const APPLY: u8 = 4;
+
+/// How tightly `term` binds, following the levels of the grammar: terms
+/// that need parentheses anywhere but on their own, then comparisons and
+/// logical operators, sums, products and finally applications.
+fn precedence(term: &Term) -> u8 {
+    match term {
+        Term::Let(_) | Term::If(_) | Term::Function(_) | Term::Tuple(_) => 0,
+        Term::Binary(b) => match b.op {
+            BinaryOp::Add | BinaryOp::Sub => 2,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 3,
+            _ => 1,
+        },
+        _ => APPLY,
+    }
+}
+
+fn operator(op: &BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Rem => "%",
+        BinaryOp::Eq => "==",
+        BinaryOp::Neq => "!=",
+        BinaryOp::Lt => "<",
+        BinaryOp::Gt => ">",
+        BinaryOp::Lte => "<=",
+        BinaryOp::Gte => ">=",
+        BinaryOp::And => "&&",
+        BinaryOp::Or => "||",
+    }
+}
+
+/// Where each comment in `contents` starts and ends, leaving out those
+/// inside strings. Line comments end before their newline.
+fn comments(contents: &str) -> Vec<(usize, usize)> {
+    let bytes = contents.as_bytes();
+    let mut comments = Vec::new();
+    let mut in_string = false;
+    let mut index = 0;
+
+    while index < bytes.len() {
+        match bytes[index] {
+            b'\\' if in_string => index += 1,
+            b'"' => in_string = !in_string,
+            b'/' if !in_string && bytes.get(index + 1) == Some(&b'/') => {
+                let end = contents[index..]
+                    .find('\n')
+                    .map_or(contents.len(), |newline| index + newline);
+                comments.push((index, end));
+                index = end;
+                continue;
+            }
+            b'/' if !in_string && bytes.get(index + 1) == Some(&b'*') => {
+                let end = contents[index + 2..]
+                    .find("*/")
+                    .map_or(contents.len(), |close| index + 2 + close + 2);
+                comments.push((index, end));
+                index = end;
+                continue;
+            }
+            _ => {}
+        }
+        index += 1;
+    }
+
+    comments
+}
@@ -1,17 +1,40 @@
-use crate::bytecode::Instruction;
-
-use std::collections::HashSet;
+use crate::{chunk::Chunk, interner::Symbol};
 
 #[derive(Clone, Debug)]
 pub struct Local {
     pub name: String,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Function {
     pub arity: u16,
-    pub bytecode: Vec<Instruction>,
-    pub captured: HashSet<String>,
-    pub index: u16,
+    pub chunk: Chunk,
+    /// The variables of enclosing scopes this one uses, sorted by name. A
+    /// `Closure` of the function pops their values in this order.
+    pub captured: Vec<Symbol>,
+    pub index: u32,
     pub locals: Vec<Local>,
+    /// The variable the function was bound to when it was defined, if any.
+    pub name: Option<String>,
+    /// The most values the frame holds at once, counting the arguments, as
+    /// found by [`crate::verifier::max_stack`]. Calls reserve this much room
+    /// up front so the stack does not grow while the function runs.
+    pub max_stack: usize,
+}
+
+impl Function {
+    /// Wraps top-level code so it can be run in a frame of its own. The
+    /// script is not part of the VM's function table, so it gets an index no
+    /// real function uses.
+    pub fn script(chunk: Chunk) -> Self {
+        Self {
+            arity: 0,
+            chunk,
+            captured: Vec::new(),
+            index: u32::MAX,
+            locals: Vec::new(),
+            name: None,
+            max_stack: 0,
+        }
+    }
 }
@@ -0,0 +1,574 @@
+use anyhow::{bail, Result};
+use num_bigint::BigInt;
+use std::{borrow::Cow, fmt, mem, rc::Rc};
+
+use crate::{
+    arithmetic::Integer,
+    interner::{StringTable, Symbol},
+    value::{FinalValue, ShortString, Tagged, Value},
+};
+
+/// Number of live objects that triggers the first collection.
+pub const DEFAULT_THRESHOLD: usize = 1 << 16;
+
+/// A handle to a value stored in a [`Heap`]. Handles are only valid while the
+/// value is reachable from the roots handed to [`Heap::collect`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Gc(pub(crate) u32);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct GcStats {
+    pub allocations: u64,
+    pub collections: u64,
+    pub freed: u64,
+    pub live: usize,
+    /// Roughly how many bytes the live values take; see [`size`].
+    pub bytes: usize,
+}
+
+/// How a [`Heap`] reclaims values.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Allocation {
+    /// Values no longer reachable are freed by the collector.
+    #[default]
+    Gc,
+    /// Nothing is freed until [`Heap::truncate`] drops every value allocated
+    /// after a point at once, trading peak memory for no collections.
+    Arena,
+}
+
+/// An arena of values with a mark-and-sweep collector. Freed slots are
+/// reused by later allocations, so values referencing each other in a cycle
+/// are reclaimed like any other garbage.
+pub struct Heap {
+    allocation: Allocation,
+    /// How deeply each tuple nests other tuples, so limits can be enforced
+    /// without walking them.
+    depths: Vec<u32>,
+    free: Vec<u32>,
+    marks: Vec<bool>,
+    next_collection: usize,
+    objects: Vec<Option<Value>>,
+    stats: GcStats,
+    strings: StringTable,
+    threshold: usize,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::new(DEFAULT_THRESHOLD)
+    }
+}
+
+impl Heap {
+    pub fn new(threshold: usize) -> Self {
+        Self {
+            allocation: Allocation::Gc,
+            depths: Vec::new(),
+            free: Vec::new(),
+            marks: Vec::new(),
+            next_collection: threshold,
+            objects: Vec::new(),
+            stats: GcStats::default(),
+            strings: StringTable::new(),
+            threshold,
+        }
+    }
+
+    /// Changes the number of live objects that triggers a collection. After
+    /// each collection the limit grows to twice the surviving objects, but
+    /// never drops below `threshold`.
+    pub fn set_threshold(&mut self, threshold: usize) {
+        self.threshold = threshold;
+        self.next_collection = threshold.max(self.stats.live * 2);
+    }
+
+    pub fn set_allocation(&mut self, allocation: Allocation) {
+        self.allocation = allocation;
+    }
+
+    pub fn allocation(&self) -> Allocation {
+        self.allocation
+    }
+
+    /// How many slots the heap has, free or not. Values allocated from now on
+    /// stay above this mark until they are freed.
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    /// Frees every value in a slot at or above `len` at once, without
+    /// tracing them. Handles to them must not be used again.
+    pub fn truncate(&mut self, len: usize) {
+        let freed_values = self.objects[len.min(self.objects.len())..].iter().flatten();
+        let freed = freed_values.clone().count();
+        self.stats.bytes -= freed_values.map(size).sum::<usize>();
+
+        self.objects.truncate(len);
+        self.depths.truncate(len);
+        self.free.retain(|index| (*index as usize) < len);
+        self.stats.freed += freed as u64;
+        self.stats.live -= freed;
+    }
+
+    pub fn threshold(&self) -> usize {
+        self.threshold
+    }
+
+    pub fn stats(&self) -> GcStats {
+        self.stats
+    }
+
+    pub fn allocate(&mut self, value: Value) -> Gc {
+        self.stats.allocations += 1;
+        self.stats.live += 1;
+        self.stats.bytes += size(&value);
+
+        let depth = match &value {
+            Value::Tuple(first, second) => {
+                1 + self.tuple_depth(*first).max(self.tuple_depth(*second))
+            }
+            _ => 0,
+        };
+
+        match self.free.pop() {
+            Some(index) => {
+                self.objects[index as usize] = Some(value);
+                self.depths[index as usize] = depth;
+                Gc(index)
+            }
+            None => {
+                self.objects.push(Some(value));
+                self.depths.push(depth);
+                Gc((self.objects.len() - 1) as u32)
+            }
+        }
+    }
+
+    /// How many tuples are nested in `value`, counting itself: zero for
+    /// anything but a tuple, one for a tuple of non-tuples, and so on.
+    pub fn tuple_depth(&self, value: Tagged) -> u32 {
+        match value {
+            Tagged::Object(handle) => self.depths[handle.0 as usize],
+            _ => 0,
+        }
+    }
+
+    /// Interns `string` in the heap's string table, which is never collected.
+    pub fn intern(&mut self, string: &str) -> Symbol {
+        self.strings.intern(string)
+    }
+
+    pub fn string(&self, symbol: Symbol) -> &Rc<str> {
+        self.strings.resolve(symbol)
+    }
+
+    /// Stores `value`, keeping integers, booleans and short strings inline.
+    pub fn store(&mut self, value: Value) -> Tagged {
+        match value {
+            Value::Bool(b) => Tagged::Bool(b),
+            Value::Integer(i) => Tagged::Integer(i),
+            Value::String(s) => match ShortString::new(&s) {
+                Some(short) => Tagged::Short(short),
+                None => Tagged::Object(self.allocate(Value::String(s))),
+            },
+            value => Tagged::Object(self.allocate(value)),
+        }
+    }
+
+    /// Stores a string, only allocating when it is too long to be inline.
+    pub fn store_str(&mut self, string: &str) -> Tagged {
+        match ShortString::new(string) {
+            Some(short) => Tagged::Short(short),
+            None => Tagged::Object(self.allocate(Value::String(string.into()))),
+        }
+    }
+
+    /// Stores the result of arithmetic, allocating it only when it is big.
+    pub fn store_integer(&mut self, integer: Integer) -> Tagged {
+        match integer {
+            Integer::Small(i) => Tagged::Integer(i),
+            Integer::Big(i) => Tagged::Object(self.allocate(Value::BigInteger(Rc::new(i)))),
+        }
+    }
+
+    pub fn get(&self, handle: Gc) -> &Value {
+        self.objects[handle.0 as usize]
+            .as_ref()
+            .expect("A handle must not outlive the value it points to.")
+    }
+
+    /// Returns the full value behind `value`, which is only borrowed from the
+    /// heap when it is not stored inline.
+    pub fn resolve(&self, value: Tagged) -> Cow<'_, Value> {
+        match value {
+            Tagged::Bool(b) => Cow::Owned(Value::Bool(b)),
+            Tagged::Integer(i) => Cow::Owned(Value::Integer(i)),
+            Tagged::Symbol(symbol) => Cow::Owned(Value::String(self.string(symbol).clone())),
+            Tagged::Short(short) => Cow::Owned(Value::String(short.as_str().into())),
+            Tagged::Object(handle) => Cow::Borrowed(self.get(handle)),
+        }
+    }
+
+    /// Whether `value` is an integer, whatever its size.
+    pub fn is_integer(&self, value: Tagged) -> bool {
+        match value {
+            Tagged::Integer(_) => true,
+            Tagged::Object(handle) => matches!(self.get(handle), Value::BigInteger(_)),
+            _ => false,
+        }
+    }
+
+    /// `value` as a big integer if it is an integer, whatever its size.
+    pub fn big_integer(&self, value: Tagged) -> Option<BigInt> {
+        match value {
+            Tagged::Integer(i) => Some(i.into()),
+            Tagged::Object(handle) => match self.get(handle) {
+                Value::BigInteger(i) => Some((**i).clone()),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The contents of `value` if it is a string, wherever it is stored.
+    pub fn text<'a>(&'a self, value: &'a Tagged) -> Option<&'a str> {
+        match value {
+            Tagged::Symbol(symbol) => Some(self.string(*symbol)),
+            Tagged::Short(short) => Some(short.as_str()),
+            Tagged::Object(handle) => match self.get(*handle) {
+                Value::String(s) => Some(s),
+                _ => None,
+            },
+            Tagged::Bool(_) | Tagged::Integer(_) => None,
+        }
+    }
+
+    /// The interned strings, in the order of their symbols.
+    pub fn strings(&self) -> impl Iterator<Item = &Rc<str>> {
+        self.strings.iter()
+    }
+
+    /// Every slot of the heap, with the tuple depth of the value in it.
+    pub fn objects(&self) -> impl Iterator<Item = Option<(&Value, u32)>> {
+        self.objects
+            .iter()
+            .zip(&self.depths)
+            .map(|(object, depth)| object.as_ref().map(|value| (value, *depth)))
+    }
+
+    /// Replaces every value in the heap with `objects`, as returned by
+    /// [`Heap::objects`], keeping the string table. Handles are positions in
+    /// `objects`, and its empty slots are free.
+    pub fn restore(&mut self, objects: Vec<Option<(Value, u32)>>) -> Result<()> {
+        (self.objects, self.depths) = objects
+            .into_iter()
+            .map(|object| match object {
+                Some((value, depth)) => (Some(value), depth),
+                None => (None, 0),
+            })
+            .unzip();
+        self.free = (0..self.objects.len() as u32)
+            .rev()
+            .filter(|index| self.objects[*index as usize].is_none())
+            .collect();
+        self.marks.clear();
+
+        let live = self.objects.len() - self.free.len();
+        self.stats = GcStats {
+            live,
+            bytes: self.objects.iter().flatten().map(size).sum(),
+            ..GcStats::default()
+        };
+        self.next_collection = self.threshold.max(live * 2);
+
+        for (index, object) in self.objects.iter().enumerate() {
+            let contents: Vec<Tagged> = match object {
+                Some(Value::Tuple(first, second)) => vec![*first, *second],
+                Some(Value::List(elements)) => elements.clone(),
+                Some(Value::Record(fields)) => fields.values().copied().collect(),
+                Some(Value::Closure(_, environment)) => {
+                    environment.iter().map(|(_, value)| *value).collect()
+                }
+                _ => Vec::new(),
+            };
+            if !contents.iter().all(|value| self.is_valid(*value)) {
+                bail!("Value {index} refers to a value that does not exist.");
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether `value` refers only to strings and values the heap holds.
+    pub fn is_valid(&self, value: Tagged) -> bool {
+        match value {
+            Tagged::Symbol(symbol) => (symbol.0 as usize) < self.strings.len(),
+            Tagged::Object(handle) => matches!(self.objects.get(handle.0 as usize), Some(Some(_))),
+            Tagged::Bool(_) | Tagged::Integer(_) | Tagged::Short(_) => true,
+        }
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.allocation == Allocation::Gc && self.stats.live >= self.next_collection
+    }
+
+    /// Frees every value not reachable from `roots`.
+    pub fn collect(&mut self, roots: impl IntoIterator<Item = Tagged>) {
+        self.marks.clear();
+        self.marks.resize(self.objects.len(), false);
+
+        let mut pending: Vec<Tagged> = roots.into_iter().collect();
+        while let Some(value) = pending.pop() {
+            let Tagged::Object(handle) = value else {
+                continue;
+            };
+
+            let index = handle.0 as usize;
+            if self.marks[index] {
+                continue;
+            }
+            self.marks[index] = true;
+
+            match self.get(handle) {
+                Value::Tuple(first, second) => pending.extend([*first, *second]),
+                Value::List(elements) => pending.extend(elements),
+                Value::Record(fields) => pending.extend(fields.values()),
+                Value::Closure(_, environment) => {
+                    pending.extend(environment.iter().map(|(_, value)| *value))
+                }
+                _ => {}
+            }
+        }
+
+        let mut freed = 0;
+        for (index, object) in self.objects.iter_mut().enumerate() {
+            if self.marks[index] {
+                continue;
+            }
+            if let Some(value) = object.take() {
+                self.stats.bytes -= size(&value);
+                self.free.push(index as u32);
+                freed += 1;
+            }
+        }
+
+        self.stats.collections += 1;
+        self.stats.freed += freed as u64;
+        self.stats.live -= freed;
+        self.next_collection = self.threshold.max(self.stats.live * 2);
+    }
+
+    /// Equality as `==` and `!=` see it. Booleans, integers and strings are
+    /// equal when they hold the same thing, wherever they are stored, and
+    /// tuples, lists and records when their elements are. Closures and
+    /// natives are only equal to themselves: two closures made apart are
+    /// different even when they run the same code on the same values, and
+    /// memoization never hands out one closure for another. Values of
+    /// different types are never equal, and comparing them never fails.
+    pub fn equals(&self, lhs: Tagged, rhs: Tagged) -> bool {
+        if lhs == rhs {
+            return true;
+        }
+
+        if let (Some(lhs), Some(rhs)) = (self.text(&lhs), self.text(&rhs)) {
+            return lhs == rhs;
+        }
+
+        match (&*self.resolve(lhs), &*self.resolve(rhs)) {
+            (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
+            (Value::Integer(i1), Value::Integer(i2)) => i1 == i2,
+            (Value::BigInteger(i1), Value::BigInteger(i2)) => i1 == i2,
+            (Value::String(s1), Value::String(s2)) => s1 == s2,
+            (Value::Tuple(v1, v2), Value::Tuple(v3, v4)) => {
+                self.equals(*v1, *v3) && self.equals(*v2, *v4)
+            }
+            (Value::List(l1), Value::List(l2)) => {
+                l1.len() == l2.len() && l1.iter().zip(l2).all(|(v1, v2)| self.equals(*v1, *v2))
+            }
+            (Value::Record(r1), Value::Record(r2)) => {
+                r1.len() == r2.len()
+                    && r1
+                        .iter()
+                        .zip(r2)
+                        .all(|((k1, v1), (k2, v2))| k1 == k2 && self.equals(*v1, *v2))
+            }
+            _ => false,
+        }
+    }
+
+    /// Copies `value` out of the heap. Walks it with an explicit stack, so
+    /// deeply nested tuples do not overflow the native one.
+    pub fn finalize(&self, value: Tagged) -> FinalValue {
+        enum Visit {
+            Value(Tagged),
+            /// Builds a tuple from the last two results.
+            Tuple,
+            /// Builds a list from the given number of last results.
+            List(usize),
+            /// Builds a record with the given keys from as many last results.
+            Record(Vec<String>),
+        }
+
+        let mut visits = vec![Visit::Value(value)];
+        let mut results = Vec::new();
+
+        while let Some(visit) = visits.pop() {
+            let result = match visit {
+                Visit::Value(value) => {
+                    if let Some(text) = self.text(&value) {
+                        results.push(FinalValue::String(text.to_owned()));
+                        continue;
+                    }
+
+                    match &*self.resolve(value) {
+                        Value::Bool(b) => FinalValue::Bool(*b),
+                        Value::Integer(i) => FinalValue::Integer(*i),
+                        Value::BigInteger(i) => FinalValue::BigInteger((**i).clone()),
+                        Value::String(s) => FinalValue::String(s.to_string()),
+                        Value::Tuple(first, second) => {
+                            visits.push(Visit::Tuple);
+                            visits.push(Visit::Value(*second));
+                            visits.push(Visit::Value(*first));
+                            continue;
+                        }
+                        Value::List(elements) => {
+                            visits.push(Visit::List(elements.len()));
+                            visits.extend(elements.iter().rev().map(|e| Visit::Value(*e)));
+                            continue;
+                        }
+                        Value::Record(fields) => {
+                            visits.push(Visit::Record(
+                                fields.keys().map(|key| key.to_string()).collect(),
+                            ));
+                            visits.extend(fields.values().rev().map(|v| Visit::Value(*v)));
+                            continue;
+                        }
+                        Value::Closure(_, _) | Value::Native(_) => FinalValue::Closure,
+                    }
+                }
+                Visit::Tuple => {
+                    let second = results.pop().expect("A tuple has a second value.");
+                    let first = results.pop().expect("A tuple has a first value.");
+                    FinalValue::Tuple(Box::new(first), Box::new(second))
+                }
+                Visit::List(length) => FinalValue::List(results.split_off(results.len() - length)),
+                Visit::Record(keys) => {
+                    let values = results.split_off(results.len() - keys.len());
+                    FinalValue::Record(keys.into_iter().zip(values).collect())
+                }
+            };
+            results.push(result);
+        }
+
+        results.pop().expect("Finalizing leaves a single value.")
+    }
+
+    pub fn allocate_final(&mut self, value: &FinalValue) -> Result<Tagged> {
+        let value = match value {
+            FinalValue::Bool(b) => Value::Bool(*b),
+            FinalValue::Integer(i) => Value::Integer(*i),
+            FinalValue::BigInteger(i) => return Ok(self.store_integer(i.clone().into())),
+            FinalValue::String(s) => return Ok(self.store_str(s)),
+            FinalValue::Tuple(first, second) => {
+                let first = self.allocate_final(first)?;
+                let second = self.allocate_final(second)?;
+                Value::Tuple(first, second)
+            }
+            FinalValue::List(elements) => Value::List(
+                elements
+                    .iter()
+                    .map(|element| self.allocate_final(element))
+                    .collect::<Result<_>>()?,
+            ),
+            FinalValue::Record(fields) => Value::Record(
+                fields
+                    .iter()
+                    .map(|(key, value)| Ok((key.as_str().into(), self.allocate_final(value)?)))
+                    .collect::<Result<_>>()?,
+            ),
+            FinalValue::Closure => bail!("Functions cannot be passed into the VM."),
+        };
+
+        Ok(self.store(value))
+    }
+
+    pub fn display(&self, value: Tagged) -> Display<'_> {
+        Display { heap: self, value }
+    }
+}
+
+/// Formats a value the way `print` shows it.
+pub struct Display<'a> {
+    heap: &'a Heap,
+    value: Tagged,
+}
+
+impl<'a> fmt::Display for Display<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(text) = self.heap.text(&self.value) {
+            return write!(f, "{text}");
+        }
+
+        match &*self.heap.resolve(self.value) {
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Integer(i) => write!(f, "{i}"),
+            Value::BigInteger(i) => write!(f, "{i}"),
+            Value::String(s) => write!(f, "{s}"),
+            Value::Tuple(first, second) => write!(
+                f,
+                "({}, {})",
+                self.heap.display(*first),
+                self.heap.display(*second)
+            ),
+            Value::List(elements) => {
+                write!(f, "[")?;
+                for (index, element) in elements.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", self.heap.display(*element))?;
+                }
+                write!(f, "]")
+            }
+            Value::Record(fields) => {
+                write!(f, "{{")?;
+                for (index, (key, value)) in fields.iter().enumerate() {
+                    if index > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{key}: {}", self.heap.display(*value))?;
+                }
+                write!(f, "}}")
+            }
+            Value::Closure(function, _) => match &function.name {
+                Some(name) => write!(f, "<#closure {name}>"),
+                None => write!(f, "<#closure>"),
+            },
+            Value::Native(native) => write!(f, "<#closure {}>", native.global_name()),
+        }
+    }
+}
+
+/// Roughly how many bytes `value` takes in the heap: its slot plus whatever
+/// it owns outside of it. Functions and natives are shared with the VM, so
+/// a closure only counts its environment.
+pub fn size(value: &Value) -> usize {
+    let owned = match value {
+        Value::String(s) => s.len(),
+        Value::BigInteger(i) => i.bits().div_ceil(8) as usize,
+        Value::List(elements) => elements.len() * mem::size_of::<Tagged>(),
+        Value::Record(fields) => fields
+            .keys()
+            .map(|key| key.len() + mem::size_of::<(Rc<str>, Tagged)>())
+            .sum(),
+        Value::Closure(_, environment) => environment.len() * mem::size_of::<(Symbol, Tagged)>(),
+        Value::Bool(_) | Value::Integer(_) | Value::Tuple(_, _) | Value::Native(_) => 0,
+    };
+
+    mem::size_of::<Option<Value>>() + owned
+}
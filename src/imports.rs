@@ -0,0 +1,219 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use rinha::{
+    ast::{self as rinha_ast, Term},
+    parser::Var,
+};
+
+use crate::ast::{parse_program, split_statements};
+
+/// Parses a program along with the files it imports.
+///
+/// A program starts with any number of `import "lib.rinha";` statements,
+/// whose paths are relative to the importing file. The top-level `let`s and
+/// statements of an imported file run before the program, as if they were
+/// written at its top, after those of the files it imports in turn. The
+/// expression an imported file ends with is ignored, and it may also end
+/// right after a `;`. Each file is imported once, however many files import
+/// it, and imports must not form a cycle.
+///
+/// Locations in imported code point into the file it comes from, so errors
+/// name that file. The `let`s spliced into the program are located at the
+/// import that brought them in.
+pub fn parse_with_imports(filename: &str, contents: &str) -> Result<rinha_ast::File> {
+    let (imports, remainder) = take_imports(filename, contents)?;
+    if imports.is_empty() {
+        return parse_program(filename, contents);
+    }
+
+    let mut importer = Importer {
+        imported: HashSet::new(),
+        chain: vec![fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename))],
+        bindings: Vec::new(),
+    };
+    let directory = Path::new(filename).parent().unwrap_or(Path::new(""));
+    for (path, location) in imports {
+        importer.import(&directory.join(path), &location, &location)?;
+    }
+
+    let mut file = parse_program(filename, &remainder)?;
+    for (name, value, location) in importer.bindings.into_iter().rev() {
+        file.expression = Term::Let(rinha_ast::Let {
+            name,
+            value,
+            next: Box::new(file.expression),
+            location,
+        });
+    }
+
+    Ok(file)
+}
+
+/// The files a program imports, directly or through other imported files,
+/// by their resolved path, along with their contents. Each file is listed
+/// once, in the order its first import is found when going through the
+/// program's imports in source order, then through those of each file in the
+/// order it was listed.
+pub fn imported_files(filename: &str, contents: &str) -> Result<Vec<(PathBuf, String)>> {
+    let mut files = Vec::new();
+    let mut seen = HashSet::new();
+    let mut pending = VecDeque::from([(PathBuf::from(filename), contents.to_owned())]);
+
+    while let Some((path, contents)) = pending.pop_front() {
+        let (imports, _) = take_imports(&path.to_string_lossy(), &contents)?;
+        let directory = path.parent().unwrap_or(Path::new("")).to_owned();
+
+        for (import, location) in imports {
+            let path = directory.join(import);
+            let canonical = fs::canonicalize(&path).with_context(|| {
+                format!(
+                    "Could not read {}, imported at {}:{}..{}.",
+                    path.display(),
+                    location.filename,
+                    location.start,
+                    location.end
+                )
+            })?;
+            if !seen.insert(canonical.clone()) {
+                continue;
+            }
+
+            let contents = fs::read_to_string(&canonical)
+                .with_context(|| format!("Could not read {}.", path.display()))?;
+            files.push((canonical, contents.clone()));
+            pending.push_back((path, contents));
+        }
+    }
+
+    Ok(files)
+}
+
+struct Importer {
+    /// Every file imported so far.
+    imported: HashSet<PathBuf>,
+    /// The files whose imports are being followed, outermost first.
+    chain: Vec<PathBuf>,
+    /// The top-level bindings of the imported files, in the order they run.
+    bindings: Vec<(Var, Box<Term>, rinha_ast::Location)>,
+}
+
+impl Importer {
+    /// Adds the bindings of the file at `path`, imported at `location`,
+    /// after those of its own imports. `origin` is the import in the
+    /// program that led to it.
+    fn import(
+        &mut self,
+        path: &Path,
+        location: &rinha_ast::Location,
+        origin: &rinha_ast::Location,
+    ) -> Result<()> {
+        let canonical = fs::canonicalize(path).with_context(|| {
+            format!(
+                "Could not read {}, imported at {}:{}..{}.",
+                path.display(),
+                location.filename,
+                location.start,
+                location.end
+            )
+        })?;
+
+        if let Some(position) = self.chain.iter().position(|file| *file == canonical) {
+            let cycle: Vec<String> = self.chain[position..]
+                .iter()
+                .chain([&canonical])
+                .map(|file| file.display().to_string())
+                .collect();
+            bail!("Import cycle: {}.", cycle.join(" → "));
+        }
+        if !self.imported.insert(canonical.clone()) {
+            return Ok(());
+        }
+
+        let filename = path.to_string_lossy();
+        let contents = fs::read_to_string(&canonical)
+            .with_context(|| format!("Could not read {filename}."))?;
+        let (imports, mut remainder) = take_imports(&filename, &contents)?;
+
+        self.chain.push(canonical);
+        let directory = path.parent().unwrap_or(Path::new(""));
+        for (import, location) in imports {
+            self.import(&directory.join(import), &location, origin)?;
+        }
+        self.chain.pop();
+
+        let statements = split_statements(&remainder);
+        if statements.iter().all(|&(start, end)| start == end) {
+            return Ok(());
+        }
+        if statements.last().is_some_and(|&(start, end)| start == end) {
+            // Stands for the expression a file ending in `;` leaves out.
+            remainder.push('0');
+        }
+
+        let mut term = parse_program(&filename, &remainder)
+            .with_context(|| {
+                format!(
+                    "Could not parse {filename}, imported at {}:{}..{}.",
+                    location.filename, location.start, location.end
+                )
+            })?
+            .expression;
+        while let Term::Let(binding) = term {
+            self.bindings
+                .push((binding.name, binding.value, origin.clone()));
+            term = *binding.next;
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits the leading `import "path";` statements off a file, returning
+/// their paths and locations along with the rest of the file. The imports
+/// are blanked out rather than removed, so offsets into the rest are
+/// offsets into the file.
+pub(crate) fn take_imports(
+    filename: &str,
+    contents: &str,
+) -> Result<(Vec<(String, rinha_ast::Location)>, String)> {
+    let mut imports = Vec::new();
+    let mut remainder = contents.as_bytes().to_vec();
+
+    for (start, end) in split_statements(contents) {
+        let Some(rest) = contents[start..end].strip_prefix("import") else {
+            break;
+        };
+        if rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_') {
+            break;
+        }
+
+        let path = rest
+            .trim()
+            .strip_prefix('"')
+            .and_then(|path| path.strip_suffix('"'))
+            .filter(|path| !path.contains(['"', '\\']));
+        let Some(path) = path else {
+            bail!("Expected a path in quotes after import at {filename}:{start}..{end}.");
+        };
+        imports.push((
+            path.to_owned(),
+            rinha_ast::Location::new(start, end, filename),
+        ));
+
+        // Blank out the `;` too, when there is one.
+        let end = (end + 1).min(contents.len());
+        for byte in &mut remainder[start..end] {
+            if *byte != b'\n' {
+                *byte = b' ';
+            }
+        }
+    }
+
+    let remainder = String::from_utf8(remainder).expect("Imports end at ASCII characters.");
+    Ok((imports, remainder))
+}
@@ -0,0 +1,76 @@
+use std::rc::Rc;
+
+use crate::{function::Function, gc::Gc, value::Environment};
+
+/// The closure a call site called last, the function it runs and the
+/// variables it captured.
+#[derive(Clone, Debug)]
+struct Entry {
+    closure: Gc,
+    function: Rc<Function>,
+    environment: Rc<Environment>,
+}
+
+/// Per-call-site caches of the closure each `Call` or `TailCall` resolved
+/// last, so a site that keeps calling the same closure skips looking it up
+/// in the heap and checking its arity. A call site is identified by the
+/// function it is in and its address there.
+///
+/// Entries are keyed by heap handle, which is reused once its value is
+/// freed, so the caches must be [cleared](InlineCaches::clear) whenever the
+/// heap frees anything.
+#[derive(Debug, Default)]
+pub struct InlineCaches {
+    /// Indexed by the function index plus one, so the top-level script, whose
+    /// index is `u32::MAX`, wraps around to zero; then by address.
+    sites: Vec<Vec<Option<Entry>>>,
+}
+
+impl InlineCaches {
+    /// The function and environment of `closure`, when it is the closure
+    /// the call site at `address` in `caller` called last.
+    pub fn get(
+        &self,
+        caller: u32,
+        address: usize,
+        closure: Gc,
+    ) -> Option<(Rc<Function>, Rc<Environment>)> {
+        let entry = self.sites.get(site(caller))?.get(address)?.as_ref()?;
+
+        (entry.closure == closure).then(|| (entry.function.clone(), entry.environment.clone()))
+    }
+
+    /// Remembers that the call site at `address` in `caller` called
+    /// `closure`, replacing whatever it called before.
+    pub fn insert(
+        &mut self,
+        caller: u32,
+        address: usize,
+        closure: Gc,
+        function: Rc<Function>,
+        environment: Rc<Environment>,
+    ) {
+        let index = site(caller);
+        if self.sites.len() <= index {
+            self.sites.resize_with(index + 1, Vec::new);
+        }
+
+        let sites = &mut self.sites[index];
+        if sites.len() <= address {
+            sites.resize(address + 1, None);
+        }
+        sites[address] = Some(Entry {
+            closure,
+            function,
+            environment,
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.sites.clear();
+    }
+}
+
+fn site(caller: u32) -> usize {
+    caller.wrapping_add(1) as usize
+}
@@ -0,0 +1,51 @@
+use std::{collections::HashMap, rc::Rc};
+
+/// A handle to a string stored once in a [`StringTable`]. Two symbols from
+/// the same table are equal exactly when their strings are.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Symbol(pub(crate) u32);
+
+/// Stores each distinct string once. Identifiers and the strings appearing
+/// in the program source live here for as long as the VM does.
+#[derive(Default)]
+pub struct StringTable {
+    strings: Vec<Rc<str>>,
+    symbols: HashMap<Rc<str>, Symbol>,
+}
+
+impl StringTable {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn intern(&mut self, string: &str) -> Symbol {
+        if let Some(symbol) = self.symbols.get(string) {
+            return *symbol;
+        }
+
+        let symbol = Symbol(self.strings.len() as u32);
+        let string: Rc<str> = Rc::from(string);
+        self.strings.push(string.clone());
+        self.symbols.insert(string, symbol);
+
+        symbol
+    }
+
+    pub fn resolve(&self, symbol: Symbol) -> &Rc<str> {
+        &self.strings[symbol.0 as usize]
+    }
+
+    /// The strings in the order they were interned, which is the order of
+    /// their symbols.
+    pub fn iter(&self) -> impl Iterator<Item = &Rc<str>> {
+        self.strings.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.strings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strings.is_empty()
+    }
+}
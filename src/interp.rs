@@ -0,0 +1,341 @@
+use anyhow::{anyhow, bail, Result};
+use std::{collections::HashMap, fmt, rc::Rc};
+
+use crate::{
+    ast::{BinaryOp, File, Term, UnaryOp},
+    value::FinalValue,
+    vm::DEFAULT_MAX_TUPLE_DEPTH,
+};
+
+/// How many terms a run may evaluate unless [`Interpreter::with_steps`] says
+/// otherwise.
+pub const DEFAULT_STEPS: u64 = 100_000;
+
+/// How deeply calls may nest unless [`Interpreter::with_call_depth`] says
+/// otherwise. The interpreter recurses on the host stack and has no tail
+/// calls, so deeper runs are given up on.
+pub const DEFAULT_CALL_DEPTH: usize = 64;
+
+/// A slow interpreter that walks the AST, written to be obviously right
+/// rather than fast. It follows the VM's semantics, down to the first
+/// top-level binding of a name being a global that functions look up when
+/// they run, so the two can be run side by side to check each other.
+///
+/// Programs that use natives or read input are not supported.
+pub struct Interpreter<'t> {
+    globals: HashMap<&'t str, Value<'t>>,
+    output: Vec<String>,
+    steps: u64,
+    depth: usize,
+    max_depth: usize,
+}
+
+/// Why a run could not tell what the VM should do. Runs failing with it
+/// say nothing about the VM.
+#[derive(Debug)]
+pub struct Inconclusive(pub &'static str);
+
+impl fmt::Display for Inconclusive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Inconclusive run: {}.", self.0)
+    }
+}
+
+impl std::error::Error for Inconclusive {}
+
+#[derive(Clone)]
+enum Value<'t> {
+    Bool(bool),
+    Integer(i32),
+    String(Rc<str>),
+    /// The elements, and how deeply the tuple nests.
+    Tuple(Rc<(Value<'t>, Value<'t>)>, u32),
+    Closure(Rc<Closure<'t>>),
+}
+
+struct Closure<'t> {
+    name: Option<&'t str>,
+    parameters: Vec<&'t str>,
+    body: &'t Term,
+    /// The variables in scope where the function was defined, by value.
+    environment: Vec<(&'t str, Value<'t>)>,
+}
+
+impl Default for Interpreter<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'t> Interpreter<'t> {
+    pub fn new() -> Self {
+        Self {
+            globals: HashMap::new(),
+            output: Vec::new(),
+            steps: DEFAULT_STEPS,
+            depth: 0,
+            max_depth: DEFAULT_CALL_DEPTH,
+        }
+    }
+
+    /// Limits the number of terms a run may evaluate. Running out fails with
+    /// [`Inconclusive`].
+    pub fn with_steps(mut self, steps: u64) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    /// Limits how deeply calls may nest. Running out fails with
+    /// [`Inconclusive`]. Deep calls need a large enough host stack.
+    pub fn with_call_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// The lines printed so far.
+    pub fn output(&self) -> &[String] {
+        &self.output
+    }
+
+    pub fn run(&mut self, file: &'t File) -> Result<FinalValue> {
+        let value = self.eval(&file.expression, &mut Vec::new(), true)?;
+        Ok(finalize(&value))
+    }
+
+    /// Evaluates `term` with the local variables in `scope`, innermost last.
+    /// `statement` says whether the term is top-level code, where a `let` of
+    /// a name not yet bound at the top level binds a global.
+    fn eval(
+        &mut self,
+        term: &'t Term,
+        scope: &mut Vec<(&'t str, Value<'t>)>,
+        statement: bool,
+    ) -> Result<Value<'t>> {
+        if self.steps == 0 {
+            bail!(Inconclusive("out of steps"));
+        }
+        self.steps -= 1;
+
+        Ok(match term {
+            Term::Int { value, .. } => Value::Integer(*value),
+            Term::Str { value, .. } => Value::String(value.as_str().into()),
+            Term::Bool { value, .. } => Value::Bool(*value),
+            Term::Var { text, .. } => self.lookup(text, scope)?,
+            Term::Binary { lhs, op, rhs, .. } => {
+                let lhs = self.eval(lhs, scope, false)?;
+                let rhs = self.eval(rhs, scope, false)?;
+                binary(*op, lhs, rhs)?
+            }
+            Term::Unary { op, value, .. } => match (op, self.eval(value, scope, false)?) {
+                (UnaryOp::Neg, Value::Integer(value)) => Value::Integer(value.wrapping_neg()),
+                (UnaryOp::Neg, _) => bail!("Operand must be an integer."),
+                (UnaryOp::Not, Value::Bool(value)) => Value::Bool(!value),
+                (UnaryOp::Not, _) => bail!("Operand must be a boolean."),
+            },
+            Term::If {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => match self.eval(condition, scope, false)? {
+                Value::Bool(true) => self.eval(then, scope, false)?,
+                Value::Bool(false) => self.eval(otherwise, scope, false)?,
+                _ => bail!("Type error: if condition must evaluate to a boolean."),
+            },
+            Term::Tuple { first, second, .. } => {
+                let first = self.eval(first, scope, false)?;
+                let second = self.eval(second, scope, false)?;
+
+                let depth = 1 + tuple_depth(&first).max(tuple_depth(&second));
+                if depth > DEFAULT_MAX_TUPLE_DEPTH {
+                    bail!("Tuple nesting depth {depth} exceeds the limit of {DEFAULT_MAX_TUPLE_DEPTH}.");
+                }
+
+                Value::Tuple(Rc::new((first, second)), depth)
+            }
+            Term::First { value, .. } => match self.eval(value, scope, false)? {
+                Value::Tuple(tuple, _) => tuple.0.clone(),
+                _ => bail!("Tried to compute `first` of a non tuple type."),
+            },
+            Term::Second { value, .. } => match self.eval(value, scope, false)? {
+                Value::Tuple(tuple, _) => tuple.1.clone(),
+                _ => bail!("Tried to compute `second` of a non tuple type."),
+            },
+            Term::Print { value, .. } => {
+                let value = self.eval(value, scope, false)?;
+                self.output.push(display(&value));
+                value
+            }
+            Term::Sequence { value, next, .. } => {
+                self.eval(value, scope, false)?;
+                self.eval(next, scope, statement)?
+            }
+            Term::Function { .. } => self.closure(term, None, scope),
+            Term::Let {
+                name, value, next, ..
+            } => {
+                let name = name.text.as_str();
+                let value = match &**value {
+                    function @ Term::Function { .. } => self.closure(function, Some(name), scope),
+                    value => self.eval(value, scope, false)?,
+                };
+
+                if statement && !self.globals.contains_key(name) {
+                    self.globals.insert(name, value);
+                    return self.eval(next, scope, true);
+                }
+
+                scope.push((name, value));
+                let result = self.eval(next, scope, false);
+                scope.pop();
+                result?
+            }
+            Term::Call {
+                callee, arguments, ..
+            } => {
+                let callee = self.eval(callee, scope, false)?;
+                let arguments = arguments
+                    .iter()
+                    .map(|argument| self.eval(argument, scope, false))
+                    .collect::<Result<Vec<_>>>()?;
+
+                let Value::Closure(closure) = callee else {
+                    bail!("Attempted to call value that is not a function!");
+                };
+                if closure.parameters.len() != arguments.len() {
+                    bail!("Attempted to call function with wrong number of arguments.");
+                }
+                if self.depth == self.max_depth {
+                    bail!(Inconclusive("calls nest too deeply"));
+                }
+
+                let mut locals = closure.environment.clone();
+                locals.extend(closure.parameters.iter().copied().zip(arguments));
+
+                self.depth += 1;
+                let result = self.eval(closure.body, &mut locals, false);
+                self.depth -= 1;
+                result?
+            }
+        })
+    }
+
+    fn closure(
+        &mut self,
+        function: &'t Term,
+        name: Option<&'t str>,
+        scope: &[(&'t str, Value<'t>)],
+    ) -> Value<'t> {
+        let Term::Function {
+            parameters, value, ..
+        } = function
+        else {
+            unreachable!("Only functions make closures.");
+        };
+
+        Value::Closure(Rc::new(Closure {
+            name,
+            parameters: parameters.iter().map(|p| p.text.as_str()).collect(),
+            body: value,
+            environment: scope.to_vec(),
+        }))
+    }
+
+    fn lookup(&self, name: &str, scope: &[(&'t str, Value<'t>)]) -> Result<Value<'t>> {
+        scope
+            .iter()
+            .rev()
+            .find(|(local, _)| *local == name)
+            .map(|(_, value)| value)
+            .or_else(|| self.globals.get(name))
+            .cloned()
+            .ok_or_else(|| anyhow!("Unknown variable {name}."))
+    }
+}
+
+fn binary<'t>(op: BinaryOp, lhs: Value<'t>, rhs: Value<'t>) -> Result<Value<'t>> {
+    use Value::{Bool, Integer};
+
+    Ok(match (op, lhs, rhs) {
+        (BinaryOp::Add, Integer(lhs), Integer(rhs)) => Integer(lhs.wrapping_add(rhs)),
+        (BinaryOp::Add, Value::String(lhs), Integer(rhs)) => {
+            Value::String(format!("{lhs}{rhs}").into())
+        }
+        (BinaryOp::Add, Integer(lhs), Value::String(rhs)) => {
+            Value::String(format!("{lhs}{rhs}").into())
+        }
+        (BinaryOp::Add, Value::String(lhs), Value::String(rhs)) => {
+            Value::String(format!("{lhs}{rhs}").into())
+        }
+        (BinaryOp::Add, _, _) => bail!("Wrong types for add."),
+        (BinaryOp::Sub, Integer(lhs), Integer(rhs)) => Integer(lhs.wrapping_sub(rhs)),
+        (BinaryOp::Mul, Integer(lhs), Integer(rhs)) => Integer(lhs.wrapping_mul(rhs)),
+        (BinaryOp::Div, Integer(_), Integer(0)) => bail!("Attempted to divide by zero"),
+        (BinaryOp::Div, Integer(lhs), Integer(rhs)) => Integer(lhs.wrapping_div(rhs)),
+        (BinaryOp::Rem, Integer(_), Integer(0)) => bail!("Attempted to take remainder by zero"),
+        (BinaryOp::Rem, Integer(lhs), Integer(rhs)) => Integer(lhs.wrapping_rem(rhs)),
+        (BinaryOp::Lt, Integer(lhs), Integer(rhs)) => Bool(lhs < rhs),
+        (BinaryOp::Gt, Integer(lhs), Integer(rhs)) => Bool(lhs > rhs),
+        (BinaryOp::Lte, Integer(lhs), Integer(rhs)) => Bool(lhs <= rhs),
+        (BinaryOp::Gte, Integer(lhs), Integer(rhs)) => Bool(lhs >= rhs),
+        (BinaryOp::Lt, Value::String(lhs), Value::String(rhs)) => Bool(lhs < rhs),
+        (BinaryOp::Gt, Value::String(lhs), Value::String(rhs)) => Bool(lhs > rhs),
+        (BinaryOp::Lte, Value::String(lhs), Value::String(rhs)) => Bool(lhs <= rhs),
+        (BinaryOp::Gte, Value::String(lhs), Value::String(rhs)) => Bool(lhs >= rhs),
+        (BinaryOp::Lt | BinaryOp::Gt | BinaryOp::Lte | BinaryOp::Gte, _, _) => {
+            bail!("Operands must be both integers or both strings.")
+        }
+        (BinaryOp::And, Bool(lhs), Bool(rhs)) => Bool(lhs && rhs),
+        (BinaryOp::Or, Bool(lhs), Bool(rhs)) => Bool(lhs || rhs),
+        (BinaryOp::Eq, lhs, rhs) => Bool(equals(&lhs, &rhs)),
+        (BinaryOp::Neq, lhs, rhs) => Bool(!equals(&lhs, &rhs)),
+        _ => bail!("Operands must be both integers."),
+    })
+}
+
+/// Structural equality, except for functions, which are only equal to
+/// themselves, as [`crate::gc::Heap::equals`] has it.
+fn equals(lhs: &Value, rhs: &Value) -> bool {
+    match (lhs, rhs) {
+        (Value::Bool(lhs), Value::Bool(rhs)) => lhs == rhs,
+        (Value::Integer(lhs), Value::Integer(rhs)) => lhs == rhs,
+        (Value::String(lhs), Value::String(rhs)) => lhs == rhs,
+        (Value::Tuple(lhs, _), Value::Tuple(rhs, _)) => {
+            equals(&lhs.0, &rhs.0) && equals(&lhs.1, &rhs.1)
+        }
+        (Value::Closure(lhs), Value::Closure(rhs)) => Rc::ptr_eq(lhs, rhs),
+        _ => false,
+    }
+}
+
+fn tuple_depth(value: &Value) -> u32 {
+    match value {
+        Value::Tuple(_, depth) => *depth,
+        _ => 0,
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Bool(b) => b.to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::String(s) => s.to_string(),
+        Value::Tuple(tuple, _) => format!("({}, {})", display(&tuple.0), display(&tuple.1)),
+        Value::Closure(closure) => match closure.name {
+            Some(name) => format!("<#closure {name}>"),
+            None => "<#closure>".to_owned(),
+        },
+    }
+}
+
+fn finalize(value: &Value) -> FinalValue {
+    match value {
+        Value::Bool(b) => FinalValue::Bool(*b),
+        Value::Integer(i) => FinalValue::Integer(*i),
+        Value::String(s) => FinalValue::String(s.to_string()),
+        Value::Tuple(tuple, _) => {
+            FinalValue::Tuple(Box::new(finalize(&tuple.0)), Box::new(finalize(&tuple.1)))
+        }
+        Value::Closure(_) => FinalValue::Closure,
+    }
+}
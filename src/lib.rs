@@ -1,6 +1,43 @@
+pub mod arithmetic;
+pub mod artifact;
+pub mod ast;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod batch;
+pub mod builtins;
 pub mod bytecode;
+pub mod cache;
 pub mod call_frame;
+pub mod capi;
+pub mod chunk;
+pub mod compare;
 pub mod compiler;
+pub mod dap;
+pub mod debugger;
+pub mod diagnostics;
+pub mod emit_c;
+pub mod emit_rust;
+pub mod emit_wasm;
+pub mod formatter;
 pub mod function;
+pub mod gc;
+pub mod imports;
+pub mod inline_cache;
+pub mod interner;
+pub mod interp;
+pub mod lsp;
+pub mod memo;
+pub mod native;
+pub mod optimizer;
+pub mod printing;
+pub mod profiler;
+pub mod range;
+pub mod replay;
+pub mod sandbox;
+pub mod scheduler;
+pub mod snapshot;
+pub mod source_map;
 pub mod value;
+pub mod verifier;
 pub mod vm;
+#[cfg(feature = "wasm")]
+pub mod wasm;
@@ -0,0 +1,657 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{BufRead, Write},
+};
+
+use anyhow::{anyhow, Context, Result};
+use miette::Diagnostic;
+use rinha::{
+    ast::{self as rinha_ast, BinaryOp, Element, Term},
+    parser::{ParseError, Var},
+};
+use serde_json::{json, Value};
+
+use crate::{
+    ast::{self, Location, SEQUENCE_BINDING},
+    dap::read_message,
+    diagnostics::CompileErrors,
+    imports,
+    source_map::LineIndex,
+    vm::Vm,
+};
+
+/// The JSON-RPC error for requests the server does not support.
+const METHOD_NOT_FOUND: i64 = -32601;
+
+/// The JSON-RPC error for requests the server failed to handle.
+const INTERNAL_ERROR: i64 = -32603;
+
+/// Serves the Language Server Protocol, which editors use to learn about the
+/// programs open in them: messages are JSON-RPC objects framed like those of
+/// [`crate::dap::DapServer`]. Each version of a document is compiled with
+/// error recovery to publish its errors and warnings, and the names it binds
+/// can be hovered, followed to where they are bound and listed as symbols.
+///
+/// Programs have no type checker, so hovers show what is evident from the
+/// term a name is bound to: the parameters of a function literal, or the
+/// type of a literal or of an arithmetic or comparison.
+pub struct LspServer<W: Write> {
+    output: W,
+    /// The open documents, by URI.
+    documents: HashMap<String, Document>,
+    /// Notifications that follow the response to the message being handled.
+    notifications: Vec<Value>,
+}
+
+struct Document {
+    /// The file the document is saved as, which its imports are relative to.
+    path: String,
+    source: Source,
+    names: Names,
+}
+
+/// The text of a file, with where its lines start to convert offsets to the
+/// positions of the protocol, which count lines from 0 and characters in
+/// UTF-16 code units.
+struct Source {
+    text: String,
+    lines: LineIndex,
+}
+
+/// The names a program binds, and where it uses them.
+#[derive(Default)]
+struct Names {
+    definitions: Vec<Definition>,
+    /// Where each name is used, with the index of its definition.
+    uses: Vec<(Location, usize)>,
+}
+
+struct Definition {
+    name: String,
+    /// Where the `let` or the parameter names it.
+    location: Location,
+    /// The `let` up to the end of the value it binds, or `None` for
+    /// parameters.
+    binding: Option<Location>,
+    /// What the value is known to be.
+    detail: Option<String>,
+    /// Whether it is bound to a function literal.
+    function: bool,
+    /// Whether it is bound by the chain of `let`s making up the script,
+    /// which functions can refer to before it is bound.
+    global: bool,
+}
+
+impl<W: Write> LspServer<W> {
+    pub fn new(output: W) -> Self {
+        Self {
+            output,
+            documents: HashMap::new(),
+            notifications: Vec::new(),
+        }
+    }
+
+    /// Answers messages from `input` until it ends or the client says to
+    /// exit.
+    pub fn serve(&mut self, mut input: impl BufRead) -> Result<()> {
+        while let Some(message) = read_message(&mut input)? {
+            let method = message["method"].as_str().unwrap_or_default().to_owned();
+            let result = self.handle(&method, &message["params"]);
+
+            // Messages without an id are notifications, which get no
+            // response, and those without a method answer nothing the
+            // server asked.
+            if let Some(id) = message.get("id").filter(|_| !method.is_empty()) {
+                let mut response = json!({ "jsonrpc": "2.0", "id": id });
+                match result {
+                    Ok(Some(result)) => response["result"] = result,
+                    Ok(None) => {
+                        response["error"] = json!({
+                            "code": METHOD_NOT_FOUND,
+                            "message": format!("Unsupported method {method}."),
+                        })
+                    }
+                    Err(error) => {
+                        response["error"] =
+                            json!({ "code": INTERNAL_ERROR, "message": error.to_string() })
+                    }
+                }
+                self.send(response)?;
+            }
+
+            for notification in std::mem::take(&mut self.notifications) {
+                self.send(notification)?;
+            }
+
+            if method == "exit" {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles a message, returning the result of its response, or `None`
+    /// if the method is not supported.
+    fn handle(&mut self, method: &str, params: &Value) -> Result<Option<Value>> {
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    // Clients send the whole text of a document on changes.
+                    "textDocumentSync": 1,
+                    "hoverProvider": true,
+                    "definitionProvider": true,
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "rvm", "version": env!("CARGO_PKG_VERSION") },
+            }),
+            "initialized" | "shutdown" | "exit" => Value::Null,
+            "textDocument/didOpen" => {
+                let document = &params["textDocument"];
+                let text = document["text"]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("Opening a document needs its text."))?;
+                self.update(uri(params)?, text);
+                Value::Null
+            }
+            "textDocument/didChange" => {
+                let text = params["contentChanges"]
+                    .as_array()
+                    .and_then(|changes| changes.last())
+                    .and_then(|change| change["text"].as_str())
+                    .ok_or_else(|| anyhow!("Changing a document needs its new text."))?;
+                self.update(uri(params)?, text);
+                Value::Null
+            }
+            "textDocument/didClose" => {
+                let uri = uri(params)?;
+                self.documents.remove(uri);
+                self.publish(uri, Vec::new());
+                Value::Null
+            }
+            "textDocument/hover" => {
+                let document = self.document(params)?;
+                document.hover(&params["position"]).unwrap_or(Value::Null)
+            }
+            "textDocument/definition" => {
+                let document = self.document(params)?;
+                match document.definition_at(&params["position"]) {
+                    Some((_, definition)) => document.location(&definition.location)?,
+                    None => Value::Null,
+                }
+            }
+            "textDocument/documentSymbol" => {
+                let document = self.document(params)?;
+                let symbols: Vec<Value> = document
+                    .names
+                    .definitions
+                    .iter()
+                    .filter(|definition| {
+                        definition.global && definition.location.filename == document.path
+                    })
+                    .filter_map(|definition| {
+                        let binding = definition.binding.as_ref()?;
+                        let mut symbol = json!({
+                            "name": definition.name,
+                            // Function or Variable.
+                            "kind": if definition.function { 12 } else { 13 },
+                            "range": document.source.range(binding),
+                            "selectionRange": document.source.range(&definition.location),
+                        });
+                        if let Some(detail) = &definition.detail {
+                            symbol["detail"] = json!(detail);
+                        }
+                        Some(symbol)
+                    })
+                    .collect();
+                json!(symbols)
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(result))
+    }
+
+    /// Takes in a new version of a document and publishes what compiling it
+    /// found.
+    fn update(&mut self, uri: &str, text: &str) {
+        let document = Document::new(path_of(uri), text);
+        let diagnostics = document.diagnose();
+
+        self.documents.insert(uri.to_owned(), document);
+        self.publish(uri, diagnostics);
+    }
+
+    fn publish(&mut self, uri: &str, diagnostics: Vec<Value>) {
+        self.notifications.push(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }));
+    }
+
+    fn document(&self, params: &Value) -> Result<&Document> {
+        let uri = uri(params)?;
+        self.documents
+            .get(uri)
+            .ok_or_else(|| anyhow!("Document {uri} is not open."))
+    }
+
+    fn send(&mut self, message: Value) -> Result<()> {
+        let body = serde_json::to_string(&message)?;
+        write!(self.output, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+        self.output.flush()?;
+        Ok(())
+    }
+}
+
+impl Document {
+    fn new(path: String, text: &str) -> Self {
+        let file = match imports::parse_with_imports(&path, text) {
+            Err(error) if error.is::<ParseError>() => ast::parse_recovering(&path, text),
+            parsed => parsed.ok(),
+        };
+        let names = file.map_or_else(Names::default, |file| Names::new(&path, &file.expression));
+
+        Self {
+            path,
+            source: Source::new(text.to_owned()),
+            names,
+        }
+    }
+
+    /// The errors and warnings compiling the document finds, as the protocol
+    /// has them.
+    fn diagnose(&self) -> Vec<Value> {
+        let diagnostic = |location: &Location, severity: u8, message: &str| {
+            json!({
+                "range": self.source.range(location),
+                "severity": severity,
+                "source": "rvm",
+                "message": message,
+            })
+        };
+        let mut diagnostics = Vec::new();
+
+        let mut vm = Vm::new().with_error_recovery(true);
+        match vm.compile_program(&self.path, &self.source.text) {
+            Err(error) if error.is::<CompileErrors>() => {}
+            Err(error) => {
+                // The parser only points at syntax errors it recovered from.
+                let spans = error
+                    .downcast_ref::<ParseError>()
+                    .and_then(|error| error.related())
+                    .into_iter()
+                    .flatten()
+                    .flat_map(|related| {
+                        let help = related.help().map(|help| help.to_string());
+                        let message = match help {
+                            Some(help) => format!("{related}: {help}"),
+                            None => related.to_string(),
+                        };
+                        related.labels().into_iter().flatten().map(move |label| {
+                            (
+                                label.offset()..label.offset() + label.len(),
+                                message.clone(),
+                            )
+                        })
+                    });
+                diagnostics.extend(spans.map(|(span, message)| {
+                    let location = Location {
+                        start: span.start,
+                        end: span.end,
+                        filename: self.path.clone(),
+                    };
+                    diagnostic(&location, 1, &message)
+                }));
+
+                if diagnostics.is_empty() {
+                    let end = self.source.text.len();
+                    let location = Location {
+                        start: end,
+                        end,
+                        filename: self.path.clone(),
+                    };
+                    diagnostics.push(diagnostic(&location, 1, &error.to_string()));
+                }
+                return diagnostics;
+            }
+            Ok(_) => {}
+        }
+
+        let report = vm.compile_report();
+        for error in &report.errors {
+            if error.location.filename == self.path {
+                diagnostics.push(diagnostic(&error.location, 1, &error.message));
+            }
+        }
+        for warning in &report.warnings {
+            if warning.location.filename == self.path {
+                let mut diagnostic = diagnostic(&warning.location, 2, &warning.message);
+                diagnostic["code"] = json!(warning.kind.name());
+                diagnostics.push(diagnostic);
+            }
+        }
+
+        diagnostics
+    }
+
+    /// The definition of the name at `position`, whether where it is bound
+    /// or where it is used, along with where the name is there.
+    fn definition_at(&self, position: &Value) -> Option<(&Location, &Definition)> {
+        let offset = self.source.offset(position)?;
+        let covers = |location: &Location| {
+            location.filename == self.path && (location.start..=location.end).contains(&offset)
+        };
+
+        let used = self
+            .names
+            .uses
+            .iter()
+            .find(|(location, _)| covers(location))
+            .map(|(location, index)| (location, &self.names.definitions[*index]));
+        used.or_else(|| {
+            self.names
+                .definitions
+                .iter()
+                .find(|definition| covers(&definition.location))
+                .map(|definition| (&definition.location, definition))
+        })
+    }
+
+    /// The name at `position` with what its value is known to be.
+    fn hover(&self, position: &Value) -> Option<Value> {
+        let (location, definition) = self.definition_at(position)?;
+        let contents = match &definition.detail {
+            Some(detail) => format!("{}: {detail}", definition.name),
+            None => definition.name.clone(),
+        };
+
+        Some(json!({
+            "contents": { "kind": "plaintext", "value": contents },
+            "range": self.source.range(location),
+        }))
+    }
+
+    /// A location of the protocol, which may be in a file the document
+    /// imports.
+    fn location(&self, location: &Location) -> Result<Value> {
+        if location.filename == self.path {
+            return Ok(json!({
+                "uri": uri_of(&self.path),
+                "range": self.source.range(location),
+            }));
+        }
+
+        let text = fs::read_to_string(&location.filename)
+            .with_context(|| format!("Could not read {}.", location.filename))?;
+        Ok(json!({
+            "uri": uri_of(&location.filename),
+            "range": Source::new(text).range(location),
+        }))
+    }
+}
+
+impl Source {
+    fn new(text: String) -> Self {
+        let lines = LineIndex::new(&text);
+        Self { text, lines }
+    }
+
+    fn position(&self, offset: usize) -> Value {
+        let offset = offset.min(self.text.len());
+        let line = self.lines.line_of(offset);
+        let start = self.lines.start_of(line).unwrap_or_default();
+        let character = self
+            .text
+            .get(start..offset)
+            .map_or(0, |text| text.encode_utf16().count());
+
+        json!({ "line": line - 1, "character": character })
+    }
+
+    fn range(&self, location: &Location) -> Value {
+        json!({ "start": self.position(location.start), "end": self.position(location.end) })
+    }
+
+    /// The offset of a position of the protocol. Characters past the end of
+    /// a line are taken to be at its end.
+    fn offset(&self, position: &Value) -> Option<usize> {
+        let line = position["line"].as_u64()? as usize + 1;
+        let character = position["character"].as_u64()? as usize;
+        let start = self.lines.start_of(line)?;
+
+        let mut units = 0;
+        for (index, c) in self.text[start..].char_indices() {
+            if units >= character || c == '\n' {
+                return Some(start + index);
+            }
+            units += c.len_utf16();
+        }
+        Some(self.text.len())
+    }
+}
+
+impl Names {
+    /// Resolves the names `term` uses to where they are bound, walking it with
+    /// an explicit stack like the compiler. `let`s are lexically scoped, and
+    /// a function literal bound by a `let` sees the name it is bound to.
+    /// Names bound nowhere in scope refer to the global of the same name, if
+    /// there is one. Only uses in the file at `path` are recorded.
+    fn new(path: &str, term: &Term) -> Self {
+        enum Visit<'t> {
+            /// A term, and whether it is in the chain of `let`s making up the
+            /// script.
+            Term(&'t Term, bool),
+            /// A function literal, with the name it is bound to, if any.
+            Function(&'t rinha_ast::Function, Option<&'t str>),
+            Bind(usize),
+            Unbind(usize),
+        }
+
+        let mut names = Self::default();
+        let mut scope: Vec<usize> = Vec::new();
+        let mut unresolved: Vec<&Var> = Vec::new();
+        let mut stack = vec![Visit::Term(term, true)];
+
+        while let Some(visit) = stack.pop() {
+            let (term, statement) = match visit {
+                Visit::Term(term, statement) => (term, statement),
+                Visit::Function(f, name) => {
+                    let detail = match name {
+                        Some(name) => format!("parameter of {name}"),
+                        None => "parameter".to_owned(),
+                    };
+                    for parameter in &f.parameters {
+                        let index =
+                            names.define(parameter, None, Some(detail.clone()), false, false);
+                        scope.push(index);
+                    }
+                    stack.push(Visit::Unbind(f.parameters.len()));
+                    stack.push(Visit::Term(&f.value, false));
+                    continue;
+                }
+                Visit::Bind(index) => {
+                    scope.push(index);
+                    continue;
+                }
+                Visit::Unbind(count) => {
+                    scope.truncate(scope.len() - count);
+                    continue;
+                }
+            };
+
+            match term {
+                Term::Bool(_) | Term::Int(_) | Term::Str(_) | Term::Error(_) => {}
+                Term::Var(var) => {
+                    let bound = scope
+                        .iter()
+                        .rev()
+                        .find(|index| names.definitions[**index].name == var.text);
+                    match bound {
+                        Some(index) => names.uses.push((var.location.clone().into(), *index)),
+                        None => unresolved.push(var),
+                    }
+                }
+                Term::First(f) => stack.push(Visit::Term(&f.value, false)),
+                Term::Second(s) => stack.push(Visit::Term(&s.value, false)),
+                Term::Print(p) => stack.push(Visit::Term(&p.value, false)),
+                Term::Tuple(t) => {
+                    stack.push(Visit::Term(&t.second, false));
+                    stack.push(Visit::Term(&t.first, false));
+                }
+                Term::Binary(b) => {
+                    stack.push(Visit::Term(&b.rhs, false));
+                    stack.push(Visit::Term(&b.lhs, false));
+                }
+                Term::If(i) => {
+                    stack.push(Visit::Term(&i.otherwise, false));
+                    stack.push(Visit::Term(&i.then, false));
+                    stack.push(Visit::Term(&i.condition, false));
+                }
+                Term::Call(c) => {
+                    for argument in c.arguments.iter().rev() {
+                        stack.push(Visit::Term(argument, false));
+                    }
+                    stack.push(Visit::Term(&c.callee, false));
+                }
+                Term::Function(f) => stack.push(Visit::Function(f, None)),
+                Term::Let(l) if l.name.text == SEQUENCE_BINDING => {
+                    stack.push(Visit::Term(&l.next, statement));
+                    stack.push(Visit::Term(&l.value, false));
+                }
+                Term::Let(l) => {
+                    let binding = Location {
+                        start: l.location.start,
+                        end: l.value.location().end,
+                        filename: l.location.filename.clone(),
+                    };
+                    let function = matches!(*l.value, Term::Function(_));
+                    let index = names.define(
+                        &l.name,
+                        Some(binding),
+                        describe(&l.value),
+                        function,
+                        statement,
+                    );
+
+                    stack.push(Visit::Unbind(1));
+                    stack.push(Visit::Term(&l.next, statement));
+                    match &*l.value {
+                        Term::Function(f) => {
+                            stack.push(Visit::Function(f, Some(&l.name.text)));
+                            stack.push(Visit::Bind(index));
+                        }
+                        value => {
+                            stack.push(Visit::Bind(index));
+                            stack.push(Visit::Term(value, false));
+                        }
+                    }
+                }
+            }
+        }
+
+        for var in unresolved {
+            let global = names
+                .definitions
+                .iter()
+                .position(|definition| definition.global && definition.name == var.text);
+            if let Some(index) = global {
+                names.uses.push((var.location.clone().into(), index));
+            }
+        }
+        names.uses.retain(|(location, _)| location.filename == path);
+
+        names
+    }
+
+    fn define(
+        &mut self,
+        name: &Var,
+        binding: Option<Location>,
+        detail: Option<String>,
+        function: bool,
+        global: bool,
+    ) -> usize {
+        self.definitions.push(Definition {
+            name: name.text.clone(),
+            location: name.location.clone().into(),
+            binding,
+            detail,
+            function,
+            global,
+        });
+        self.definitions.len() - 1
+    }
+}
+
+/// What `term` evidently evaluates to, if anything.
+fn describe(term: &Term) -> Option<String> {
+    let description = match term {
+        Term::Function(f) => {
+            let parameters: Vec<&str> = f.parameters.iter().map(|p| p.text.as_str()).collect();
+            return Some(format!("fn ({})", parameters.join(", ")));
+        }
+        Term::Int(_) => "int",
+        Term::Str(_) => "str",
+        Term::Bool(_) => "bool",
+        Term::Tuple(_) => "tuple",
+        Term::Binary(b) => match b.op {
+            // Adding strings concatenates them.
+            BinaryOp::Add => return None,
+            BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => "int",
+            _ => "bool",
+        },
+        _ => return None,
+    };
+
+    Some(description.to_owned())
+}
+
+fn uri(params: &Value) -> Result<&str> {
+    params["textDocument"]["uri"]
+        .as_str()
+        .ok_or_else(|| anyhow!("The request names no document."))
+}
+
+/// The path of a `file:` URI, or the URI itself for other schemes.
+fn path_of(uri: &str) -> String {
+    let Some(path) = uri.strip_prefix("file://") else {
+        return uri.to_owned();
+    };
+
+    let mut bytes = Vec::new();
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..2)
+            .filter(|_| byte == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                bytes.push(byte);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+fn uri_of(path: &str) -> String {
+    let mut uri = "file://".to_owned();
+    for byte in path.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    uri
+}
@@ -1,24 +1,923 @@
 use anyhow::{bail, Context, Result};
-use std::{env::args, fs, io::read_to_string};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde_json::{json, Value};
+use std::{
+    env, fs,
+    io::{self, read_to_string, IsTerminal},
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+use tracing_subscriber::{filter::LevelFilter, fmt::format::FmtSpan, EnvFilter};
 
-use rvm::vm::Vm;
+use rvm::{
+    arithmetic::Overflow,
+    artifact::CompiledProgram,
+    ast,
+    batch::{self, BatchOptions},
+    cache::CompilationCache,
+    compare::{compare_directory, shell_quote, Outcome},
+    dap::DapServer,
+    debugger::Debugger,
+    diagnostics::{render_error, CompileErrors},
+    emit_c, emit_rust, emit_wasm, formatter,
+    gc::Allocation,
+    lsp::LspServer,
+    native::NativeRegistry,
+    optimizer::{Pass, Passes},
+    printing::{Captured, PrintResult, Printing},
+    profiler,
+    replay::ReplayLog,
+    sandbox::{Effects, SandboxPolicy},
+    source_map::LineIndex,
+    value::FinalValue,
+    vm::{RuntimeError, Vm, DEFAULT_MAX_CALL_DEPTH},
+};
+
+#[derive(Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// The program to run.
+    #[arg(default_value = "/var/rinha/source.rinha")]
+    path: String,
+
+    /// Prints the program's AST as JSON, in the format of the rinha
+    /// specification, instead of running it.
+    #[arg(long)]
+    emit_ast: bool,
+
+    /// Enables the natives of a namespace, such as `io` or `math`. Can be
+    /// repeated.
+    #[arg(long = "allow", value_name = "NAMESPACE")]
+    allowed: Vec<String>,
+
+    /// Keeps the program from the terminal: `print` and reading input fail
+    /// with an error, do nothing with `--pure=ignore`, or, with
+    /// `--pure=capture`, print only into the `--output json` document. No
+    /// natives can be allowed along with it.
+    #[arg(
+        long,
+        value_enum,
+        value_name = "MODE",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "error",
+        conflicts_with = "allowed"
+    )]
+    pure: Option<PureMode>,
+
+    /// Stores compiled programs in this directory and reuses them when the
+    /// same source is run again.
+    #[arg(long, value_name = "DIRECTORY")]
+    cache_dir: Option<PathBuf>,
+
+    /// Passes a value to the program, which reads the first one as `arg0`,
+    /// the second as `arg1` and so on, and their count as `argc`. Values that
+    /// parse as integers are passed as integers. Can be repeated.
+    #[arg(long = "arg", value_name = "VALUE", allow_hyphen_values = true)]
+    arguments: Vec<String>,
+
+    /// How to report the run. `json` prints a single document with the
+    /// result, the printed lines and the number of instructions executed.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// How to report errors. `text` underlines the code they are about in
+    /// the program's source, along with the calls that led to runtime
+    /// errors. `json` prints a document to standard error with the message
+    /// and, for runtime errors, every active call frame with its function,
+    /// source file, line, column and instruction.
+    #[arg(long, value_enum, default_value_t = ErrorFormat::Text)]
+    error_format: ErrorFormat,
+
+    /// How to reclaim memory. `arena` never runs the garbage collector and
+    /// frees everything at once when the program ends, using more memory
+    /// for less work.
+    #[arg(long, value_enum, default_value_t = AllocationMode::Gc)]
+    alloc: AllocationMode,
+
+    /// What arithmetic does when the result does not fit in 32 bits: wrap
+    /// around, clamp to the nearest integer that fits, fail the run, or
+    /// promote it to a big integer.
+    #[arg(long, value_enum, default_value_t = OverflowMode::Wrapping)]
+    overflow: OverflowMode,
+
+    /// What `print` evaluates to: the value it printed, as the specification
+    /// says, or the string it printed.
+    #[arg(long, value_enum, default_value_t = PrintResultMode::Argument)]
+    print_result: PrintResultMode,
+
+    /// Leaves out the newline `print` writes after each value.
+    #[arg(long)]
+    no_print_newline: bool,
+
+    /// Reports what the VM did to standard error once the program ends:
+    /// instructions executed per opcode, peak stack and frame depths,
+    /// memoization hits and misses and values allocated.
+    #[arg(long)]
+    stats: bool,
+
+    /// Writes which functions the program spent its instructions in to this
+    /// file, as collapsed stacks that `inferno-flamegraph` or
+    /// `flamegraph.pl` turn into a flamegraph.
+    #[arg(long, value_name = "FILE")]
+    profile: Option<PathBuf>,
+
+    /// Re-executes this percentage of the calls answered from the
+    /// memoization table and checks their results against it. Entries that
+    /// disagree are reported to standard error and fail the run.
+    #[arg(long, value_name = "PERCENT", value_parser = clap::value_parser!(u8).range(0..=100))]
+    verify_memo: Option<u8>,
+
+    /// Keeps at most this many results in the memoization table, evicting
+    /// the ones not used for a while.
+    #[arg(long, value_name = "ENTRIES")]
+    memo_capacity: Option<usize>,
+
+    /// Turns memoization off, to find out whether it changes how a program
+    /// behaves.
+    #[arg(long, conflicts_with = "memo_capacity")]
+    no_memo: bool,
+
+    /// Stops the program once the values it keeps alive take roughly more
+    /// than this many bytes.
+    #[arg(long, value_name = "BYTES")]
+    memory_limit: Option<usize>,
+
+    /// Stops the program once this many calls are running at once.
+    #[arg(long, value_name = "CALLS", default_value_t = DEFAULT_MAX_CALL_DEPTH)]
+    max_call_depth: usize,
+
+    /// Writes every line the program reads and every result natives return
+    /// to this file, so `--replay` can run the program again exactly as it
+    /// ran this time.
+    #[arg(long, value_name = "FILE", conflicts_with = "replay")]
+    record: Option<PathBuf>,
+
+    /// Runs the program with the lines and native results `--record` wrote
+    /// to this file, instead of reading input and calling natives.
+    #[arg(long, value_name = "FILE")]
+    replay: Option<PathBuf>,
+
+    /// How many instructions pass between the profiler's samples.
+    #[arg(long, value_name = "INSTRUCTIONS", default_value_t = profiler::DEFAULT_INTERVAL)]
+    profile_interval: u64,
+
+    #[command(flatten)]
+    optimizer: OptimizerArgs,
+}
+
+#[derive(Args)]
+struct OptimizerArgs {
+    /// Which optimizer passes to run: none at 0, the long standing ones at 1
+    /// and all of them at 2.
+    #[arg(long, default_value_t = Passes::MAX_LEVEL)]
+    opt_level: u8,
+
+    /// Turns off one optimizer pass, such as `ranges` or `tail-calls`, to
+    /// find out whether it changes how a program behaves. Can be repeated.
+    #[arg(long = "disable-pass", value_name = "PASS")]
+    disabled: Vec<String>,
+}
+
+impl OptimizerArgs {
+    fn passes(&self) -> Result<Passes> {
+        self.disabled
+            .iter()
+            .try_fold(Passes::level(self.opt_level)?, |passes, name| {
+                Ok(passes.without(Pass::from_name(name)?))
+            })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum PureMode {
+    Error,
+    Ignore,
+    Capture,
+}
+
+impl From<PureMode> for Effects {
+    fn from(mode: PureMode) -> Self {
+        match mode {
+            PureMode::Error => Effects::Denied,
+            PureMode::Ignore => Effects::Ignored,
+            PureMode::Capture => Effects::Captured,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum AllocationMode {
+    Gc,
+    Arena,
+}
+
+impl From<AllocationMode> for Allocation {
+    fn from(mode: AllocationMode) -> Self {
+        match mode {
+            AllocationMode::Gc => Allocation::Gc,
+            AllocationMode::Arena => Allocation::Arena,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum OverflowMode {
+    Wrapping,
+    Saturating,
+    Checked,
+    Promoting,
+}
+
+impl From<OverflowMode> for Overflow {
+    fn from(mode: OverflowMode) -> Self {
+        match mode {
+            OverflowMode::Wrapping => Overflow::Wrapping,
+            OverflowMode::Saturating => Overflow::Saturating,
+            OverflowMode::Checked => Overflow::Checked,
+            OverflowMode::Promoting => Overflow::Promoting,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum PrintResultMode {
+    Argument,
+    Text,
+}
+
+impl From<PrintResultMode> for PrintResult {
+    fn from(mode: PrintResultMode) -> Self {
+        match mode {
+            PrintResultMode::Argument => PrintResult::Argument,
+            PrintResultMode::Text => PrintResult::Text,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Target {
+    /// An .rvmc artifact, which can be run in place of the source.
+    Rvmc,
+    /// A WebAssembly module importing its input and output from the host.
+    Wasm,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Language {
+    /// A Rust program, for `rustc -O`.
+    Rust,
+    /// A C11 program, for machines without a Rust toolchain.
+    C,
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Runs every .rinha program and .json AST in a directory at once, each
+    /// in a VM of its own, reporting what each one did.
+    Batch {
+        directory: PathBuf,
+
+        /// How many instructions each program may execute.
+        #[arg(long)]
+        fuel: Option<u64>,
+
+        /// How many milliseconds each program may take.
+        #[arg(long, value_name = "MILLISECONDS")]
+        timeout: Option<u64>,
+
+        /// Roughly how many bytes of values each program may keep alive.
+        #[arg(long, value_name = "BYTES")]
+        memory_limit: Option<usize>,
+
+        /// How many programs run at once. Defaults to one per core.
+        #[arg(long)]
+        threads: Option<usize>,
+
+        /// How to report the runs. `json` prints an array with a document
+        /// per program.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+
+        #[command(flatten)]
+        optimizer: OptimizerArgs,
+    },
+    /// Compiles a program to a standalone Rust or C program, which `rustc
+    /// -O` or any C compiler builds into a native binary running it without
+    /// the VM.
+    Build {
+        path: PathBuf,
+
+        /// Where to write the source. Defaults to the program's path with
+        /// the language's extension.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// What language to write the program in.
+        #[arg(long, value_enum, default_value_t = Language::Rust)]
+        emit: Language,
+
+        #[command(flatten)]
+        optimizer: OptimizerArgs,
+    },
+    /// Compiles a program without running it, reporting the warnings
+    /// compiling it found: unused, shadowed and undefined variables,
+    /// branches that never run and calls with the wrong number of
+    /// arguments. Compiling goes on past errors, which are all reported
+    /// before the warnings, and then the check fails.
+    Check {
+        path: PathBuf,
+
+        /// How to report the warnings. `json` prints the compile report.
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+    },
+    /// Runs every .rinha program in a directory on rvm and on another
+    /// implementation, reporting the programs whose output differs.
+    Compare {
+        /// Shell command running the other implementation. The program path
+        /// is appended as its last argument.
+        #[arg(long)]
+        against: String,
+
+        directory: PathBuf,
+    },
+    /// Compiles a program into an .rvmc artifact, which can be run in place
+    /// of its source, or into a WebAssembly module.
+    Compile {
+        path: PathBuf,
+
+        /// Where to write the artifact. Defaults to the program's path with
+        /// the target's extension.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        /// What to compile the program to.
+        #[arg(long, value_enum, default_value_t = Target::Rvmc)]
+        target: Target,
+
+        /// Compresses the artifact with zstd.
+        #[arg(long)]
+        zstd: bool,
+
+        #[command(flatten)]
+        optimizer: OptimizerArgs,
+    },
+    /// Speaks the Debug Adapter Protocol over standard input and output, so
+    /// editors such as VS Code can debug programs.
+    Dap,
+    /// Runs a program under a debugger driven by commands on standard input,
+    /// one per line: `break LINE|FUNCTION`, `clear LINE|FUNCTION`, `run`,
+    /// `step`, `stepexpr`, `nextline`, `continue`, `stack`, `locals [FRAME]`,
+    /// `env [FRAME]` and `quit`.
+    Debug { path: PathBuf },
+    /// An alias for `build --emit c`, taking the same arguments but `--emit`.
+    /// It predates `build` emitting C and is kept for the scripts using it.
+    EmitC {
+        path: PathBuf,
+
+        /// Where to write the C source. Defaults to the program's path with
+        /// a .c extension.
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+
+        #[command(flatten)]
+        optimizer: OptimizerArgs,
+    },
+    /// Rewrites programs in the canonical format: one statement per line,
+    /// blocks indented by two spaces and no more parentheses than needed.
+    Fmt {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+
+        /// Changes nothing, but lists the programs that are not formatted
+        /// and fails if there are any.
+        #[arg(long)]
+        check: bool,
+    },
+    /// Shows where an .rvmc artifact came from and what it contains.
+    Inspect { path: PathBuf },
+    /// Speaks the Language Server Protocol over standard input and output,
+    /// so editors can show the errors and warnings in programs, what names
+    /// are bound to and where.
+    Lsp,
+}
 
 fn main() -> Result<()> {
-    let args: Vec<String> = args().collect();
+    let cli = Cli::parse();
+    init_tracing();
+
+    match &cli.command {
+        None if cli.emit_ast => emit_ast(&cli.path),
+        None => match run(&cli) {
+            Err(error) if cli.error_format == ErrorFormat::Json => {
+                eprintln!(
+                    "{}",
+                    serde_json::to_string_pretty(&error_document(&error, &cli.path))?
+                );
+                process::exit(1);
+            }
+            Err(error) => match source_of(&cli.path) {
+                Some(source) => {
+                    let colors = io::stderr().is_terminal();
+                    eprint!("{}", render_error(&error, &cli.path, &source, colors));
+                    process::exit(1);
+                }
+                None => Err(error),
+            },
+            result => result,
+        },
+        Some(Command::Batch {
+            directory,
+            fuel,
+            timeout,
+            memory_limit,
+            threads,
+            output,
+            optimizer,
+        }) => {
+            let options = BatchOptions {
+                fuel: *fuel,
+                timeout: timeout.map(Duration::from_millis),
+                memory_limit: *memory_limit,
+                passes: optimizer.passes()?,
+                threads: *threads,
+            };
+            run_batch(directory, &options, *output)
+        }
+        Some(Command::Build {
+            path,
+            output,
+            emit,
+            optimizer,
+        }) => build(path, output.clone(), *emit, optimizer.passes()?),
+        Some(Command::Check { path, output }) => check(path, *output),
+        Some(Command::Compare { against, directory }) => compare(against, directory),
+        Some(Command::Compile {
+            path,
+            output,
+            target,
+            zstd,
+            optimizer,
+        }) => compile(path, output.clone(), *target, *zstd, optimizer.passes()?),
+        Some(Command::Dap) => DapServer::new(io::stdout()).serve(io::stdin().lock()),
+        Some(Command::Debug { path }) => debug(path),
+        Some(Command::EmitC {
+            path,
+            output,
+            optimizer,
+        }) => build(path, output.clone(), Language::C, optimizer.passes()?),
+        Some(Command::Fmt { paths, check }) => format(paths, *check),
+        Some(Command::Inspect { path }) => inspect(path),
+        Some(Command::Lsp) => LspServer::new(io::stdout()).serve(io::stdin().lock()),
+    }
+}
 
-    let path = match args.len() {
-        1 => "/var/rinha/source.rinha",
-        2 => &args[1],
-        _ => bail!("Usage: rvm <filepath>."),
+fn build(path: &Path, output: Option<PathBuf>, emit: Language, passes: Passes) -> Result<()> {
+    let contents = fs::read_to_string(path).context("Could not read file.")?;
+
+    let program = Vm::new()
+        .with_passes(passes)
+        .compile_program(&path.to_string_lossy(), &contents)?;
+
+    let (source, extension) = match emit {
+        Language::Rust => (emit_rust::emit_rust(&program)?, "rs"),
+        Language::C => (emit_c::emit_c(&program)?, "c"),
     };
 
+    let output = output.unwrap_or_else(|| path.with_extension(extension));
+    fs::write(&output, source).with_context(|| format!("Could not write {}.", output.display()))?;
+
+    Ok(())
+}
+
+fn check(path: &Path, output: OutputFormat) -> Result<()> {
+    let source = fs::read_to_string(path).context("Could not read file.")?;
+    let filename = path.to_string_lossy();
+
+    let mut vm = Vm::new().with_error_recovery(true);
+    if let Err(error) = vm.compile_program(&filename, &source) {
+        if !error.is::<CompileErrors>() {
+            return Err(error);
+        }
+    }
+    let report = vm.compile_report();
+
+    match output {
+        OutputFormat::Text => eprint!(
+            "{}",
+            report.render(&filename, &source, io::stderr().is_terminal())
+        ),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(report)?),
+    }
+
+    if !report.errors.is_empty() {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn format(paths: &[PathBuf], check: bool) -> Result<()> {
+    let mut unformatted = 0;
+
+    for path in paths {
+        let source = fs::read_to_string(path)
+            .with_context(|| format!("Could not read {}.", path.display()))?;
+        let filename = path.to_string_lossy();
+
+        let formatted = match formatter::format_program(&filename, &source) {
+            Ok(formatted) => formatted,
+            Err(error) => {
+                let colors = io::stderr().is_terminal();
+                eprint!("{}", render_error(&error, &filename, &source, colors));
+                process::exit(1);
+            }
+        };
+        if formatted == source {
+            continue;
+        }
+
+        if check {
+            println!("{}", path.display());
+            unformatted += 1;
+        } else {
+            fs::write(path, formatted)
+                .with_context(|| format!("Could not write {}.", path.display()))?;
+        }
+    }
+
+    if unformatted > 0 {
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn debug(path: &Path) -> Result<()> {
+    let source = fs::read_to_string(path).context("Could not read file.")?;
+
+    let mut debugger = Debugger::new(&path.to_string_lossy(), &source)?;
+    debugger.serve(io::stdin().lock(), &mut io::stdout())
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    let passes = cli.optimizer.passes()?;
+    let output = cli.output;
+
+    let policy = cli
+        .allowed
+        .iter()
+        .fold(SandboxPolicy::new(), |policy, namespace| {
+            policy.allow(namespace)
+        });
+
+    let mut vm = Vm::new()
+        .with_natives(&NativeRegistry::standard(), &policy)
+        .with_arguments(&cli.arguments)
+        .with_passes(passes.clone())
+        .with_allocation(cli.alloc.into())
+        .with_overflow(cli.overflow.into())
+        .with_printing(Printing {
+            newline: !cli.no_print_newline,
+            result: cli.print_result.into(),
+        })
+        .with_max_call_depth(cli.max_call_depth);
+
+    if cli.stats {
+        vm = vm.with_opcode_histogram();
+    }
+
+    if cli.profile.is_some() {
+        vm = vm.with_profiler(cli.profile_interval);
+    }
+
+    if cli.no_memo {
+        vm = vm.with_memo_capacity(0);
+    } else if let Some(capacity) = cli.memo_capacity {
+        vm = vm.with_memo_capacity(capacity);
+    }
+
+    if let Some(percentage) = cli.verify_memo {
+        vm = vm.with_memo_verification(percentage);
+    }
+
+    if let Some(bytes) = cli.memory_limit {
+        vm = vm.with_memory_limit(bytes);
+    }
+
+    if let Some(mode) = cli.pure {
+        vm = vm.with_effects(mode.into());
+    }
+
+    if cli.record.is_some() {
+        vm = vm.with_recording();
+    }
+
+    if let Some(path) = &cli.replay {
+        let bytes =
+            fs::read(path).with_context(|| format!("Could not read {}.", path.display()))?;
+        vm = vm.with_replay(ReplayLog::from_bytes(&bytes)?);
+    }
+
+    let printed = Captured::default();
+    if output == OutputFormat::Json {
+        vm = vm.with_output(printed.clone());
+    }
+
+    let result = execute(&mut vm, &cli.path, cli.cache_dir.as_deref(), passes);
+
+    if output == OutputFormat::Json {
+        let stdout = printed.text();
+        let lines: Vec<&str> = match cli.pure {
+            Some(PureMode::Capture) => vm.printed().iter().map(String::as_str).collect(),
+            _ => stdout.lines().collect(),
+        };
+        let mut document = json!({
+            "result": result.as_ref().ok(),
+            "stdout": lines,
+            "instructions_executed": vm.instructions_executed(),
+        });
+        if let Err(error) = &result {
+            document["error"] = json!(error.to_string());
+        }
+
+        println!("{}", serde_json::to_string_pretty(&document)?);
+    }
+
+    if cli.stats {
+        report_stats(&vm);
+    }
+
+    if let (Some(path), Some(log)) = (&cli.record, vm.take_recording()) {
+        fs::write(path, log.to_bytes())
+            .with_context(|| format!("Could not write {}.", path.display()))?;
+    }
+
+    if let Some(profile) = &cli.profile {
+        let mut file = fs::File::create(profile)
+            .with_context(|| format!("Could not write {}.", profile.display()))?;
+        vm.write_profile(&mut file)?;
+    }
+
+    let mismatches = vm.memo_mismatches();
+    for mismatch in mismatches {
+        let arguments = mismatch
+            .arguments
+            .iter()
+            .map(serde_json::to_string)
+            .collect::<Result<Vec<_>, _>>()?;
+        eprintln!(
+            "unsound memoization: {}({}) was memoized as {} but computes {}",
+            mismatch.function,
+            arguments.join(", "),
+            serde_json::to_string(&mismatch.memoized)?,
+            serde_json::to_string(&mismatch.computed)?
+        );
+    }
+    if result.is_ok() && !mismatches.is_empty() {
+        bail!("Memoization was unsound for {} calls.", mismatches.len());
+    }
+
+    result.map(|_| ())
+}
+
+fn report_stats(vm: &Vm) {
+    let stats = vm.stats();
+
+    eprintln!("instructions:       {}", stats.instructions);
+    for (opcode, count) in &stats.opcodes {
+        eprintln!("  {opcode:<18}{count}");
+    }
+    eprintln!("peak stack depth:   {}", stats.peak_stack_depth);
+    eprintln!("peak frame depth:   {}", stats.peak_frame_depth);
+    eprintln!("memoization hits:   {}", stats.memoization_hits);
+    eprintln!("memoization misses: {}", stats.memoization_misses);
+    eprintln!("memoization checks: {}", stats.memoization_checks);
+    eprintln!("memo evictions:     {}", stats.memoization_evictions);
+    eprintln!("values allocated:   {}", stats.allocations);
+    eprintln!("call cache hits:    {}", stats.inline_cache_hits);
+    eprintln!("call cache misses:  {}", stats.inline_cache_misses);
+}
+
+fn execute(
+    vm: &mut Vm,
+    path: &str,
+    cache_dir: Option<&Path>,
+    passes: Passes,
+) -> Result<FinalValue> {
+    if path.ends_with(".rvmc") {
+        let bytes = fs::read(path).context("Could not read file.")?;
+        let program = CompiledProgram::from_bytes(&bytes)?;
+        return vm.interpret_program(&program);
+    }
+
     let file = fs::File::open(path)?;
     let contents: String = read_to_string(file).context("Could not read file.")?;
 
-    let mut vm = Vm::new();
-    let _result = vm.interpret(path, &contents)?;
+    if path.ends_with(".json") {
+        vm.interpret_json(&contents)
+    } else if let Some(cache_dir) = cache_dir {
+        let program = CompilationCache::new(cache_dir)
+            .with_passes(passes)
+            .compile(path, &contents)?;
+        vm.interpret_program(&program)
+    } else {
+        vm.interpret(path, &contents)
+    }
+}
+
+/// The source of the program at `path`, unless it is an artifact or an AST
+/// or cannot be read.
+fn source_of(path: &str) -> Option<String> {
+    if path.ends_with(".rvmc") || path.ends_with(".json") {
+        return None;
+    }
+
+    fs::read_to_string(path).ok()
+}
+
+/// Describes an error as JSON, resolving the frames of runtime errors to
+/// lines and columns of the program's source. Programs loaded from
+/// artifacts or ASTs have no source to resolve them against.
+fn error_document(error: &anyhow::Error, path: &str) -> Value {
+    let Some(runtime_error) = error.downcast_ref::<RuntimeError>() else {
+        return json!({ "error": error.to_string(), "frames": [] });
+    };
+
+    let lines = source_of(path).map(|source| LineIndex::new(&source));
+
+    let frames: Vec<Value> = runtime_error
+        .trace
+        .frames
+        .iter()
+        .map(|frame| {
+            let position = frame.offset.zip(lines.as_ref());
+            json!({
+                "function": frame.function,
+                "file": path,
+                "line": position.map(|(offset, lines)| lines.line_of(offset)),
+                "column": position.map(|(offset, lines)| lines.column_of(offset)),
+                "instruction": frame.address,
+            })
+        })
+        .collect();
+
+    json!({ "error": runtime_error.error.to_string(), "frames": frames })
+}
+
+/// Logs to stderr what `RUST_LOG` asks for, such as `RUST_LOG=rvm=debug` for
+/// how long each phase and function took to compile and how much fuel runs
+/// used. Nothing is logged by default.
+fn init_tracing() {
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::OFF.into())
+        .from_env_lossy();
+
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_writer(io::stderr)
+        .init();
+}
+
+fn emit_ast(path: &str) -> Result<()> {
+    let contents = fs::read_to_string(path).context("Could not read file.")?;
+    let file = ast::File::parse(path, &contents)?;
+
+    println!("{}", serde_json::to_string_pretty(&file)?);
+
+    Ok(())
+}
+
+fn compile(
+    path: &Path,
+    output: Option<PathBuf>,
+    target: Target,
+    zstd: bool,
+    passes: Passes,
+) -> Result<()> {
+    let contents = fs::read_to_string(path).context("Could not read file.")?;
+
+    let program = Vm::new()
+        .with_passes(passes)
+        .compile_program(&path.to_string_lossy(), &contents)?;
+
+    let (bytes, extension) = match target {
+        Target::Rvmc if zstd => (compress(&program)?, "rvmc"),
+        Target::Rvmc => (program.to_bytes()?, "rvmc"),
+        Target::Wasm if zstd => bail!("Only .rvmc artifacts can be compressed."),
+        Target::Wasm => (emit_wasm::emit_wasm(&program)?, "wasm"),
+    };
+
+    let output = output.unwrap_or_else(|| path.with_extension(extension));
+    fs::write(&output, bytes).with_context(|| format!("Could not write {}.", output.display()))?;
+
+    Ok(())
+}
+
+fn inspect(path: &Path) -> Result<()> {
+    let bytes = fs::read(path).context("Could not read file.")?;
+
+    let header = CompiledProgram::read_header(&bytes)?;
+    let program = CompiledProgram::from_bytes(&bytes)?;
+    let metadata = &program.metadata;
+
+    let instructions = program.script.len()
+        + program
+            .functions
+            .iter()
+            .map(|function| function.bytecode.len())
+            .sum::<usize>();
+
+    println!("format version:   {}", header.version);
+    println!(
+        "compression:      {}",
+        if header.compressed { "zstd" } else { "none" }
+    );
+    println!("source:           {}", metadata.filename);
+    println!("source sha-256:   {}", metadata.source_digest());
+    println!("compiler version: {}", metadata.compiler_version);
+    println!("options:          {}", metadata.options.join(", "));
+    println!("constants:        {}", program.constants.len());
+    println!("identifiers:      {}", program.identifiers.len());
+    println!("functions:        {}", program.functions.len());
+    println!("instructions:     {instructions}");
+
+    Ok(())
+}
+
+#[cfg(feature = "zstd")]
+fn compress(program: &CompiledProgram) -> Result<Vec<u8>> {
+    program.to_compressed_bytes(19)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress(_program: &CompiledProgram) -> Result<Vec<u8>> {
+    bail!("rvm was built without the zstd feature.")
+}
+
+fn run_batch(directory: &Path, options: &BatchOptions, output: OutputFormat) -> Result<()> {
+    let reports = batch::run_all(&batch::programs(directory)?, options)?;
+    let failures = reports
+        .iter()
+        .filter(|report| report.error.is_some())
+        .count();
+
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    } else {
+        for report in &reports {
+            println!("{report}");
+        }
+        println!("{} programs run, {failures} failed.", reports.len());
+    }
+
+    if failures > 0 {
+        bail!("{failures} programs failed.");
+    }
+
+    Ok(())
+}
+
+fn compare(against: &str, directory: &Path) -> Result<()> {
+    let executable = env::current_exe()?;
+    let ours = shell_quote(&executable.to_string_lossy());
+
+    let comparisons = compare_directory(&ours, against, directory)?;
+
+    let mut differences = 0;
+    for comparison in &comparisons {
+        if comparison.outcome != Outcome::Same {
+            differences += 1;
+        }
+        println!("{comparison}");
+    }
+
+    println!(
+        "{} programs compared, {differences} with differences.",
+        comparisons.len()
+    );
 
-    //println!("{}", result);
+    if differences > 0 {
+        bail!("The implementations disagree on {differences} programs.");
+    }
 
     Ok(())
 }
@@ -0,0 +1,161 @@
+use std::{collections::HashMap, mem};
+
+use crate::value::Tagged;
+
+/// The function and arguments a call's result is memoized under.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemoKey {
+    pub function: u32,
+    pub arguments: Box<[Tagged]>,
+}
+
+impl MemoKey {
+    /// Returns `None` unless every argument can be part of a key; see
+    /// [`memoizable`].
+    pub fn new(function: u32, arguments: &[Tagged]) -> Option<Self> {
+        memoizable(arguments).then(|| Self {
+            function,
+            arguments: arguments.into(),
+        })
+    }
+}
+
+/// Whether calls with these arguments can be memoized. Integers, booleans,
+/// interned and short strings are compared by what they hold; heap values
+/// are only known by their handle, which is reused once they are collected.
+pub fn memoizable(arguments: &[Tagged]) -> bool {
+    arguments
+        .iter()
+        .all(|argument| !matches!(argument, Tagged::Object(_)))
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Entry {
+    value: Tagged,
+    /// Whether the entry was used since the clock hand last passed it.
+    referenced: bool,
+}
+
+/// The results of pure calls, by function and then by arguments, so a
+/// lookup hashes the arguments in place on the stack. A bounded table
+/// evicts entries with the clock algorithm, which approximates evicting the
+/// least recently used one.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct MemoTable {
+    functions: HashMap<u32, HashMap<Box<[Tagged]>, Entry>>,
+    /// The most entries kept, or `None` for no limit.
+    capacity: Option<usize>,
+    /// The key of every entry, in the order the hand visits them. Only kept
+    /// when the table is bounded.
+    clock: Vec<MemoKey>,
+    hand: usize,
+    pub evictions: u64,
+    /// Roughly how many bytes the entries take.
+    bytes: usize,
+}
+
+impl MemoTable {
+    pub fn bounded(capacity: usize) -> Self {
+        Self {
+            capacity: Some(capacity),
+            ..Self::default()
+        }
+    }
+
+    /// Whether anything is memoized at all.
+    pub fn is_enabled(&self) -> bool {
+        self.capacity != Some(0)
+    }
+
+    pub fn get(&mut self, function: u32, arguments: &[Tagged]) -> Option<Tagged> {
+        let entry = self.functions.get_mut(&function)?.get_mut(arguments)?;
+        entry.referenced = true;
+        Some(entry.value)
+    }
+
+    pub fn insert(&mut self, key: MemoKey, value: Tagged) {
+        let entry = Entry {
+            value,
+            referenced: false,
+        };
+        let calls = self.functions.entry(key.function).or_default();
+        if let Some(existing) = calls.get_mut(&key.arguments) {
+            *existing = entry;
+            return;
+        }
+
+        match self.capacity {
+            None => {}
+            Some(0) => return,
+            Some(capacity) if self.clock.len() < capacity => self.clock.push(key.clone()),
+            Some(capacity) => {
+                self.evict();
+                self.clock[self.hand] = key.clone();
+                self.hand = (self.hand + 1) % capacity;
+            }
+        }
+
+        self.bytes += entry_size(&key.arguments);
+        self.functions
+            .entry(key.function)
+            .or_default()
+            .insert(key.arguments, entry);
+    }
+
+    /// Advances the hand to the first entry not used since it last passed
+    /// and removes that entry, leaving the hand on its slot.
+    fn evict(&mut self) {
+        loop {
+            let key = &self.clock[self.hand];
+            let calls = self
+                .functions
+                .get_mut(&key.function)
+                .expect("Every key on the clock is in the table.");
+            let entry = calls
+                .get_mut(&key.arguments)
+                .expect("Every key on the clock is in the table.");
+
+            if !entry.referenced {
+                calls.remove(&key.arguments);
+                self.bytes -= entry_size(&key.arguments);
+                self.evictions += 1;
+                return;
+            }
+
+            entry.referenced = false;
+            self.hand = (self.hand + 1) % self.clock.len();
+        }
+    }
+
+    /// Empties the table, keeping its capacity.
+    pub fn clear(&mut self) {
+        self.functions.clear();
+        self.clock.clear();
+        self.hand = 0;
+        self.bytes = 0;
+    }
+
+    /// Roughly how many bytes the table takes, not counting the values it
+    /// keeps alive in the heap.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = (u32, &[Tagged], Tagged)> {
+        self.functions.iter().flat_map(|(function, calls)| {
+            calls
+                .iter()
+                .map(|(arguments, entry)| (*function, &arguments[..], entry.value))
+        })
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = Tagged> + '_ {
+        self.functions
+            .values()
+            .flat_map(|calls| calls.values().map(|entry| entry.value))
+    }
+}
+
+fn entry_size(arguments: &[Tagged]) -> usize {
+    mem::size_of::<(Box<[Tagged]>, Entry)>() + mem::size_of_val(arguments)
+}
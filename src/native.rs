@@ -0,0 +1,151 @@
+use anyhow::{bail, Context, Result};
+use std::{
+    cell::Cell,
+    fmt, fs,
+    rc::Rc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{sandbox::SandboxPolicy, value::FinalValue};
+
+pub type NativeFunction = Box<dyn Fn(&[FinalValue]) -> Result<NativeResult>>;
+
+/// What a native function produced when called.
+pub enum NativeResult {
+    Ready(FinalValue),
+    /// The result is not available yet. The VM suspends at the call and the
+    /// embedder supplies the result later through `Vm::resume_with`.
+    Pending,
+}
+
+/// A function implemented by the host, callable from rinha programs.
+pub struct Native {
+    pub name: String,
+    /// How many arguments the function takes, or `None` if it takes any
+    /// number of them.
+    pub arity: Option<u16>,
+    pub function: NativeFunction,
+    /// Whether the function has no side effects and its result depends on
+    /// its arguments alone, so calls to it can be memoized.
+    pub pure: bool,
+}
+
+impl fmt::Debug for Native {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.arity {
+            Some(arity) => write!(f, "Native({}, {arity})", self.name),
+            None => write!(f, "Native({}, ...)", self.name),
+        }
+    }
+}
+
+/// Identifies a call to a native function that is waiting for its result.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SuspensionToken {
+    pub id: u64,
+    pub native: String,
+    pub arguments: Vec<FinalValue>,
+}
+
+/// Natives grouped into namespaces, such as `io.read_file` or `math.random`.
+/// Programs call them with `/` separating the namespace (`io/read_file(path)`),
+/// since rinha identifiers cannot contain dots.
+#[derive(Default)]
+pub struct NativeRegistry {
+    natives: Vec<(String, Rc<Native>)>,
+}
+
+impl NativeRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The natives shipped with the VM: `io.read_file(path)` and
+    /// `math.random(bound)`.
+    pub fn standard() -> Self {
+        let mut registry = Self::new();
+
+        registry
+            .register("io.read_file", 1, |arguments| match arguments {
+                [FinalValue::String(path)] => {
+                    let contents = fs::read_to_string(path)
+                        .with_context(|| format!("Could not read file {path}."))?;
+                    Ok(NativeResult::Ready(FinalValue::String(contents)))
+                }
+                _ => bail!("io.read_file expects a path."),
+            })
+            .expect("Standard natives have valid names.");
+
+        let state = Cell::new(seed());
+        registry
+            .register("math.random", 1, move |arguments| match arguments {
+                [FinalValue::Integer(bound)] if *bound > 0 => {
+                    let mut x = state.get();
+                    x ^= x << 13;
+                    x ^= x >> 7;
+                    x ^= x << 17;
+                    state.set(x);
+                    let value = (x % *bound as u64) as i32;
+                    Ok(NativeResult::Ready(FinalValue::Integer(value)))
+                }
+                _ => bail!("math.random expects a positive bound."),
+            })
+            .expect("Standard natives have valid names.");
+
+        registry
+    }
+
+    /// Adds a native under its qualified `namespace.name`.
+    pub fn register<F>(&mut self, name: &str, arity: u16, function: F) -> Result<()>
+    where
+        F: Fn(&[FinalValue]) -> Result<NativeResult> + 'static,
+    {
+        let Some((namespace, _)) = name.split_once('.') else {
+            bail!("Native {name} must be named namespace.name.");
+        };
+
+        if self.natives.iter().any(|(_, native)| native.name == name) {
+            bail!("Native {name} is already registered.");
+        }
+
+        let native = Native {
+            name: name.to_owned(),
+            arity: Some(arity),
+            function: Box::new(function),
+            pure: false,
+        };
+        self.natives.push((namespace.to_owned(), Rc::new(native)));
+
+        Ok(())
+    }
+
+    /// The natives of every namespace `policy` allows.
+    pub fn allowed<'a>(
+        &'a self,
+        policy: &'a SandboxPolicy,
+    ) -> impl Iterator<Item = &'a Rc<Native>> {
+        self.natives
+            .iter()
+            .filter(|(namespace, _)| policy.allows(namespace))
+            .map(|(_, native)| native)
+    }
+}
+
+impl Native {
+    /// The name programs use to call the native.
+    pub fn global_name(&self) -> String {
+        self.name.replacen('.', "/", 1)
+    }
+}
+
+/// A nonzero seed for `math.random`, from the clock where there is one.
+/// Asking for the time panics on wasm32-unknown-unknown.
+fn seed() -> u64 {
+    if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+        return 1;
+    }
+
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(1, |elapsed| elapsed.as_nanos() as u64 | 1)
+}
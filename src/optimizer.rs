@@ -0,0 +1,666 @@
+use anyhow::{bail, Result};
+
+use crate::{
+    bytecode::Instruction,
+    range::{self, Fact},
+    value::Value,
+    vm::Vm,
+};
+
+/// An optimization that can be turned off on its own, to find out which one
+/// changes the behavior of a program.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Pass {
+    /// [`peephole`], which also folds constants.
+    Peephole,
+    /// [`propagate_ranges`].
+    Ranges,
+    /// [`eliminate_dead_code`].
+    DeadCode,
+    /// Compiling calls in tail position to `TailCall`s.
+    TailCalls,
+    /// [`fuse`].
+    Fusion,
+    /// Compiling calls to small leaf functions to a copy of their body; see
+    /// [`crate::compiler::Compiler::with_inlining`].
+    Inline,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 6] = [
+        Pass::Peephole,
+        Pass::Ranges,
+        Pass::DeadCode,
+        Pass::TailCalls,
+        Pass::Fusion,
+        Pass::Inline,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Peephole => "peephole",
+            Pass::Ranges => "ranges",
+            Pass::DeadCode => "dead-code",
+            Pass::TailCalls => "tail-calls",
+            Pass::Fusion => "fusion",
+            Pass::Inline => "inline",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match Pass::ALL.into_iter().find(|pass| pass.name() == name) {
+            Some(pass) => Ok(pass),
+            None => {
+                let names: Vec<&str> = Pass::ALL.iter().map(|pass| pass.name()).collect();
+                bail!(
+                    "Unknown optimizer pass {name}, expected one of {}.",
+                    names.join(", ")
+                )
+            }
+        }
+    }
+
+    /// The lowest optimization level the pass runs at.
+    fn level(self) -> u8 {
+        match self {
+            Pass::Peephole | Pass::DeadCode | Pass::TailCalls => 1,
+            Pass::Ranges | Pass::Fusion | Pass::Inline => 2,
+        }
+    }
+}
+
+/// The passes a program is compiled with. Defaults to every pass.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Passes {
+    enabled: Vec<Pass>,
+}
+
+impl Default for Passes {
+    fn default() -> Self {
+        Self {
+            enabled: Pass::ALL.to_vec(),
+        }
+    }
+}
+
+impl Passes {
+    pub const MAX_LEVEL: u8 = 2;
+
+    /// The passes of an optimization level: none at 0, the cheap and long
+    /// standing ones at 1 and all of them at 2.
+    pub fn level(level: u8) -> Result<Self> {
+        if level > Self::MAX_LEVEL {
+            bail!(
+                "Unknown optimization level {level}, expected at most {}.",
+                Self::MAX_LEVEL
+            );
+        }
+
+        Ok(Self {
+            enabled: Pass::ALL
+                .into_iter()
+                .filter(|pass| pass.level() <= level)
+                .collect(),
+        })
+    }
+
+    pub fn without(mut self, pass: Pass) -> Self {
+        self.enabled.retain(|enabled| *enabled != pass);
+        self
+    }
+
+    pub fn contains(&self, pass: Pass) -> bool {
+        self.enabled.contains(&pass)
+    }
+
+    /// The names of the enabled passes, as recorded in compiled artifacts.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.enabled.iter().map(|pass| pass.name()).collect()
+    }
+}
+
+/// Removes instructions that can never be executed.
+///
+/// Conditionals whose condition is a literal `true` or `false` are replaced
+/// by their taken branch, and everything that cannot be reached from the
+/// first instruction (such as code following a `Return`) is dropped. Jump
+/// offsets are rewritten to account for the removed instructions.
+pub fn eliminate_dead_code(bytecode: &[Instruction]) -> Vec<Instruction> {
+    eliminate_dead_code_with_offsets(bytecode, &mut Vec::new())
+}
+
+/// Like [`eliminate_dead_code`], also dropping the source offsets of the
+/// removed instructions from `offsets`.
+pub(crate) fn eliminate_dead_code_with_offsets(
+    bytecode: &[Instruction],
+    offsets: &mut Vec<usize>,
+) -> Vec<Instruction> {
+    let mut bytecode = fold_constant_conditions(bytecode, offsets);
+
+    let reachable = compute_reachable(&bytecode);
+    bytecode = remove_instructions(&bytecode, &reachable, offsets);
+
+    loop {
+        let keep: Vec<bool> = bytecode
+            .iter()
+            .map(|instruction| !matches!(instruction, Instruction::Jump(0)))
+            .collect();
+
+        if keep.iter().all(|k| *k) {
+            break;
+        }
+
+        bytecode = remove_instructions(&bytecode, &keep, offsets);
+    }
+
+    bytecode
+}
+
+/// Rewrites short instruction sequences into cheaper equivalents until no
+/// more rewrites apply:
+///
+/// - `Constant(x); Constant(y); <operator>` becomes the precomputed result,
+///   unless evaluating it would fail at runtime;
+/// - `True; If(n)` falls through and `False; If(n)` becomes `Jump(n)`;
+/// - jumps landing on another `Jump` go straight to its target;
+/// - `GlobalSet(i); GlobalGet(i)` becomes `Dup; GlobalSet(i)`.
+///
+/// New constants are registered in `vm`.
+pub fn peephole(bytecode: &[Instruction], vm: &mut Vm) -> Result<Vec<Instruction>> {
+    peephole_with_offsets(bytecode, &mut Vec::new(), vm)
+}
+
+/// Like [`peephole`], keeping `offsets` in step with the instructions.
+/// Rewritten instructions keep the offset of the first one they replace.
+pub(crate) fn peephole_with_offsets(
+    bytecode: &[Instruction],
+    offsets: &mut Vec<usize>,
+    vm: &mut Vm,
+) -> Result<Vec<Instruction>> {
+    let mut bytecode = bytecode.to_vec();
+
+    loop {
+        let (rewritten, changed) = peephole_step(&bytecode, offsets, vm)?;
+        bytecode = rewritten;
+
+        if !changed {
+            return Ok(bytecode);
+        }
+    }
+}
+
+/// Rewrites instructions whose result [`range::analyze`] proves constant:
+///
+/// - a `LocalGet` of a local known to hold a single integer or boolean
+///   becomes that literal;
+/// - a comparison of two literals or locals feeding an `If` becomes `True`
+///   or `False` when the ranges of its operands decide it, so the branch
+///   that is never taken can be eliminated.
+///
+/// `frame_size` is the number of values the frame starts with, as for the
+/// verifier. New constants are registered in `vm`.
+pub fn propagate_ranges(
+    bytecode: &[Instruction],
+    frame_size: usize,
+    vm: &mut Vm,
+) -> Result<Vec<Instruction>> {
+    propagate_ranges_with_offsets(bytecode, &mut Vec::new(), frame_size, vm)
+}
+
+/// Like [`propagate_ranges`], keeping `offsets` in step with the
+/// instructions.
+pub(crate) fn propagate_ranges_with_offsets(
+    bytecode: &[Instruction],
+    offsets: &mut Vec<usize>,
+    frame_size: usize,
+    vm: &mut Vm,
+) -> Result<Vec<Instruction>> {
+    let facts = range::analyze(bytecode, frame_size, vm);
+    let is_target = compute_targets(bytecode);
+
+    let mut result = bytecode.to_vec();
+    let mut keep = vec![true; bytecode.len()];
+
+    let literal = |fact: Option<Fact>, vm: &mut Vm| -> Result<Option<Instruction>> {
+        Ok(match fact {
+            Some(Fact::Integer(range)) => match range.constant() {
+                Some(value) => Some(Instruction::Constant(
+                    vm.create_constant(Value::Integer(value))?,
+                )),
+                None => None,
+            },
+            Some(Fact::Bool(Some(value))) => Some(boolean(value)),
+            _ => None,
+        })
+    };
+    let is_operand = |instruction: &Instruction| {
+        matches!(
+            instruction,
+            Instruction::Constant(_)
+                | Instruction::True
+                | Instruction::False
+                | Instruction::LocalGet(_, _)
+        )
+    };
+    let is_comparison = |instruction: &Instruction| {
+        matches!(
+            instruction,
+            Instruction::Eq
+                | Instruction::Neq
+                | Instruction::Lt
+                | Instruction::Lte
+                | Instruction::Gt
+                | Instruction::Gte
+        )
+    };
+
+    let mut address = 0;
+    while address < bytecode.len() {
+        let window = &bytecode[address..];
+        let enters_window = |length: usize| (1..length).any(|i| is_target[address + i]);
+
+        if let [lhs, rhs, comparison, Instruction::If(_), ..] = window {
+            if is_operand(lhs) && is_operand(rhs) && is_comparison(comparison) && !enters_window(3)
+            {
+                if let Some(decided) = literal(facts[address + 2], vm)? {
+                    keep[address] = false;
+                    keep[address + 1] = false;
+                    result[address + 2] = decided;
+                    address += 4;
+                    continue;
+                }
+            }
+        }
+
+        if let Instruction::LocalGet(_, _) = bytecode[address] {
+            if let Some(constant) = literal(facts[address], vm)? {
+                result[address] = constant;
+            }
+        }
+
+        address += 1;
+    }
+
+    Ok(remove_instructions(&result, &keep, offsets))
+}
+
+/// Fuses instruction sequences common in tight recursive loops into single
+/// instructions, saving their dispatch:
+///
+/// - `LocalGet; Constant; Add` and `LocalGet; Constant; Sub` become
+///   `LocalGetConstantAdd` and `LocalGetConstantSub`;
+/// - `Constant; Lt` and `Constant; Eq` become `ConstantLt` and `ConstantEq`;
+/// - `LocalGet; TailCall` becomes `LocalGetTailCall`.
+///
+/// Sequences jumped into are left alone. The other passes only know the
+/// plain instructions, so this one runs last.
+pub fn fuse(bytecode: &[Instruction]) -> Vec<Instruction> {
+    fuse_with_offsets(bytecode, &mut Vec::new())
+}
+
+/// Like [`fuse`], keeping `offsets` in step with the instructions. Fused
+/// instructions keep the offset of the first one they replace.
+pub(crate) fn fuse_with_offsets(
+    bytecode: &[Instruction],
+    offsets: &mut Vec<usize>,
+) -> Vec<Instruction> {
+    let is_target = compute_targets(bytecode);
+
+    let mut result = bytecode.to_vec();
+    let mut keep = vec![true; bytecode.len()];
+
+    let mut address = 0;
+    while address < bytecode.len() {
+        let window = &bytecode[address..];
+        let enters_window = |length: usize| (1..length).any(|i| is_target[address + i]);
+
+        let (fused, length) = match window {
+            [Instruction::LocalGet(index, identifier), Instruction::Constant(constant), Instruction::Add, ..]
+                if !enters_window(3) =>
+            {
+                (
+                    Instruction::LocalGetConstantAdd(*index, *identifier, *constant),
+                    3,
+                )
+            }
+            [Instruction::LocalGet(index, identifier), Instruction::Constant(constant), Instruction::Sub, ..]
+                if !enters_window(3) =>
+            {
+                (
+                    Instruction::LocalGetConstantSub(*index, *identifier, *constant),
+                    3,
+                )
+            }
+            [Instruction::Constant(constant), Instruction::Lt, ..] if !enters_window(2) => {
+                (Instruction::ConstantLt(*constant), 2)
+            }
+            [Instruction::Constant(constant), Instruction::Eq, ..] if !enters_window(2) => {
+                (Instruction::ConstantEq(*constant), 2)
+            }
+            [Instruction::LocalGet(index, identifier), Instruction::TailCall(arity), ..]
+                if !enters_window(2) =>
+            {
+                (
+                    Instruction::LocalGetTailCall(*index, *identifier, *arity),
+                    2,
+                )
+            }
+            _ => {
+                address += 1;
+                continue;
+            }
+        };
+
+        result[address] = fused;
+        keep[address + 1..address + length].fill(false);
+        address += length;
+    }
+
+    remove_instructions(&result, &keep, offsets)
+}
+
+/// Returns the absolute address an `If`, `Jump` or `JumpBack` located at
+/// `address` transfers control to, or `None` for any other instruction.
+pub(crate) fn jump_target(address: usize, instruction: &Instruction) -> Option<usize> {
+    match instruction {
+        Instruction::If(offset) | Instruction::Jump(offset) => Some(address + 1 + *offset as usize),
+        Instruction::JumpBack(offset) => address.checked_sub(*offset as usize),
+        _ => None,
+    }
+}
+
+/// Rebuilds an `If`, `Jump` or `JumpBack` located at `address` so that it
+/// transfers control to `target`.
+pub(crate) fn retarget(instruction: &Instruction, address: usize, target: usize) -> Instruction {
+    match instruction {
+        Instruction::If(_) => Instruction::If((target - address - 1) as u32),
+        Instruction::Jump(_) => Instruction::Jump((target - address - 1) as u32),
+        Instruction::JumpBack(_) => Instruction::JumpBack((address - target) as u32),
+        _ => instruction.clone(),
+    }
+}
+
+fn peephole_step(
+    bytecode: &[Instruction],
+    offsets: &mut Vec<usize>,
+    vm: &mut Vm,
+) -> Result<(Vec<Instruction>, bool)> {
+    let is_target = compute_targets(bytecode);
+
+    let mut result = bytecode.to_vec();
+    let mut keep = vec![true; bytecode.len()];
+    let mut changed = false;
+
+    for (address, instruction) in bytecode.iter().enumerate() {
+        if let Some(target) = jump_target(address, instruction) {
+            let final_target = follow_jumps(bytecode, target);
+
+            if final_target != target {
+                result[address] = retarget(instruction, address, final_target);
+                changed = true;
+            }
+        }
+    }
+
+    let mut address = 0;
+    while address < bytecode.len() {
+        let window = &bytecode[address..];
+        let enters_window = |length: usize| (1..length).any(|i| is_target[address + i]);
+
+        match window {
+            [Instruction::Constant(x), Instruction::Constant(y), operator, ..]
+                if !enters_window(3) =>
+            {
+                if let Some(folded) = fold_binary(vm, *x, *y, operator)? {
+                    result[address] = folded;
+                    keep[address + 1] = false;
+                    keep[address + 2] = false;
+                    changed = true;
+                    address += 3;
+                    continue;
+                }
+            }
+            [Instruction::True, Instruction::If(_), ..] if !enters_window(2) => {
+                keep[address] = false;
+                keep[address + 1] = false;
+                changed = true;
+                address += 2;
+                continue;
+            }
+            [Instruction::False, Instruction::If(_), ..] if !enters_window(2) => {
+                let target = jump_target(address + 1, &result[address + 1])
+                    .expect("An If always has a target.");
+                keep[address] = false;
+                result[address + 1] = retarget(&Instruction::Jump(0), address + 1, target);
+                changed = true;
+                address += 2;
+                continue;
+            }
+            [Instruction::GlobalSet(set), Instruction::GlobalGet(get), ..]
+                if set == get && !enters_window(2) =>
+            {
+                result[address] = Instruction::Dup;
+                result[address + 1] = Instruction::GlobalSet(*set);
+                changed = true;
+                address += 2;
+                continue;
+            }
+            _ => {}
+        }
+
+        address += 1;
+    }
+
+    Ok((remove_instructions(&result, &keep, offsets), changed))
+}
+
+/// Evaluates a binary operator over two constants, mirroring the VM. Returns
+/// `None` when the operation would fail at runtime, so the error still
+/// happens when (and if) the code is executed, and when it overflows, since
+/// what happens then is up to the [`crate::arithmetic::Overflow`] of the VM
+/// running it.
+fn fold_binary(
+    vm: &mut Vm,
+    lhs: u32,
+    rhs: u32,
+    operator: &Instruction,
+) -> Result<Option<Instruction>> {
+    let value = match (vm.constant(lhs), vm.constant(rhs), operator) {
+        (Value::Integer(lhs), Value::Integer(rhs), _) => match operator {
+            Instruction::Add => match lhs.checked_add(*rhs) {
+                Some(result) => Value::Integer(result),
+                None => return Ok(None),
+            },
+            Instruction::Sub => match lhs.checked_sub(*rhs) {
+                Some(result) => Value::Integer(result),
+                None => return Ok(None),
+            },
+            Instruction::Mul => match lhs.checked_mul(*rhs) {
+                Some(result) => Value::Integer(result),
+                None => return Ok(None),
+            },
+            Instruction::Div => match lhs.checked_div(*rhs) {
+                Some(result) => Value::Integer(result),
+                None => return Ok(None),
+            },
+            Instruction::Rem => match lhs.checked_rem(*rhs) {
+                Some(result) => Value::Integer(result),
+                None => return Ok(None),
+            },
+            Instruction::BitAnd => Value::Integer(lhs & rhs),
+            Instruction::BitOr => Value::Integer(lhs | rhs),
+            Instruction::BitXor => Value::Integer(lhs ^ rhs),
+            Instruction::Shl => Value::Integer(lhs.wrapping_shl(*rhs as u32)),
+            Instruction::Shr => Value::Integer(lhs.wrapping_shr(*rhs as u32)),
+            Instruction::Eq => return Ok(Some(boolean(lhs == rhs))),
+            Instruction::Neq => return Ok(Some(boolean(lhs != rhs))),
+            Instruction::Gt => return Ok(Some(boolean(lhs > rhs))),
+            Instruction::Lt => return Ok(Some(boolean(lhs < rhs))),
+            Instruction::Gte => return Ok(Some(boolean(lhs >= rhs))),
+            Instruction::Lte => return Ok(Some(boolean(lhs <= rhs))),
+            _ => return Ok(None),
+        },
+        (Value::String(lhs), Value::String(rhs), Instruction::Add) => {
+            Value::String(format!("{lhs}{rhs}").into())
+        }
+        (Value::String(lhs), Value::Integer(rhs), Instruction::Add) => {
+            Value::String(format!("{lhs}{rhs}").into())
+        }
+        (Value::Integer(lhs), Value::String(rhs), Instruction::Add) => {
+            Value::String(format!("{lhs}{rhs}").into())
+        }
+        (Value::String(lhs), Value::String(rhs), Instruction::Lt) => {
+            return Ok(Some(boolean(lhs < rhs)))
+        }
+        (Value::String(lhs), Value::String(rhs), Instruction::Gt) => {
+            return Ok(Some(boolean(lhs > rhs)))
+        }
+        (Value::String(lhs), Value::String(rhs), Instruction::Lte) => {
+            return Ok(Some(boolean(lhs <= rhs)))
+        }
+        (Value::String(lhs), Value::String(rhs), Instruction::Gte) => {
+            return Ok(Some(boolean(lhs >= rhs)))
+        }
+        (lhs, rhs, Instruction::Eq) => return Ok(Some(boolean(lhs == rhs))),
+        (lhs, rhs, Instruction::Neq) => return Ok(Some(boolean(lhs != rhs))),
+        _ => return Ok(None),
+    };
+
+    let index = vm.create_constant(value)?;
+    Ok(Some(Instruction::Constant(index)))
+}
+
+fn boolean(value: bool) -> Instruction {
+    if value {
+        Instruction::True
+    } else {
+        Instruction::False
+    }
+}
+
+/// Follows a chain of unconditional jumps starting at `address`, returning
+/// where it finally lands. Cycles are cut after visiting every instruction.
+fn follow_jumps(bytecode: &[Instruction], mut address: usize) -> usize {
+    for _ in 0..bytecode.len() {
+        match bytecode.get(address) {
+            Some(instruction @ Instruction::Jump(_)) => {
+                address = jump_target(address, instruction).expect("A Jump always has a target.");
+            }
+            _ => break,
+        }
+    }
+
+    address
+}
+
+fn compute_targets(bytecode: &[Instruction]) -> Vec<bool> {
+    let mut is_target = vec![false; bytecode.len() + 1];
+    for (address, instruction) in bytecode.iter().enumerate() {
+        if let Some(target) = jump_target(address, instruction) {
+            is_target[target] = true;
+        }
+    }
+
+    is_target
+}
+
+fn fold_constant_conditions(
+    bytecode: &[Instruction],
+    offsets: &mut Vec<usize>,
+) -> Vec<Instruction> {
+    let is_target = compute_targets(bytecode);
+
+    let mut result = bytecode.to_vec();
+    let mut keep = vec![true; bytecode.len()];
+
+    for address in 1..bytecode.len() {
+        // Some other path may reach the `If` with a different condition on
+        // the stack, so it can only be folded when the literal is the sole way in.
+        if !matches!(bytecode[address], Instruction::If(_)) || is_target[address] {
+            continue;
+        }
+
+        match bytecode[address - 1] {
+            Instruction::True => {
+                keep[address - 1] = false;
+                keep[address] = false;
+            }
+            Instruction::False => {
+                keep[address - 1] = false;
+                result[address] = retarget(
+                    &Instruction::Jump(0),
+                    address,
+                    jump_target(address, &bytecode[address]).expect("An If always has a target."),
+                );
+            }
+            _ => {}
+        }
+    }
+
+    remove_instructions(&result, &keep, offsets)
+}
+
+fn compute_reachable(bytecode: &[Instruction]) -> Vec<bool> {
+    let mut reachable = vec![false; bytecode.len()];
+    let mut pending = vec![0];
+
+    while let Some(address) = pending.pop() {
+        if address >= bytecode.len() || reachable[address] {
+            continue;
+        }
+
+        reachable[address] = true;
+
+        let instruction = &bytecode[address];
+        if let Some(target) = jump_target(address, instruction) {
+            pending.push(target);
+        }
+
+        if !matches!(
+            instruction,
+            Instruction::Jump(_) | Instruction::JumpBack(_) | Instruction::Return(_)
+        ) {
+            pending.push(address + 1);
+        }
+    }
+
+    reachable
+}
+
+/// Drops every instruction whose `keep` entry is false, along with its
+/// source offset when `offsets` has one per instruction. Jumps into a
+/// removed instruction land on the first kept instruction after it.
+fn remove_instructions(
+    bytecode: &[Instruction],
+    keep: &[bool],
+    offsets: &mut Vec<usize>,
+) -> Vec<Instruction> {
+    if offsets.len() == bytecode.len() {
+        let mut kept = keep.iter();
+        offsets.retain(|_| *kept.next().expect("There is a keep entry per offset."));
+    }
+
+    let mut new_addresses = Vec::with_capacity(bytecode.len() + 1);
+    let mut kept = 0;
+    for k in keep {
+        new_addresses.push(kept);
+        if *k {
+            kept += 1;
+        }
+    }
+    new_addresses.push(kept);
+
+    bytecode
+        .iter()
+        .enumerate()
+        .filter(|(address, _)| keep[*address])
+        .map(
+            |(address, instruction)| match jump_target(address, instruction) {
+                Some(target) => {
+                    retarget(instruction, new_addresses[address], new_addresses[target])
+                }
+                None => instruction.clone(),
+            },
+        )
+        .collect()
+}
@@ -0,0 +1,58 @@
+use std::{cell::RefCell, io, io::Write, rc::Rc};
+
+/// How `print` behaves. The defaults follow the rinha specification, and the
+/// alternatives let a run's output match other implementations byte for byte.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Printing {
+    /// Whether each printed value is followed by a newline.
+    pub newline: bool,
+    pub result: PrintResult,
+}
+
+impl Default for Printing {
+    fn default() -> Self {
+        Self {
+            newline: true,
+            result: PrintResult::Argument,
+        }
+    }
+}
+
+/// What a `print` expression evaluates to.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum PrintResult {
+    /// The value it printed, as the specification says.
+    #[default]
+    Argument,
+    /// The string it printed, so `print(1)` is `"1"`.
+    Text,
+}
+
+/// Collects what a program prints, for callers that report it rather than
+/// letting it reach standard output. Clones share the same buffer, so one
+/// can be handed to [`Vm::with_output`](crate::vm::Vm::with_output) and the
+/// other read once the program ran.
+#[derive(Clone, Debug, Default)]
+pub struct Captured(Rc<RefCell<Vec<u8>>>);
+
+impl Captured {
+    /// Takes what was printed so far, leaving nothing behind.
+    pub fn take(&self) -> Vec<u8> {
+        self.0.take()
+    }
+
+    /// What was printed so far, with invalid UTF-8 replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+}
+
+impl Write for Captured {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buffer)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+use std::{collections::HashMap, io::Write};
+
+use anyhow::Result;
+
+use crate::function::Function;
+
+/// How many instructions pass between samples unless
+/// [`crate::vm::Vm::with_profiler`] says otherwise.
+pub const DEFAULT_INTERVAL: u64 = 100;
+
+/// Samples the call stack every `interval` instructions. Sampling counts
+/// instructions rather than time, so the same program always produces the
+/// same profile.
+pub struct Profiler {
+    interval: u64,
+    countdown: u64,
+    /// How often each call stack was sampled, keyed by the indices of its
+    /// functions, outermost first.
+    samples: HashMap<Vec<u32>, u64>,
+}
+
+impl Profiler {
+    pub fn new(interval: u64) -> Self {
+        let interval = interval.max(1);
+
+        Self {
+            interval,
+            countdown: interval,
+            samples: HashMap::new(),
+        }
+    }
+
+    /// Counts one instruction, returning whether the stack should be
+    /// sampled now.
+    pub fn tick(&mut self) -> bool {
+        self.countdown -= 1;
+
+        if self.countdown == 0 {
+            self.countdown = self.interval;
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn sample(&mut self, stack: impl Iterator<Item = u32>) {
+        *self.samples.entry(stack.collect()).or_default() += 1;
+    }
+
+    pub fn samples(&self) -> u64 {
+        self.samples.values().sum()
+    }
+
+    /// Writes the samples as collapsed stacks, one line per distinct stack
+    /// with its frames separated by semicolons and followed by the number of
+    /// instructions it accounts for. This is the input format of
+    /// `flamegraph.pl` and inferno.
+    pub fn write_collapsed(
+        &self,
+        functions: &[impl AsRef<Function>],
+        output: &mut impl Write,
+    ) -> Result<()> {
+        let name = |index: u32| match functions.get(index as usize) {
+            Some(function) => {
+                let name = function.as_ref().name.as_deref().unwrap_or("<anonymous>");
+                format!("{name}#{index}")
+            }
+            None => "<script>".to_owned(),
+        };
+
+        let mut lines: Vec<(String, u64)> = self
+            .samples
+            .iter()
+            .map(|(stack, count)| {
+                let frames: Vec<String> = stack.iter().map(|index| name(*index)).collect();
+                (frames.join(";"), count * self.interval)
+            })
+            .collect();
+        lines.sort();
+
+        for (stack, count) in lines {
+            writeln!(output, "{stack} {count}")?;
+        }
+
+        Ok(())
+    }
+}
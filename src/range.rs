@@ -0,0 +1,689 @@
+use std::rc::Rc;
+
+use crate::{bytecode::Instruction, optimizer::jump_target, value::Value, vm::Vm};
+
+/// An inclusive interval of integers.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Range {
+    pub min: i32,
+    pub max: i32,
+}
+
+impl Range {
+    pub const FULL: Range = Range {
+        min: i32::MIN,
+        max: i32::MAX,
+    };
+
+    pub fn point(value: i32) -> Self {
+        Self {
+            min: value,
+            max: value,
+        }
+    }
+
+    /// The only value in the range, if there is just one.
+    pub fn constant(&self) -> Option<i32> {
+        (self.min == self.max).then_some(self.min)
+    }
+
+    fn hull(self, other: Range) -> Range {
+        Range {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Builds a range from bounds computed without overflowing. Arithmetic
+    /// wraps around, so bounds that do not fit could land anywhere.
+    fn wrapping(min: i64, max: i64) -> Range {
+        if min < i32::MIN as i64 || max > i32::MAX as i64 {
+            Range::FULL
+        } else {
+            Range {
+                min: min as i32,
+                max: max as i32,
+            }
+        }
+    }
+
+    /// The range with the given bounds, or `None` if they leave it empty.
+    fn clamped(min: i64, max: i64) -> Option<Range> {
+        (min <= max).then(|| Range::wrapping(min, max))
+    }
+
+    fn bounds(self) -> (i64, i64) {
+        (self.min as i64, self.max as i64)
+    }
+}
+
+/// What is known about a value.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Fact {
+    Integer(Range),
+    Bool(Option<bool>),
+    Unknown,
+}
+
+impl Fact {
+    fn join(self, other: Fact) -> Fact {
+        match (self, other) {
+            (Fact::Integer(lhs), Fact::Integer(rhs)) => Fact::Integer(lhs.hull(rhs)),
+            (Fact::Bool(lhs), Fact::Bool(rhs)) if lhs == rhs => Fact::Bool(lhs),
+            (Fact::Bool(_), Fact::Bool(_)) => Fact::Bool(None),
+            _ => Fact::Unknown,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Comparison {
+    Eq,
+    Neq,
+    Lt,
+    Lte,
+    Gt,
+    Gte,
+}
+
+impl Comparison {
+    fn of(instruction: &Instruction) -> Option<Self> {
+        match instruction {
+            Instruction::Eq => Some(Comparison::Eq),
+            Instruction::Neq => Some(Comparison::Neq),
+            Instruction::Lt => Some(Comparison::Lt),
+            Instruction::Lte => Some(Comparison::Lte),
+            Instruction::Gt => Some(Comparison::Gt),
+            Instruction::Gte => Some(Comparison::Gte),
+            _ => None,
+        }
+    }
+
+    /// The comparison that holds with the operands swapped.
+    fn flip(self) -> Self {
+        match self {
+            Comparison::Lt => Comparison::Gt,
+            Comparison::Lte => Comparison::Gte,
+            Comparison::Gt => Comparison::Lt,
+            Comparison::Gte => Comparison::Lte,
+            equality => equality,
+        }
+    }
+
+    /// The comparison that holds when this one does not.
+    fn negate(self) -> Self {
+        match self {
+            Comparison::Eq => Comparison::Neq,
+            Comparison::Neq => Comparison::Eq,
+            Comparison::Lt => Comparison::Gte,
+            Comparison::Lte => Comparison::Gt,
+            Comparison::Gt => Comparison::Lte,
+            Comparison::Gte => Comparison::Lt,
+        }
+    }
+
+    /// The result of comparing any value in `lhs` with any in `rhs`, if it is
+    /// always the same.
+    fn decide(self, lhs: Range, rhs: Range) -> Option<bool> {
+        match self {
+            Comparison::Lt if lhs.max < rhs.min => Some(true),
+            Comparison::Lt if lhs.min >= rhs.max => Some(false),
+            Comparison::Lte if lhs.max <= rhs.min => Some(true),
+            Comparison::Lte if lhs.min > rhs.max => Some(false),
+            Comparison::Gt | Comparison::Gte => self.flip().decide(rhs, lhs),
+            Comparison::Eq if lhs.constant().is_some() && lhs == rhs => Some(true),
+            Comparison::Eq if lhs.max < rhs.min || rhs.max < lhs.min => Some(false),
+            Comparison::Neq => Comparison::Eq.decide(lhs, rhs).map(|equal| !equal),
+            _ => None,
+        }
+    }
+
+    /// Narrows `value` to the values for which the comparison can hold
+    /// against some value of `bound`, or `None` when there are none.
+    fn constrain(self, value: Range, bound: Range) -> Option<Range> {
+        let (min, max) = value.bounds();
+        let (bound_min, bound_max) = bound.bounds();
+
+        match self {
+            Comparison::Eq => Range::clamped(min.max(bound_min), max.min(bound_max)),
+            Comparison::Neq => match bound.constant() {
+                Some(excluded) if excluded == value.min => Range::clamped(min + 1, max),
+                Some(excluded) if excluded == value.max => Range::clamped(min, max - 1),
+                _ => Some(value),
+            },
+            Comparison::Lt => Range::clamped(min, max.min(bound_max - 1)),
+            Comparison::Lte => Range::clamped(min, max.min(bound_max)),
+            Comparison::Gt => Range::clamped(min.max(bound_min + 1), max),
+            Comparison::Gte => Range::clamped(min.max(bound_min), max),
+        }
+    }
+}
+
+/// A comparison of a local with a value known to lie in `bound`, whose
+/// outcome says something about the local on each side of an `If`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Test {
+    local: u16,
+    comparison: Comparison,
+    bound: Range,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Entry {
+    fact: Fact,
+    /// The slot this value was copied from, which holds the same value.
+    local: Option<u16>,
+    test: Option<Test>,
+}
+
+impl Entry {
+    fn new(fact: Fact) -> Self {
+        Self {
+            fact,
+            local: None,
+            test: None,
+        }
+    }
+
+    fn join(self, other: Entry) -> Entry {
+        Entry {
+            fact: self.fact.join(other.fact),
+            local: self.local.filter(|_| self.local == other.local),
+            test: self.test.filter(|_| self.test == other.test),
+        }
+    }
+}
+
+struct Node {
+    entry: Entry,
+    below: Option<Rc<Node>>,
+}
+
+impl Drop for Node {
+    // Stacks can be very tall, so they are freed without recursing.
+    fn drop(&mut self) {
+        let mut below = self.below.take();
+        while let Some(node) = below {
+            match Rc::try_unwrap(node) {
+                Ok(mut node) => below = node.below.take(),
+                Err(_) => break,
+            }
+        }
+    }
+}
+
+/// An abstract stack. The paths leaving a branch share everything below
+/// the values they change, so forking and joining them only costs as much
+/// as those changes.
+#[derive(Clone, Default)]
+struct Stack {
+    top: Option<Rc<Node>>,
+    height: usize,
+}
+
+impl Stack {
+    fn push(&mut self, entry: Entry) {
+        self.top = Some(Rc::new(Node {
+            entry,
+            below: self.top.take(),
+        }));
+        self.height += 1;
+    }
+
+    fn pop(&mut self) -> Option<Entry> {
+        let node = self.top.take()?;
+        self.height -= 1;
+
+        match Rc::try_unwrap(node) {
+            Ok(mut node) => {
+                self.top = node.below.take();
+                Some(node.entry)
+            }
+            Err(node) => {
+                self.top = node.below.clone();
+                Some(node.entry)
+            }
+        }
+    }
+
+    fn top(&self) -> Option<Entry> {
+        self.top.as_ref().map(|node| node.entry)
+    }
+
+    fn get(&self, slot: usize) -> Option<Entry> {
+        let mut node = self.top.as_ref()?;
+        for _ in slot + 1..self.height {
+            node = node.below.as_ref()?;
+        }
+
+        (slot < self.height).then_some(node.entry)
+    }
+
+    /// Pops the values down to and including `slot`, bottom first.
+    fn split_off(&mut self, slot: usize) -> Option<Vec<Entry>> {
+        if slot >= self.height {
+            return None;
+        }
+
+        let mut entries: Vec<Entry> = (slot..self.height)
+            .map(|_| self.pop())
+            .collect::<Option<_>>()?;
+        entries.reverse();
+        Some(entries)
+    }
+
+    fn join(&self, other: &Stack) -> Option<Stack> {
+        if self.height != other.height {
+            return None;
+        }
+
+        let mut entries = Vec::new();
+        let (mut lhs, mut rhs) = (self.top.as_ref(), other.top.as_ref());
+        while let (Some(l), Some(r)) = (lhs, rhs) {
+            if Rc::ptr_eq(l, r) {
+                break;
+            }
+
+            entries.push(l.entry.join(r.entry));
+            lhs = l.below.as_ref();
+            rhs = r.below.as_ref();
+        }
+
+        let mut stack = Stack {
+            top: lhs.cloned(),
+            height: self.height - entries.len(),
+        };
+        for entry in entries.into_iter().rev() {
+            stack.push(entry);
+        }
+
+        Some(stack)
+    }
+}
+
+/// Infers what each instruction of `bytecode` pushes, most notably bounds
+/// for integers. Locals are narrowed on each side of an `If` testing them,
+/// so in `if (n < 2) { ... } else { ... }` the else branch knows `n >= 2`.
+/// Arithmetic wraps around, so results that could overflow are unbounded.
+///
+/// `frame_size` is the number of values the frame starts with, as for the
+/// verifier. The result has the fact about the value each instruction
+/// pushes, and `None` for instructions that push nothing or that no path
+/// reaches. Bytecode the verifier would reject yields no facts at all.
+pub fn analyze(bytecode: &[Instruction], frame_size: usize, vm: &Vm) -> Vec<Option<Fact>> {
+    let mut facts = vec![None; bytecode.len()];
+    let mut states: Vec<Option<Stack>> = vec![None; bytecode.len()];
+
+    let mut entry = Stack::default();
+    for _ in 0..frame_size {
+        entry.push(Entry::new(Fact::Unknown));
+    }
+    if let Some(state) = states.first_mut() {
+        *state = Some(entry);
+    }
+
+    for address in 0..bytecode.len() {
+        let Some(stack) = states[address].take() else {
+            continue;
+        };
+
+        let Some(successors) = step(address, &bytecode[address], stack, vm) else {
+            return vec![None; bytecode.len()];
+        };
+
+        for (target, stack) in successors {
+            let Some(stack) = stack else {
+                continue;
+            };
+
+            if target == address + 1 && pushes(&bytecode[address]) {
+                facts[address] = stack.top().map(|entry| entry.fact);
+            }
+
+            // Jumps only go forward, so every state is complete by the time
+            // its instruction is reached.
+            let Some(state) = states.get_mut(target).filter(|_| target > address) else {
+                return vec![None; bytecode.len()];
+            };
+
+            *state = match state.take() {
+                Some(known) => match known.join(&stack) {
+                    Some(joined) => Some(joined),
+                    None => return vec![None; bytecode.len()],
+                },
+                None => Some(stack),
+            };
+        }
+    }
+
+    facts
+}
+
+fn pushes(instruction: &Instruction) -> bool {
+    !matches!(
+        instruction,
+        Instruction::GlobalSet(_)
+            | Instruction::Pop
+            | Instruction::If(_)
+            | Instruction::Jump(_)
+            | Instruction::JumpBack(_)
+            | Instruction::Return(_)
+    )
+}
+
+type Successors = Vec<(usize, Option<Stack>)>;
+
+/// Runs `instruction` on `stack`, returning the states it leaves for the
+/// instructions that may run next, where `None` means the path cannot be
+/// taken. Returns `None` when the stack underflows.
+fn step(
+    address: usize,
+    instruction: &Instruction,
+    mut stack: Stack,
+    vm: &Vm,
+) -> Option<Successors> {
+    let next = address + 1;
+
+    match *instruction {
+        Instruction::Constant(index) => stack.push(Entry::new(constant(vm, index))),
+        Instruction::True => stack.push(Entry::new(Fact::Bool(Some(true)))),
+        Instruction::False => stack.push(Entry::new(Fact::Bool(Some(false)))),
+        Instruction::GlobalGet(_) | Instruction::ReadLine => stack.push(Entry::new(Fact::Unknown)),
+        Instruction::ReadInt => stack.push(Entry::new(Fact::Integer(Range::FULL))),
+        Instruction::LocalGet(slot, _) => {
+            let entry = stack.get(slot as usize)?;
+            stack.push(Entry {
+                local: Some(slot),
+                ..entry
+            });
+        }
+        Instruction::Dup => {
+            let entry = stack.top()?;
+            stack.push(entry);
+        }
+        // What `print` evaluates to depends on the VM's printing options,
+        // which the compiled program may be run under any of.
+        Instruction::Print => {
+            stack.pop()?;
+            stack.push(Entry::new(Fact::Unknown));
+        }
+        Instruction::Add
+        | Instruction::Sub
+        | Instruction::Mul
+        | Instruction::Div
+        | Instruction::Rem
+        | Instruction::BitAnd
+        | Instruction::BitOr
+        | Instruction::BitXor
+        | Instruction::Shl
+        | Instruction::Shr
+        | Instruction::And
+        | Instruction::Or => {
+            let rhs = stack.pop()?;
+            let lhs = stack.pop()?;
+            stack.push(Entry::new(arithmetic(instruction, lhs.fact, rhs.fact)));
+        }
+        Instruction::Eq
+        | Instruction::Neq
+        | Instruction::Lt
+        | Instruction::Lte
+        | Instruction::Gt
+        | Instruction::Gte => {
+            let comparison = Comparison::of(instruction)?;
+            let rhs = stack.pop()?;
+            let lhs = stack.pop()?;
+            stack.push(compare(comparison, lhs, rhs));
+        }
+        Instruction::Tuple => {
+            stack.pop()?;
+            stack.pop()?;
+            stack.push(Entry::new(Fact::Unknown));
+        }
+        Instruction::First | Instruction::Second | Instruction::Show => {
+            stack.pop()?;
+            stack.push(Entry::new(Fact::Unknown));
+        }
+        Instruction::Neg => {
+            let value = stack.pop()?;
+            let zero = Fact::Integer(Range::point(0));
+            stack.push(Entry::new(arithmetic(&Instruction::Sub, zero, value.fact)));
+        }
+        Instruction::Not => {
+            let fact = match stack.pop()?.fact {
+                Fact::Bool(value) => Fact::Bool(value.map(|value| !value)),
+                _ => Fact::Bool(None),
+            };
+            stack.push(Entry::new(fact));
+        }
+        Instruction::GlobalSet(_) | Instruction::Pop => {
+            stack.pop()?;
+        }
+        Instruction::Closure(index) => {
+            for _ in 0..vm.functions.get(index as usize)?.captured.len() {
+                stack.pop()?;
+            }
+            stack.push(Entry::new(Fact::Unknown));
+        }
+        Instruction::Call(arity) | Instruction::TailCall(arity) => {
+            for _ in 0..=arity {
+                stack.pop()?;
+            }
+            stack.push(Entry::new(Fact::Unknown));
+        }
+        Instruction::LocalGetConstantAdd(slot, _, index)
+        | Instruction::LocalGetConstantSub(slot, _, index) => {
+            let operator = match instruction {
+                Instruction::LocalGetConstantAdd(_, _, _) => Instruction::Add,
+                _ => Instruction::Sub,
+            };
+            let lhs = stack.get(slot as usize)?;
+            stack.push(Entry::new(arithmetic(
+                &operator,
+                lhs.fact,
+                constant(vm, index),
+            )));
+        }
+        Instruction::ConstantLt(index) | Instruction::ConstantEq(index) => {
+            let comparison = match instruction {
+                Instruction::ConstantLt(_) => Comparison::Lt,
+                _ => Comparison::Eq,
+            };
+            let lhs = stack.pop()?;
+            stack.push(compare(comparison, lhs, Entry::new(constant(vm, index))));
+        }
+        Instruction::LocalGetTailCall(slot, _, arity) => {
+            stack.get(slot as usize)?;
+            for _ in 0..arity {
+                stack.pop()?;
+            }
+            stack.push(Entry::new(Fact::Unknown));
+        }
+        Instruction::Slide(count) => {
+            let mut top = stack.pop()?;
+            for _ in 0..count {
+                stack.pop()?;
+            }
+
+            // The slots it refers to may be gone, and reused by other values.
+            let slot = stack.height as u16;
+            top.local = top.local.filter(|local| *local < slot);
+            top.test = top.test.filter(|test| test.local < slot);
+            stack.push(top);
+        }
+        Instruction::If(_) => {
+            let target = jump_target(address, instruction)?;
+            let condition = stack.pop()?;
+
+            let (then, otherwise) = match condition.fact {
+                Fact::Bool(Some(true)) => (Some(stack), None),
+                Fact::Bool(Some(false)) => (None, Some(stack)),
+                _ => match condition.test {
+                    Some(test) => (refine(&stack, test, true), refine(&stack, test, false)),
+                    None => (Some(stack.clone()), Some(stack)),
+                },
+            };
+
+            return Some(vec![(next, then), (target, otherwise)]);
+        }
+        Instruction::Jump(_) => {
+            return Some(vec![(jump_target(address, instruction)?, Some(stack))]);
+        }
+        // The function starts over knowing nothing about its arguments,
+        // which is already what its first instruction assumes.
+        Instruction::JumpBack(_) | Instruction::Return(_) => return Some(Vec::new()),
+    }
+
+    Some(vec![(next, Some(stack))])
+}
+
+fn constant(vm: &Vm, index: u32) -> Fact {
+    match vm.constant(index) {
+        Value::Integer(value) => Fact::Integer(Range::point(*value)),
+        Value::Bool(value) => Fact::Bool(Some(*value)),
+        _ => Fact::Unknown,
+    }
+}
+
+fn arithmetic(instruction: &Instruction, lhs: Fact, rhs: Fact) -> Fact {
+    let (Fact::Integer(lhs), Fact::Integer(rhs)) = (lhs, rhs) else {
+        return match (instruction, lhs, rhs) {
+            // Adding anything but two integers concatenates strings.
+            (Instruction::Add, _, _) => Fact::Unknown,
+            (Instruction::And, Fact::Bool(lhs), Fact::Bool(rhs)) => Fact::Bool(match (lhs, rhs) {
+                (Some(false), _) | (_, Some(false)) => Some(false),
+                (Some(true), Some(true)) => Some(true),
+                _ => None,
+            }),
+            (Instruction::Or, Fact::Bool(lhs), Fact::Bool(rhs)) => Fact::Bool(match (lhs, rhs) {
+                (Some(true), _) | (_, Some(true)) => Some(true),
+                (Some(false), Some(false)) => Some(false),
+                _ => None,
+            }),
+            (Instruction::And | Instruction::Or, _, _) => Fact::Bool(None),
+            // Anything else fails unless both operands are integers.
+            _ => Fact::Integer(Range::FULL),
+        };
+    };
+
+    let ((a, b), (c, d)) = (lhs.bounds(), rhs.bounds());
+    let corners = |f: fn(i64, i64) -> i64| {
+        let values = [f(a, c), f(a, d), f(b, c), f(b, d)];
+        let min = values.into_iter().min().expect("There are four corners.");
+        let max = values.into_iter().max().expect("There are four corners.");
+        Range::wrapping(min, max)
+    };
+
+    let range = match instruction {
+        Instruction::Add => Range::wrapping(a + c, b + d),
+        Instruction::Sub => Range::wrapping(a - d, b - c),
+        Instruction::Mul => corners(|x, y| x * y),
+        Instruction::Div if c > 0 || d < 0 => corners(|x, y| x / y),
+        Instruction::Div => {
+            let magnitude = a.abs().max(b.abs());
+            Range::wrapping(-magnitude, magnitude)
+        }
+        Instruction::Rem => {
+            // The remainder takes the sign of the dividend, and is smaller
+            // than both operands in magnitude.
+            let magnitude = (c.abs().max(d.abs()) - 1).max(0);
+            let min = if a >= 0 { 0 } else { a.max(-magnitude) };
+            let max = if b <= 0 { 0 } else { b.min(magnitude) };
+            Range::wrapping(min, max)
+        }
+        // Masking with a non-negative integer can only clear bits.
+        Instruction::BitAnd if a >= 0 || c >= 0 => {
+            let max = match (a >= 0, c >= 0) {
+                (true, true) => b.min(d),
+                (true, false) => b,
+                _ => d,
+            };
+            Range::wrapping(0, max)
+        }
+        Instruction::BitAnd
+        | Instruction::BitOr
+        | Instruction::BitXor
+        | Instruction::Shl
+        | Instruction::Shr => Range::FULL,
+        _ => return Fact::Bool(None),
+    };
+
+    Fact::Integer(range)
+}
+
+fn compare(comparison: Comparison, lhs: Entry, rhs: Entry) -> Entry {
+    let ordered = !matches!(comparison, Comparison::Eq | Comparison::Neq);
+
+    let decided = match (lhs.fact, rhs.fact) {
+        (Fact::Integer(lhs), Fact::Integer(rhs)) => comparison.decide(lhs, rhs),
+        (Fact::Bool(Some(lhs)), Fact::Bool(Some(rhs))) if !ordered => {
+            Some((lhs == rhs) == (comparison == Comparison::Eq))
+        }
+        (Fact::Integer(_), Fact::Bool(_)) | (Fact::Bool(_), Fact::Integer(_)) if !ordered => {
+            Some(comparison == Comparison::Neq)
+        }
+        _ => None,
+    };
+
+    // Strings can be ordered too, so only comparing with an integer says
+    // the local is one.
+    let bound = |fact: Fact| match fact {
+        Fact::Integer(range) => Some(range),
+        _ => None,
+    };
+
+    let test = match (lhs.local, rhs.local) {
+        (Some(local), _) => bound(rhs.fact).map(|bound| Test {
+            local,
+            comparison,
+            bound,
+        }),
+        (None, Some(local)) => bound(lhs.fact).map(|bound| Test {
+            local,
+            comparison: comparison.flip(),
+            bound,
+        }),
+        (None, None) => None,
+    };
+
+    Entry {
+        fact: Fact::Bool(decided),
+        local: None,
+        test,
+    }
+}
+
+/// The stack after `test` came out as `outcome`, or `None` if it cannot.
+fn refine(stack: &Stack, test: Test, outcome: bool) -> Option<Stack> {
+    let comparison = if outcome {
+        test.comparison
+    } else {
+        test.comparison.negate()
+    };
+
+    let mut stack = stack.clone();
+    let slot = test.local as usize;
+
+    let Some(entry) = stack.get(slot) else {
+        return Some(stack);
+    };
+    let current = match entry.fact {
+        Fact::Integer(range) => range,
+        // Only integers can be ordered or equal to an integer.
+        Fact::Unknown if comparison != Comparison::Neq => Range::FULL,
+        _ => return Some(stack),
+    };
+    let refined = Fact::Integer(comparison.constrain(current, test.bound)?);
+
+    let mut entries = stack.split_off(slot)?;
+    entries[0].fact = refined;
+    for entry in &mut entries[1..] {
+        if entry.local == Some(test.local) {
+            entry.fact = refined;
+        }
+    }
+    for entry in entries {
+        stack.push(entry);
+    }
+
+    Some(stack)
+}
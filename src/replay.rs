@@ -0,0 +1,162 @@
+use anyhow::{bail, Context, Result};
+use std::collections::VecDeque;
+
+use crate::{
+    artifact::{Reader, Writer},
+    snapshot::{read_final, write_final},
+    value::FinalValue,
+};
+
+/// The first bytes of every replay log.
+pub const MAGIC: &[u8; 4] = b"RVMR";
+
+/// Version of the replay log format written by this build.
+pub const VERSION: u8 = 1;
+
+/// Something a run took from outside the program, which may differ from one
+/// run to the next.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// A line `read_line` or `read_int` read, without its line terminator.
+    Line(String),
+    /// What a native returned, or the message it failed with.
+    Native {
+        name: String,
+        result: Result<FinalValue, String>,
+    },
+}
+
+/// The events of a run in the order they happened, so a later run can be
+/// given the same ones with [`crate::vm::Vm::with_replay`] and do exactly
+/// the same.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ReplayLog {
+    pub events: Vec<Event>,
+}
+
+impl ReplayLog {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::default();
+
+        writer.bytes.extend(MAGIC);
+        writer.bytes.push(VERSION);
+
+        writer.varint(self.events.len() as u64);
+        for event in &self.events {
+            match event {
+                Event::Line(line) => {
+                    writer.bytes.push(0);
+                    writer.string(line);
+                }
+                Event::Native {
+                    name,
+                    result: Ok(value),
+                } => {
+                    writer.bytes.push(1);
+                    writer.string(name);
+                    write_final(&mut writer, value);
+                }
+                Event::Native {
+                    name,
+                    result: Err(message),
+                } => {
+                    writer.bytes.push(2);
+                    writer.string(name);
+                    writer.string(message);
+                }
+            }
+        }
+
+        writer.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::decode(bytes).context("Could not read replay log.")
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            bail!("Not a replay log.");
+        }
+        let version = reader.byte()?;
+        if version != VERSION {
+            bail!(
+                "Replay log version {version} is not supported; this build reads version {VERSION}."
+            );
+        }
+
+        let events = (0..reader.varint()?)
+            .map(|_| {
+                Ok(match reader.byte()? {
+                    0 => Event::Line(reader.string()?),
+                    1 => Event::Native {
+                        name: reader.string()?,
+                        result: Ok(read_final(&mut reader)?),
+                    },
+                    2 => Event::Native {
+                        name: reader.string()?,
+                        result: Err(reader.string()?),
+                    },
+                    tag => bail!("Unknown event tag {tag}."),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        if !reader.is_finished() {
+            bail!("Unexpected trailing bytes.");
+        }
+
+        Ok(Self { events })
+    }
+}
+
+/// Whether a VM is writing down the events of its runs or taking them from
+/// an earlier run's log.
+#[derive(Debug, Default)]
+pub(crate) enum Journal {
+    #[default]
+    Off,
+    Recording(Vec<Event>),
+    Replaying(VecDeque<Event>),
+}
+
+impl Journal {
+    /// The line to read instead of reading one, when replaying.
+    pub fn replayed_line(&mut self) -> Result<Option<String>> {
+        match self.next("read a line")? {
+            None => Ok(None),
+            Some(Event::Line(line)) => Ok(Some(line)),
+            Some(event) => bail!("The replay log has {event:?} where the run read a line."),
+        }
+    }
+
+    /// What to answer instead of calling the native `name`, when replaying.
+    pub fn replayed_native(&mut self, name: &str) -> Result<Option<Result<FinalValue, String>>> {
+        match self.next("called a native")? {
+            None => Ok(None),
+            Some(Event::Native {
+                name: recorded,
+                result,
+            }) if recorded == name => Ok(Some(result)),
+            Some(event) => bail!("The replay log has {event:?} where the run called {name}."),
+        }
+    }
+
+    pub fn record(&mut self, event: Event) {
+        if let Journal::Recording(events) = self {
+            events.push(event);
+        }
+    }
+
+    fn next(&mut self, action: &str) -> Result<Option<Event>> {
+        match self {
+            Journal::Replaying(events) => match events.pop_front() {
+                Some(event) => Ok(Some(event)),
+                None => bail!("The replay log ended before the run {action}."),
+            },
+            _ => Ok(None),
+        }
+    }
+}
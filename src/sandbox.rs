@@ -0,0 +1,40 @@
+use std::collections::HashSet;
+
+/// What programs are allowed to do beyond pure computation. Natives are
+/// enabled a whole namespace at a time; everything is denied by default.
+#[derive(Clone, Debug, Default)]
+pub struct SandboxPolicy {
+    namespaces: HashSet<String>,
+}
+
+impl SandboxPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn allow(mut self, namespace: &str) -> Self {
+        self.namespaces.insert(namespace.to_owned());
+        self
+    }
+
+    pub fn allows(&self, namespace: &str) -> bool {
+        self.namespaces.contains(namespace)
+    }
+}
+
+/// What `print`, `read_line` and `read_int` do. Anything but
+/// [`Effects::Allowed`] keeps programs from reaching the host's terminal,
+/// for running code that is not trusted.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Effects {
+    /// Programs print to the VM's output and read from its input.
+    #[default]
+    Allowed,
+    /// Printed lines are kept for the embedder to read with
+    /// [`crate::vm::Vm::printed`], and there is no input to read.
+    Captured,
+    /// Printing does nothing and there is no input to read.
+    Ignored,
+    /// Printing or reading stops the program with an error.
+    Denied,
+}
@@ -0,0 +1,125 @@
+use anyhow::{anyhow, bail, Result};
+use std::collections::VecDeque;
+
+use crate::{
+    native::SuspensionToken,
+    value::FinalValue,
+    vm::{Execution, Vm},
+};
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct ProgramId(usize);
+
+/// A native call some program is waiting on.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Pending {
+    pub program: ProgramId,
+    pub token: SuspensionToken,
+}
+
+enum Program {
+    Suspended(Box<Vm>),
+    Finished(Result<FinalValue>),
+    Taken,
+}
+
+/// Runs many programs on a single thread. Programs run until they finish or
+/// suspend on a native call; suspensions are queued in the order they
+/// happen, and the embedder's event loop resolves them with
+/// [`Scheduler::resume_with`] as results become available.
+#[derive(Default)]
+pub struct Scheduler {
+    pending: VecDeque<Pending>,
+    programs: Vec<Program>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts a program on `vm`, which should already have its natives
+    /// registered, and runs it until it finishes or first suspends.
+    pub fn spawn(&mut self, mut vm: Vm, filename: &str, contents: &str) -> ProgramId {
+        let program = ProgramId(self.programs.len());
+
+        let execution = vm.start(filename, contents);
+        self.programs.push(Program::Taken);
+        self.settle(program, vm, execution);
+
+        program
+    }
+
+    /// Removes and returns the oldest suspension that has not been resolved.
+    pub fn next_pending(&mut self) -> Option<Pending> {
+        self.pending.pop_front()
+    }
+
+    pub fn pending(&self) -> impl Iterator<Item = &Pending> {
+        self.pending.iter()
+    }
+
+    /// Whether every program has finished.
+    pub fn is_idle(&self) -> bool {
+        self.programs
+            .iter()
+            .all(|program| !matches!(program, Program::Suspended(_)))
+    }
+
+    /// Resolves a suspension with `value` and runs its program until it
+    /// finishes or suspends again.
+    pub fn resume_with(&mut self, pending: &Pending, value: FinalValue) -> Result<()> {
+        let slot = self
+            .programs
+            .get_mut(pending.program.0)
+            .ok_or_else(|| anyhow!("Unknown program {:?}.", pending.program))?;
+
+        match slot {
+            Program::Suspended(vm) if vm.suspension() == Some(&pending.token) => {}
+            _ => bail!(
+                "Program {:?} is not waiting on call {}.",
+                pending.program,
+                pending.token.id
+            ),
+        }
+
+        let Program::Suspended(vm) = std::mem::replace(slot, Program::Taken) else {
+            unreachable!("The program was just checked to be suspended.");
+        };
+
+        self.pending.retain(|queued| queued != pending);
+
+        let mut vm = *vm;
+        let execution = vm.resume_with(&pending.token, value);
+        self.settle(pending.program, vm, execution);
+
+        Ok(())
+    }
+
+    /// Returns the result of a finished program. Each result can only be taken once.
+    pub fn take_result(&mut self, program: ProgramId) -> Option<Result<FinalValue>> {
+        let slot = self.programs.get_mut(program.0)?;
+
+        match std::mem::replace(slot, Program::Taken) {
+            Program::Finished(result) => Some(result),
+            other => {
+                *slot = other;
+                None
+            }
+        }
+    }
+
+    fn settle(&mut self, program: ProgramId, vm: Vm, execution: Result<Execution>) {
+        self.programs[program.0] = match execution {
+            Ok(Execution::Suspended(token)) => {
+                self.pending.push_back(Pending { program, token });
+                Program::Suspended(Box::new(vm))
+            }
+            Ok(Execution::Finished(value)) => Program::Finished(Ok(value)),
+            Ok(Execution::Paused) => Program::Finished(Err(anyhow!(
+                "Programs run by a scheduler cannot pause at breakpoints."
+            ))),
+            Err(error) => Program::Finished(Err(error)),
+        };
+    }
+}
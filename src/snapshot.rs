@@ -0,0 +1,470 @@
+use anyhow::{bail, Context, Result};
+use num_bigint::BigInt;
+
+use crate::{
+    artifact::{unzigzag, zigzag, CompiledProgram, Reader, Writer},
+    call_frame::{ElidedCalls, ELIDED_CALLS},
+    gc::Gc,
+    interner::Symbol,
+    memo::MemoKey,
+    native::SuspensionToken,
+    value::{FinalValue, ShortString, Tagged},
+};
+
+/// The first bytes of every snapshot.
+pub const MAGIC: &[u8; 4] = b"RVMS";
+
+/// Version of the snapshot format written by this build.
+pub const VERSION: u8 = 3;
+
+/// The state of a paused or suspended run, as saved by
+/// [`crate::vm::Vm::snapshot`]. Heap handles and symbols keep their numbers,
+/// so values refer to each other as they did in the saved VM.
+pub(crate) struct Snapshot {
+    /// The function table and the top-level bytecode.
+    pub program: CompiledProgram,
+    /// The source offsets of each function's instructions, then the
+    /// script's.
+    pub offsets: Vec<Vec<usize>>,
+    pub strings: Vec<String>,
+    /// Every heap slot, with the tuple depth of the value in it.
+    pub objects: Vec<Option<(Object, u32)>>,
+    pub stack: Vec<Tagged>,
+    pub frames: Vec<Frame>,
+    pub globals: Vec<(Symbol, Tagged)>,
+    pub natives: Vec<(Symbol, Tagged)>,
+    pub memoization: Vec<(MemoKey, Tagged)>,
+    pub paused: bool,
+    pub suspension: Option<SuspensionToken>,
+    pub next_suspension: u64,
+}
+
+/// A heap value, with functions referred to by their index and natives by
+/// their name.
+pub(crate) enum Object {
+    Bool(bool),
+    Integer(i32),
+    BigInteger(BigInt),
+    String(String),
+    Tuple(Tagged, Tagged),
+    List(Vec<Tagged>),
+    Record(Vec<(String, Tagged)>),
+    Closure(u32, Vec<(Symbol, Tagged)>),
+    Native(String),
+}
+
+pub(crate) struct Frame {
+    /// The index of the function the frame runs, or `None` for the script.
+    pub function: Option<u32>,
+    pub closure: Option<Gc>,
+    pub instruction_pointer: usize,
+    pub frame_index: usize,
+    pub elided: ElidedCalls,
+    pub memo_key: Option<MemoKey>,
+    pub pure: bool,
+}
+
+impl Snapshot {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut writer = Writer::default();
+
+        writer.bytes.extend(MAGIC);
+        writer.bytes.push(VERSION);
+
+        let program = self.program.to_bytes()?;
+        writer.varint(program.len() as u64);
+        writer.bytes.extend(program);
+
+        writer.varint(self.offsets.len() as u64);
+        for offsets in &self.offsets {
+            writer.varint(offsets.len() as u64);
+            for offset in offsets {
+                writer.varint(*offset as u64);
+            }
+        }
+
+        writer.strings(&self.strings);
+
+        writer.varint(self.objects.len() as u64);
+        for object in &self.objects {
+            let Some((object, depth)) = object else {
+                writer.bytes.push(0);
+                continue;
+            };
+
+            match object {
+                Object::Bool(b) => writer.bytes.extend([1, *b as u8]),
+                Object::Integer(i) => {
+                    writer.bytes.push(2);
+                    writer.varint(zigzag(*i));
+                }
+                Object::String(s) => {
+                    writer.bytes.push(3);
+                    writer.string(s);
+                }
+                Object::Tuple(first, second) => {
+                    writer.bytes.push(4);
+                    write_tagged(&mut writer, *first);
+                    write_tagged(&mut writer, *second);
+                }
+                Object::Closure(function, environment) => {
+                    writer.bytes.push(5);
+                    writer.varint(*function as u64);
+                    write_bindings(&mut writer, environment);
+                }
+                Object::Native(name) => {
+                    writer.bytes.push(6);
+                    writer.string(name);
+                }
+                Object::List(elements) => {
+                    writer.bytes.push(7);
+                    writer.varint(elements.len() as u64);
+                    for element in elements {
+                        write_tagged(&mut writer, *element);
+                    }
+                }
+                Object::Record(fields) => {
+                    writer.bytes.push(8);
+                    writer.varint(fields.len() as u64);
+                    for (key, value) in fields {
+                        writer.string(key);
+                        write_tagged(&mut writer, *value);
+                    }
+                }
+                Object::BigInteger(i) => {
+                    writer.bytes.push(9);
+                    writer.string(&i.to_string());
+                }
+            }
+            writer.varint(*depth as u64);
+        }
+
+        writer.varint(self.stack.len() as u64);
+        for value in &self.stack {
+            write_tagged(&mut writer, *value);
+        }
+
+        writer.varint(self.frames.len() as u64);
+        for frame in &self.frames {
+            match frame.function {
+                Some(function) => writer.varint(function as u64 + 1),
+                None => writer.varint(0),
+            }
+            match frame.closure {
+                Some(handle) => writer.varint(handle.0 as u64 + 1),
+                None => writer.varint(0),
+            }
+            writer.varint(frame.instruction_pointer as u64);
+            writer.varint(frame.frame_index as u64);
+            writer.varint(frame.elided.count);
+            for function in frame.elided.recent {
+                writer.varint(function as u64);
+            }
+            match &frame.memo_key {
+                Some(key) => {
+                    writer.bytes.push(1);
+                    write_memo_key(&mut writer, key);
+                }
+                None => writer.bytes.push(0),
+            }
+            writer.bytes.push(frame.pure as u8);
+        }
+
+        write_bindings(&mut writer, &self.globals);
+        write_bindings(&mut writer, &self.natives);
+
+        writer.varint(self.memoization.len() as u64);
+        for (key, value) in &self.memoization {
+            write_memo_key(&mut writer, key);
+            write_tagged(&mut writer, *value);
+        }
+
+        writer.bytes.push(self.paused as u8);
+
+        match &self.suspension {
+            Some(token) => {
+                writer.bytes.push(1);
+                writer.varint(token.id);
+                writer.string(&token.native);
+                writer.varint(token.arguments.len() as u64);
+                for argument in &token.arguments {
+                    write_final(&mut writer, argument);
+                }
+            }
+            None => writer.bytes.push(0),
+        }
+        writer.varint(self.next_suspension);
+
+        Ok(writer.bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        Self::decode(bytes).context("Could not read snapshot.")
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(bytes);
+
+        if reader.take(MAGIC.len())? != MAGIC {
+            bail!("Not a snapshot.");
+        }
+        let version = reader.byte()?;
+        if version != VERSION {
+            bail!(
+                "Snapshot version {version} is not supported; this build reads version {VERSION}."
+            );
+        }
+
+        let length = reader.operand()?;
+        let program = CompiledProgram::from_bytes(reader.take(length)?)?;
+
+        let offsets = (0..reader.varint()?)
+            .map(|_| {
+                (0..reader.varint()?)
+                    .map(|_| reader.operand())
+                    .collect::<Result<_>>()
+            })
+            .collect::<Result<_>>()?;
+
+        let strings = reader.strings()?;
+
+        let objects = (0..reader.varint()?)
+            .map(|_| {
+                let object = match reader.byte()? {
+                    0 => return Ok(None),
+                    1 => Object::Bool(reader.byte()? != 0),
+                    2 => Object::Integer(unzigzag(reader.varint()?)?),
+                    3 => Object::String(reader.string()?),
+                    4 => Object::Tuple(read_tagged(&mut reader)?, read_tagged(&mut reader)?),
+                    5 => Object::Closure(reader.operand()?, read_bindings(&mut reader)?),
+                    6 => Object::Native(reader.string()?),
+                    7 => Object::List(
+                        (0..reader.varint()?)
+                            .map(|_| read_tagged(&mut reader))
+                            .collect::<Result<_>>()?,
+                    ),
+                    8 => Object::Record(
+                        (0..reader.varint()?)
+                            .map(|_| Ok((reader.string()?, read_tagged(&mut reader)?)))
+                            .collect::<Result<_>>()?,
+                    ),
+                    9 => Object::BigInteger(read_big_integer(&mut reader)?),
+                    kind => bail!("Unknown kind of value {kind}."),
+                };
+                Ok(Some((object, reader.operand()?)))
+            })
+            .collect::<Result<_>>()?;
+
+        let stack = (0..reader.varint()?)
+            .map(|_| read_tagged(&mut reader))
+            .collect::<Result<_>>()?;
+
+        let frames = (0..reader.varint()?)
+            .map(|_| {
+                let function = reader.operand::<u32>()?.checked_sub(1);
+                let closure = reader.operand::<u32>()?.checked_sub(1).map(Gc);
+                let instruction_pointer = reader.operand()?;
+                let frame_index = reader.operand()?;
+
+                let mut elided = ElidedCalls {
+                    count: reader.varint()?,
+                    recent: [0; ELIDED_CALLS],
+                };
+                for function in &mut elided.recent {
+                    *function = reader.operand()?;
+                }
+                let memo_key = match reader.byte()? {
+                    0 => None,
+                    _ => Some(read_memo_key(&mut reader)?),
+                };
+                let pure = reader.byte()? != 0;
+
+                Ok(Frame {
+                    function,
+                    closure,
+                    instruction_pointer,
+                    frame_index,
+                    elided,
+                    memo_key,
+                    pure,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        let globals = read_bindings(&mut reader)?;
+        let natives = read_bindings(&mut reader)?;
+
+        let memoization = (0..reader.varint()?)
+            .map(|_| {
+                let key = read_memo_key(&mut reader)?;
+                Ok((key, read_tagged(&mut reader)?))
+            })
+            .collect::<Result<_>>()?;
+
+        let paused = reader.byte()? != 0;
+
+        let suspension = match reader.byte()? {
+            0 => None,
+            _ => Some(SuspensionToken {
+                id: reader.varint()?,
+                native: reader.string()?,
+                arguments: (0..reader.varint()?)
+                    .map(|_| read_final(&mut reader))
+                    .collect::<Result<_>>()?,
+            }),
+        };
+        let next_suspension = reader.varint()?;
+
+        if !reader.is_finished() {
+            bail!("Unexpected trailing bytes.");
+        }
+
+        Ok(Self {
+            program,
+            offsets,
+            strings,
+            objects,
+            stack,
+            frames,
+            globals,
+            natives,
+            memoization,
+            paused,
+            suspension,
+            next_suspension,
+        })
+    }
+}
+
+fn write_tagged(writer: &mut Writer, value: Tagged) {
+    match value {
+        Tagged::Bool(b) => writer.bytes.extend([0, b as u8]),
+        Tagged::Integer(i) => {
+            writer.bytes.push(1);
+            writer.varint(zigzag(i));
+        }
+        Tagged::Symbol(symbol) => {
+            writer.bytes.push(2);
+            writer.varint(symbol.0 as u64);
+        }
+        Tagged::Short(short) => {
+            writer.bytes.push(3);
+            writer.string(short.as_str());
+        }
+        Tagged::Object(handle) => {
+            writer.bytes.push(4);
+            writer.varint(handle.0 as u64);
+        }
+    }
+}
+
+fn read_tagged(reader: &mut Reader) -> Result<Tagged> {
+    Ok(match reader.byte()? {
+        0 => Tagged::Bool(reader.byte()? != 0),
+        1 => Tagged::Integer(unzigzag(reader.varint()?)?),
+        2 => Tagged::Symbol(Symbol(reader.operand()?)),
+        3 => match ShortString::new(&reader.string()?) {
+            Some(short) => Tagged::Short(short),
+            None => bail!("Short string too long."),
+        },
+        4 => Tagged::Object(Gc(reader.operand()?)),
+        tag => bail!("Unknown value tag {tag}."),
+    })
+}
+
+fn write_memo_key(writer: &mut Writer, key: &MemoKey) {
+    writer.varint(key.function as u64);
+    writer.varint(key.arguments.len() as u64);
+    for argument in &key.arguments {
+        write_tagged(writer, *argument);
+    }
+}
+
+fn read_memo_key(reader: &mut Reader) -> Result<MemoKey> {
+    Ok(MemoKey {
+        function: reader.operand()?,
+        arguments: (0..reader.varint()?)
+            .map(|_| read_tagged(reader))
+            .collect::<Result<_>>()?,
+    })
+}
+
+fn write_bindings(writer: &mut Writer, bindings: &[(Symbol, Tagged)]) {
+    writer.varint(bindings.len() as u64);
+    for (name, value) in bindings {
+        writer.varint(name.0 as u64);
+        write_tagged(writer, *value);
+    }
+}
+
+fn read_bindings(reader: &mut Reader) -> Result<Vec<(Symbol, Tagged)>> {
+    (0..reader.varint()?)
+        .map(|_| Ok((Symbol(reader.operand()?), read_tagged(reader)?)))
+        .collect()
+}
+
+pub(crate) fn write_final(writer: &mut Writer, value: &FinalValue) {
+    match value {
+        FinalValue::Bool(b) => writer.bytes.extend([0, *b as u8]),
+        FinalValue::Integer(i) => {
+            writer.bytes.push(1);
+            writer.varint(zigzag(*i));
+        }
+        FinalValue::String(s) => {
+            writer.bytes.push(2);
+            writer.string(s);
+        }
+        FinalValue::Tuple(first, second) => {
+            writer.bytes.push(3);
+            write_final(writer, first);
+            write_final(writer, second);
+        }
+        FinalValue::Closure => writer.bytes.push(4),
+        FinalValue::List(elements) => {
+            writer.bytes.push(5);
+            writer.varint(elements.len() as u64);
+            for element in elements {
+                write_final(writer, element);
+            }
+        }
+        FinalValue::Record(fields) => {
+            writer.bytes.push(6);
+            writer.varint(fields.len() as u64);
+            for (key, value) in fields {
+                writer.string(key);
+                write_final(writer, value);
+            }
+        }
+        FinalValue::BigInteger(i) => {
+            writer.bytes.push(7);
+            writer.string(&i.to_string());
+        }
+    }
+}
+
+pub(crate) fn read_final(reader: &mut Reader) -> Result<FinalValue> {
+    Ok(match reader.byte()? {
+        0 => FinalValue::Bool(reader.byte()? != 0),
+        1 => FinalValue::Integer(unzigzag(reader.varint()?)?),
+        2 => FinalValue::String(reader.string()?),
+        3 => FinalValue::Tuple(Box::new(read_final(reader)?), Box::new(read_final(reader)?)),
+        4 => FinalValue::Closure,
+        5 => FinalValue::List(
+            (0..reader.varint()?)
+                .map(|_| read_final(reader))
+                .collect::<Result<_>>()?,
+        ),
+        6 => FinalValue::Record(
+            (0..reader.varint()?)
+                .map(|_| Ok((reader.string()?, read_final(reader)?)))
+                .collect::<Result<_>>()?,
+        ),
+        7 => FinalValue::BigInteger(read_big_integer(reader)?),
+        tag => bail!("Unknown value tag {tag}."),
+    })
+}
+
+/// Reads a big integer, which is written as its digits.
+fn read_big_integer(reader: &mut Reader) -> Result<BigInt> {
+    reader.string()?.parse().context("Malformed big integer.")
+}
@@ -0,0 +1,31 @@
+/// Finds the line and column of byte offsets into a source file, as the
+/// compiler records them for each instruction. Lines and columns count
+/// from 1, and columns count bytes.
+#[derive(Clone, Debug)]
+pub struct LineIndex {
+    /// Where each line starts.
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .collect();
+
+        Self { starts }
+    }
+
+    pub fn line_of(&self, offset: usize) -> usize {
+        self.starts.partition_point(|start| *start <= offset)
+    }
+
+    pub fn column_of(&self, offset: usize) -> usize {
+        offset - self.starts[self.line_of(offset) - 1] + 1
+    }
+
+    /// Where a line starts, if the source has that many.
+    pub fn start_of(&self, line: usize) -> Option<usize> {
+        self.starts.get(line.checked_sub(1)?).copied()
+    }
+}
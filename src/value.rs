@@ -1,78 +1,170 @@
+use num_bigint::BigInt;
+use serde::{ser::SerializeTuple, Serialize, Serializer};
 use std::{
     cmp::{Eq, PartialEq},
-    convert::From,
+    collections::BTreeMap,
     fmt,
     rc::Rc,
 };
 
-use crate::function::Function;
+use crate::{function::Function, gc::Gc, interner::Symbol, native::Native};
 
+/// The representation values have on the stack and inside other values.
+/// Integers, booleans, interned strings and short strings are stored inline
+/// and everything else lives in the heap, so it fits in 64 bits and is copied
+/// instead of reference counted.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Tagged {
+    Bool(bool),
+    Integer(i32),
+    Symbol(Symbol),
+    Short(ShortString),
+    Object(Gc),
+}
+
+const _: () = assert!(std::mem::size_of::<Tagged>() == 8);
+
+/// A string short enough to be stored in a [`Tagged`] itself, which takes
+/// the bytes left over by the tag.
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+pub struct ShortString {
+    length: u8,
+    bytes: [u8; ShortString::CAPACITY],
+}
+
+impl ShortString {
+    pub const CAPACITY: usize = 6;
+
+    /// Returns `None` when `string` is longer than [`ShortString::CAPACITY`] bytes.
+    pub fn new(string: &str) -> Option<Self> {
+        if string.len() > Self::CAPACITY {
+            return None;
+        }
+
+        let mut bytes = [0; Self::CAPACITY];
+        bytes[..string.len()].copy_from_slice(string.as_bytes());
+
+        Some(Self {
+            length: string.len() as u8,
+            bytes,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.bytes[..self.length as usize])
+            .expect("Short strings are built from valid strings.")
+    }
+}
+
+impl fmt::Debug for ShortString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.as_str())
+    }
+}
+
+/// A value in full, as stored in the constant table and in the heap.
 #[derive(Clone)]
-pub enum Value<'a> {
+pub enum Value {
     Bool(bool),
     Integer(i32),
-    String(String),
-    Tuple(Box<Rc<Value<'a>>>, Box<Rc<Value<'a>>>),
-    Closure(&'a Function, Vec<(&'a str, Rc<Value<'a>>)>),
+    /// An integer that does not fit in 32 bits, only made by VMs promoting
+    /// results that overflow.
+    BigInteger(Rc<BigInt>),
+    String(Rc<str>),
+    Tuple(Tagged, Tagged),
+    List(Vec<Tagged>),
+    Record(BTreeMap<Rc<str>, Tagged>),
+    Closure(Rc<Function>, Rc<Environment>),
+    Native(Rc<Native>),
 }
 
-impl<'a> fmt::Debug for Value<'a> {
+/// The values a closure captured, by name, in the order of
+/// [`Function::captured`]. Frames running the closure share it, so reading a
+/// captured variable does not go through the heap.
+pub type Environment = [(Symbol, Tagged)];
+
+impl fmt::Debug for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Value::Bool(b) => write!(f, "Bool({b})"),
             Value::Integer(i) => write!(f, "Integer({i})"),
+            Value::BigInteger(i) => write!(f, "BigInteger({i})"),
             Value::String(s) => write!(f, "String({s})"),
             Value::Tuple(t1, t2) => write!(f, "Tuple({t1:?}, {t2:?})"),
+            Value::List(elements) => write!(f, "List({elements:?})"),
+            Value::Record(fields) => write!(f, "Record({fields:?})"),
             Value::Closure(fun, _) => write!(f, "Closure({})", fun.index),
+            Value::Native(native) => write!(f, "Native({})", native.name),
         }
     }
 }
 
-impl<'a> fmt::Display for Value<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Bool(b) => write!(f, "{b}"),
-            Value::Integer(i) => write!(f, "{i}"),
-            Value::String(s) => write!(f, "{s}"),
-            Value::Tuple(t1, t2) => write!(f, "({t1}, {t2})"),
-            Value::Closure { .. } => write!(f, "<#closure>"),
-        }
-    }
-}
-
-impl<'a> PartialEq for Value<'a> {
+/// Tuples, lists and records compare by handle, since their elements live in the heap, and so do
+/// the captured values of closures, which are equal when they also run the same function. Natives
+/// are only equal to themselves. Programs compare values with [`crate::gc::Heap::equals`], where
+/// closures are only equal to themselves too.
+impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (Value::Bool(b1), Value::Bool(b2)) => b1 == b2,
             (Value::Integer(i1), Value::Integer(i2)) => i1 == i2,
+            (Value::BigInteger(i1), Value::BigInteger(i2)) => i1 == i2,
             (Value::String(s1), Value::String(s2)) => s1 == s2,
             (Value::Tuple(v1, v2), Value::Tuple(v3, v4)) => v1 == v3 && v2 == v4,
+            (Value::List(l1), Value::List(l2)) => l1 == l2,
+            (Value::Record(r1), Value::Record(r2)) => r1 == r2,
+            (Value::Closure(f1, e1), Value::Closure(f2, e2)) => Rc::ptr_eq(f1, f2) && e1 == e2,
+            (Value::Native(n1), Value::Native(n2)) => Rc::ptr_eq(n1, n2),
             _ => false,
         }
     }
 }
 
-impl<'a> Eq for Value<'a> {}
+impl Eq for Value {}
+
+/// The largest integer a JSON number holds exactly in readers, such as
+/// JavaScript, that parse numbers as doubles: 2^53 - 1.
+pub const MAX_SAFE_INTEGER: i64 = (1 << 53) - 1;
 
+/// A value copied out of the VM once a program ends. Integers that do not
+/// fit in 32 bits, which only promoting overflow produces, are
+/// [`FinalValue::BigInteger`]s, and serialize as strings beyond
+/// ±[`MAX_SAFE_INTEGER`].
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum FinalValue {
     Bool(bool),
     Integer(i32),
+    BigInteger(BigInt),
     String(String),
     Tuple(Box<FinalValue>, Box<FinalValue>),
+    List(Vec<FinalValue>),
+    Record(BTreeMap<String, FinalValue>),
     Closure,
 }
 
-impl<'a> From<&'a Value<'a>> for FinalValue {
-    fn from(value: &'a Value<'a>) -> Self {
-        match value {
-            Value::Bool(b) => Self::Bool(*b),
-            Value::Integer(i) => Self::Integer(*i),
-            Value::String(s) => Self::String(s.clone()),
-            Value::Tuple(v1, v2) => {
-                Self::Tuple(Box::new((&***v1).into()), Box::new((&***v2).into()))
+/// Big integers serialize as numbers when JSON readers hold them exactly,
+/// within ±[`MAX_SAFE_INTEGER`], and as a string of their digits otherwise,
+/// so no reader silently rounds them. Tuples serialize as two-element arrays, lists as arrays,
+/// records as objects and functions as the string `print` shows for them.
+impl Serialize for FinalValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            FinalValue::Bool(b) => serializer.serialize_bool(*b),
+            FinalValue::Integer(i) => serializer.serialize_i32(*i),
+            FinalValue::BigInteger(i) => match i64::try_from(i) {
+                Ok(i) if i.abs() <= MAX_SAFE_INTEGER => serializer.serialize_i64(i),
+                _ => serializer.collect_str(i),
+            },
+            FinalValue::String(s) => serializer.serialize_str(s),
+            FinalValue::Tuple(first, second) => {
+                let mut tuple = serializer.serialize_tuple(2)?;
+                tuple.serialize_element(first)?;
+                tuple.serialize_element(second)?;
+                tuple.end()
             }
-            Value::Closure(_, _) => Self::Closure,
+            FinalValue::List(elements) => serializer.collect_seq(elements),
+            FinalValue::Record(fields) => serializer.collect_map(fields),
+            FinalValue::Closure => serializer.serialize_str("<#closure>"),
         }
     }
 }
@@ -0,0 +1,181 @@
+use anyhow::{bail, Result};
+
+use crate::{bytecode::Instruction, optimizer::jump_target};
+
+/// The sizes of the tables a piece of bytecode indexes into.
+#[derive(Clone, Copy, Debug)]
+pub struct Tables<'a> {
+    pub constants: usize,
+    /// How many variables each function captures, which is how many values
+    /// a `Closure` of it pops.
+    pub functions: &'a [usize],
+    pub identifiers: usize,
+}
+
+/// Statically checks that `bytecode` can be run without corrupting the stack.
+///
+/// `frame_size` is the number of values the frame starts with (the arguments
+/// of a function, or zero for the top-level script). Every instruction must
+/// be reached with the same stack height along all paths, never pop more
+/// than the frame holds, only reference existing table entries, only jump
+/// inside the bytecode and never fall off its end. A `Return(n)` must find
+/// exactly `n` locals plus the result on the stack, a `TailCall` may only
+/// appear right before a return, and a `JumpBack` must find new arguments
+/// above the frame's and jump back to the first instruction.
+pub fn verify(bytecode: &[Instruction], frame_size: usize, tables: Tables) -> Result<()> {
+    stack_heights(bytecode, frame_size, tables).map(|_| ())
+}
+
+/// Verifies `bytecode` like [`verify`], returning the most values its frame
+/// holds at once, counting the `frame_size` it starts with.
+pub fn max_stack(bytecode: &[Instruction], frame_size: usize, tables: Tables) -> Result<usize> {
+    analyze(bytecode, frame_size, tables).map(|(_, max)| max)
+}
+
+/// Verifies `bytecode` like [`verify`], returning the stack height each
+/// instruction runs at, or `None` for the unreachable ones.
+pub(crate) fn stack_heights(
+    bytecode: &[Instruction],
+    frame_size: usize,
+    tables: Tables,
+) -> Result<Vec<Option<usize>>> {
+    analyze(bytecode, frame_size, tables).map(|(heights, _)| heights)
+}
+
+fn analyze(
+    bytecode: &[Instruction],
+    frame_size: usize,
+    tables: Tables,
+) -> Result<(Vec<Option<usize>>, usize)> {
+    let mut heights: Vec<Option<usize>> = vec![None; bytecode.len()];
+    let mut max = frame_size;
+    let mut pending = vec![(0, frame_size)];
+
+    if bytecode.is_empty() {
+        bail!("Bytecode must not be empty.");
+    }
+
+    while let Some((address, height)) = pending.pop() {
+        if address >= bytecode.len() {
+            bail!("Execution falls off the end of the bytecode at {address}.");
+        }
+
+        match heights[address] {
+            Some(known) if known == height => continue,
+            Some(known) => {
+                bail!("Instruction {address} is reached with stack heights {known} and {height}.")
+            }
+            None => heights[address] = Some(height),
+        }
+
+        let instruction = &bytecode[address];
+        let (popped, pushed) = match *instruction {
+            Instruction::Closure(index) => (
+                tables.functions.get(index as usize).copied().unwrap_or(0),
+                1,
+            ),
+            // The new arguments go where the frame's are, and everything
+            // above them is dropped.
+            Instruction::JumpBack(_) if height >= 2 * frame_size => (height - frame_size, 0),
+            Instruction::JumpBack(_) => (2 * frame_size, 0),
+            _ => instruction.stack_effect(),
+        };
+
+        if height < popped {
+            bail!("Instruction {address} ({instruction:?}) underflows the stack.");
+        }
+
+        check_operands(address, instruction, height, tables)?;
+
+        let height = height - popped + pushed;
+        max = max.max(height);
+
+        if let Some(target) = jump_target(address, instruction) {
+            pending.push((target, height));
+        }
+
+        match instruction {
+            Instruction::TailCall(_) | Instruction::LocalGetTailCall(_, _, _)
+                if !returns_after(bytecode, address + 1) =>
+            {
+                bail!("Instruction {address} is a tail call that is not followed by a return.")
+            }
+            Instruction::JumpBack(_) if jump_target(address, instruction) != Some(0) => {
+                bail!("Instruction {address} jumps back somewhere other than the start of the function.")
+            }
+            Instruction::Jump(_) | Instruction::JumpBack(_) => {}
+            Instruction::Return(locals) => {
+                if height != *locals as usize + 1 {
+                    bail!(
+                        "Instruction {address} returns with {height} values on the stack, expected {}.",
+                        *locals as usize + 1
+                    );
+                }
+            }
+            _ => pending.push((address + 1, height)),
+        }
+    }
+
+    Ok((heights, max))
+}
+
+/// Whether execution starting at `address` reaches a `Return` without running
+/// anything but unconditional jumps and slides.
+fn returns_after(bytecode: &[Instruction], mut address: usize) -> bool {
+    for _ in 0..bytecode.len() {
+        match bytecode.get(address) {
+            Some(Instruction::Return(_)) => return true,
+            Some(Instruction::Slide(_)) => address += 1,
+            Some(instruction @ Instruction::Jump(_)) => {
+                address = jump_target(address, instruction).expect("A Jump always has a target.");
+            }
+            _ => return false,
+        }
+    }
+
+    false
+}
+
+fn check_operands(
+    address: usize,
+    instruction: &Instruction,
+    height: usize,
+    tables: Tables,
+) -> Result<()> {
+    match *instruction {
+        Instruction::Constant(index)
+        | Instruction::ConstantLt(index)
+        | Instruction::ConstantEq(index)
+            if index as usize >= tables.constants =>
+        {
+            bail!("Instruction {address} references unknown constant {index}.")
+        }
+        Instruction::GlobalGet(index) | Instruction::GlobalSet(index)
+            if index as usize >= tables.identifiers =>
+        {
+            bail!("Instruction {address} references unknown identifier {index}.")
+        }
+        Instruction::LocalGetConstantAdd(_, _, constant)
+        | Instruction::LocalGetConstantSub(_, _, constant)
+            if constant as usize >= tables.constants =>
+        {
+            bail!("Instruction {address} references unknown constant {constant}.")
+        }
+        Instruction::LocalGet(index, identifier)
+        | Instruction::LocalGetConstantAdd(index, identifier, _)
+        | Instruction::LocalGetConstantSub(index, identifier, _)
+        | Instruction::LocalGetTailCall(index, identifier, _) => {
+            if identifier as usize >= tables.identifiers {
+                bail!("Instruction {address} references unknown identifier {identifier}.");
+            }
+            if index as usize >= height {
+                bail!("Instruction {address} reads local {index} outside of the frame.");
+            }
+            Ok(())
+        }
+        Instruction::Closure(index) if index as usize >= tables.functions.len() => {
+            bail!("Instruction {address} references unknown function {index}.")
+        }
+        _ => Ok(()),
+    }
+}
@@ -1,286 +1,2097 @@
-use anyhow::{anyhow, bail, Result};
-use rinha::{ast::Term, parser::parse_or_report};
-use std::rc::Rc;
+use anyhow::{anyhow, bail, Context, Result};
+use rinha::{ast::Term, parser::ParseError};
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::{Duration, Instant};
+use std::{
+    cmp::Ordering,
+    collections::HashMap,
+    fmt,
+    io::{self, BufRead, Write},
+    rc::Rc,
+};
+
+use crate::{
+    arithmetic::{Operator, Overflow},
+    artifact::{CompiledFunction, CompiledProgram, Constant, Metadata},
+    ast, builtins,
+    bytecode::{Instruction, OPCODE_NAMES},
+    call_frame::{CallFrame, ElidedCalls, StackTrace, TraceFrame},
+    chunk::Chunk,
+    compiler::{CallPosition, Compiler},
+    debugger::{Breakpoints, Frame},
+    diagnostics::CompileReport,
+    function::{Function, Local},
+    gc::{Allocation, Gc, GcStats, Heap},
+    imports,
+    inline_cache::InlineCaches,
+    interner::Symbol,
+    memo::{self, MemoKey, MemoTable},
+    native::{Native, NativeRegistry, NativeResult, SuspensionToken},
+    optimizer::{self, Pass, Passes},
+    printing::{PrintResult, Printing},
+    profiler::Profiler,
+    replay::{Event, Journal, ReplayLog},
+    sandbox::{Effects, SandboxPolicy},
+    snapshot::{Frame as SnapshotFrame, Object, Snapshot},
+    value::{Environment, FinalValue, ShortString, Tagged, Value},
+    verifier::{self, Tables},
+};
+
+/// The passes programs are compiled with unless [`Vm::with_passes`] says
+/// otherwise, as recorded in compiled artifacts.
+pub const COMPILE_OPTIONS: &[&str] = &[
+    "peephole",
+    "ranges",
+    "dead-code",
+    "tail-calls",
+    "fusion",
+    "inline",
+];
+
+/// How deeply tuples may nest unless [`Vm::with_max_tuple_depth`] says
+/// otherwise. Far beyond what programs build on purpose, yet shallow enough
+/// to print and compare within a 2 MiB thread stack.
+pub const DEFAULT_MAX_TUPLE_DEPTH: u32 = 4096;
+
+/// How many calls may be running at once unless [`Vm::with_max_call_depth`]
+/// says otherwise. Frames live on the heap, so only memory bounds how deep
+/// a program may recurse; this stops runaway recursion long before it runs
+/// out.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1_000_000;
+
+/// How many instructions run between checks of the deadline set by
+/// [`Vm::with_timeout`].
+pub const DEADLINE_INTERVAL: u64 = 4096;
+
+/// How far a run got before handing control back to the embedder.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Execution {
+    Finished(FinalValue),
+    /// A native function returned [`NativeResult::Pending`]. The run can be
+    /// continued with [`Vm::resume_with`] once its result is known.
+    Suspended(SuspensionToken),
+    /// The run reached a breakpoint. It can be continued with [`Vm::resume`]
+    /// or [`Vm::step`].
+    Paused,
+}
+
+/// An error raised while running a program, along with the calls that led
+/// to it. The trace is left out of the message when the error happened at
+/// the top level.
+#[derive(Debug)]
+pub struct RuntimeError {
+    pub error: anyhow::Error,
+    pub trace: StackTrace,
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+
+        let frames = &self.trace.frames;
+        if frames.len() > 1 || frames.iter().any(|frame| frame.elided_count > 0) {
+            write!(f, "\n{}", self.trace)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// The error a run stops with when it uses more memory than
+/// [`Vm::with_memory_limit`] allows.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct MemoryLimitExceeded {
+    /// Roughly how many bytes the run was using.
+    pub used: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Memory limit exceeded: the program uses about {} bytes, more than the limit of {}.",
+            self.used, self.limit
+        )
+    }
+}
+
+impl std::error::Error for MemoryLimitExceeded {}
+
+/// A memoization table entry that disagreed with the result of running the
+/// call again.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MemoMismatch {
+    pub function: String,
+    pub arguments: Vec<FinalValue>,
+    pub memoized: FinalValue,
+    pub computed: FinalValue,
+}
+
+/// Which memoization hits are re-executed, and what they turned up.
+struct MemoVerification {
+    percentage: u32,
+    /// Accumulates `percentage` on every hit; each time it reaches 100 a
+    /// hit is checked. This spreads checks evenly and deterministically.
+    credit: u32,
+    mismatches: Vec<MemoMismatch>,
+}
+
+/// Counters describing what a [`Vm`] has done.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct VmStats {
+    pub instructions: u64,
+    /// The opcodes executed and how often, most frequent first. Empty unless
+    /// enabled with [`Vm::with_opcode_histogram`].
+    pub opcodes: Vec<(&'static str, u64)>,
+    pub peak_stack_depth: usize,
+    pub peak_frame_depth: usize,
+    /// Calls answered from the memoization table, and calls that could have
+    /// been but were not.
+    pub memoization_hits: u64,
+    pub memoization_misses: u64,
+    /// Memoization hits re-executed to check the table; see
+    /// [`Vm::with_memo_verification`].
+    pub memoization_checks: u64,
+    /// Entries dropped to keep the table within [`Vm::with_memo_capacity`].
+    pub memoization_evictions: u64,
+    /// Values allocated in the heap.
+    pub allocations: u64,
+    /// Calls whose closure was the one their call site called last, and
+    /// calls that had to look it up.
+    pub inline_cache_hits: u64,
+    pub inline_cache_misses: u64,
+}
+
+/// What a `Call` or `TailCall` found under its arguments.
+enum Callee {
+    Native(Rc<Native>),
+    Function(Rc<Function>, Rc<Environment>),
+}
+
+pub struct Vm {
+    /// Where the heap stood when the current run started, when allocating
+    /// from an arena, so everything above it can be freed once the run ends.
+    arena_start: Option<usize>,
+    /// Where runs pause for the debugger, if anywhere.
+    breakpoints: Option<Breakpoints>,
+    /// The functions of [`builtins::builtins`], which are stored in the heap
+    /// only once a program uses them.
+    builtins: Vec<Rc<Native>>,
+    call_frames: Vec<CallFrame>,
+    /// The warnings compiling the last program found, and its errors when
+    /// recovering from them.
+    compile_report: CompileReport,
+    constants: Vec<Value>,
+    /// Where each constant sits in `constants`, so compiling a constant the
+    /// table already holds does not scan it.
+    constant_indices: HashMap<Tagged, u32>,
+    /// The constants as pushed on the stack, with strings already stored
+    /// inline or interned.
+    constant_values: Vec<Tagged>,
+    /// The address of the instruction running in the innermost frame.
+    current_address: usize,
+    /// When runs stop with an error for taking too long, if ever. There is
+    /// no clock to read in the browser.
+    #[cfg(not(target_arch = "wasm32"))]
+    deadline: Option<Instant>,
+    effects: Effects,
+    /// Whether compiling goes on past errors to report them all; see
+    /// [`Vm::with_error_recovery`].
+    error_recovery: bool,
+    fuel: Option<u64>,
+    inline_caches: InlineCaches,
+    max_call_depth: usize,
+    max_tuple_depth: u32,
+    /// How many bytes of values runs may keep alive; see
+    /// [`Vm::with_memory_limit`].
+    memory_limit: Option<usize>,
+    pub functions: Vec<Rc<Function>>,
+    globals: Vec<(Symbol, Tagged)>,
+    heap: Heap,
+    identifiers: Vec<Symbol>,
+    /// Where each identifier sits in `identifiers`.
+    identifier_indices: HashMap<Symbol, u32>,
+    /// Where `read_line` and `read_int` read from, or `None` for standard
+    /// input.
+    input: Option<Box<dyn BufRead>>,
+    /// Where runs write down what they read and what natives returned, or
+    /// take it from; see [`Vm::with_recording`].
+    journal: Journal,
+    memoization: MemoTable,
+    memo_verification: Option<MemoVerification>,
+    natives: Vec<(Symbol, Tagged)>,
+    next_suspension: u64,
+    opcode_counts: Option<Box<[u64; Instruction::OPCODES]>>,
+    /// Where `print` writes to, or `None` for standard output.
+    output: Option<Box<dyn Write>>,
+    overflow: Overflow,
+    passes: Passes,
+    /// Whether the current run stopped at a breakpoint.
+    paused: bool,
+    /// What each `print` wrote under [`Effects::Captured`].
+    printed: Vec<String>,
+    printing: Printing,
+    profiler: Option<Profiler>,
+    stack: Vec<Tagged>,
+    stats: VmStats,
+    suspension: Option<SuspensionToken>,
+}
+
+macro_rules! pop_operands {
+    ($self: ident) => {{
+        let rhs = $self
+            .stack
+            .pop()
+            .ok_or_else(|| anyhow!("Expected operand, but stack was empty."))?;
+
+        let lhs = $self
+            .stack
+            .pop()
+            .ok_or_else(|| anyhow!("Expected operand, but stack was empty."))?;
+
+        let result: Result<(Tagged, Tagged)> = Ok((lhs, rhs));
+        result
+    }};
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Self {
+        Self {
+            arena_start: None,
+            breakpoints: None,
+            builtins: builtins::builtins().into_iter().map(Rc::new).collect(),
+            call_frames: Vec::new(),
+            compile_report: CompileReport::default(),
+            constants: Vec::new(),
+            constant_indices: HashMap::new(),
+            constant_values: Vec::new(),
+            current_address: 0,
+            #[cfg(not(target_arch = "wasm32"))]
+            deadline: None,
+            effects: Effects::default(),
+            error_recovery: false,
+            fuel: None,
+            inline_caches: InlineCaches::default(),
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_tuple_depth: DEFAULT_MAX_TUPLE_DEPTH,
+            memory_limit: None,
+            functions: Vec::new(),
+            globals: Vec::new(),
+            heap: Heap::default(),
+            identifiers: Vec::new(),
+            identifier_indices: HashMap::new(),
+            input: None,
+            journal: Journal::Off,
+            memoization: MemoTable::default(),
+            memo_verification: None,
+            natives: Vec::new(),
+            next_suspension: 0,
+            opcode_counts: None,
+            output: None,
+            overflow: Overflow::default(),
+            passes: Passes::default(),
+            paused: false,
+            printed: Vec::new(),
+            printing: Printing::default(),
+            profiler: None,
+            stack: Vec::new(),
+            stats: VmStats::default(),
+            suspension: None,
+        }
+    }
+
+    pub fn interpret(&mut self, filename: &str, contents: &str) -> Result<FinalValue> {
+        let execution = self.start(filename, contents);
+        self.finish(execution)
+    }
+
+    /// Runs a program given as a JSON AST, the format the rinha specification
+    /// distributes pre-parsed programs in.
+    pub fn interpret_json(&mut self, json: &str) -> Result<FinalValue> {
+        let file: ast::File = serde_json::from_str(json).context("Could not parse JSON AST.")?;
+        let script = self.compile_expression(file.expression.into())?;
+
+        self.enter_script(script);
+        let execution = self.run();
+        self.finish(execution)
+    }
+
+    /// Runs a compiled program to completion; see [`Vm::start_program`].
+    pub fn interpret_program(&mut self, program: &CompiledProgram) -> Result<FinalValue> {
+        let execution = self.start_program(program);
+        self.finish(execution)
+    }
+
+    /// Ends a run that must have finished. With arena allocation, everything
+    /// the run allocated is freed at once, and so are the globals and
+    /// memoized results referring to it.
+    fn finish(&mut self, execution: Result<Execution>) -> Result<FinalValue> {
+        if let Some(start) = self.arena_start.take() {
+            self.call_frames.clear();
+            self.stack.clear();
+            self.globals.clear();
+            self.memoization.clear();
+            self.suspension = None;
+            self.paused = false;
+            self.heap.truncate(start);
+            self.inline_caches.clear();
+        }
+
+        expect_finished(execution?)
+    }
+
+    /// Compiles and runs a program until it either finishes or a native
+    /// function suspends it.
+    pub fn start(&mut self, filename: &str, contents: &str) -> Result<Execution> {
+        let script = self.compile_source(filename, contents)?;
+
+        self.enter_script(script);
+        self.run()
+    }
+
+    /// Compiles a program into an artifact that can be saved and later run
+    /// without its source by [`Vm::start_program`], on any number of VMs.
+    pub fn compile_program(&mut self, filename: &str, contents: &str) -> Result<CompiledProgram> {
+        let script = self.compile_source(filename, contents)?;
+
+        self.export_program(
+            script.bytecode,
+            Metadata::new(filename, contents, &self.passes.names()),
+        )
+    }
+
+    /// The VM's tables and `script` as a compiled program.
+    fn export_program(
+        &self,
+        script: Vec<Instruction>,
+        metadata: Metadata,
+    ) -> Result<CompiledProgram> {
+        let functions = self
+            .functions
+            .iter()
+            .map(|function| CompiledFunction {
+                arity: function.arity,
+                bytecode: function.chunk.bytecode.clone(),
+                captured: function
+                    .captured
+                    .iter()
+                    .map(|symbol| self.heap.string(*symbol).to_string())
+                    .collect(),
+                locals: function.locals.iter().map(|l| l.name.clone()).collect(),
+                name: function.name.clone(),
+            })
+            .collect();
+
+        Ok(CompiledProgram {
+            constants: self
+                .constants
+                .iter()
+                .map(Constant::try_from)
+                .collect::<Result<_>>()?,
+            functions,
+            identifiers: self
+                .identifiers
+                .iter()
+                .map(|symbol| self.heap.string(*symbol).to_string())
+                .collect(),
+            metadata,
+            script,
+        })
+    }
+
+    /// Loads a compiled program and runs it like [`Vm::start`]. Its tables
+    /// replace the VM's own, so the VM must not have compiled anything yet.
+    /// Artifacts may come from anywhere, so their bytecode is checked with
+    /// [`Vm::verify`] before it runs.
+    pub fn start_program(&mut self, program: &CompiledProgram) -> Result<Execution> {
+        self.load_program(program)?;
+        self.verify(&program.script)?;
+
+        self.enter_script(Chunk::new(program.script.clone()));
+        self.run()
+    }
+
+    fn ensure_unused(&self) -> Result<()> {
+        if !(self.constants.is_empty() && self.functions.is_empty() && self.identifiers.is_empty())
+        {
+            bail!(
+                "A compiled program can only be loaded into a VM that has not compiled anything."
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Fills the VM's tables from a compiled program.
+    fn load_program(&mut self, program: &CompiledProgram) -> Result<()> {
+        self.ensure_unused()?;
+
+        if program.functions.len() >= u32::MAX as usize {
+            bail!("Cannot create more than {} functions.", u32::MAX);
+        }
+
+        for constant in &program.constants {
+            self.push_constant(constant.into())?;
+        }
+
+        for identifier in &program.identifiers {
+            let symbol = self.heap.intern(identifier);
+            self.push_identifier(symbol);
+        }
+
+        for (index, function) in program.functions.iter().enumerate() {
+            let function = Function {
+                arity: function.arity,
+                chunk: Chunk::new(function.bytecode.clone()),
+                captured: function
+                    .captured
+                    .iter()
+                    .map(|name| self.heap.intern(name))
+                    .collect(),
+                index: index as u32,
+                locals: function
+                    .locals
+                    .iter()
+                    .map(|name| Local { name: name.clone() })
+                    .collect(),
+                name: function.name.clone(),
+                max_stack: 0,
+            };
+            self.functions.push(Rc::new(function));
+        }
+
+        self.measure_stacks(0)
+    }
+
+    /// Sets the [`Function::max_stack`] of the functions from `first` on,
+    /// which also verifies their bytecode.
+    fn measure_stacks(&mut self, first: usize) -> Result<()> {
+        let captured: Vec<usize> = self
+            .functions
+            .iter()
+            .map(|function| function.captured.len())
+            .collect();
+        let tables = Tables {
+            constants: self.constants.len(),
+            functions: &captured,
+            identifiers: self.identifiers.len(),
+        };
+
+        for function in &mut self.functions[first..] {
+            let function =
+                Rc::get_mut(function).expect("Functions being loaded are not shared yet.");
+            function.max_stack =
+                verifier::max_stack(&function.chunk.bytecode, function.arity as usize, tables)?;
+        }
+
+        Ok(())
+    }
+
+    /// Continues a suspended run, using `value` as the result of the native
+    /// call `token` was issued for.
+    pub fn resume_with(&mut self, token: &SuspensionToken, value: FinalValue) -> Result<Execution> {
+        match &self.suspension {
+            None => bail!("There is no suspended execution to resume."),
+            Some(suspension) if suspension.id != token.id => bail!(
+                "Execution is suspended at call {} to {}, not at call {}.",
+                suspension.id,
+                suspension.native,
+                token.id
+            ),
+            Some(_) => self.suspension = None,
+        }
+
+        let value = self.heap.allocate_final(&value)?;
+        self.stack.push(value);
+        self.run()
+    }
+
+    /// Continues a run paused at a breakpoint until it reaches the next one.
+    pub fn resume(&mut self) -> Result<Execution> {
+        self.continue_paused(false)
+    }
+
+    /// Runs a single instruction of a run paused at a breakpoint, pausing
+    /// again before the next one.
+    pub fn step(&mut self) -> Result<Execution> {
+        self.continue_paused(true)
+    }
+
+    fn continue_paused(&mut self, stepping: bool) -> Result<Execution> {
+        if !self.paused {
+            bail!("There is no paused execution to resume.");
+        }
+        self.paused = false;
+
+        // A run restored from a snapshot may continue without breakpoints.
+        if let Some(breakpoints) = &mut self.breakpoints {
+            breakpoints.resume(stepping);
+        }
+        self.run()
+    }
+
+    /// Saves a paused or suspended run, so it can be continued later, and
+    /// by another VM, after loading it with [`Vm::restore`].
+    pub fn snapshot(&self) -> Result<Vec<u8>> {
+        if !self.paused && self.suspension.is_none() {
+            bail!("Only a paused or suspended run can be snapshotted.");
+        }
+
+        let script = &self
+            .call_frames
+            .first()
+            .ok_or_else(|| anyhow!("There is no active call frame."))?
+            .function;
+
+        let objects = self
+            .heap
+            .objects()
+            .map(|object| {
+                object.map(|(value, depth)| {
+                    let object = match value {
+                        Value::Bool(b) => Object::Bool(*b),
+                        Value::Integer(i) => Object::Integer(*i),
+                        Value::BigInteger(i) => Object::BigInteger((**i).clone()),
+                        Value::String(s) => Object::String(s.to_string()),
+                        Value::Tuple(first, second) => Object::Tuple(*first, *second),
+                        Value::List(elements) => Object::List(elements.clone()),
+                        Value::Record(fields) => Object::Record(
+                            fields
+                                .iter()
+                                .map(|(key, value)| (key.to_string(), *value))
+                                .collect(),
+                        ),
+                        Value::Closure(function, environment) => {
+                            Object::Closure(function.index, environment.to_vec())
+                        }
+                        Value::Native(native) => Object::Native(native.global_name()),
+                    };
+                    (object, depth)
+                })
+            })
+            .collect();
+
+        // Frames re-executing a memoized call are not checked once restored,
+        // since the restoring VM may not verify memoization.
+        let frames = self
+            .call_frames
+            .iter()
+            .map(|frame| SnapshotFrame {
+                function: (frame.function.index != u32::MAX).then_some(frame.function.index),
+                closure: frame.closure,
+                instruction_pointer: frame.instruction_pointer,
+                frame_index: frame.frame_index,
+                elided: frame.elided,
+                memo_key: frame.memo_key.clone(),
+                pure: frame.pure,
+            })
+            .collect();
+
+        Snapshot {
+            program: self.export_program(script.chunk.bytecode.clone(), Metadata::default())?,
+            offsets: self
+                .functions
+                .iter()
+                .chain([script])
+                .map(|function| function.chunk.offsets.clone())
+                .collect(),
+            strings: self
+                .heap
+                .strings()
+                .map(|string| string.to_string())
+                .collect(),
+            objects,
+            stack: self.stack.clone(),
+            frames,
+            globals: self.globals.clone(),
+            natives: self.natives.clone(),
+            memoization: self
+                .memoization
+                .entries()
+                .map(|(function, arguments, value)| {
+                    let key = MemoKey {
+                        function,
+                        arguments: arguments.into(),
+                    };
+                    (key, value)
+                })
+                .collect(),
+            paused: self.paused,
+            suspension: self.suspension.clone(),
+            next_suspension: self.next_suspension,
+        }
+        .to_bytes()
+    }
+
+    /// Loads a run saved by [`Vm::snapshot`], to be continued with
+    /// [`Vm::resume`] or [`Vm::resume_with`]. Like [`Vm::start_program`], it
+    /// needs a VM that has not compiled anything, and the VM must provide the
+    /// natives the run used.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<()> {
+        self.ensure_unused()?;
+        let snapshot = Snapshot::from_bytes(bytes)?;
+
+        let natives: HashMap<String, Rc<Native>> = self
+            .builtins
+            .iter()
+            .map(|builtin| (builtin.global_name(), builtin.clone()))
+            .chain(self.natives.iter().filter_map(
+                |(_, native)| match &*self.heap.resolve(*native) {
+                    Value::Native(native) => Some((native.global_name(), native.clone())),
+                    _ => None,
+                },
+            ))
+            .collect();
+
+        let mut heap = Heap::new(self.heap.threshold());
+        for (index, string) in snapshot.strings.iter().enumerate() {
+            if heap.intern(string) != Symbol(index as u32) {
+                bail!("The snapshot interns {string:?} twice.");
+            }
+        }
+        self.heap = heap;
+        self.inline_caches.clear();
+
+        self.load_program(&snapshot.program)?;
+        self.verify(&snapshot.program.script)?;
+
+        let mut offsets = snapshot.offsets.into_iter();
+        for function in &mut self.functions {
+            Rc::get_mut(function)
+                .expect("Functions that were just loaded are not shared.")
+                .chunk
+                .offsets = offsets.next().unwrap_or_default();
+        }
+        let script = Rc::new(Function::script(Chunk {
+            bytecode: snapshot.program.script.clone(),
+            offsets: offsets.next().unwrap_or_default(),
+        }));
+
+        let objects = snapshot
+            .objects
+            .into_iter()
+            .map(|object| {
+                let Some((object, depth)) = object else {
+                    return Ok(None);
+                };
+
+                let value = match object {
+                    Object::Bool(b) => Value::Bool(b),
+                    Object::Integer(i) => Value::Integer(i),
+                    Object::BigInteger(i) => Value::BigInteger(Rc::new(i)),
+                    Object::String(s) => Value::String(s.into()),
+                    Object::Tuple(first, second) => Value::Tuple(first, second),
+                    Object::List(elements) => Value::List(elements),
+                    Object::Record(fields) => Value::Record(
+                        fields
+                            .into_iter()
+                            .map(|(key, value)| (key.into(), value))
+                            .collect(),
+                    ),
+                    Object::Closure(index, environment) => {
+                        let function = self
+                            .functions
+                            .get(index as usize)
+                            .ok_or_else(|| anyhow!("Function {index} does not exist."))?;
+                        Value::Closure(function.clone(), environment.into())
+                    }
+                    Object::Native(name) => match natives.get(&name) {
+                        Some(native) => Value::Native(native.clone()),
+                        None => bail!("The snapshot uses native {name}, which is not available."),
+                    },
+                };
+                Ok(Some((value, depth)))
+            })
+            .collect::<Result<_>>()?;
+        self.heap.restore(objects)?;
+
+        let mut frames = Vec::new();
+        for frame in snapshot.frames {
+            let function = match frame.function {
+                Some(index) => self
+                    .functions
+                    .get(index as usize)
+                    .ok_or_else(|| anyhow!("Function {index} does not exist."))?
+                    .clone(),
+                None => script.clone(),
+            };
+            let environment = match frame.closure {
+                Some(closure) if self.heap.is_valid(Tagged::Object(closure)) => {
+                    match self.heap.get(closure) {
+                        Value::Closure(_, environment) => environment.clone(),
+                        _ => bail!("The snapshot has an invalid call frame."),
+                    }
+                }
+                Some(_) => bail!("The snapshot has an invalid call frame."),
+                None => Rc::from([]),
+            };
+            if frame.instruction_pointer > function.chunk.len()
+                || frame.frame_index > snapshot.stack.len()
+                || frame
+                    .memo_key
+                    .as_ref()
+                    .is_some_and(|key| !memo::memoizable(&key.arguments))
+            {
+                bail!("The snapshot has an invalid call frame.");
+            }
+
+            frames.push(CallFrame {
+                function,
+                closure: frame.closure,
+                environment,
+                instruction_pointer: frame.instruction_pointer,
+                frame_index: frame.frame_index,
+                elided: frame.elided,
+                memo_check: None,
+                memo_key: frame.memo_key,
+                pure: frame.pure,
+            });
+        }
+        if frames.is_empty() {
+            bail!("The snapshot has no call frames.");
+        }
+
+        let roots = snapshot
+            .stack
+            .iter()
+            .chain(snapshot.globals.iter().map(|(_, value)| value))
+            .chain(snapshot.natives.iter().map(|(_, value)| value))
+            .chain(
+                snapshot
+                    .memoization
+                    .iter()
+                    .flat_map(|(key, value)| key.arguments.iter().chain([value])),
+            );
+        if !roots.into_iter().all(|value| self.heap.is_valid(*value)) {
+            bail!("The snapshot refers to a value that does not exist.");
+        }
+        if !snapshot
+            .memoization
+            .iter()
+            .all(|(key, _)| memo::memoizable(&key.arguments))
+        {
+            bail!("The snapshot memoizes a call by a heap value.");
+        }
+
+        self.call_frames = frames;
+        self.stack = snapshot.stack;
+        self.globals = snapshot.globals;
+        self.natives = snapshot.natives;
+        self.memoization.clear();
+        for (key, value) in snapshot.memoization {
+            self.memoization.insert(key, value);
+        }
+        self.paused = snapshot.paused;
+        self.suspension = snapshot.suspension;
+        self.next_suspension = snapshot.next_suspension;
+
+        Ok(())
+    }
+
+    /// The call frames of a paused run, innermost first.
+    pub fn frames(&self) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let mut end = self.stack.len();
+
+        for (depth, frame) in self.call_frames.iter().rev().enumerate() {
+            let function = &frame.function;
+            let address = if depth == 0 {
+                frame.instruction_pointer
+            } else {
+                frame.instruction_pointer - 1
+            };
+
+            let slots = self.stack[frame.frame_index..end]
+                .iter()
+                .enumerate()
+                .map(|(slot, value)| {
+                    let name = (slot < function.arity as usize)
+                        .then(|| function.locals[slot].name.clone());
+                    (name, self.heap.finalize(*value))
+                })
+                .collect();
+
+            let environment = frame
+                .environment
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        self.heap.string(*name).to_string(),
+                        self.heap.finalize(*value),
+                    )
+                })
+                .collect();
+
+            frames.push(Frame {
+                function: self.function_name(function.index),
+                address,
+                instruction: function.chunk.bytecode.get(address).cloned(),
+                offset: function.chunk.offset(address),
+                slots,
+                environment,
+            });
+
+            // The callee sits right under the frame's arguments.
+            end = frame.frame_index.saturating_sub(1);
+        }
+
+        frames
+    }
+
+    /// How many call frames the run has.
+    pub fn frame_depth(&self) -> usize {
+        self.call_frames.len()
+    }
+
+    /// Where in the source a paused run is about to continue, when known.
+    /// This is cheaper than [`Vm::frames`] when that is all that matters.
+    pub fn paused_offset(&self) -> Option<usize> {
+        let frame = self.call_frames.last()?;
+        frame.function.chunk.offset(frame.instruction_pointer)
+    }
+
+    /// Pauses runs where `breakpoints` say, returning
+    /// [`Execution::Paused`].
+    pub fn with_breakpoints(mut self, breakpoints: Breakpoints) -> Self {
+        self.breakpoints = Some(breakpoints);
+        self
+    }
+
+    pub fn breakpoints(&self) -> Option<&Breakpoints> {
+        self.breakpoints.as_ref()
+    }
+
+    pub fn breakpoints_mut(&mut self) -> Option<&mut Breakpoints> {
+        self.breakpoints.as_mut()
+    }
+
+    /// The native call the VM is waiting on, if any.
+    pub fn suspension(&self) -> Option<&SuspensionToken> {
+        self.suspension.as_ref()
+    }
+
+    /// Makes a function implemented by the host, such as a clock or an HTTP
+    /// client, available to programs under `name`. Definitions made by the
+    /// program itself take precedence.
+    pub fn register_native<F>(&mut self, name: &str, arity: u16, function: F)
+    where
+        F: Fn(&[FinalValue]) -> Result<FinalValue> + 'static,
+    {
+        self.register_suspendable_native(name, arity, move |arguments| {
+            function(arguments).map(NativeResult::Ready)
+        });
+    }
+
+    /// Like [`Vm::register_native`], but the function may answer
+    /// [`NativeResult::Pending`] to suspend the run until the embedder calls
+    /// [`Vm::resume_with`].
+    pub fn register_suspendable_native<F>(&mut self, name: &str, arity: u16, function: F)
+    where
+        F: Fn(&[FinalValue]) -> Result<NativeResult> + 'static,
+    {
+        let native = Native {
+            name: name.to_owned(),
+            arity: Some(arity),
+            function: Box::new(function),
+            pure: false,
+        };
+
+        let native = self.heap.store(Value::Native(Rc::new(native)));
+        let name = self.heap.intern(name);
+        self.natives.push((name, native));
+    }
+
+    /// Makes the natives of every namespace `policy` allows available to
+    /// programs.
+    pub fn with_natives(mut self, registry: &NativeRegistry, policy: &SandboxPolicy) -> Self {
+        for native in registry.allowed(policy) {
+            let name = self.heap.intern(&native.global_name());
+            let native = self.heap.store(Value::Native(native.clone()));
+            self.natives.push((name, native));
+        }
+
+        self
+    }
+
+    /// Forgets every program the VM has run, keeping its natives, its
+    /// settings and the memory it has already allocated, so a pooled VM can
+    /// take a new program without being rebuilt. Interned strings are kept
+    /// as well, since later programs are likely to use the same names.
+    pub fn reset(&mut self) {
+        self.arena_start = None;
+        self.call_frames.clear();
+        self.constants.clear();
+        self.constant_indices.clear();
+        self.constant_values.clear();
+        self.functions.clear();
+        self.globals.clear();
+        self.identifiers.clear();
+        self.identifier_indices.clear();
+        self.memoization.clear();
+        self.printed.clear();
+        self.stack.clear();
+        self.suspension = None;
+
+        self.collect_garbage();
+    }
+
+    /// Limits the number of instructions a run may execute. Once the fuel is
+    /// exhausted, execution stops with an error.
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// Chooses what `print`, `read_line` and `read_int` do, so untrusted
+    /// programs can be kept from the host's terminal.
+    pub fn with_effects(mut self, effects: Effects) -> Self {
+        self.effects = effects;
+        self
+    }
+
+    /// The lines programs printed under [`Effects::Captured`], oldest first.
+    /// Without [`Printing::newline`], these are what each `print` wrote.
+    pub fn printed(&self) -> &[String] {
+        &self.printed
+    }
+
+    /// Chooses whether `print` ends what it writes with a newline and what
+    /// it evaluates to.
+    pub fn with_printing(mut self, printing: Printing) -> Self {
+        self.printing = printing;
+        self
+    }
+
+    /// Limits how long the VM may spend running programs, counting from
+    /// now. The clock is only read every [`DEADLINE_INTERVAL`] instructions,
+    /// so a run may overshoot slightly before stopping with an error.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.deadline = Some(Instant::now() + timeout);
+        self
+    }
+
+    /// Makes compiling a program fail with every error in it rather than
+    /// with the first, as [`crate::diagnostics::CompileErrors`], which are
+    /// also kept in the [`Vm::compile_report`]. A program with syntax errors
+    /// is compiled as far as the parser recovered from them, so they are
+    /// reported along with the rest.
+    pub fn with_error_recovery(mut self, enabled: bool) -> Self {
+        self.error_recovery = enabled;
+        self
+    }
+
+    /// Compiles programs with only the given optimizer passes.
+    pub fn with_passes(mut self, passes: Passes) -> Self {
+        self.passes = passes;
+        self
+    }
+
+    /// Writes down every line runs read and every result natives return, to
+    /// be taken with [`Vm::take_recording`] and given to another VM with
+    /// [`Vm::with_replay`]. Results supplied by [`Vm::resume_with`] are not
+    /// written down.
+    pub fn with_recording(mut self) -> Self {
+        self.journal = Journal::Recording(Vec::new());
+        self
+    }
+
+    /// Makes runs read lines and get native results from `log` instead of
+    /// reading input and calling natives, so they do exactly what the
+    /// recorded run did. A run that reads or calls something else than the
+    /// log says stops with an error.
+    pub fn with_replay(mut self, log: ReplayLog) -> Self {
+        self.journal = Journal::Replaying(log.events.into());
+        self
+    }
+
+    /// What the VM recorded since [`Vm::with_recording`], leaving it
+    /// recording afresh. `None` when it is not recording.
+    pub fn take_recording(&mut self) -> Option<ReplayLog> {
+        match &mut self.journal {
+            Journal::Recording(events) => Some(ReplayLog {
+                events: std::mem::take(events),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Sends what programs `print` to `output` instead of standard output.
+    pub fn with_output(mut self, output: impl Write + 'static) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// Makes `read_line` and `read_int` read from `input` instead of standard
+    /// input.
+    pub fn with_reader(mut self, input: impl BufRead + 'static) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    /// Chooses what arithmetic does when the result does not fit in 32 bits.
+    /// It wraps around unless told otherwise. Promoting results skips the
+    /// ranges pass when compiling, since it assumes integers fit.
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Limits how many calls may be running at once, the script included.
+    /// Making a call beyond that fails the run.
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    /// Limits how deeply tuples may nest. Building a deeper one fails, since
+    /// printing, comparing or returning it would take as deep a recursion.
+    pub fn with_max_tuple_depth(mut self, depth: u32) -> Self {
+        self.max_tuple_depth = depth;
+        self
+    }
+
+    /// Stops runs with a [`MemoryLimitExceeded`] error once the values they
+    /// keep alive and the memoization table take roughly more than `bytes`.
+    /// Going over first collects garbage and empties the memoization table,
+    /// so only values the program can still reach count against the limit.
+    pub fn with_memory_limit(mut self, bytes: usize) -> Self {
+        self.memory_limit = Some(bytes);
+        self
+    }
+
+    /// Sets how many live values the heap may hold before the garbage
+    /// collector runs. The limit grows with the values that survive a collection.
+    pub fn with_gc_threshold(mut self, threshold: usize) -> Self {
+        self.heap.set_threshold(threshold);
+        self
+    }
+
+    /// Chooses how the heap reclaims values. With [`Allocation::Arena`] the
+    /// collector never runs, and everything a run allocates is freed at once
+    /// when [`Vm::interpret`] and its variants return, so the program's
+    /// globals do not outlive the run. Runs that suspend or pause keep
+    /// their values until [`Vm::reset`].
+    pub fn with_allocation(mut self, allocation: Allocation) -> Self {
+        self.heap.set_allocation(allocation);
+        self
+    }
+
+    /// The program's global definitions, in the order they were first made.
+    pub fn globals(&self) -> Vec<(String, FinalValue)> {
+        self.globals
+            .iter()
+            .map(|(name, value)| {
+                (
+                    self.heap.string(*name).to_string(),
+                    self.heap.finalize(*value),
+                )
+            })
+            .collect()
+    }
+
+    /// Calls `name`, a function defined by a program run on the VM or a
+    /// native, with `arguments`. The program's globals stay as they were left.
+    pub fn call_function(&mut self, name: &str, arguments: &[FinalValue]) -> Result<FinalValue> {
+        let symbol = self.heap.intern(name);
+        let function = self
+            .globals
+            .iter()
+            .chain(&self.natives)
+            .find(|global| global.0 == symbol)
+            .map(|global| global.1)
+            .ok_or_else(|| anyhow!("Unknown function {name}."))?;
+
+        if arguments.len() > u16::MAX as usize {
+            bail!(
+                "Cannot call a function with more than {} arguments.",
+                u16::MAX
+            );
+        }
+
+        self.enter_script(Chunk::new(vec![
+            Instruction::Call(arguments.len() as u16),
+            Instruction::Return(0),
+        ]));
+
+        self.stack.push(function);
+        for argument in arguments {
+            let argument = self.heap.allocate_final(argument)?;
+            self.stack.push(argument);
+        }
+
+        expect_finished(self.run()?)
+    }
+
+    /// Defines a global for the programs run on the VM, as a top-level `let`
+    /// would.
+    pub fn define_global(&mut self, name: &str, value: &FinalValue) -> Result<()> {
+        let symbol = self.heap.intern(name);
+        let value = self.heap.allocate_final(value)?;
+        self.set_global(symbol, value);
+        Ok(())
+    }
+
+    /// Passes arguments to the programs run on the VM: `argc` holds how many
+    /// there are and `arg0`, `arg1` and so on hold each of them, as an integer
+    /// when it parses as one and as a string otherwise. [`Vm::reset`] forgets
+    /// them along with the other globals.
+    pub fn with_arguments(mut self, arguments: &[String]) -> Self {
+        let count = FinalValue::Integer(arguments.len() as i32);
+        self.define_global("argc", &count)
+            .expect("Integers can always be stored.");
+
+        for (position, argument) in arguments.iter().enumerate() {
+            let value = match argument.parse() {
+                Ok(integer) => FinalValue::Integer(integer),
+                Err(_) => FinalValue::String(argument.clone()),
+            };
+
+            self.define_global(&format!("arg{position}"), &value)
+                .expect("Integers and strings can always be stored.");
+        }
+
+        self
+    }
+
+    /// Keeps at most `capacity` results in the memoization table, evicting
+    /// the ones not used for a while. A capacity of 0 turns memoization off,
+    /// which rules it out when a program behaves unexpectedly.
+    pub fn with_memo_capacity(mut self, capacity: usize) -> Self {
+        self.memoization = MemoTable::bounded(capacity);
+        self
+    }
+
+    /// Re-executes `percentage` percent of the calls the memoization table
+    /// would answer, comparing the result with the table's. Disagreements
+    /// are recorded in [`Vm::memo_mismatches`] and the computed result is
+    /// used.
+    pub fn with_memo_verification(mut self, percentage: u8) -> Self {
+        self.memo_verification = Some(MemoVerification {
+            percentage: percentage.min(100) as u32,
+            credit: 0,
+            mismatches: Vec::new(),
+        });
+        self
+    }
+
+    /// The memoization entries found unsound since
+    /// [`Vm::with_memo_verification`].
+    pub fn memo_mismatches(&self) -> &[MemoMismatch] {
+        match &self.memo_verification {
+            Some(verification) => &verification.mismatches,
+            None => &[],
+        }
+    }
+
+    /// Counts how many times each opcode is executed, which slows dispatch
+    /// down a little. See [`Vm::opcode_histogram`].
+    pub fn with_opcode_histogram(mut self) -> Self {
+        self.opcode_counts = Some(Box::new([0; Instruction::OPCODES]));
+        self
+    }
+
+    /// Samples the call stack every `interval` instructions, for
+    /// [`Vm::write_profile`].
+    pub fn with_profiler(mut self, interval: u64) -> Self {
+        self.profiler = Some(Profiler::new(interval));
+        self
+    }
+
+    /// Writes the call stacks sampled since [`Vm::with_profiler`] as
+    /// collapsed stacks, ready to be turned into a flamegraph. Functions are
+    /// named after their variable and index in the function table.
+    pub fn write_profile(&self, output: &mut impl Write) -> Result<()> {
+        let profiler = self
+            .profiler
+            .as_ref()
+            .ok_or_else(|| anyhow!("The profiler is not enabled."))?;
+
+        profiler.write_collapsed(&self.functions, output)
+    }
+
+    /// The opcodes executed so far and how often, most frequent first, or
+    /// `None` unless enabled with [`Vm::with_opcode_histogram`].
+    pub fn opcode_histogram(&self) -> Option<Vec<(&'static str, u64)>> {
+        let counts = self.opcode_counts.as_ref()?;
+
+        let mut histogram: Vec<(&'static str, u64)> = OPCODE_NAMES
+            .iter()
+            .zip(counts.iter())
+            .filter(|(_, count)| **count > 0)
+            .map(|(name, count)| (*name, *count))
+            .collect();
+        histogram.sort_by(|(_, lhs), (_, rhs)| rhs.cmp(lhs));
+
+        Some(histogram)
+    }
+
+    /// How many instructions the VM has executed since it was created.
+    pub fn instructions_executed(&self) -> u64 {
+        self.stats.instructions
+    }
+
+    /// The warnings compiling the last program found, and its errors when
+    /// recovering from them.
+    pub fn compile_report(&self) -> &CompileReport {
+        &self.compile_report
+    }
+
+    /// What the VM has done since it was created.
+    pub fn stats(&self) -> VmStats {
+        VmStats {
+            opcodes: self.opcode_histogram().unwrap_or_default(),
+            allocations: self.heap.stats().allocations,
+            memoization_evictions: self.memoization.evictions,
+            ..self.stats.clone()
+        }
+    }
+
+    pub fn gc_stats(&self) -> GcStats {
+        self.heap.stats()
+    }
+
+    /// Frees every value that is no longer reachable from the stack, the
+    /// call frames, the globals, the natives or the memoization table.
+    pub fn collect_garbage(&mut self) {
+        let roots = self
+            .stack
+            .iter()
+            .copied()
+            .chain(
+                self.call_frames
+                    .iter()
+                    .filter_map(|frame| frame.closure.map(Tagged::Object)),
+            )
+            .chain(self.globals.iter().map(|(_, value)| *value))
+            .chain(self.natives.iter().map(|(_, value)| *value))
+            .chain(self.memoization.values());
+
+        self.heap.collect(roots);
+        self.inline_caches.clear();
+
+        tracing::trace!(
+            live = self.heap.stats().live,
+            bytes = self.heap.stats().bytes,
+            "Collected garbage."
+        );
+    }
+
+    /// Roughly how many bytes the values in the heap and the memoization
+    /// table take.
+    pub fn memory_used(&self) -> usize {
+        self.heap.stats().bytes + self.memoization.bytes()
+    }
+
+    /// Fails once the memory in use stays above `limit` after freeing
+    /// everything that can be.
+    fn check_memory(&mut self, limit: usize) -> Result<()> {
+        if self.memory_used() <= limit {
+            return Ok(());
+        }
+
+        self.memoization.clear();
+        if self.heap.allocation() == Allocation::Gc {
+            self.collect_garbage();
+        }
+
+        let used = self.memory_used();
+        if used > limit {
+            return Err(MemoryLimitExceeded { used, limit }.into());
+        }
+
+        Ok(())
+    }
+
+    /// Runs already compiled bytecode as the top-level script. The bytecode is
+    /// trusted to be well formed; see [`Vm::verify`].
+    pub fn run_bytecode(&mut self, bytecode: &[Instruction]) -> Result<FinalValue> {
+        self.enter_script(Chunk::new(bytecode.to_vec()));
+
+        match self.run()? {
+            Execution::Finished(value) => Ok(value),
+            Execution::Suspended(token) => bail!(
+                "Execution suspended waiting for native function {}.",
+                token.native
+            ),
+            Execution::Paused => bail!("Execution paused at a breakpoint."),
+        }
+    }
+
+    /// Checks `bytecode` as a top-level script, along with the bytecode of every
+    /// function known to the VM, against the constant, identifier and function tables.
+    pub fn verify(&self, bytecode: &[Instruction]) -> Result<()> {
+        let captured: Vec<usize> = self
+            .functions
+            .iter()
+            .map(|function| function.captured.len())
+            .collect();
+        let tables = Tables {
+            constants: self.constants.len(),
+            functions: &captured,
+            identifiers: self.identifiers.len(),
+        };
+
+        for function in &self.functions {
+            verifier::verify(&function.chunk.bytecode, function.arity as usize, tables)?;
+        }
+
+        verifier::verify(bytecode, 0, tables)
+    }
+
+    pub fn create_constant(&mut self, value: Value) -> Result<u32> {
+        let tagged = self.constant_value(&value)?;
+        match self.constant_indices.get(&tagged) {
+            Some(index) => Ok(*index),
+            None => self.push_constant(value),
+        }
+    }
+
+    pub fn create_identifier(&mut self, identifier: String) -> Result<u32> {
+        let symbol = self.heap.intern(&identifier);
+        if let Some(index) = self.identifier_indices.get(&symbol) {
+            return Ok(*index);
+        }
+
+        if self.identifiers.len() >= u32::MAX as usize {
+            bail!("Cannot create more than {} identifiers.", u32::MAX);
+        }
+
+        Ok(self.push_identifier(symbol))
+    }
+
+    fn push_identifier(&mut self, symbol: Symbol) -> u32 {
+        let index = self.identifiers.len() as u32;
+        self.identifiers.push(symbol);
+        self.identifier_indices.entry(symbol).or_insert(index);
+        index
+    }
+
+    pub(crate) fn constant(&self, index: u32) -> &Value {
+        &self.constants[index as usize]
+    }
+
+    pub(crate) fn intern(&mut self, string: &str) -> Symbol {
+        self.heap.intern(string)
+    }
+
+    /// Whether code can read `name` as a global without the program binding
+    /// it: a global defined beforehand, a native or a builtin.
+    pub(crate) fn defines(&self, name: &str) -> bool {
+        let named = |symbol: &Symbol| **self.heap.string(*symbol) == *name;
+
+        self.globals.iter().any(|(symbol, _)| named(symbol))
+            || self.natives.iter().any(|(symbol, _)| named(symbol))
+            || self.builtins.iter().any(|builtin| *builtin.name == *name)
+    }
+
+    /// Stores the builtin named `identifier` in the heap the first time a
+    /// program uses it, making it a native from then on.
+    fn load_builtin(&mut self, identifier: Symbol) -> Option<Tagged> {
+        let builtin = self
+            .builtins
+            .iter()
+            .find(|builtin| *builtin.name == **self.heap.string(identifier))?
+            .clone();
+        let native = self.heap.store(Value::Native(builtin));
+        self.natives.push((identifier, native));
+        Some(native)
+    }
+
+    fn push_constant(&mut self, value: Value) -> Result<u32> {
+        if self.constants.len() >= u32::MAX as usize {
+            bail!("Cannot create more than {} constants.", u32::MAX);
+        }
+
+        let tagged = self.constant_value(&value)?;
+        let index = self.constants.len() as u32;
+        self.constants.push(value);
+        self.constant_values.push(tagged);
+        self.constant_indices.entry(tagged).or_insert(index);
+        Ok(index)
+    }
+
+    /// The value `constant` is pushed on the stack as, which also tells
+    /// constants apart: interned strings are equal only when their symbols
+    /// are.
+    fn constant_value(&mut self, constant: &Value) -> Result<Tagged> {
+        Ok(match constant {
+            Value::Bool(b) => Tagged::Bool(*b),
+            Value::Integer(i) => Tagged::Integer(*i),
+            // Stored like the same string made at runtime, so both make the
+            // same memoization key.
+            Value::String(s) => match ShortString::new(s) {
+                Some(short) => Tagged::Short(short),
+                None => Tagged::Symbol(self.heap.intern(s)),
+            },
+            _ => bail!("Only booleans, integers and strings can be constants."),
+        })
+    }
+
+    /// Parses, compiles and optimizes a program and the files it imports,
+    /// returning its top-level code. Its functions are added to the VM's
+    /// function table.
+    fn compile_source(&mut self, filename: &str, contents: &str) -> Result<Chunk> {
+        let recovering = self.error_recovery;
+        let file =
+            tracing::info_span!("parse", filename).in_scope(
+                || match imports::parse_with_imports(filename, contents) {
+                    Err(error) if recovering && error.is::<ParseError>() => {
+                        ast::parse_recovering(filename, contents).ok_or(error)
+                    }
+                    parsed => parsed,
+                },
+            )?;
+        self.compile_expression(file.expression)
+    }
+
+    fn compile_expression(&mut self, expression: Term) -> Result<Chunk> {
+        let first_function = self.functions.len();
+
+        let mut script = tracing::info_span!("compile").in_scope(|| self.compile(expression))?;
+        let offset = script.offsets.last().copied().unwrap_or(0);
+        script.push(Instruction::Return(0), offset);
+
+        let _optimizing = tracing::info_span!("optimize").entered();
+        let script = tracing::debug_span!("optimize_function", function = "<script>")
+            .in_scope(|| self.optimize(script, 0))?;
+
+        for index in first_function..self.functions.len() {
+            let function = Rc::get_mut(&mut self.functions[index])
+                .expect("Freshly compiled functions are not shared yet.");
+            let chunk = std::mem::take(&mut function.chunk);
+            let arity = function.arity as usize;
+            let name = self.function_name(index as u32);
+            let optimized = tracing::debug_span!("optimize_function", function = name)
+                .in_scope(|| self.optimize(chunk, arity))?;
+
+            Rc::get_mut(&mut self.functions[index])
+                .expect("Freshly compiled functions are not shared yet.")
+                .chunk = optimized;
+        }
+        self.measure_stacks(first_function)?;
+
+        Ok(script)
+    }
+
+    /// Runs the optimizer passes over the code of a frame starting with
+    /// `frame_size` values, keeping the source offsets of its instructions
+    /// in step.
+    fn optimize(&mut self, mut chunk: Chunk, frame_size: usize) -> Result<Chunk> {
+        let Chunk { bytecode, offsets } = &mut chunk;
+
+        if self.passes.contains(Pass::Peephole) {
+            *bytecode = optimizer::peephole_with_offsets(bytecode, offsets, self)?;
+        }
+        // Ranges assume integers fit in 32 bits, which is not so once they
+        // may be promoted.
+        if self.passes.contains(Pass::Ranges) && self.overflow != Overflow::Promoting {
+            *bytecode =
+                optimizer::propagate_ranges_with_offsets(bytecode, offsets, frame_size, self)?;
+
+            if self.passes.contains(Pass::Peephole) {
+                *bytecode = optimizer::peephole_with_offsets(bytecode, offsets, self)?;
+            }
+        }
+        if self.passes.contains(Pass::DeadCode) {
+            *bytecode = optimizer::eliminate_dead_code_with_offsets(bytecode, offsets);
+        }
+        if self.passes.contains(Pass::Fusion) {
+            *bytecode = optimizer::fuse_with_offsets(bytecode, offsets);
+        }
+
+        Ok(chunk)
+    }
+
+    /// Compiles top-level code, along with the source offset of each
+    /// instruction.
+    fn compile(&mut self, term: Term) -> Result<Chunk> {
+        let mut compiler = Compiler::new()
+            .with_tail_calls(self.passes.contains(Pass::TailCalls))
+            .with_inlining(self.passes.contains(Pass::Inline))
+            .with_error_recovery(self.error_recovery);
+        let script = compiler.compile(term, self, CallPosition::Unknown);
+        self.compile_report = compiler.take_report();
+        script
+    }
+
+    /// Discards whatever a previous run left behind and sets up `script` as
+    /// the top-level frame.
+    fn enter_script(&mut self, script: Chunk) {
+        if self.heap.allocation() == Allocation::Arena {
+            self.arena_start = Some(self.heap.len());
+        }
+        self.call_frames.clear();
+        self.stack.clear();
+        self.suspension = None;
+        self.paused = false;
+        // Every script runs as the same function index.
+        self.inline_caches.clear();
+
+        self.call_frames.push(CallFrame {
+            function: Rc::new(Function::script(script)),
+            closure: None,
+            environment: Rc::from([]),
+            instruction_pointer: 0,
+            frame_index: 0,
+            elided: ElidedCalls::default(),
+            memo_check: None,
+            memo_key: None,
+            pure: true,
+        });
+    }
+
+    /// Redefinitions, including those made by a later program run on the
+    /// same VM, replace the old value.
+    fn set_global(&mut self, name: Symbol, value: Tagged) {
+        match self.globals.iter_mut().find(|g| g.0 == name) {
+            Some(global) => global.1 = value,
+            None => self.globals.push((name, value)),
+        }
+    }
+
+    fn push(&mut self, value: Value) {
+        let value = self.heap.store(value);
+        self.stack.push(value);
+    }
+
+    /// Reads the next line of input without its line terminator. Once the
+    /// input is exhausted, every line is empty.
+    fn read_line(&mut self) -> Result<String> {
+        self.mark_impure();
+
+        match self.effects {
+            Effects::Allowed => {}
+            Effects::Captured | Effects::Ignored => return Ok(String::new()),
+            Effects::Denied => bail!("Reading input is not allowed."),
+        }
+
+        if let Some(line) = self.journal.replayed_line()? {
+            return Ok(line);
+        }
+
+        let mut line = String::new();
+        match &mut self.input {
+            Some(input) => input.read_line(&mut line),
+            None => io::stdin().lock().read_line(&mut line),
+        }
+        .context("Could not read input.")?;
+
+        let length = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(length);
+        self.journal.record(Event::Line(line.clone()));
+        Ok(line)
+    }
+
+    /// Resolves the value a call in `caller` at the current address found
+    /// under its `arity` arguments, checking closures take that many. The
+    /// closure the call site resolved last is taken from its inline cache.
+    fn callee(&mut self, caller: u32, closure: Gc, arity: u16) -> Result<Callee> {
+        let address = self.current_address;
+        if let Some((function, environment)) = self.inline_caches.get(caller, address, closure) {
+            self.stats.inline_cache_hits += 1;
+            return Ok(Callee::Function(function, environment));
+        }
+
+        match self.heap.get(closure) {
+            Value::Native(native) => Ok(Callee::Native(native.clone())),
+            Value::Closure(function, environment) => {
+                if function.arity != arity {
+                    bail!("Attempted to call function with wrong number of arguments.");
+                }
 
-use crate::{
-    bytecode::Instruction,
-    call_frame::CallFrame,
-    compiler::{CallPosition, Compiler},
-    function::Function,
-    value::{FinalValue, Value},
-};
+                let (function, environment) = (function.clone(), environment.clone());
+                self.stats.inline_cache_misses += 1;
+                self.inline_caches.insert(
+                    caller,
+                    address,
+                    closure,
+                    function.clone(),
+                    environment.clone(),
+                );
+                Ok(Callee::Function(function, environment))
+            }
+            _ => bail!("Attempted to call value that is not a function!"),
+        }
+    }
 
-pub struct Vm<'a> {
-    call_frames: Vec<CallFrame<'a>>,
-    constants: Vec<Value<'a>>,
-    current_execution: Option<(u16, i32)>,
-    pub functions: Vec<Function>,
-    globals: Vec<(&'a str, Rc<Value<'a>>)>,
-    identifiers: Vec<String>,
-    memoization: Vec<((u16, i32), Rc<Value<'a>>)>,
-    pure: bool,
-    stack: Vec<Rc<Value<'a>>>,
-}
+    /// Calls a native whose arguments are on top of the stack, replacing them
+    /// and the native itself with the result. Returns a token instead when
+    /// the result is not available yet.
+    fn call_native(&mut self, native: &Native, arity: u16) -> Result<Option<SuspensionToken>> {
+        if native.arity.is_some_and(|expected| expected != arity) {
+            bail!("Attempted to call function with wrong number of arguments.");
+        }
 
-macro_rules! pop_operands {
-    ($self: ident) => {{
-        let rhs = $self
-            .stack
-            .pop()
-            .ok_or_else(|| anyhow!("Expected operand, but stack was empty."))?;
+        if !native.pure {
+            self.mark_impure();
+        }
 
-        let lhs = $self
+        let arguments: Vec<FinalValue> = self
             .stack
-            .pop()
-            .ok_or_else(|| anyhow!("Expected operand, but stack was empty."))?;
+            .drain(self.stack.len() - arity as usize..)
+            .map(|argument| self.heap.finalize(argument))
+            .collect();
+        self.stack.pop();
+
+        let result = match self.journal.replayed_native(&native.name)? {
+            Some(Ok(value)) => NativeResult::Ready(value),
+            Some(Err(message)) => bail!("{message}"),
+            None => match (native.function)(&arguments) {
+                Ok(NativeResult::Ready(value)) => {
+                    self.journal.record(Event::Native {
+                        name: native.name.clone(),
+                        result: Ok(value.clone()),
+                    });
+                    NativeResult::Ready(value)
+                }
+                Ok(NativeResult::Pending) => NativeResult::Pending,
+                Err(error) => {
+                    self.journal.record(Event::Native {
+                        name: native.name.clone(),
+                        result: Err(format!("{error:#}")),
+                    });
+                    return Err(error);
+                }
+            },
+        };
 
-        let result: Result<(Rc<Value<'_>>, Rc<Value<'_>>)> = Ok((lhs, rhs));
-        result
-    }};
-}
+        match result {
+            NativeResult::Ready(value) => {
+                let value = self.heap.allocate_final(&value)?;
+                self.stack.push(value);
+                Ok(None)
+            }
+            NativeResult::Pending => {
+                self.next_suspension += 1;
+                Ok(Some(SuspensionToken {
+                    id: self.next_suspension,
+                    native: native.name.clone(),
+                    arguments,
+                }))
+            }
+        }
+    }
 
-impl<'a> Vm<'a> {
-    pub fn new() -> Self {
-        Self {
-            call_frames: Vec::new(),
-            constants: Vec::new(),
-            current_execution: None,
-            functions: Vec::new(),
-            globals: Vec::new(),
-            identifiers: Vec::new(),
-            memoization: Vec::new(),
-            pure: true,
-            stack: Vec::new(),
+    fn suspend(&mut self, instruction_pointer: usize, token: SuspensionToken) -> Result<Execution> {
+        self.current_frame()?.instruction_pointer = instruction_pointer;
+        self.suspension = Some(token.clone());
+
+        Ok(Execution::Suspended(token))
+    }
+
+    /// Records a side effect, or the making of a closure, which keeps the
+    /// results of the calls in progress from being memoized.
+    fn mark_impure(&mut self) {
+        if let Some(frame) = self.call_frames.last_mut() {
+            frame.pure = false;
         }
     }
 
-    pub fn interpret(&'a mut self, filename: &str, contents: &str) -> Result<FinalValue> {
-        let file = parse_or_report(filename, contents)?;
+    fn current_frame(&mut self) -> Result<&mut CallFrame> {
+        self.call_frames
+            .last_mut()
+            .ok_or_else(|| anyhow!("There is no active call frame."))
+    }
 
-        let mut bytecode = self.compile(file.expression)?;
-        bytecode.push(Instruction::Return(0));
-        let bytecode = Box::leak(Box::new(bytecode));
+    /// Where the top `count` values of the stack start.
+    fn stack_start(&self, count: usize) -> Result<usize> {
+        self.stack
+            .len()
+            .checked_sub(count)
+            .ok_or_else(|| anyhow!("Expected operand, but self.stack was empty."))
+    }
 
-        let result = self.run(bytecode)?;
-        Ok(result)
+    /// The local in `index` of the frame starting at `frame_index`.
+    fn local(&self, frame_index: usize, index: u16, identifier_index: u32) -> Result<Tagged> {
+        match self.stack.get(frame_index + index as usize) {
+            Some(value) => Ok(*value),
+            None => {
+                let identifier = self.identifiers[identifier_index as usize];
+                bail!("Variable {} not found.", self.heap.string(identifier));
+            }
+        }
     }
 
-    pub fn create_constant(&mut self, value: Value<'a>) -> Result<u16> {
-        if self.constants.len() >= u16::MAX as usize {
-            bail!("Cannot create more than {} constants.", u16::MAX);
+    /// Adds integers, or concatenates when either side is a string.
+    fn add(&mut self, lhs: Tagged, rhs: Tagged) -> Result<Tagged> {
+        let heap = &self.heap;
+        if heap.is_integer(lhs) && heap.is_integer(rhs) {
+            return self.arithmetic(Operator::Add, lhs, rhs);
         }
 
-        let position = self.constants.iter().position(|v| *v == value);
+        let text = match (heap.text(&lhs), heap.text(&rhs)) {
+            (Some(lhs), None) if heap.is_integer(rhs) => format!("{lhs}{}", heap.display(rhs)),
+            (None, Some(rhs)) if heap.is_integer(lhs) => format!("{}{rhs}", heap.display(lhs)),
+            (Some(lhs), Some(rhs)) => format!("{lhs}{rhs}"),
+            _ => {
+                bail!("Wrong types for add.");
+            }
+        };
+
+        Ok(self.heap.store_str(&text))
+    }
+
+    /// Applies `operator` to integers. Results that overflow are handled as
+    /// [`Vm::with_overflow`] says, and big integers only show up when it
+    /// promotes them.
+    fn arithmetic(&mut self, operator: Operator, lhs: Tagged, rhs: Tagged) -> Result<Tagged> {
+        let result = match (lhs, rhs) {
+            (Tagged::Integer(lhs), Tagged::Integer(rhs)) => {
+                self.overflow.apply(operator, lhs, rhs)?
+            }
+            _ => match (self.heap.big_integer(lhs), self.heap.big_integer(rhs)) {
+                (Some(lhs), Some(rhs)) => operator.apply_big(&lhs, &rhs)?,
+                _ => bail!("Operands must be both integers."),
+            },
+        };
 
-        Ok(position.unwrap_or_else(|| {
-            self.constants.push(value);
-            self.constants.len() - 1
-        }) as u16)
+        Ok(self.heap.store_integer(result))
     }
 
-    pub fn create_identifier(&mut self, identifier: String) -> Result<u16> {
-        if self.identifiers.len() >= u16::MAX as usize {
-            bail!("Cannot create more than {} identifiers.", u16::MAX);
+    /// Whether the memoization hit being served should be re-executed.
+    fn sample_memo_hit(&mut self) -> bool {
+        let Some(verification) = &mut self.memo_verification else {
+            return false;
+        };
+
+        verification.credit += verification.percentage;
+        if verification.credit < 100 {
+            return false;
         }
 
-        let position = self.identifiers.iter().position(|i| *i == identifier);
+        verification.credit -= 100;
+        true
+    }
+
+    fn check_memoized(&mut self, key: MemoKey, memoized: Tagged, result: Tagged) {
+        self.stats.memoization_checks += 1;
+
+        let memoized = self.heap.finalize(memoized);
+        let computed = self.heap.finalize(result);
+        if memoized == computed {
+            return;
+        }
 
-        Ok(position.unwrap_or_else(|| {
-            self.identifiers.push(identifier);
-            self.identifiers.len() - 1
-        }) as u16)
+        let mismatch = MemoMismatch {
+            function: self.function_name(key.function),
+            arguments: key
+                .arguments
+                .iter()
+                .map(|argument| self.heap.finalize(*argument))
+                .collect(),
+            memoized,
+            computed,
+        };
+        // Runs restored from a snapshot may no longer verify memoization.
+        if let Some(verification) = &mut self.memo_verification {
+            verification.mismatches.push(mismatch);
+        }
     }
 
-    fn compile(&mut self, term: Term) -> Result<Vec<Instruction>> {
-        let mut compiler = Compiler::new(None);
-        compiler.compile(term, self, CallPosition::Unknown)
+    fn pause(&mut self, instruction_pointer: usize) -> Result<Execution> {
+        self.current_frame()?.instruction_pointer = instruction_pointer;
+        self.paused = true;
+
+        Ok(Execution::Paused)
     }
 
-    fn run(&'a mut self, bytecode: &'a [Instruction]) -> Result<FinalValue> {
-        let initial_frame = CallFrame {
-            bytecode: &bytecode,
-            closure: Rc::new(Value::Bool(false)),
-            instruction_pointer: 0,
-            frame_index: 0,
-        };
+    fn run(&mut self) -> Result<Execution> {
+        let _running = tracing::info_span!("run").entered();
+        let instructions = self.stats.instructions;
+
+        let execution = self.execute();
+        tracing::debug!(
+            instructions = self.stats.instructions - instructions,
+            fuel_left = self.fuel,
+            finished = matches!(execution, Ok(Execution::Finished(_))),
+            "Stopped running."
+        );
+
+        execution.map_err(|error| {
+            RuntimeError {
+                error,
+                trace: self.stack_trace(),
+            }
+            .into()
+        })
+    }
+
+    fn function_name(&self, index: u32) -> String {
+        match self.functions.get(index as usize) {
+            Some(function) => function.name.as_deref().unwrap_or("<anonymous>").to_owned(),
+            None => "<script>".to_owned(),
+        }
+    }
+
+    /// Orders integers by value, whatever their size, and strings by their
+    /// UTF-8 bytes, which is the order of their code points. Nothing else
+    /// can be ordered.
+    fn compare(&self, lhs: Tagged, rhs: Tagged) -> Result<Ordering> {
+        if let (Tagged::Integer(lhs), Tagged::Integer(rhs)) = (lhs, rhs) {
+            return Ok(lhs.cmp(&rhs));
+        }
+
+        if let (Some(lhs), Some(rhs)) = (self.heap.text(&lhs), self.heap.text(&rhs)) {
+            return Ok(lhs.cmp(rhs));
+        }
+
+        match (self.heap.big_integer(lhs), self.heap.big_integer(rhs)) {
+            (Some(lhs), Some(rhs)) => Ok(lhs.cmp(&rhs)),
+            _ => bail!("Operands must be both integers or both strings."),
+        }
+    }
+
+    fn stack_trace(&self) -> StackTrace {
+        let name = |index: u32| self.function_name(index);
+
+        let frames = self
+            .call_frames
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(depth, frame)| {
+                let address = if depth == 0 {
+                    self.current_address
+                } else {
+                    frame.instruction_pointer.saturating_sub(1)
+                };
+
+                TraceFrame {
+                    function: name(frame.function.index),
+                    address,
+                    offset: frame.function.chunk.offset(address),
+                    elided_count: frame.elided.count(),
+                    elided: frame.elided.recent().map(name).collect(),
+                }
+            })
+            .collect();
 
-        self.call_frames.push(initial_frame);
+        StackTrace { frames }
+    }
 
+    fn execute(&mut self) -> Result<Execution> {
         loop {
-            let bytecode;
+            let function;
+            let environment;
             let mut instruction_pointer;
             let frame_index;
-            let mut environment = &Vec::new();
 
             if let Some(call_frame) = self.call_frames.last() {
+                function = call_frame.function.clone();
+                environment = call_frame.environment.clone();
                 frame_index = call_frame.frame_index;
                 instruction_pointer = call_frame.instruction_pointer;
-                bytecode = &call_frame.bytecode[instruction_pointer..];
-                if let Value::Closure(_, new_environment) = &*call_frame.closure {
-                    environment = new_environment;
-                }
             } else {
                 break;
             }
 
-            self.pure = true;
+            self.stats.peak_frame_depth = self.stats.peak_frame_depth.max(self.call_frames.len());
+
+            loop {
+                if let Some(breakpoints) = &mut self.breakpoints {
+                    if breakpoints.should_pause(
+                        &function,
+                        instruction_pointer,
+                        self.call_frames.len(),
+                    ) {
+                        return self.pause(instruction_pointer);
+                    }
+                }
 
-            let mut skip = 0;
-            for instruction in bytecode {
+                let instruction = function
+                    .chunk
+                    .bytecode
+                    .get(instruction_pointer)
+                    .ok_or_else(|| anyhow!("Execution fell off the end of the bytecode."))?;
+                self.current_address = instruction_pointer;
                 instruction_pointer += 1;
+                self.stats.instructions += 1;
+                self.stats.peak_stack_depth = self.stats.peak_stack_depth.max(self.stack.len());
+
+                if let Some(fuel) = &mut self.fuel {
+                    if *fuel == 0 {
+                        bail!("Out of fuel.");
+                    }
+                    *fuel -= 1;
+                }
+
+                #[cfg(not(target_arch = "wasm32"))]
+                if let Some(deadline) = self.deadline {
+                    if self.stats.instructions.is_multiple_of(DEADLINE_INTERVAL)
+                        && Instant::now() >= deadline
+                    {
+                        bail!("Timed out.");
+                    }
+                }
+
+                if let Some(counts) = &mut self.opcode_counts {
+                    counts[instruction.opcode() as usize] += 1;
+                }
+
+                if let Some(profiler) = &mut self.profiler {
+                    if profiler.tick() {
+                        profiler.sample(self.call_frames.iter().map(|frame| frame.function.index));
+                    }
+                }
 
-                if skip > 0 {
-                    skip -= 1;
-                    continue;
+                // Between instructions every live value is reachable from the
+                // VM's roots, so this is the only place collections happen.
+                if self.heap.should_collect() {
+                    self.collect_garbage();
+                }
+
+                if let Some(limit) = self.memory_limit {
+                    self.check_memory(limit)?;
                 }
 
                 match *instruction {
                     Instruction::Constant(index) => {
-                        let value = self.constants[index as usize].clone();
-                        self.stack.push(Rc::new(value));
+                        self.stack.push(self.constant_values[index as usize]);
                     }
                     Instruction::True => {
-                        let value = Value::Bool(true);
-                        self.stack.push(Rc::new(value));
+                        self.stack.push(Tagged::Bool(true));
                     }
                     Instruction::False => {
-                        let value = Value::Bool(false);
-                        self.stack.push(Rc::new(value));
+                        self.stack.push(Tagged::Bool(false));
                     }
                     Instruction::Add => {
                         let (lhs, rhs) = pop_operands!(self)?;
-
-                        match (lhs.as_ref(), rhs.as_ref()) {
-                            (Value::Integer(lhs), Value::Integer(rhs)) => {
-                                self.stack.push(Rc::new(Value::Integer(lhs + rhs)));
-                            }
-                            (Value::String(lhs), Value::Integer(rhs)) => {
-                                self.stack
-                                    .push(Rc::new(Value::String(format!("{lhs}{rhs}"))));
-                            }
-                            (Value::Integer(lhs), Value::String(rhs)) => {
-                                self.stack
-                                    .push(Rc::new(Value::String(format!("{lhs}{rhs}"))));
-                            }
-                            (Value::String(lhs), Value::String(rhs)) => {
-                                self.stack
-                                    .push(Rc::new(Value::String(format!("{lhs}{rhs}"))));
-                            }
-                            _ => {
-                                bail!("Wrong types for add.");
-                            }
-                        }
+                        let value = self.add(lhs, rhs)?;
+                        self.stack.push(value);
+                    }
+                    Instruction::LocalGetConstantAdd(index, identifier_index, constant) => {
+                        let lhs = self.local(frame_index, index, identifier_index)?;
+                        let value = self.add(lhs, self.constant_values[constant as usize])?;
+                        self.stack.push(value);
+                    }
+                    Instruction::LocalGetConstantSub(index, identifier_index, constant) => {
+                        let lhs = self.local(frame_index, index, identifier_index)?;
+                        let rhs = self.constant_values[constant as usize];
+                        let value = self.arithmetic(Operator::Sub, lhs, rhs)?;
+                        self.stack.push(value);
                     }
                     Instruction::Sub => {
                         let (lhs, rhs) = pop_operands!(self)?;
-
-                        if let (Value::Integer(lhs), Value::Integer(rhs)) =
-                            (lhs.as_ref(), rhs.as_ref())
-                        {
-                            self.stack.push(Rc::new(Value::Integer(lhs - rhs)));
-                        } else {
-                            bail!("Operands must be both integers.");
-                        }
+                        let value = self.arithmetic(Operator::Sub, lhs, rhs)?;
+                        self.stack.push(value);
                     }
                     Instruction::Mul => {
                         let (lhs, rhs) = pop_operands!(self)?;
-
-                        if let (Value::Integer(lhs), Value::Integer(rhs)) =
-                            (lhs.as_ref(), rhs.as_ref())
-                        {
-                            self.stack.push(Rc::new(Value::Integer(lhs * rhs)));
-                        } else {
-                            bail!("Operands must be both integers.");
+                        let value = self.arithmetic(Operator::Mul, lhs, rhs)?;
+                        self.stack.push(value);
+                    }
+                    Instruction::Neg => {
+                        let value = self
+                            .stack
+                            .pop()
+                            .ok_or_else(|| anyhow!("Expected operand, but stack was empty."))?;
+
+                        if !self.heap.is_integer(value) {
+                            bail!("Operand must be an integer.");
                         }
+                        let value = self.arithmetic(Operator::Sub, Tagged::Integer(0), value)?;
+                        self.stack.push(value);
+                    }
+                    Instruction::Not => {
+                        let value = self
+                            .stack
+                            .pop()
+                            .ok_or_else(|| anyhow!("Expected operand, but stack was empty."))?;
+
+                        let Tagged::Bool(value) = value else {
+                            bail!("Operand must be a boolean.");
+                        };
+                        self.stack.push(Tagged::Bool(!value));
                     }
                     Instruction::Div => {
                         let (lhs, rhs) = pop_operands!(self)?;
-
-                        if let (Value::Integer(lhs), Value::Integer(rhs)) =
-                            (lhs.as_ref(), rhs.as_ref())
-                        {
-                            let result = lhs
-                                .checked_div(*rhs)
-                                .ok_or_else(|| anyhow!("Attempted to divide by zero"))?;
-
-                            self.stack.push(Rc::new(Value::Integer(result)));
-                        } else {
-                            bail!("Operands must be both integers.");
-                        }
+                        let value = self.arithmetic(Operator::Div, lhs, rhs)?;
+                        self.stack.push(value);
                     }
                     Instruction::Rem => {
                         let (lhs, rhs) = pop_operands!(self)?;
+                        let value = self.arithmetic(Operator::Rem, lhs, rhs)?;
+                        self.stack.push(value);
+                    }
+                    Instruction::BitAnd
+                    | Instruction::BitOr
+                    | Instruction::BitXor
+                    | Instruction::Shl
+                    | Instruction::Shr => {
+                        let (lhs, rhs) = pop_operands!(self)?;
 
-                        if let (Value::Integer(lhs), Value::Integer(rhs)) =
-                            (lhs.as_ref(), rhs.as_ref())
-                        {
-                            let result = lhs
-                                .checked_rem(*rhs)
-                                .ok_or_else(|| anyhow!("Attempted to take remainder by zero"))?;
+                        if let (Tagged::Integer(lhs), Tagged::Integer(rhs)) = (lhs, rhs) {
+                            let result = match *instruction {
+                                Instruction::BitAnd => lhs & rhs,
+                                Instruction::BitOr => lhs | rhs,
+                                Instruction::BitXor => lhs ^ rhs,
+                                Instruction::Shl => lhs.wrapping_shl(rhs as u32),
+                                _ => lhs.wrapping_shr(rhs as u32),
+                            };
 
-                            self.stack.push(Rc::new(Value::Integer(result)));
+                            self.stack.push(Tagged::Integer(result));
                         } else {
                             bail!("Operands must be both integers.");
                         }
                     }
                     Instruction::Eq => {
                         let (lhs, rhs) = pop_operands!(self)?;
-                        self.stack.push(Rc::new(Value::Bool(lhs == rhs)));
+                        let value = self.heap.equals(lhs, rhs);
+                        self.stack.push(Tagged::Bool(value));
                     }
                     Instruction::Neq => {
                         let (lhs, rhs) = pop_operands!(self)?;
-                        self.stack.push(Rc::new(Value::Bool(lhs != rhs)));
-                    }
-                    Instruction::Gt => {
-                        let (lhs, rhs) = pop_operands!(self)?;
-
-                        if let (Value::Integer(lhs), Value::Integer(rhs)) =
-                            (lhs.as_ref(), rhs.as_ref())
-                        {
-                            self.stack.push(Rc::new(Value::Bool(lhs > rhs)));
-                        } else {
-                            bail!("Operands must be both integers.");
-                        }
+                        let value = !self.heap.equals(lhs, rhs);
+                        self.stack.push(Tagged::Bool(value));
                     }
-                    Instruction::Lt => {
+                    Instruction::Gt | Instruction::Lt | Instruction::Gte | Instruction::Lte => {
                         let (lhs, rhs) = pop_operands!(self)?;
+                        let ordering = self.compare(lhs, rhs)?;
 
-                        if let (Value::Integer(lhs), Value::Integer(rhs)) =
-                            (lhs.as_ref(), rhs.as_ref())
-                        {
-                            self.stack.push(Rc::new(Value::Bool(lhs < rhs)));
-                        } else {
-                            bail!("Operands must be both integers.");
-                        }
+                        let value = match *instruction {
+                            Instruction::Gt => ordering.is_gt(),
+                            Instruction::Lt => ordering.is_lt(),
+                            Instruction::Gte => ordering.is_ge(),
+                            _ => ordering.is_le(),
+                        };
+                        self.stack.push(Tagged::Bool(value));
                     }
-                    Instruction::Gte => {
-                        let (lhs, rhs) = pop_operands!(self)?;
-
-                        if let (Value::Integer(lhs), Value::Integer(rhs)) =
-                            (lhs.as_ref(), rhs.as_ref())
-                        {
-                            self.stack.push(Rc::new(Value::Bool(lhs >= rhs)));
-                        } else {
-                            bail!("Operands must be both integers.");
-                        }
+                    Instruction::ConstantLt(constant) => {
+                        let lhs = self
+                            .stack
+                            .pop()
+                            .ok_or_else(|| anyhow!("Expected operand, but stack was empty."))?;
+                        let rhs = self.constant_values[constant as usize];
+
+                        let value = self.compare(lhs, rhs)?.is_lt();
+                        self.stack.push(Tagged::Bool(value));
                     }
-                    Instruction::Lte => {
-                        let (lhs, rhs) = pop_operands!(self)?;
-
-                        if let (Value::Integer(lhs), Value::Integer(rhs)) =
-                            (lhs.as_ref(), rhs.as_ref())
-                        {
-                            self.stack.push(Rc::new(Value::Bool(lhs <= rhs)));
-                        } else {
-                            bail!("Operands must be both integers.");
-                        }
+                    Instruction::ConstantEq(constant) => {
+                        let lhs = self
+                            .stack
+                            .pop()
+                            .ok_or_else(|| anyhow!("Expected operand, but stack was empty."))?;
+                        let value = self
+                            .heap
+                            .equals(lhs, self.constant_values[constant as usize]);
+                        self.stack.push(Tagged::Bool(value));
                     }
                     // TODO: handle short-circuiting
                     Instruction::And => {
                         let (lhs, rhs) = pop_operands!(self)?;
 
-                        if let (Value::Bool(lhs), Value::Bool(rhs)) = (lhs.as_ref(), rhs.as_ref()) {
-                            self.stack.push(Rc::new(Value::Bool(*lhs && *rhs)));
+                        if let (Tagged::Bool(lhs), Tagged::Bool(rhs)) = (lhs, rhs) {
+                            self.stack.push(Tagged::Bool(lhs && rhs));
                         } else {
                             bail!("Operands must be both integers.");
                         }
@@ -288,24 +2099,35 @@ impl<'a> Vm<'a> {
                     Instruction::Or => {
                         let (lhs, rhs) = pop_operands!(self)?;
 
-                        if let (Value::Bool(lhs), Value::Bool(rhs)) = (lhs.as_ref(), rhs.as_ref()) {
-                            self.stack.push(Rc::new(Value::Bool(*lhs || *rhs)));
+                        if let (Tagged::Bool(lhs), Tagged::Bool(rhs)) = (lhs, rhs) {
+                            self.stack.push(Tagged::Bool(lhs || rhs));
                         } else {
                             bail!("Operands must be both integers.");
                         }
                     }
                     Instruction::Tuple => {
                         let (first, second) = pop_operands!(self)?;
-                        let value = Value::Tuple(Box::new(first), Box::new(second));
-                        self.stack.push(Rc::new(value));
+
+                        let depth = 1 + self
+                            .heap
+                            .tuple_depth(first)
+                            .max(self.heap.tuple_depth(second));
+                        if depth > self.max_tuple_depth {
+                            bail!(
+                                "Tuple nesting depth {depth} exceeds the limit of {}.",
+                                self.max_tuple_depth
+                            );
+                        }
+
+                        self.push(Value::Tuple(first, second));
                     }
                     Instruction::First => {
                         let value = self.stack.pop().ok_or_else(|| {
                             anyhow!("Expected operand, but self.stack was empty.")
                         })?;
 
-                        if let Value::Tuple(first, _) = value.as_ref() {
-                            self.stack.push(*first.clone());
+                        if let Value::Tuple(first, _) = &*self.heap.resolve(value) {
+                            self.stack.push(*first);
                         } else {
                             bail!("Tried to compute `first` of a non tuple type.");
                         }
@@ -315,51 +2137,104 @@ impl<'a> Vm<'a> {
                             anyhow!("Expected operand, but self.stack was empty.")
                         })?;
 
-                        if let Value::Tuple(_, second) = value.as_ref() {
-                            self.stack.push(*second.clone());
+                        if let Value::Tuple(_, second) = &*self.heap.resolve(value) {
+                            self.stack.push(*second);
                         } else {
                             bail!("Tried to compute `second` of a non tuple type.");
                         }
                     }
                     Instruction::Print => {
-                        self.pure = false;
-                        let value = self.stack.last().ok_or_else(|| {
+                        self.mark_impure();
+                        let value = self.stack.pop().ok_or_else(|| {
                             anyhow!("Error printing. No value found in the self.stack to be set.")
                         })?;
-                        println!("{value}");
+
+                        let line = self.heap.display(value);
+                        let end = if self.printing.newline { "\n" } else { "" };
+                        match (self.effects, &mut self.output) {
+                            (Effects::Allowed, Some(output)) => {
+                                write!(output, "{line}{end}").context("Could not write output.")?
+                            }
+                            (Effects::Allowed, None) => {
+                                let mut stdout = io::stdout().lock();
+                                write!(stdout, "{line}{end}").context("Could not write output.")?;
+                                if end.is_empty() {
+                                    stdout.flush().context("Could not write output.")?;
+                                }
+                            }
+                            (Effects::Captured, _) => self.printed.push(line.to_string()),
+                            (Effects::Ignored, _) => {}
+                            (Effects::Denied, _) => bail!("Printing is not allowed."),
+                        }
+
+                        let result = match self.printing.result {
+                            PrintResult::Argument => value,
+                            PrintResult::Text => {
+                                let text = self.heap.display(value).to_string();
+                                self.heap.store_str(&text)
+                            }
+                        };
+                        self.stack.push(result);
+                    }
+                    Instruction::ReadLine => {
+                        let line = self.read_line()?;
+                        let value = self.heap.store_str(&line);
+                        self.stack.push(value);
+                    }
+                    Instruction::ReadInt => {
+                        let line = self.read_line()?;
+                        let value = line.trim().parse().map_err(|_| {
+                            anyhow!("Expected an integer as input, but read {line:?}.")
+                        })?;
+                        self.stack.push(Tagged::Integer(value));
+                    }
+                    Instruction::Show => {
+                        let value = self
+                            .stack
+                            .pop()
+                            .ok_or_else(|| anyhow!("Expected operand, but stack was empty."))?;
+
+                        let text = self.heap.display(value).to_string();
+                        let value = self.heap.store_str(&text);
+                        self.stack.push(value);
+                    }
+                    Instruction::Dup => {
+                        let value = self.stack.last().ok_or_else(|| {
+                            anyhow!("Expected operand, but self.stack was empty.")
+                        })?;
+                        self.stack.push(*value);
+                    }
+                    Instruction::Pop => {
+                        self.stack.pop().ok_or_else(|| {
+                            anyhow!("Expected operand, but self.stack was empty.")
+                        })?;
                     }
                     Instruction::GlobalSet(index) => {
-                        let identifier = &self.identifiers[index as usize];
+                        let identifier = self.identifiers[index as usize];
 
                         let value = self.stack.pop().ok_or_else(|| { anyhow!(
                             "Error setting global variable. No value found in the self.stack to be set."
                         )})?;
-                        let _ = self.globals.push((identifier, value));
+
+                        self.set_global(identifier, value);
                     }
                     Instruction::GlobalGet(index) => {
-                        let identifier = self.identifiers[index as usize].as_str();
-
-                        let value = environment
-                            .iter()
-                            .find(|v| v.0 == identifier)
-                            .map(|v| v.1.clone())
-                            .or(self
-                                .globals
-                                .iter()
-                                .find(|g| g.0 == identifier)
-                                .map(|g| g.1.clone()))
-                            .ok_or_else(|| anyhow!("Unknown variable {identifier}."))?
-                            .clone();
+                        let identifier = self.identifiers[index as usize];
+
+                        let captured = environment.iter().find(|v| v.0 == identifier).map(|v| v.1);
+
+                        let value = captured
+                            .or(self.globals.iter().find(|g| g.0 == identifier).map(|g| g.1))
+                            .or(self.natives.iter().find(|n| n.0 == identifier).map(|n| n.1))
+                            .or_else(|| self.load_builtin(identifier))
+                            .ok_or_else(|| {
+                                anyhow!("Unknown variable {}.", self.heap.string(identifier))
+                            })?;
 
                         self.stack.push(value);
                     }
                     Instruction::LocalGet(index, identifier_index) => {
-                        let absolute_index = frame_index + index as usize;
-                        if absolute_index >= self.stack.len() {
-                            let identifier = &self.identifiers[identifier_index as usize];
-                            bail!("Variable {identifier} not found.");
-                        }
-                        let value = self.stack[absolute_index].clone();
+                        let value = self.local(frame_index, index, identifier_index)?;
                         self.stack.push(value);
                     }
                     Instruction::If(jump) => {
@@ -367,182 +2242,228 @@ impl<'a> Vm<'a> {
                             anyhow!("Error in if. No value found in the self.stack to be tested.")
                         })?;
 
-                        if let Value::Bool(b) = *value {
+                        if let Tagged::Bool(b) = value {
                             if !b {
-                                skip = jump;
-                                continue;
+                                instruction_pointer += jump as usize;
                             }
                         } else {
                             bail!("Type error: if condition must evaluate to a boolean.");
                         }
                     }
                     Instruction::Jump(jump) => {
-                        skip = jump;
+                        instruction_pointer += jump as usize;
+                    }
+                    Instruction::JumpBack(jump) => {
+                        let arity = function.arity as usize;
+                        let arguments = self.stack_start(arity)?;
+                        self.stack.copy_within(arguments.., frame_index);
+                        self.stack.truncate(frame_index + arity);
+
+                        self.current_frame()?.elided.record(function.index);
+                        instruction_pointer = self.current_address - jump as usize;
                     }
                     Instruction::Closure(index) => {
-                        let function = &self.functions[index as usize];
-                        let parent = &self
-                            .call_frames
-                            .last()
-                            .expect("There is always at least one call frame active.")
-                            .closure;
-
-                        let mut environment = Vec::new();
-
-                        if let Value::Closure(parent_function, parent_environment) = parent.as_ref()
-                        {
-                            for captured in &function.captured {
-                                let captured = captured.as_str();
-                                let index = parent_function
-                                    .locals
-                                    .iter()
-                                    .position(|l| l.name == *captured);
-
-                                if let Some(index) = index {
-                                    let absolute_index = frame_index + index as usize;
-                                    environment
-                                        .push((captured, self.stack[absolute_index].clone()));
-                                } else {
-                                    let captured_in_parent = parent_environment
-                                        .iter()
-                                        .find(|v| v.0 == captured)
-                                        .map(|v| v.1.clone());
-                                    if let Some(captured_in_parent) = captured_in_parent {
-                                        environment.push((captured, captured_in_parent.clone()));
-                                    }
-                                }
-                            }
-                        }
-
-                        let closure = Value::Closure(function, environment);
-                        self.stack.push(Rc::new(closure));
+                        let function = self.functions[index as usize].clone();
+
+                        // The captured values are on top of the stack, in the
+                        // same order as the names.
+                        let start = self.stack_start(function.captured.len())?;
+                        let values = self.stack.split_off(start);
+                        let environment = function.captured.iter().copied().zip(values).collect();
+
+                        // A new closure is equal only to itself, so the call
+                        // making it cannot be answered with an earlier one's.
+                        self.mark_impure();
+                        self.push(Value::Closure(function, environment));
+                    }
+                    Instruction::Slide(count) => {
+                        let start = self.stack_start(count as usize + 1)?;
+                        let top = self.stack[self.stack.len() - 1];
+                        self.stack.truncate(start);
+                        self.stack.push(top);
                     }
                     Instruction::Call(arity) => {
-                        let closure_index = self.stack.len() - 1 - arity as usize;
-                        let closure = &self.stack[closure_index];
-                        let closure = closure.clone();
+                        let closure_index = self.stack_start(arity as usize + 1)?;
+                        let Tagged::Object(closure) = self.stack[closure_index] else {
+                            bail!("Attempted to call value that is not a function!");
+                        };
 
-                        if let Value::Closure(function, _) = *closure {
-                            if function.arity != arity {
-                                bail!("Attempted to call function with wrong number of arguments.");
+                        let callee = self.callee(function.index, closure, arity)?;
+                        let (function, environment) = match callee {
+                            Callee::Native(native) => {
+                                if let Some(token) = self.call_native(&native, arity)? {
+                                    return self.suspend(instruction_pointer, token);
+                                }
+                                continue;
                             }
+                            Callee::Function(function, environment) => (function, environment),
+                        };
 
-                            if arity == 1 {
-                                let last_argument = &self.stack[self.stack.len() - 1];
-                                if let Value::Integer(i) = **last_argument {
-                                    if let Some((_, memoized)) =
-                                        self.memoization.iter().find(|m| m.0 == (function.index, i))
-                                    {
-                                        self.stack.truncate(self.stack.len() - 2);
-                                        self.stack.push(memoized.clone());
-                                        continue;
-                                    }
-
-                                    self.current_execution = Some((function.index, i));
+                        let mut memo_check = None;
+                        let mut memo_key = None;
+                        // Closures that captured variables can return
+                        // different results for the same arguments.
+                        let arguments = &self.stack[self.stack.len() - arity as usize..];
+                        if arity > 0
+                            && self.memoization.is_enabled()
+                            && function.captured.is_empty()
+                            && memo::memoizable(arguments)
+                        {
+                            if let Some(memoized) = self.memoization.get(function.index, arguments)
+                            {
+                                self.stats.memoization_hits += 1;
+
+                                if !self.sample_memo_hit() {
+                                    self.stack.truncate(self.stack.len() - arity as usize - 1);
+                                    self.stack.push(memoized);
+                                    continue;
                                 }
+                                let arguments = &self.stack[self.stack.len() - arity as usize..];
+                                memo_check = MemoKey::new(function.index, arguments)
+                                    .map(|key| Box::new((key, memoized)));
+                            } else {
+                                self.stats.memoization_misses += 1;
+                                memo_key = MemoKey::new(function.index, arguments);
                             }
+                        }
 
-                            let current_frame = self
-                                .call_frames
-                                .last_mut()
-                                .expect("There is at least one active call frame at all times.");
-
-                            current_frame.instruction_pointer = instruction_pointer;
+                        if self.call_frames.len() >= self.max_call_depth {
+                            bail!(
+                                "Call depth exceeds the limit of {} calls.",
+                                self.max_call_depth
+                            );
+                        }
 
-                            let new_frame = CallFrame {
-                                bytecode: &function.bytecode,
-                                closure,
-                                instruction_pointer: 0,
-                                frame_index: self.stack.len() - arity as usize,
-                            };
-                            self.call_frames.push(new_frame);
+                        self.current_frame()?.instruction_pointer = instruction_pointer;
+                        self.stack
+                            .reserve(function.max_stack.saturating_sub(arity as usize));
+
+                        let new_frame = CallFrame {
+                            function,
+                            closure: Some(closure),
+                            environment,
+                            instruction_pointer: 0,
+                            frame_index: self.stack.len() - arity as usize,
+                            elided: ElidedCalls::default(),
+                            memo_check,
+                            memo_key,
+                            pure: true,
+                        };
+                        self.call_frames.push(new_frame);
 
-                            break;
-                        } else {
-                            bail!("Attempted to call value that is not a function!");
-                        }
+                        break;
                     }
-                    Instruction::TailCall(arity) => {
-                        let closure_index = self.stack.len() - 1 - arity as usize;
-                        let closure = &self.stack[closure_index];
-                        let closure = closure.clone();
+                    Instruction::TailCall(arity) | Instruction::LocalGetTailCall(_, _, arity) => {
+                        if let Instruction::LocalGetTailCall(index, identifier_index, _) =
+                            *instruction
+                        {
+                            let value = self.local(frame_index, index, identifier_index)?;
+                            self.stack.push(value);
+                        }
 
-                        if let Value::Closure(function, _) = *closure {
-                            if function.arity != arity {
-                                bail!("Attempted to call function with wrong number of arguments.");
-                            }
+                        let closure_index = self.stack_start(arity as usize + 1)?;
+                        let Tagged::Object(closure) = self.stack[closure_index] else {
+                            bail!("Attempted to call value that is not a function!");
+                        };
 
-                            if arity == 1 {
-                                let last_argument = &self.stack[self.stack.len() - 2];
-                                if let Value::Integer(i) = **last_argument {
-                                    if let Some((_, memoized)) =
-                                        self.memoization.iter().find(|m| m.0 == (function.index, i))
-                                    {
-                                        self.stack.truncate(self.stack.len() - 2);
-                                        self.stack.push(memoized.clone());
-                                        continue;
-                                    }
-
-                                    self.current_execution = Some((function.index, i));
+                        let callee = self.callee(function.index, closure, arity)?;
+                        let (function, environment) = match callee {
+                            Callee::Native(native) => {
+                                if let Some(token) = self.call_native(&native, arity)? {
+                                    return self.suspend(instruction_pointer, token);
                                 }
+                                continue;
                             }
+                            Callee::Function(function, environment) => (function, environment),
+                        };
 
-                            let current_frame = self
-                                .call_frames
-                                .last_mut()
-                                .expect("There is at least one active call frame at all times.");
-
-                            current_frame.instruction_pointer = instruction_pointer;
+                        // A hit leaves the result for the `Return` that
+                        // follows every tail call.
+                        let arguments = &self.stack[self.stack.len() - arity as usize..];
+                        if arity > 0
+                            && self.memoization.is_enabled()
+                            && function.captured.is_empty()
+                            && memo::memoizable(arguments)
+                        {
+                            if let Some(memoized) = self.memoization.get(function.index, arguments)
+                            {
+                                self.stats.memoization_hits += 1;
+                                self.stack.truncate(self.stack.len() - arity as usize - 1);
+                                self.stack.push(memoized);
+                                continue;
+                            }
 
-                            let last_frame = self
-                                .call_frames
-                                .pop()
-                                .expect("A tail call can only exist within another function");
+                            self.stats.memoization_misses += 1;
+                        }
 
-                            let kept: Vec<Rc<Value<'_>>> = self
-                                .stack
-                                .drain(self.stack.len() - arity as usize - 1..)
-                                .collect();
+                        self.current_frame()?.instruction_pointer = instruction_pointer;
 
-                            let locals_to_remove = match *last_frame.closure {
-                                Value::Closure(f, _) => f.locals.len(),
-                                _ => unreachable!(),
-                            };
+                        let last_frame = self
+                            .call_frames
+                            .pop()
+                            .ok_or_else(|| anyhow!("There is no active call frame."))?;
+
+                        let frame_start = last_frame.base();
+
+                        // Slide the callee and its arguments down over the frame
+                        // being left.
+                        let kept = self.stack.len() - arity as usize - 1;
+                        self.stack.copy_within(kept.., frame_start);
+                        self.stack.truncate(frame_start + arity as usize + 1);
+                        self.stack
+                            .reserve(function.max_stack.saturating_sub(arity as usize));
+
+                        let mut elided = last_frame.elided;
+                        elided.record(last_frame.function.index);
+
+                        let new_frame = CallFrame {
+                            function,
+                            closure: Some(closure),
+                            environment,
+                            instruction_pointer: 0,
+                            frame_index: self.stack.len() - arity as usize,
+                            elided,
+                            memo_check: None,
+                            // The callee's result is the replaced frame's, so
+                            // it is memoized under the replaced frame's key.
+                            memo_key: last_frame.memo_key,
+                            pure: last_frame.pure,
+                        };
+                        self.call_frames.push(new_frame);
 
-                            self.stack.truncate(self.stack.len() - locals_to_remove - 1);
+                        break;
+                    }
+                    Instruction::Return(_) => {
+                        let result = self.stack.pop().ok_or_else(|| {
+                            anyhow!("Expected operand, but self.stack was empty.")
+                        })?;
 
-                            self.stack.extend(kept);
+                        let frame = self
+                            .call_frames
+                            .pop()
+                            .ok_or_else(|| anyhow!("There is no active call frame."))?;
+                        let base = frame.base();
 
-                            let new_frame = CallFrame {
-                                bytecode: &function.bytecode,
-                                closure,
-                                instruction_pointer: 0,
-                                frame_index: self.stack.len() - arity as usize,
-                            };
-                            self.call_frames.push(new_frame);
+                        if let Some(key) = frame.memo_key {
+                            if frame.pure {
+                                self.memoization.insert(key, result);
+                            }
+                        }
 
-                            break;
-                        } else {
-                            bail!("Attempted to call value that is not a function!");
+                        if let Some((key, memoized)) = frame.memo_check.map(|check| *check) {
+                            self.check_memoized(key, memoized, result);
                         }
-                    }
-                    Instruction::Return(arity) => {
-                        let result = self.stack.pop().expect("Function must have a return value");
 
-                        if let Some(execution) = self.current_execution {
-                            if self.pure {
-                                self.memoization.push((execution, result.clone()));
+                        if !frame.pure {
+                            if let Some(caller) = self.call_frames.last_mut() {
+                                caller.pure = false;
                             }
-                        };
-
-                        self.current_execution = None;
-
-                        for _ in 0..arity + 1 {
-                            self.stack.pop();
                         }
 
+                        // Whatever locals are left go with the frame, however
+                        // many `Return` expected.
+                        self.stack.truncate(base);
                         self.stack.push(result);
-                        self.call_frames.pop();
 
                         break;
                     }
@@ -550,10 +2471,37 @@ impl<'a> Vm<'a> {
             }
         }
 
-        let value = self.stack.last().expect(
+        if self.stack.len() != 1 {
+            bail!(
+                "Stack imbalance: expected a single value at the end of the execution, found {}.",
+                self.stack.len()
+            );
+        }
+
+        let value = self.stack.pop().expect(
             "At the end of the execution, there must be at least one value in the self.stack.",
         );
 
-        Ok(value.as_ref().into())
+        Ok(Execution::Finished(self.heap.finalize(value)))
+    }
+}
+
+/// Compiles a program with the default optimizer passes, for running as
+/// many times as needed with [`Vm::interpret_program`], each time on a new
+/// VM with its own limits and output.
+pub fn compile(filename: &str, contents: &str) -> Result<CompiledProgram> {
+    Vm::new().compile_program(filename, contents)
+}
+
+fn expect_finished(execution: Execution) -> Result<FinalValue> {
+    match execution {
+        Execution::Finished(value) => Ok(value),
+        Execution::Suspended(token) => bail!(
+            "Execution suspended waiting for native function {}, which needs an embedder to resume it.",
+            token.native
+        ),
+        Execution::Paused => {
+            bail!("Execution paused at a breakpoint, which needs a debugger to resume it.")
+        }
     }
 }
@@ -0,0 +1,39 @@
+use std::io;
+
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+
+use crate::{printing::Captured, value::FinalValue, vm::Vm};
+
+/// What running a program in the browser produced.
+#[derive(Serialize)]
+struct Interpretation {
+    output: String,
+    value: Option<FinalValue>,
+    error: Option<String>,
+}
+
+/// Runs a program without natives or input, returning an object with what
+/// it printed as `output`, and either its result as `value` or the error
+/// that stopped it as `error`. Integers JavaScript numbers do not hold
+/// exactly come as strings of their digits. Throws if the object cannot be
+/// built.
+#[wasm_bindgen]
+pub fn interpret(source: &str) -> Result<JsValue, JsValue> {
+    let printed = Captured::default();
+    let mut vm = Vm::new()
+        .with_reader(io::empty())
+        .with_output(printed.clone());
+
+    let (value, error) = match vm.interpret("playground.rinha", source) {
+        Ok(value) => (Some(value), None),
+        Err(error) => (None, Some(error.to_string())),
+    };
+    let interpretation = Interpretation {
+        output: printed.text(),
+        value,
+        error,
+    };
+
+    Ok(serde_wasm_bindgen::to_value(&interpretation)?)
+}
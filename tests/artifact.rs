@@ -0,0 +1,351 @@
+use std::{sync::Arc, thread};
+
+use rvm::{
+    artifact::{CompiledFunction, CompiledProgram, Constant, Metadata, MAGIC},
+    bytecode::Instruction,
+    sandbox::Effects,
+    value::FinalValue,
+    vm::{self, Vm, COMPILE_OPTIONS},
+};
+
+const PROGRAM: &str = r#"
+    let fib = fn (n) => {
+        if (n < 2) { n } else { fib(n - 1) + fib(n - 2) }
+    };
+    let greet = fn (name) => { "hello, " + name };
+    (greet("world"), (fib(20), 0 - 1000000))
+"#;
+
+fn expected() -> FinalValue {
+    FinalValue::Tuple(
+        Box::new(FinalValue::String("hello, world".to_owned())),
+        Box::new(FinalValue::Tuple(
+            Box::new(FinalValue::Integer(6765)),
+            Box::new(FinalValue::Integer(-1000000)),
+        )),
+    )
+}
+
+#[test]
+fn artifacts_run_like_their_source() {
+    let program = Vm::new().compile_program("test", PROGRAM).unwrap();
+    let bytes = program.to_bytes().unwrap();
+    assert!(bytes.starts_with(MAGIC));
+
+    let loaded = CompiledProgram::from_bytes(&bytes).unwrap();
+    assert_eq!(Vm::new().interpret_program(&loaded).unwrap(), expected());
+}
+
+#[test]
+fn programs_run_on_many_threads_at_once() {
+    let program = Arc::new(vm::compile("test", PROGRAM).unwrap());
+
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let program = Arc::clone(&program);
+            thread::spawn(move || Vm::new().interpret_program(&program).unwrap())
+        })
+        .collect();
+
+    for worker in workers {
+        assert_eq!(worker.join().unwrap(), expected());
+    }
+}
+
+#[test]
+fn programs_compile_once_and_run_on_many_vms() {
+    let program = vm::compile("test", "let _ = print(\"hi\"); 1 + 2").unwrap();
+
+    let mut starved = Vm::new().with_fuel(1);
+    assert!(starved.interpret_program(&program).is_err());
+
+    let mut capturing = Vm::new().with_effects(Effects::Captured);
+    assert_eq!(
+        capturing.interpret_program(&program).unwrap(),
+        FinalValue::Integer(3)
+    );
+    assert_eq!(capturing.printed(), ["hi"]);
+
+    let mut ignoring = Vm::new().with_effects(Effects::Ignored);
+    assert_eq!(
+        ignoring.interpret_program(&program).unwrap(),
+        FinalValue::Integer(3)
+    );
+    assert!(ignoring.printed().is_empty());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn compressed_artifacts_round_trip() {
+    let program = Vm::new().compile_program("test", PROGRAM).unwrap();
+    let bytes = program.to_compressed_bytes(3).unwrap();
+
+    let loaded = CompiledProgram::from_bytes(&bytes).unwrap();
+    assert_eq!(Vm::new().interpret_program(&loaded).unwrap(), expected());
+}
+
+#[test]
+fn constant_pool_is_sorted_and_deduplicated() {
+    let program = CompiledProgram {
+        constants: vec![
+            Constant::String("prefix_b".into()),
+            Constant::Integer(300),
+            Constant::Integer(-5),
+            Constant::String("prefix_a".into()),
+            Constant::Integer(300),
+        ],
+        functions: Vec::new(),
+        identifiers: Vec::new(),
+        metadata: Metadata::default(),
+        script: vec![
+            Instruction::Constant(4),
+            Instruction::Constant(2),
+            Instruction::Tuple,
+            Instruction::Constant(0),
+            Instruction::Tuple,
+            Instruction::Return(0),
+        ],
+    };
+
+    let loaded = CompiledProgram::from_bytes(&program.to_bytes().unwrap()).unwrap();
+    assert_eq!(
+        loaded.constants,
+        vec![
+            Constant::Integer(-5),
+            Constant::Integer(300),
+            Constant::String("prefix_a".into()),
+            Constant::String("prefix_b".into()),
+        ]
+    );
+    assert_eq!(
+        &loaded.script[..4],
+        &[
+            Instruction::Constant(1),
+            Instruction::Constant(0),
+            Instruction::Tuple,
+            Instruction::Constant(3),
+        ]
+    );
+
+    let result = Vm::new().interpret_program(&loaded).unwrap();
+    assert_eq!(
+        result,
+        FinalValue::Tuple(
+            Box::new(FinalValue::Tuple(
+                Box::new(FinalValue::Integer(300)),
+                Box::new(FinalValue::Integer(-5))
+            )),
+            Box::new(FinalValue::String("prefix_b".to_owned()))
+        )
+    );
+}
+
+#[test]
+fn generated_constant_pools_stay_small() {
+    let constants: Vec<Constant> = (0..1000)
+        .map(|i| Constant::Integer(1_000_000 + i * 3))
+        .chain((0..1000).map(|i| Constant::String(format!("generated_identifier_{i:04}").into())))
+        .collect();
+
+    let program = CompiledProgram {
+        constants,
+        functions: Vec::new(),
+        identifiers: Vec::new(),
+        metadata: Metadata::default(),
+        script: vec![Instruction::True, Instruction::Return(0)],
+    };
+
+    // Each integer takes a one byte delta, and each string only repeats the
+    // digits that differ from the previous one.
+    let bytes = program.to_bytes().unwrap();
+    assert!(bytes.len() < 8_000, "{} bytes", bytes.len());
+
+    let loaded = CompiledProgram::from_bytes(&bytes).unwrap();
+    assert_eq!(loaded, program);
+}
+
+#[test]
+fn compiled_tables_hold_each_constant_and_identifier_once() {
+    let program = Vm::new()
+        .compile_program(
+            "test",
+            r#"
+                let twice = fn (f) => { (f(1, "a long string"), f(1, "a long string")) };
+                let again = fn (f) => { (f(2, "short"), f(2, "short")) };
+                (twice, again)
+            "#,
+        )
+        .unwrap();
+
+    assert_eq!(
+        program.constants,
+        [
+            Constant::Integer(1),
+            Constant::String("a long string".into()),
+            Constant::Integer(2),
+            Constant::String("short".into()),
+        ]
+    );
+    let mut identifiers = program.identifiers.clone();
+    identifiers.sort();
+    identifiers.dedup();
+    assert_eq!(identifiers.len(), program.identifiers.len());
+}
+
+#[test]
+fn tables_may_outgrow_sixteen_bit_indices() {
+    const SIZE: u32 = u16::MAX as u32 + 2;
+
+    let program = CompiledProgram {
+        constants: (0..SIZE).map(|i| Constant::Integer(i as i32)).collect(),
+        functions: (0..SIZE)
+            .map(|i| CompiledFunction {
+                arity: 0,
+                bytecode: vec![Instruction::Constant(i), Instruction::Return(0)],
+                captured: Vec::new(),
+                locals: Vec::new(),
+                name: None,
+            })
+            .collect(),
+        identifiers: (0..SIZE).map(|i| format!("global{i}")).collect(),
+        metadata: Metadata::default(),
+        script: vec![
+            Instruction::Closure(SIZE - 1),
+            Instruction::Call(0),
+            Instruction::GlobalSet(SIZE - 1),
+            Instruction::GlobalGet(SIZE - 1),
+            Instruction::Constant(u16::MAX as u32),
+            Instruction::Tuple,
+            Instruction::Return(0),
+        ],
+    };
+
+    let loaded = CompiledProgram::from_bytes(&program.to_bytes().unwrap()).unwrap();
+    assert_eq!(loaded, program);
+    assert_eq!(
+        Vm::new().interpret_program(&loaded).unwrap(),
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(SIZE as i32 - 1)),
+            Box::new(FinalValue::Integer(u16::MAX as i32)),
+        )
+    );
+}
+
+#[test]
+fn malformed_artifacts_are_rejected() {
+    let bytes = Vm::new()
+        .compile_program("test", PROGRAM)
+        .unwrap()
+        .to_bytes()
+        .unwrap();
+
+    assert!(CompiledProgram::from_bytes(b"RVMX\x01\x00").is_err());
+    assert!(CompiledProgram::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+
+    let mut trailing = bytes.clone();
+    trailing.push(0);
+    assert!(CompiledProgram::from_bytes(&trailing).is_err());
+
+    let mut program = CompiledProgram::from_bytes(&bytes).unwrap();
+    program.script.insert(0, Instruction::Closure(100));
+    assert!(Vm::new().interpret_program(&program).is_err());
+}
+
+#[test]
+fn programs_load_only_into_fresh_vms() {
+    let program = Vm::new().compile_program("test", PROGRAM).unwrap();
+
+    let mut vm = Vm::new();
+    vm.interpret("test", "1 + 1").unwrap();
+    assert!(vm.interpret_program(&program).is_err());
+}
+
+#[test]
+fn artifacts_record_their_origin() {
+    let program = Vm::new().compile_program("fib.rinha", PROGRAM).unwrap();
+    let metadata = &program.metadata;
+
+    assert_eq!(metadata.filename, "fib.rinha");
+    assert_eq!(metadata.compiler_version, env!("CARGO_PKG_VERSION"));
+    assert_eq!(metadata.options, COMPILE_OPTIONS);
+    assert_eq!(metadata.source_digest().len(), 64);
+    assert_eq!(
+        Metadata::new("empty.rinha", "", &[]).source_digest(),
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+    );
+
+    let bytes = program.to_bytes().unwrap();
+    let header = CompiledProgram::read_header(&bytes).unwrap();
+    assert!(!header.compressed);
+    assert_eq!(&header.metadata, metadata);
+    assert_eq!(
+        &CompiledProgram::from_bytes(&bytes).unwrap().metadata,
+        metadata
+    );
+}
+
+#[test]
+fn every_instruction_round_trips() {
+    let script = vec![
+        Instruction::Constant(0),
+        Instruction::True,
+        Instruction::False,
+        Instruction::Add,
+        Instruction::Sub,
+        Instruction::Mul,
+        Instruction::Div,
+        Instruction::Rem,
+        Instruction::Eq,
+        Instruction::Neq,
+        Instruction::Gt,
+        Instruction::Lt,
+        Instruction::Gte,
+        Instruction::Lte,
+        Instruction::And,
+        Instruction::Or,
+        Instruction::Tuple,
+        Instruction::First,
+        Instruction::Second,
+        Instruction::Print,
+        Instruction::Dup,
+        Instruction::GlobalGet(70_000),
+        Instruction::GlobalSet(2),
+        Instruction::LocalGet(1, 65_000),
+        Instruction::If(70_000),
+        Instruction::Jump(1),
+        Instruction::Closure(100_000),
+        Instruction::Call(2),
+        Instruction::Return(3),
+        Instruction::TailCall(1),
+        Instruction::Slide(2),
+        Instruction::ReadLine,
+        Instruction::ReadInt,
+        Instruction::Pop,
+        Instruction::LocalGetConstantAdd(0, 1, 0),
+        Instruction::LocalGetConstantSub(2, 3, 0),
+        Instruction::ConstantLt(0),
+        Instruction::ConstantEq(0),
+        Instruction::LocalGetTailCall(1, 2, 3),
+        Instruction::Neg,
+        Instruction::Not,
+        Instruction::Show,
+        Instruction::BitAnd,
+        Instruction::BitOr,
+        Instruction::BitXor,
+        Instruction::Shl,
+        Instruction::Shr,
+        Instruction::JumpBack(5),
+    ];
+    assert_eq!(script.len(), Instruction::OPCODES);
+
+    let program = CompiledProgram {
+        constants: vec![Constant::Integer(1)],
+        functions: Vec::new(),
+        identifiers: Vec::new(),
+        metadata: Metadata::default(),
+        script,
+    };
+
+    let loaded = CompiledProgram::from_bytes(&program.to_bytes().unwrap()).unwrap();
+    assert_eq!(loaded, program);
+}
@@ -0,0 +1,75 @@
+use std::{env, fs, path::PathBuf, time::Duration};
+
+use rvm::{
+    batch::{programs, run_all, BatchOptions},
+    value::FinalValue,
+};
+
+fn corpus(name: &str, programs: &[(&str, &str)]) -> PathBuf {
+    let directory = env::temp_dir().join(format!("rvm-batch-{name}-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    for (file, contents) in programs {
+        fs::write(directory.join(file), contents).unwrap();
+    }
+
+    directory
+}
+
+#[test]
+fn every_program_is_reported_in_order() {
+    let fib = fs::read_to_string(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fib.json")).unwrap();
+    let directory = corpus(
+        "reports",
+        &[
+            ("a.rinha", "let _ = print(1); 1 + 1"),
+            ("b.json", &fib),
+            ("c.rinha", "1 / 0"),
+            ("notes.txt", "not a program"),
+        ],
+    );
+
+    let paths = programs(&directory).unwrap();
+    let reports = run_all(&paths, &BatchOptions::default()).unwrap();
+
+    let names: Vec<_> = reports
+        .iter()
+        .map(|report| report.program.file_name().unwrap().to_str().unwrap())
+        .collect();
+    assert_eq!(names, ["a.rinha", "b.json", "c.rinha"]);
+
+    assert_eq!(reports[0].result, Some(FinalValue::Integer(2)));
+    assert_eq!(reports[0].stdout, ["1"]);
+    assert!(reports[0].instructions_executed > 0);
+    assert_eq!(reports[1].result, Some(FinalValue::Integer(55)));
+    assert_eq!(reports[2].result, None);
+    assert!(reports[2]
+        .error
+        .as_ref()
+        .unwrap()
+        .contains("divide by zero"));
+}
+
+#[test]
+fn runaway_programs_are_stopped() {
+    let directory = corpus(
+        "limits",
+        &[("loop.rinha", "let f = fn (n) => { f(n + 1) }; f(0)")],
+    );
+    let paths = programs(&directory).unwrap();
+
+    let options = BatchOptions {
+        fuel: Some(1000),
+        ..BatchOptions::default()
+    };
+    let reports = run_all(&paths, &options).unwrap();
+    assert!(reports[0].error.as_ref().unwrap().contains("Out of fuel."));
+
+    let options = BatchOptions {
+        timeout: Some(Duration::from_millis(50)),
+        threads: Some(2),
+        ..BatchOptions::default()
+    };
+    let reports = run_all(&paths, &options).unwrap();
+    assert!(reports[0].error.as_ref().unwrap().contains("Timed out."));
+}
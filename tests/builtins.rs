@@ -0,0 +1,285 @@
+use rvm::{value::FinalValue, vm::Vm};
+
+fn interpret(source: &str) -> anyhow::Result<FinalValue> {
+    Vm::new().interpret("test", source)
+}
+
+fn string(s: &str) -> FinalValue {
+    FinalValue::String(s.to_owned())
+}
+
+#[test]
+fn str_length_counts_characters() {
+    assert_eq!(
+        interpret(r#"str_length("héllo")"#).unwrap(),
+        FinalValue::Integer(5)
+    );
+    assert_eq!(
+        interpret(r#"str_length("")"#).unwrap(),
+        FinalValue::Integer(0)
+    );
+    assert!(interpret("str_length(1)").is_err());
+}
+
+#[test]
+fn str_slice_takes_a_range_of_characters() {
+    assert_eq!(
+        interpret(r#"str_slice("héllo", 1, 3)"#).unwrap(),
+        string("él")
+    );
+    assert_eq!(
+        interpret(r#"str_slice("hello", 5, 5)"#).unwrap(),
+        string("")
+    );
+    assert_eq!(
+        interpret(r#"str_slice("hello", 2, 6)"#)
+            .unwrap_err()
+            .to_string(),
+        "str_slice expects 0 <= start <= end <= 5, got 2 and 6."
+    );
+    assert!(interpret(r#"str_slice("hello", 3, 2)"#).is_err());
+}
+
+#[test]
+fn int_to_str_formats_integers() {
+    assert_eq!(interpret("int_to_str(0 - 42)").unwrap(), string("-42"));
+    assert!(interpret(r#"int_to_str("1")"#).is_err());
+}
+
+#[test]
+fn str_to_int_parses_integers() {
+    assert_eq!(
+        interpret(r#"str_to_int(" 42 ")"#).unwrap(),
+        FinalValue::Integer(42)
+    );
+    assert_eq!(
+        interpret(r#"str_to_int("-2147483648")"#).unwrap(),
+        FinalValue::Integer(i32::MIN)
+    );
+    assert_eq!(
+        interpret(r#"str_to_int("4x")"#).unwrap_err().to_string(),
+        "str_to_int expects an integer, but got \"4x\"."
+    );
+    assert!(interpret(r#"str_to_int("2147483648")"#).is_err());
+}
+
+#[test]
+fn min_and_max_compare_integers() {
+    assert_eq!(interpret("min(3, 0 - 4)").unwrap(), FinalValue::Integer(-4));
+    assert_eq!(interpret("max(3, 0 - 4)").unwrap(), FinalValue::Integer(3));
+    assert!(interpret("min(true, 1)").is_err());
+    assert!(interpret("max(1)").is_err());
+}
+
+#[test]
+fn abs_wraps_like_arithmetic() {
+    assert_eq!(interpret("abs(0 - 7)").unwrap(), FinalValue::Integer(7));
+    assert_eq!(
+        interpret("let min = 0 - 2147483647; abs(min - 1)").unwrap(),
+        FinalValue::Integer(i32::MIN)
+    );
+    assert!(interpret(r#"abs("1")"#).is_err());
+}
+
+#[test]
+fn programs_can_shadow_builtins() {
+    assert_eq!(
+        interpret("let min = fn (a, b) => { a + b }; min(1, 2)").unwrap(),
+        FinalValue::Integer(3)
+    );
+}
+
+#[test]
+fn calls_using_builtins_are_still_memoized() {
+    let mut vm = Vm::new();
+    let fib = "
+        let fib = fn (n) => { if (n < 2) { max(n, 0) } else { fib(n - 1) + fib(n - 2) } };
+        fib(30)
+    ";
+    assert_eq!(
+        vm.interpret("test", fib).unwrap(),
+        FinalValue::Integer(832040)
+    );
+    assert!(vm.stats().memoization_hits > 0);
+}
+
+fn list(elements: &[i32]) -> FinalValue {
+    FinalValue::List(elements.iter().map(|i| FinalValue::Integer(*i)).collect())
+}
+
+#[test]
+fn lists_are_built_and_pushed_to() {
+    assert_eq!(interpret("list()").unwrap(), list(&[]));
+    assert_eq!(
+        interpret("let l = list(1, 2); let m = push(l, 3); (l, m)").unwrap(),
+        FinalValue::Tuple(Box::new(list(&[1, 2])), Box::new(list(&[1, 2, 3])))
+    );
+    assert!(interpret("push(1, 2)").is_err());
+}
+
+#[test]
+fn get_and_length_read_lists() {
+    assert_eq!(
+        interpret(r#"get(list(1, "two", (3, 4)), 1)"#).unwrap(),
+        string("two")
+    );
+    assert_eq!(
+        interpret("length(list(1, 2, 3))").unwrap(),
+        FinalValue::Integer(3)
+    );
+    assert_eq!(
+        interpret("get(list(1, 2), 2)").unwrap_err().to_string(),
+        "get expects an index below 2, got 2."
+    );
+    assert!(interpret("get(list(1, 2), 0 - 1)").is_err());
+    assert!(interpret(r#"length("abc")"#).is_err());
+}
+
+#[test]
+fn lists_compare_and_print_structurally() {
+    assert_eq!(
+        interpret("list(1, (2, 3)) == push(list(1), (2, 3))").unwrap(),
+        FinalValue::Bool(true)
+    );
+    assert_eq!(
+        interpret("list(1, 2) == list(2, 1)").unwrap(),
+        FinalValue::Bool(false)
+    );
+    assert_eq!(
+        interpret(r#"print(list(1, "a", list()))"#).unwrap(),
+        FinalValue::List(vec![
+            FinalValue::Integer(1),
+            string("a"),
+            FinalValue::List(vec![]),
+        ])
+    );
+}
+
+#[test]
+fn lists_serialize_as_arrays() {
+    let value = interpret("list(1, (2, 3))").unwrap();
+    assert_eq!(serde_json::to_string(&value).unwrap(), "[1,[2,3]]");
+}
+
+fn record(fields: &[(&str, i32)]) -> FinalValue {
+    FinalValue::Record(
+        fields
+            .iter()
+            .map(|(key, value)| (key.to_string(), FinalValue::Integer(*value)))
+            .collect(),
+    )
+}
+
+#[test]
+fn records_are_set_without_changing_the_original() {
+    assert_eq!(interpret("record()").unwrap(), record(&[]));
+    assert_eq!(
+        interpret(r#"let r = set(record(), "a", 1); let s = set(r, "a", 2); (r, s)"#).unwrap(),
+        FinalValue::Tuple(Box::new(record(&[("a", 1)])), Box::new(record(&[("a", 2)])))
+    );
+    assert!(interpret(r#"set(record(), 1, 2)"#).is_err());
+}
+
+#[test]
+fn get_and_has_read_records() {
+    let r = r#"let r = set(set(record(), "a", 1), "b", list(2));"#;
+    assert_eq!(
+        interpret(&format!(r#"{r} get(r, "b")"#)).unwrap(),
+        list(&[2])
+    );
+    assert_eq!(
+        interpret(&format!(r#"{r} (has(r, "a"), has(r, "c"))"#)).unwrap(),
+        FinalValue::Tuple(
+            Box::new(FinalValue::Bool(true)),
+            Box::new(FinalValue::Bool(false))
+        )
+    );
+    assert_eq!(
+        interpret(&format!(r#"{r} get(r, "c")"#))
+            .unwrap_err()
+            .to_string(),
+        "get expects a key of the record, got \"c\"."
+    );
+    assert!(interpret(r#"get(record(), 0)"#).is_err());
+}
+
+#[test]
+fn records_compare_and_print_structurally() {
+    assert_eq!(
+        interpret(r#"set(set(record(), "a", 1), "b", 2) == set(set(record(), "b", 2), "a", 1)"#)
+            .unwrap(),
+        FinalValue::Bool(true)
+    );
+    assert_eq!(
+        interpret(r#"set(record(), "a", 1) == set(record(), "a", 2)"#).unwrap(),
+        FinalValue::Bool(false)
+    );
+    assert_eq!(
+        serde_json::to_string(&interpret(r#"set(set(record(), "b", (1, 2)), "a", 3)"#).unwrap())
+            .unwrap(),
+        r#"{"a":3,"b":[1,2]}"#
+    );
+}
+
+#[test]
+fn show_converts_values_to_their_printed_text() {
+    assert_eq!(
+        interpret(r#"show((1, (true, "a")))"#).unwrap(),
+        string("(1, (true, a))")
+    );
+    assert_eq!(
+        interpret("let f = fn (x) => { x }; show(f)").unwrap(),
+        string("<#closure f>")
+    );
+    assert_eq!(
+        interpret(r#"show(push(list(1), 2)) + "!""#).unwrap(),
+        string("[1, 2]!")
+    );
+    assert!(interpret("show(1, 2)").is_err());
+}
+
+#[test]
+fn bitwise_builtins_manipulate_integer_bits() {
+    assert_eq!(
+        interpret("(band(12, 10), (bor(12, 10), bxor(12, 10)))").unwrap(),
+        interpret("(8, (14, 6))").unwrap()
+    );
+    assert_eq!(
+        interpret("(shl(1, 31), (shl(1, 33), (shr(0 - 16, 2), shr(0 - 1, 31))))").unwrap(),
+        interpret("((0 - 2147483647) - 1, (2, (0 - 4, 0 - 1)))").unwrap()
+    );
+    assert_eq!(
+        interpret(r#"band(1, "a")"#).unwrap_err().to_string(),
+        "Operands must be both integers."
+    );
+    assert_eq!(
+        interpret("let band = fn (a, b) => { a + b }; band(1, 2)").unwrap(),
+        FinalValue::Integer(3)
+    );
+}
+
+#[test]
+fn random_threads_its_seed_through_the_program() {
+    let source = r#"
+        let roll = fn (n, seed, total) => {
+            if (n == 0) {
+                total
+            } else {
+                let step = random(seed);
+                roll(n - 1, second(step), total + first(step) % 6)
+            }
+        };
+        (roll(1000, 42, 0), random(42) == random(42))
+    "#;
+    let FinalValue::Tuple(total, same) = interpret(source).unwrap() else {
+        panic!("Expected a tuple.");
+    };
+    let FinalValue::Integer(total) = *total else {
+        panic!("Expected an integer.");
+    };
+    // Each roll is between 0 and 5, so a thousand of them average near 2.5.
+    assert!((2000..3000).contains(&total), "{total}");
+    assert_eq!(*same, FinalValue::Bool(true));
+    assert_eq!(interpret(source).unwrap(), interpret(source).unwrap());
+    assert!(interpret(r#"random("a")"#).is_err());
+}
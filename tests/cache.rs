@@ -0,0 +1,103 @@
+use std::{env, fs, path::PathBuf, process};
+
+use rvm::{
+    artifact::{CompiledProgram, Metadata},
+    cache::CompilationCache,
+    value::FinalValue,
+    vm::{Vm, COMPILE_OPTIONS},
+};
+
+fn cache_directory(test: &str) -> PathBuf {
+    let directory = env::temp_dir().join(format!("rvm-cache-{test}-{}", process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    directory
+}
+
+#[test]
+fn compiled_programs_are_reused() {
+    let directory = cache_directory("reuse");
+    let cache = CompilationCache::new(&directory);
+    let source = "let f = fn (x) => { x * 2 }; f(21)";
+
+    let program = cache.compile("first.rinha", source).unwrap();
+    let key = program.metadata.cache_key();
+    assert!(cache.path(&key).exists());
+
+    // Plant different bytecode under the same key to tell a cache hit apart
+    // from a recompilation.
+    let mut planted = Vm::new().compile_program("other.rinha", "7").unwrap();
+    planted.metadata = program.metadata.clone();
+    fs::write(cache.path(&key), planted.to_bytes().unwrap()).unwrap();
+
+    let cached = cache.compile("copy.rinha", source).unwrap();
+    assert_eq!(
+        Vm::new().interpret_program(&cached).unwrap(),
+        FinalValue::Integer(7)
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn unusable_artifacts_are_replaced() {
+    let directory = cache_directory("replace");
+    let cache = CompilationCache::new(&directory);
+    let source = "1 + 2";
+
+    let key = Metadata::new("test.rinha", source, COMPILE_OPTIONS).cache_key();
+    fs::create_dir_all(&directory).unwrap();
+    fs::write(cache.path(&key), b"not an artifact").unwrap();
+
+    let program = cache.compile("test.rinha", source).unwrap();
+    assert_eq!(
+        Vm::new().interpret_program(&program).unwrap(),
+        FinalValue::Integer(3)
+    );
+
+    let stored = CompiledProgram::from_bytes(&fs::read(cache.path(&key)).unwrap()).unwrap();
+    assert_eq!(stored.metadata.cache_key(), key);
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn keys_depend_on_source_and_options() {
+    let key =
+        |source: &str, options: &[&str]| Metadata::new("a.rinha", source, options).cache_key();
+
+    assert_eq!(
+        key("1", COMPILE_OPTIONS),
+        Metadata::new("b.rinha", "1", COMPILE_OPTIONS).cache_key()
+    );
+    assert_ne!(key("1", COMPILE_OPTIONS), key("2", COMPILE_OPTIONS));
+    assert_ne!(key("1", COMPILE_OPTIONS), key("1", &["peephole"]));
+}
+
+#[test]
+fn imported_files_are_part_of_the_key() {
+    let directory = cache_directory("imports");
+    let cache = CompilationCache::new(directory.join("cache"));
+    let main = "import \"lib.rinha\";\nvalue";
+
+    let run = |project: &str| {
+        let path = directory.join(project).join("main.rinha");
+        let program = cache.compile(&path.to_string_lossy(), main).unwrap();
+        Vm::new().interpret_program(&program).unwrap()
+    };
+    let write_lib = |project: &str, value: i32| {
+        let project = directory.join(project);
+        fs::create_dir_all(&project).unwrap();
+        fs::write(project.join("lib.rinha"), format!("let value = {value};")).unwrap();
+    };
+
+    write_lib("a", 1);
+    write_lib("b", 2);
+    assert_eq!(run("a"), FinalValue::Integer(1));
+    assert_eq!(run("b"), FinalValue::Integer(2));
+
+    write_lib("a", 3);
+    assert_eq!(run("a"), FinalValue::Integer(3));
+    assert_eq!(run("b"), FinalValue::Integer(2));
+
+    fs::remove_dir_all(directory).unwrap();
+}
@@ -0,0 +1,49 @@
+use std::ffi::{CStr, CString};
+
+use rvm::capi::{rvm_free, rvm_interpret, rvm_last_error, rvm_new, rvm_result_free};
+
+#[test]
+fn programs_run_through_the_c_api() {
+    let source = CString::new(r#"let _ = print("hi"); (1 + 2, "three")"#).unwrap();
+
+    unsafe {
+        let rvm = rvm_new();
+        let result = rvm_interpret(rvm, source.as_ptr());
+
+        assert!(result.ok);
+        assert_eq!(CStr::from_ptr(result.value).to_str(), Ok(r#"[3,"three"]"#));
+        assert_eq!(CStr::from_ptr(result.output).to_str(), Ok("hi\n"));
+        assert_eq!(result.output_length, 3);
+        assert!(rvm_last_error(rvm).is_null());
+
+        rvm_result_free(result);
+        rvm_free(rvm);
+    }
+}
+
+#[test]
+fn errors_are_kept_until_the_next_call() {
+    let failing = CString::new("let _ = print(1); 1 / 0").unwrap();
+    let passing = CString::new("true").unwrap();
+
+    unsafe {
+        let rvm = rvm_new();
+
+        let result = rvm_interpret(rvm, failing.as_ptr());
+        assert!(!result.ok);
+        assert!(result.value.is_null());
+        assert_eq!(CStr::from_ptr(result.output).to_str(), Ok("1\n"));
+        assert_eq!(
+            CStr::from_ptr(rvm_last_error(rvm)).to_str(),
+            Ok("Attempted to divide by zero")
+        );
+        rvm_result_free(result);
+
+        let result = rvm_interpret(rvm, passing.as_ptr());
+        assert!(result.ok);
+        assert!(rvm_last_error(rvm).is_null());
+        rvm_result_free(result);
+
+        rvm_free(rvm);
+    }
+}
@@ -0,0 +1,150 @@
+//! Snapshots of the bytecode representative programs compile to, so changes
+//! to the compiler and the optimizer show up as reviewable diffs in
+//! `tests/snapshots`. After an intended change, rerun with
+//! `INSTA_UPDATE=always` or `cargo insta review` to accept the new output.
+
+use std::fmt::Write;
+
+use insta::assert_snapshot;
+use rvm::{artifact::CompiledProgram, bytecode::Instruction, optimizer::Passes, vm::Vm};
+
+/// Lists the script and then every function, one instruction per line,
+/// with the constants and names instructions refer to spelled out.
+fn disassemble(program: &CompiledProgram) -> String {
+    let mut listing = String::new();
+
+    writeln!(listing, "script:").unwrap();
+    instructions(&mut listing, program, &program.script);
+
+    for (index, function) in program.functions.iter().enumerate() {
+        let name = function.name.as_deref().unwrap_or("<anonymous>");
+        writeln!(
+            listing,
+            "\nfunction {index} {name}({}) captures [{}] locals [{}]:",
+            function.arity,
+            function.captured.join(", "),
+            function.locals.join(", ")
+        )
+        .unwrap();
+        instructions(&mut listing, program, &function.bytecode);
+    }
+
+    listing
+}
+
+fn instructions(listing: &mut String, program: &CompiledProgram, bytecode: &[Instruction]) {
+    for (address, instruction) in bytecode.iter().enumerate() {
+        let operand = match *instruction {
+            Instruction::Constant(index)
+            | Instruction::ConstantLt(index)
+            | Instruction::ConstantEq(index) => {
+                format!("{:?}", program.constants[index as usize])
+            }
+            Instruction::GlobalGet(index)
+            | Instruction::GlobalSet(index)
+            | Instruction::LocalGet(_, index)
+            | Instruction::LocalGetTailCall(_, index, _) => {
+                program.identifiers[index as usize].clone()
+            }
+            Instruction::LocalGetConstantAdd(_, identifier, constant)
+            | Instruction::LocalGetConstantSub(_, identifier, constant) => format!(
+                "{}, {:?}",
+                program.identifiers[identifier as usize], program.constants[constant as usize]
+            ),
+            Instruction::Closure(index) => program.functions[index as usize]
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("function {index}")),
+            _ => String::new(),
+        };
+
+        let instruction = format!("{instruction:?}");
+        if operand.is_empty() {
+            writeln!(listing, "{address:4}  {instruction}").unwrap();
+        } else {
+            writeln!(listing, "{address:4}  {instruction:<24} ; {operand}").unwrap();
+        }
+    }
+}
+
+fn compile(source: &str, level: u8) -> String {
+    let program = Vm::new()
+        .with_passes(Passes::level(level).unwrap())
+        .compile_program("test", source)
+        .unwrap();
+    disassemble(&program)
+}
+
+const FIB: &str = "
+let fib = fn (n) => {
+  if (n < 2) {
+    n
+  } else {
+    fib(n - 1) + fib(n - 2)
+  }
+};
+
+print(fib(10))
+";
+
+#[test]
+fn recursive_calls() {
+    assert_snapshot!(compile(FIB, Passes::MAX_LEVEL));
+}
+
+#[test]
+fn recursive_calls_unoptimized() {
+    assert_snapshot!(compile(FIB, 0));
+}
+
+#[test]
+fn tail_calls() {
+    assert_snapshot!(compile(
+        "
+        let sum = fn (n, total) => {
+          if (n == 0) { total } else { sum(n - 1, total + n) }
+        };
+        print(sum(100, 0))
+        ",
+        Passes::MAX_LEVEL
+    ));
+}
+
+#[test]
+fn closures_and_shadowing() {
+    assert_snapshot!(compile(
+        "
+        let add = fn (x) => { fn (y) => { x + y } };
+        let x = 10;
+        let inner = fn () => {
+          let x = x * 2;
+          add(x)(1)
+        };
+        print(inner())
+        ",
+        Passes::MAX_LEVEL
+    ));
+}
+
+#[test]
+fn tuples_and_strings() {
+    assert_snapshot!(compile(
+        r#"
+        let pair = ("answer: ", 42);
+        let show = fn (p) => { first(p) + second(p) };
+        print(show(pair))
+        "#,
+        Passes::MAX_LEVEL
+    ));
+}
+
+#[test]
+fn constant_conditions() {
+    assert_snapshot!(compile(
+        "
+        let f = fn (n) => { if (1 < 2) { n * 2 } else { n / 0 } };
+        print(f(3 + 4))
+        ",
+        Passes::MAX_LEVEL
+    ));
+}
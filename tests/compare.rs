@@ -0,0 +1,85 @@
+use std::{env, fs, path::PathBuf};
+
+use rvm::compare::{compare, compare_directory, normalize, Outcome, RunOutput};
+
+fn corpus(name: &str, programs: &[(&str, &str)]) -> PathBuf {
+    let directory = env::temp_dir().join(format!("rvm-compare-{name}-{}", std::process::id()));
+    fs::create_dir_all(&directory).unwrap();
+
+    for (file, contents) in programs {
+        fs::write(directory.join(file), contents).unwrap();
+    }
+
+    directory
+}
+
+#[test]
+fn normalization_ignores_trailing_whitespace() {
+    assert_eq!(normalize("1 \r\n2\n\n\n"), vec!["1", "2"]);
+}
+
+#[test]
+fn first_differing_line_is_reported() {
+    let ours = RunOutput {
+        success: true,
+        lines: vec!["1".to_owned(), "2".to_owned()],
+    };
+    let theirs = RunOutput {
+        success: true,
+        lines: vec!["1".to_owned()],
+    };
+
+    assert_eq!(
+        compare(&ours, &theirs),
+        Outcome::Different {
+            line: 1,
+            ours: Some("2".to_owned()),
+            theirs: None,
+        }
+    );
+}
+
+#[test]
+fn failures_on_one_side_are_reported() {
+    let ours = RunOutput {
+        success: false,
+        lines: Vec::new(),
+    };
+    let theirs = RunOutput {
+        success: true,
+        lines: Vec::new(),
+    };
+
+    assert_eq!(
+        compare(&ours, &theirs),
+        Outcome::StatusMismatch {
+            ours: false,
+            theirs: true,
+        }
+    );
+}
+
+#[test]
+fn directory_comparison_runs_every_program() {
+    let directory = corpus(
+        "directory",
+        &[
+            ("a.rinha", "print(1)\n"),
+            ("b.rinha", "print(2)"),
+            ("c.txt", ""),
+        ],
+    );
+
+    let same = compare_directory("cat", "cat", &directory).unwrap();
+    assert_eq!(same.len(), 2);
+    assert!(same.iter().all(|c| c.outcome == Outcome::Same));
+
+    let different = compare_directory("cat", "echo 'print(1)' #", &directory).unwrap();
+    assert_eq!(different[0].outcome, Outcome::Same);
+    assert!(matches!(
+        different[1].outcome,
+        Outcome::Different { line: 0, .. }
+    ));
+
+    fs::remove_dir_all(directory).unwrap();
+}
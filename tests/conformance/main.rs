@@ -0,0 +1,96 @@
+//! Runs the example programs of the official rinha repository, vendored in
+//! `tests/conformance/official`, and checks what each prints and evaluates
+//! to against the `.expected` file next to it. Reports how many conform and
+//! fails unless all of them do.
+//!
+//! `cargo test --test conformance -- --update-expected` rewrites the
+//! `.expected` files with what the VM does now, for reviewing as a diff.
+
+use serde_json::{json, Value};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use rvm::{printing::Captured, vm::Vm};
+
+/// The programs in `tests/conformance/official`.
+fn corpus() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/official");
+    let mut programs: Vec<PathBuf> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "rinha")
+        })
+        .collect();
+    programs.sort();
+    programs
+}
+
+/// What the program printed and the value it evaluated to, or `null` when
+/// it failed.
+fn run(path: &Path) -> Value {
+    let source = fs::read_to_string(path).unwrap();
+
+    let output = Captured::default();
+    let mut vm = Vm::new().with_output(output.clone());
+    let result = vm.interpret(&path.to_string_lossy(), &source);
+
+    json!({
+        "stdout": String::from_utf8(output.take()).unwrap(),
+        "value": result.ok(),
+    })
+}
+
+fn main() -> ExitCode {
+    let update = env::args().any(|argument| argument == "--update-expected");
+
+    let programs = corpus();
+    let mut conforming = 0;
+
+    for program in &programs {
+        let name = program.file_stem().unwrap().to_string_lossy();
+        let expected_path = program.with_extension("expected");
+        let actual = run(program);
+
+        if update {
+            let document = serde_json::to_string_pretty(&actual).unwrap();
+            fs::write(&expected_path, document + "\n").unwrap();
+            println!("updated  {name}");
+            conforming += 1;
+            continue;
+        }
+
+        let expected: Option<Value> = fs::read_to_string(&expected_path)
+            .ok()
+            .and_then(|document| serde_json::from_str(&document).ok());
+
+        match expected {
+            Some(expected) if expected == actual => {
+                println!("ok       {name}");
+                conforming += 1;
+            }
+            Some(expected) => println!("differs  {name}: expected {expected}, got {actual}"),
+            None => println!("missing  {name}: no readable {}", expected_path.display()),
+        }
+    }
+
+    let percentage = if programs.is_empty() {
+        0.0
+    } else {
+        100.0 * conforming as f64 / programs.len() as f64
+    };
+    println!(
+        "\nconformance: {conforming} of {} programs ({percentage:.1}%)",
+        programs.len()
+    );
+
+    if !programs.is_empty() && conforming == programs.len() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
@@ -0,0 +1,171 @@
+use std::{env, fs, io::BufRead, io::Read};
+
+use rvm::dap::DapServer;
+use serde_json::{json, Value};
+
+const PROGRAM: &str = "let make = fn (x) => {
+  fn (y) => { x + y }
+};
+let add = make(10);
+print(add(32))
+";
+
+fn frame(message: Value) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{body}", body.len())
+}
+
+fn read_messages(mut output: &[u8]) -> Vec<Value> {
+    let mut messages = Vec::new();
+
+    loop {
+        let mut header = String::new();
+        if output.read_line(&mut header).unwrap() == 0 {
+            return messages;
+        }
+        let length: usize = header
+            .trim()
+            .strip_prefix("Content-Length: ")
+            .unwrap()
+            .parse()
+            .unwrap();
+        output.read_line(&mut String::new()).unwrap();
+
+        let mut body = vec![0; length];
+        output.read_exact(&mut body).unwrap();
+        messages.push(serde_json::from_slice(&body).unwrap());
+    }
+}
+
+/// Sends `requests` to a server, returning every message it answered with.
+fn session(requests: &[(&str, Value)]) -> Vec<Value> {
+    let input: String = requests
+        .iter()
+        .enumerate()
+        .map(|(seq, (command, arguments))| {
+            frame(json!({
+                "seq": seq + 1,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            }))
+        })
+        .collect();
+
+    let mut output = Vec::new();
+    DapServer::new(&mut output).serve(input.as_bytes()).unwrap();
+    read_messages(&output)
+}
+
+#[test]
+fn a_session_stops_at_breakpoints_and_shows_variables() {
+    let path = env::temp_dir().join(format!("rvm-dap-{}.rinha", std::process::id()));
+    fs::write(&path, PROGRAM).unwrap();
+
+    let requests = [
+        ("initialize", json!({ "adapterID": "rvm" })),
+        ("launch", json!({ "program": path })),
+        ("setBreakpoints", json!({ "breakpoints": [{ "line": 2 }] })),
+        ("configurationDone", json!({})),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("variables", json!({ "variablesReference": 1 })),
+        ("variables", json!({ "variablesReference": 2 })),
+        ("continue", json!({ "threadId": 1 })),
+        ("variables", json!({ "variablesReference": 2 })),
+        ("stepOut", json!({ "threadId": 1 })),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("continue", json!({ "threadId": 1 })),
+        ("disconnect", json!({})),
+    ];
+    let messages = session(&requests);
+    fs::remove_file(&path).unwrap();
+
+    let response = |request_seq: u64| {
+        messages
+            .iter()
+            .find(|m| m["type"] == "response" && m["request_seq"] == request_seq)
+            .unwrap()
+    };
+    let events: Vec<&str> = messages
+        .iter()
+        .filter(|m| m["type"] == "event")
+        .map(|m| m["event"].as_str().unwrap())
+        .collect();
+
+    assert!(messages
+        .iter()
+        .all(|m| m["type"] != "response" || m["success"] == true));
+    assert_eq!(
+        events,
+        [
+            "initialized",
+            "stopped",
+            "stopped",
+            "stopped",
+            "output",
+            "exited",
+            "terminated"
+        ]
+    );
+
+    let frames = &response(5)["body"]["stackFrames"];
+    assert_eq!(frames[0]["name"], "make");
+    assert_eq!(frames[0]["line"], 2);
+    assert_eq!(frames[1]["name"], "<script>");
+    assert_eq!(frames[1]["line"], 4);
+
+    assert_eq!(
+        response(6)["body"]["variables"],
+        json!([{ "name": "x", "value": "10", "variablesReference": 0 }])
+    );
+    assert_eq!(response(7)["body"]["variables"], json!([]));
+    assert_eq!(
+        response(9)["body"]["variables"],
+        json!([{ "name": "x", "value": "10", "variablesReference": 0 }])
+    );
+
+    let frames = &response(11)["body"]["stackFrames"];
+    assert_eq!(frames.as_array().unwrap().len(), 1);
+    assert_eq!(frames[0]["line"], 5);
+
+    let printed = messages.iter().find(|m| m["event"] == "output").unwrap();
+    assert_eq!(printed["body"]["output"], "42\n");
+}
+
+#[test]
+fn steps_go_by_expression_on_request() {
+    let path = env::temp_dir().join(format!("rvm-dap-steps-{}.rinha", std::process::id()));
+    fs::write(&path, PROGRAM).unwrap();
+
+    let statement = json!({ "threadId": 1, "granularity": "statement" });
+    let requests = [
+        ("initialize", json!({ "adapterID": "rvm" })),
+        ("launch", json!({ "program": path })),
+        ("setBreakpoints", json!({ "breakpoints": [{ "line": 4 }] })),
+        ("configurationDone", json!({})),
+        ("stepIn", statement.clone()),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("stepIn", statement.clone()),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("stepIn", statement.clone()),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("next", json!({ "threadId": 1 })),
+        ("stackTrace", json!({ "threadId": 1 })),
+        ("disconnect", json!({})),
+    ];
+    let messages = session(&requests);
+    fs::remove_file(&path).unwrap();
+
+    // Each stack trace as the innermost frame's line and column, and how
+    // many frames there are.
+    let positions: Vec<(u64, u64, usize)> = messages
+        .iter()
+        .filter(|m| m["command"] == "stackTrace")
+        .map(|m| {
+            let frames = m["body"]["stackFrames"].as_array().unwrap();
+            let line = frames[0]["line"].as_u64().unwrap();
+            (line, frames[0]["column"].as_u64().unwrap(), frames.len())
+        })
+        .collect();
+    assert_eq!(positions, [(4, 16, 1), (4, 11, 1), (2, 3, 2), (4, 11, 1)]);
+}
@@ -0,0 +1,112 @@
+use rvm::{
+    debugger::{Breakpoints, Debugger},
+    optimizer::Passes,
+    value::FinalValue,
+    vm::{Execution, Vm},
+};
+
+const PROGRAM: &str = "let make = fn (x) => {
+  fn (y) => x + y
+};
+let add = make(10);
+add(32)
+";
+
+#[test]
+fn breakpoints_pause_at_lines_and_functions() {
+    let mut breakpoints = Breakpoints::new(PROGRAM);
+    breakpoints.add_function("make");
+    breakpoints.add_line(5);
+
+    let mut vm = Vm::new()
+        .with_passes(Passes::level(0).unwrap())
+        .with_breakpoints(breakpoints);
+
+    assert_eq!(vm.start("test", PROGRAM).unwrap(), Execution::Paused);
+    let frames = vm.frames();
+    assert_eq!(frames[0].function, "make");
+    assert_eq!(frames[0].address, 0);
+    assert_eq!(
+        frames[0].slots,
+        vec![(Some("x".to_owned()), FinalValue::Integer(10))]
+    );
+    assert_eq!(frames[1].function, "<script>");
+
+    assert_eq!(vm.resume().unwrap(), Execution::Paused);
+    let frames = vm.frames();
+    assert_eq!(frames.len(), 1);
+    assert_eq!(
+        vm.breakpoints().unwrap().line_of(frames[0].offset.unwrap()),
+        5
+    );
+
+    assert_eq!(
+        vm.resume().unwrap(),
+        Execution::Finished(FinalValue::Integer(42))
+    );
+    assert!(vm.resume().is_err());
+}
+
+#[test]
+fn the_protocol_steps_and_inspects_closures() {
+    let mut debugger = Debugger::new("test", PROGRAM).unwrap();
+    let mut command = |line: &str| {
+        debugger
+            .command(line)
+            .unwrap_or_else(|error| format!("error: {error}"))
+    };
+
+    assert_eq!(command("step"), "error: The program is not paused.");
+    assert_eq!(command("break 2"), "breakpoint at 2");
+    assert_eq!(command("run"), "paused at line 2 in make: LocalGet(0, 0)");
+    assert_eq!(
+        command("continue"),
+        "paused at line 2 in <anonymous>: GlobalGet(0)"
+    );
+    assert_eq!(command("env"), "x = 10");
+    assert_eq!(command("locals"), "0 y = 32");
+    assert_eq!(
+        command("stack"),
+        "#0 line 2 in <anonymous>: GlobalGet(0)\n#1 line 5 in <script>: Call(1)"
+    );
+    assert_eq!(
+        command("step"),
+        "paused at line 2 in <anonymous>: LocalGet(0, 1)"
+    );
+    assert_eq!(command("continue"), "finished: 42");
+}
+
+#[test]
+fn source_steps_show_where_they_stop() {
+    let mut debugger = Debugger::new("test", PROGRAM).unwrap();
+    let mut command = |line: &str| {
+        debugger
+            .command(line)
+            .unwrap_or_else(|error| format!("error: {error}"))
+    };
+
+    assert_eq!(command("break 4"), "breakpoint at 4");
+    assert_eq!(command("run"), "paused at line 4 in <script>: GlobalGet(2)");
+    assert_eq!(
+        command("stepexpr"),
+        "paused at line 4 in <script>: Constant(0)\nlet add = make(10);\n               ^"
+    );
+    assert_eq!(
+        command("stepexpr"),
+        "paused at line 4 in <script>: Call(1)\nlet add = make(10);\n          ^"
+    );
+    assert_eq!(
+        command("stepexpr"),
+        "paused at line 2 in make: LocalGet(0, 0)\n  fn (y) => x + y\n  ^"
+    );
+    // Stepping over the end of a function stops in its caller.
+    assert_eq!(
+        command("nextline"),
+        "paused at line 4 in <script>: GlobalSet(3)\nlet add = make(10);\n          ^"
+    );
+    assert_eq!(
+        command("nextline"),
+        "paused at line 5 in <script>: GlobalGet(3)\nadd(32)\n^"
+    );
+    assert_eq!(command("nextline"), "finished: 42");
+}
@@ -0,0 +1,142 @@
+use rinha::{
+    ast::{
+        Binary, BinaryOp, Bool, Call, First, Function, If, Int, Let, Location, Second, Term, Tuple,
+    },
+    parser::Var,
+};
+use rvm::{
+    bytecode::Instruction,
+    compiler::{CallPosition, Compiler},
+    value::FinalValue,
+    vm::Vm,
+};
+
+const DEPTH: usize = 1_000_000;
+
+/// A tiny xorshift generator, so failures can be reproduced from the seed alone.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+fn int(value: i32) -> Term {
+    Term::Int(Int {
+        value,
+        location: Location::default(),
+    })
+}
+
+fn var(text: &str) -> Var {
+    Var {
+        text: text.to_owned(),
+        location: Location::default(),
+    }
+}
+
+fn add(lhs: Term, rhs: Term) -> Term {
+    Term::Binary(Binary {
+        lhs: Box::new(lhs),
+        op: BinaryOp::Add,
+        rhs: Box::new(rhs),
+        location: Location::default(),
+    })
+}
+
+fn tuple(first: Term, second: Term) -> Term {
+    Term::Tuple(Tuple {
+        first: Box::new(first),
+        second: Box::new(second),
+        location: Location::default(),
+    })
+}
+
+/// Wraps a literal `DEPTH` times in random terms that each add one or leave
+/// the value unchanged, returning the tree and the value it evaluates to.
+/// The tree is built bottom up, so building it does not recurse either.
+/// `let`s only wrap the outermost terms, as a local must sit within the
+/// first 65536 values of its frame.
+fn generate(seed: u64) -> (Term, i32) {
+    let mut rng = Rng::new(seed);
+    let mut term = int(1);
+    let mut expected = 1;
+
+    for depth in 0..DEPTH {
+        term = match rng.below(100) {
+            0..=29 => {
+                expected += 1;
+                add(term, int(1))
+            }
+            30..=59 => {
+                expected += 1;
+                add(int(1), term)
+            }
+            60..=71 => Term::First(First {
+                value: Box::new(tuple(term, int(0))),
+                location: Location::default(),
+            }),
+            72..=83 => Term::Second(Second {
+                value: Box::new(tuple(int(0), term)),
+                location: Location::default(),
+            }),
+            84..=98 => Term::If(If {
+                condition: Box::new(Term::Bool(Bool {
+                    value: true,
+                    location: Location::default(),
+                })),
+                then: Box::new(term),
+                otherwise: Box::new(int(0)),
+                location: Location::default(),
+            }),
+            _ if depth >= DEPTH - 10_000 => Term::Let(Let {
+                name: var("x"),
+                value: Box::new(term),
+                next: Box::new(Term::Var(var("x"))),
+                location: Location::default(),
+            }),
+            _ if rng.below(500) == 0 => Term::Call(Call {
+                callee: Box::new(Term::Function(Function {
+                    parameters: vec![var("unused")],
+                    value: Box::new(term),
+                    location: Location::default(),
+                })),
+                arguments: vec![int(0)],
+                location: Location::default(),
+            }),
+            _ => add(term, int(0)),
+        };
+    }
+
+    (term, expected)
+}
+
+#[test]
+fn deeply_nested_terms_compile_and_run() {
+    for seed in 0..3 {
+        let (term, expected) = generate(seed);
+
+        let mut vm = Vm::new();
+        let mut bytecode = Compiler::new()
+            .compile(term, &mut vm, CallPosition::Unknown)
+            .unwrap_or_else(|error| panic!("seed {seed}: {error}"))
+            .bytecode;
+        bytecode.push(Instruction::Return(0));
+
+        vm.verify(&bytecode)
+            .unwrap_or_else(|error| panic!("seed {seed}: generated invalid bytecode: {error}"));
+        assert_eq!(
+            vm.run_bytecode(&bytecode).unwrap(),
+            FinalValue::Integer(expected),
+            "seed {seed}"
+        );
+    }
+}
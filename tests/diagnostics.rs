@@ -0,0 +1,179 @@
+use rvm::{
+    diagnostics::{render_error, CompileErrors, CompileReport, WarningKind},
+    optimizer::Passes,
+    vm::Vm,
+};
+
+fn check(source: &str) -> CompileReport {
+    let mut vm = Vm::new();
+    vm.compile_program("test.rinha", source).unwrap();
+    vm.compile_report().clone()
+}
+
+fn kinds(report: &CompileReport) -> Vec<(WarningKind, &str)> {
+    report
+        .warnings
+        .iter()
+        .map(|warning| (warning.kind, warning.message.as_str()))
+        .collect()
+}
+
+#[test]
+fn suspicious_code_is_reported_without_failing_to_compile() {
+    let source = r#"
+        let unused = 1;
+        let f = fn (n) => {
+            let x = n;
+            let x = x + 1;
+            let y = 2;
+            g(x, 1)
+        };
+        let g = fn (n) => if (false) { n } else { 0 - n };
+        print(f(1))
+    "#;
+
+    assert_eq!(
+        kinds(&check(source)),
+        [
+            (
+                WarningKind::ShadowedBinding,
+                "Variable x shadows another variable of the same name."
+            ),
+            (WarningKind::UnusedBinding, "Variable y is never used."),
+            (
+                WarningKind::UnreachableBranch,
+                "This branch never runs, since the condition is always false."
+            ),
+            (WarningKind::UnusedBinding, "Variable unused is never used."),
+            (
+                WarningKind::WrongArity,
+                "Function g takes 1 argument but is called with 2."
+            ),
+        ]
+    );
+
+    let report = check(source);
+    let branch = &report.warnings[2].location;
+    assert_eq!(&source[branch.start..branch.end], "n");
+}
+
+#[test]
+fn variables_bound_nowhere_are_reported_before_running() {
+    let source = r#"
+        let f = fn (n) => if (false) { missing } else { g(n) };
+        let g = fn (n) => n + argc;
+        let _ = print(later);
+        let later = 1;
+        f(1)
+    "#;
+
+    let mut vm = Vm::new().with_arguments(&[]);
+    vm.compile_program("test.rinha", source).unwrap();
+    let report = vm.compile_report();
+
+    assert_eq!(
+        kinds(report)
+            .into_iter()
+            .filter(|(kind, _)| *kind == WarningKind::UnboundVariable)
+            .collect::<Vec<_>>(),
+        [
+            (
+                WarningKind::UnboundVariable,
+                "Variable later is not defined."
+            ),
+            (
+                WarningKind::UnboundVariable,
+                "Variable missing is not defined."
+            ),
+        ]
+    );
+}
+
+#[test]
+fn clean_programs_have_no_warnings() {
+    let source = r#"
+        let fib = fn (n) => if (n < 2) { n } else { fib(n - 1) + fib(n - 2) };
+        let _ = print(fib(10));
+        let square = fn (n) => n * n;
+        let apply = fn (f, x) => f(x);
+        apply(square, 3)
+    "#;
+
+    assert!(check(source).is_empty());
+}
+
+#[test]
+fn warnings_render_with_the_source_they_point_at() {
+    let source = "let x = 1;\nprint(2)";
+    let rendered = check(source).render("test.rinha", source, false);
+
+    assert!(rendered.contains("unused_binding"));
+    assert!(rendered.contains("Variable x is never used."));
+    assert!(rendered.contains("let x = 1;"));
+    assert!(rendered.contains("bound here"));
+}
+
+#[test]
+fn runtime_errors_underline_the_failing_term_and_its_callers() {
+    let source = "let div = fn (a, b) => a / b;\nlet f = fn (n) => div(n, 0) + 1;\nf(3)";
+    let error = Vm::new()
+        .with_passes(Passes::level(0).unwrap())
+        .interpret("test.rinha", source)
+        .unwrap_err();
+    let rendered = render_error(&error, "test.rinha", source, false);
+
+    assert!(rendered.contains("Attempted to divide by zero"));
+    // Each term is underlined as wide as it is.
+    for (label, underline) in [
+        ("failed in div", "──┬──"),
+        ("called from f", "────┬────"),
+        ("called from <script>", "──┬─"),
+    ] {
+        assert!(
+            rendered.contains(label),
+            "{label} is missing from {rendered}"
+        );
+        assert!(rendered.contains(&format!(" {underline}\n")));
+    }
+}
+
+#[test]
+fn compile_errors_underline_the_term_they_are_about() {
+    let source = "let f = fn (a) => a;\nf(1, 2)";
+    let error = Vm::new().interpret("test.rinha", source).unwrap_err();
+    let rendered = render_error(&error, "test.rinha", source, false);
+
+    assert!(rendered.contains("Function f takes 1 argument but is called with 2"));
+    assert!(!rendered.contains("test.rinha:21..28"));
+    assert!(rendered.contains("───┬───"));
+}
+
+#[test]
+fn error_recovery_reports_every_error_in_a_program() {
+    let source = "let f = fn (a) => a;\nlet x = (1 + );\nlet y = show(1, 2);\nf(x, y)";
+
+    let error = Vm::new().interpret("test.rinha", source).unwrap_err();
+    assert!(error.downcast_ref::<CompileErrors>().is_none());
+
+    let mut vm = Vm::new().with_error_recovery(true);
+    let error = vm.compile_program("test.rinha", source).unwrap_err();
+    let CompileErrors(errors) = error.downcast_ref().unwrap();
+
+    let messages: Vec<&str> = errors
+        .iter()
+        .map(|error| error.message.lines().next().unwrap())
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "Unrecognized token `)` found at 34:35",
+            "Function show takes 1 argument but is called with 2",
+            "Function f takes 1 argument but is called with 2",
+        ]
+    );
+    assert_eq!(vm.compile_report().errors, *errors);
+    assert_eq!(error.to_string(), "Found 3 errors compiling the program.");
+
+    let rendered = render_error(&error, "test.rinha", source, false);
+    assert_eq!(rendered.matches("× ").count(), 3);
+}
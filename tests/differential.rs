@@ -0,0 +1,155 @@
+use std::io;
+
+use rvm::{
+    ast::File,
+    interp::{Inconclusive, Interpreter},
+    optimizer::Passes,
+    printing::Captured,
+    value::FinalValue,
+    vm::{RuntimeError, Vm},
+};
+
+#[path = "../fuzz/fuzz_targets/generator.rs"]
+mod generator;
+
+/// Enough to get through a few loops, while keeping runs fast.
+const FUEL: u64 = 20_000;
+
+const PROGRAMS: u64 = 3_000;
+
+/// The bytes the generator turns into the program for `seed`, from a
+/// xorshift generator so runs are reproducible.
+fn input(seed: u64) -> Vec<u8> {
+    let mut state = seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1;
+
+    (0..256)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as u8
+        })
+        .collect()
+}
+
+enum Verdict {
+    Agree,
+    /// The VM did not compile the program, or one of the engines gave up on
+    /// it.
+    Skipped,
+}
+
+/// Runs `file` on the VM at every optimization level and on the reference
+/// interpreter, panicking when they disagree.
+fn check(file: &File) -> Verdict {
+    let json = serde_json::to_string(file).unwrap();
+
+    let mut interpreter = Interpreter::new().with_steps(FUEL);
+    let expected = interpreter.run(file);
+    if expected
+        .as_ref()
+        .is_err_and(|error| error.is::<Inconclusive>())
+    {
+        return Verdict::Skipped;
+    }
+    let expected = expected.map(|value| (value, interpreter.output().to_vec()));
+
+    for level in 0..=Passes::MAX_LEVEL {
+        let output = Captured::default();
+        let mut vm = Vm::new()
+            .with_passes(Passes::level(level).unwrap())
+            .with_fuel(FUEL)
+            .with_output(output.clone())
+            .with_reader(io::empty());
+
+        let actual = match vm.interpret_json(&json) {
+            Ok(value) => {
+                let lines = String::from_utf8(output.take()).unwrap();
+                Ok((value, lines.lines().map(str::to_owned).collect()))
+            }
+            Err(error) if !error.is::<RuntimeError>() => return Verdict::Skipped,
+            Err(error) if format!("{error:#}").contains("Out of fuel") => return Verdict::Skipped,
+            Err(error) => Err(error),
+        };
+
+        match (&expected, &actual) {
+            (Ok(expected), Ok(actual)) if expected == actual => {}
+            (Err(_), Err(_)) => {}
+            _ => panic!(
+                "The VM at level {level} disagrees with the interpreter on {json}:\n\
+                 interpreter: {expected:?}\nvm: {actual:?}"
+            ),
+        }
+    }
+
+    Verdict::Agree
+}
+
+fn check_source(source: &str) -> FinalValue {
+    let file = File::parse("test.rinha", source).unwrap();
+    assert!(matches!(check(&file), Verdict::Agree));
+    Interpreter::new().run(&file).unwrap()
+}
+
+#[test]
+fn the_vm_agrees_with_the_interpreter_on_generated_programs() {
+    let mut agreed = 0;
+
+    for seed in 0..PROGRAMS {
+        let input = input(seed);
+        let file = generator::Generator::new(&input).file();
+
+        if let Verdict::Agree = check(&file) {
+            agreed += 1;
+        }
+    }
+
+    // Some programs are not compiled or are given up on, but most must be
+    // compared to mean something.
+    assert!(
+        agreed > PROGRAMS / 2,
+        "Only {agreed} programs were compared."
+    );
+}
+
+#[test]
+fn closures_with_different_captures_are_not_memoized_together() {
+    let result = check_source(
+        "let add = fn (x) => { fn (y) => { x + y } }; let a = add(1); let b = add(2); (a(5), b(5))",
+    );
+    assert_eq!(
+        result,
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(6)),
+            Box::new(FinalValue::Integer(7))
+        )
+    );
+}
+
+#[test]
+fn results_are_memoized_under_the_call_that_returned_them() {
+    let result = check_source(
+        "let g = fn (x, y) => { x * 100 }; let f = fn (n) => { g(n, 0) + 1 }; (f(1), f(1))",
+    );
+    assert_eq!(
+        result,
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(101)),
+            Box::new(FinalValue::Integer(101))
+        )
+    );
+}
+
+#[test]
+fn calls_with_side_effects_are_not_memoized() {
+    let result = check_source(
+        "let noisy = fn (n) => { let _ = print(n); n * 2 }; let f = fn (n) => { noisy(n) + 1 }; (f(1), f(1))",
+    );
+    assert_eq!(
+        result,
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(3)),
+            Box::new(FinalValue::Integer(3))
+        )
+    );
+}
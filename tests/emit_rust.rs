@@ -0,0 +1,140 @@
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{self, Command, Output, Stdio},
+};
+
+use rvm::{emit_rust::emit_rust, vm::Vm};
+
+/// Builds `source` with rustc and runs it with `input`, or returns `None`
+/// when there is no rustc.
+fn build_and_run(name: &str, source: &str, input: &str) -> Option<Output> {
+    let program = Vm::new().compile_program(name, source).unwrap();
+    let rust = emit_rust(&program).unwrap();
+
+    let directory = env::temp_dir().join(format!("rvm-emit-rust-{name}-{}", process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let source_path = directory.join("program.rs");
+    let binary = directory.join("program");
+    fs::write(&source_path, rust).unwrap();
+
+    let compiled = Command::new(env::var("RUSTC").unwrap_or_else(|_| "rustc".to_owned()))
+        .args(["--edition", "2021", "-O", "-D", "warnings", "-o"])
+        .arg(&binary)
+        .arg(&source_path)
+        .output();
+    let compiled = match compiled {
+        Ok(compiled) => compiled,
+        Err(_) => {
+            eprintln!("Skipping, since there is no rustc.");
+            return None;
+        }
+    };
+    assert!(
+        compiled.status.success(),
+        "{}",
+        String::from_utf8_lossy(&compiled.stderr)
+    );
+
+    let mut child = Command::new(&binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    fs::remove_dir_all(&directory).unwrap();
+    Some(output)
+}
+
+fn stress_program(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/stress")
+        .join(name)
+}
+
+#[test]
+fn built_programs_print_what_the_vm_prints() {
+    for name in [
+        "ackermann",
+        "deep_closures",
+        "mutual_recursion",
+        "string_building",
+        "tuple_lists",
+    ] {
+        let source = fs::read_to_string(stress_program(&format!("{name}.rinha"))).unwrap();
+        let expected = fs::read_to_string(stress_program(&format!("{name}.out"))).unwrap();
+
+        let Some(output) = build_and_run(name, &source, "") else {
+            return;
+        };
+        assert!(
+            output.status.success(),
+            "{name} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            expected,
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn built_programs_read_input_and_report_errors() {
+    let source = r#"
+        let n = read_int();
+        let name = read_line();
+        let greet = fn (x) => { print("hello, " + name + " " + x) };
+        let _ = greet(n * 2);
+        let f = fn (x) => { print(show((f, (x, "a")))) };
+        let _ = f(1);
+        let divide = fn (x) => { x / (n - 21) };
+        divide(1)
+    "#;
+
+    let Some(output) = build_and_run("errors", source, " 21 \nworld\r\n") else {
+        return;
+    };
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "hello, world 42\n(<#closure f>, (1, a))\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Error: Attempted to divide by zero\n"
+    );
+}
+
+#[test]
+fn built_programs_order_strings() {
+    let source = r#"
+        let a = read_line();
+        let b = read_line();
+        print((a < b, (a <= b, (a > b, (a >= b, (b < "abd", "é" > b))))));
+        print(a < 1)
+    "#;
+
+    let Some(output) = build_and_run("strings", source, "ab\nabc\n") else {
+        return;
+    };
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "(true, (true, (false, (false, (true, true)))))\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Error: Operands must be both integers or both strings.\n"
+    );
+}
@@ -0,0 +1,206 @@
+use std::{
+    env, fs,
+    io::Write,
+    path::{Path, PathBuf},
+    process::{self, Command, Output, Stdio},
+};
+
+use rvm::{
+    artifact::{CompiledProgram, Constant, Metadata},
+    bytecode::Instruction,
+    emit_wasm::emit_wasm,
+    vm::Vm,
+};
+
+/// Runs the module emitted for `source` under Node.js with `input`, or
+/// returns `None` when Node.js is not installed.
+fn run(name: &str, source: &str, input: &str) -> Option<Output> {
+    let program = Vm::new().compile_program(name, source).unwrap();
+    run_program(name, &program, input)
+}
+
+fn run_program(name: &str, program: &CompiledProgram, input: &str) -> Option<Output> {
+    let wasm = emit_wasm(program).unwrap();
+
+    let directory = env::temp_dir().join(format!("rvm-emit-wasm-{name}-{}", process::id()));
+    fs::create_dir_all(&directory).unwrap();
+    let module = directory.join("program.wasm");
+    fs::write(&module, wasm).unwrap();
+
+    let child = Command::new("node")
+        .arg("--stack-size=65500")
+        .arg(Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/wasm_host.mjs"))
+        .arg(&module)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(_) => {
+            eprintln!("Skipping, since there is no Node.js.");
+            return None;
+        }
+    };
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    fs::remove_dir_all(&directory).unwrap();
+    Some(output)
+}
+
+/// `print((-(0 - 5), !true))` then `-true`, which the rinha grammar cannot
+/// spell.
+fn unary_program() -> CompiledProgram {
+    CompiledProgram {
+        constants: vec![Constant::Integer(-5)],
+        functions: Vec::new(),
+        identifiers: Vec::new(),
+        metadata: Metadata::default(),
+        script: vec![
+            Instruction::Constant(0),
+            Instruction::Neg,
+            Instruction::True,
+            Instruction::Not,
+            Instruction::Tuple,
+            Instruction::Print,
+            Instruction::Pop,
+            Instruction::True,
+            Instruction::Neg,
+            Instruction::Return(0),
+        ],
+    }
+}
+
+fn stress_program(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/stress")
+        .join(name)
+}
+
+#[test]
+fn emitted_modules_print_what_the_vm_prints() {
+    for name in [
+        "ackermann",
+        "deep_closures",
+        "mutual_recursion",
+        "string_building",
+        "tuple_lists",
+    ] {
+        let source = fs::read_to_string(stress_program(&format!("{name}.rinha"))).unwrap();
+        let expected = fs::read_to_string(stress_program(&format!("{name}.out"))).unwrap();
+
+        let Some(output) = run(name, &source, "") else {
+            return;
+        };
+        assert!(
+            output.status.success(),
+            "{name} failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            expected,
+            "{name}"
+        );
+    }
+}
+
+#[test]
+fn emitted_modules_read_input_and_report_errors() {
+    let source = r#"
+        let n = read_int();
+        let name = read_line();
+        let show = fn (x) => { print("hello, " + name + " " + x) };
+        let _ = show(n * 2);
+        let flag = (0 - n, true);
+        let pair = (show, flag);
+        let _ = print(pair);
+        let divide = fn (x) => { x / (n - 21) };
+        divide(1)
+    "#;
+
+    let Some(output) = run("errors", source, " 21 \nworld\r\n") else {
+        return;
+    };
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "hello, world 42\n(<#closure show>, (-21, true))\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Error: Attempted to divide by zero\n"
+    );
+}
+
+#[test]
+fn emitted_modules_negate() {
+    let Some(output) = run_program("unary", &unary_program(), "") else {
+        return;
+    };
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "(5, false)\n");
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Error: Operand must be an integer.\n"
+    );
+}
+
+#[test]
+fn emitted_modules_show_values() {
+    let source = r#"let f = fn (x) => { x }; print(show((f, (1, "a"))) + "!")"#;
+    let Some(output) = run("show", source, "") else {
+        return;
+    };
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "(<#closure f>, (1, a))!\n"
+    );
+}
+
+#[test]
+fn emitted_modules_manipulate_bits() {
+    let source = r#"
+        let x = read_int();
+        print((band(x, 10), (bor(x, 10), bxor(x, 10))));
+        print((shl(x, 28), (shl(1, 33), (shr(0 - x, 2), shr(0 - 1, 31)))))
+    "#;
+    let Some(output) = run("bits", source, "12\n") else {
+        return;
+    };
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "(8, (14, 6))\n(-1073741824, (2, (-3, -1)))\n"
+    );
+}
+
+#[test]
+fn emitted_modules_order_strings() {
+    let source = r#"
+        let a = read_line();
+        let b = read_line();
+        print((a < b, (a <= b, (a > b, (a >= b, (b < "abd", "é" > b))))));
+        print(a < 1)
+    "#;
+
+    let Some(output) = run("strings", source, "ab\nabc\n") else {
+        return;
+    };
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "(true, (true, (false, (false, (true, true)))))\n"
+    );
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "Error: Operands must be both integers or both strings.\n"
+    );
+}
@@ -0,0 +1,85 @@
+use std::{fs, path::Path};
+
+use rvm::{formatter::format_program, printing::Captured, value::FinalValue, vm::Vm};
+
+fn run(source: &str) -> (FinalValue, Vec<u8>) {
+    let output = Captured::default();
+    let result = Vm::new()
+        .with_output(output.clone())
+        .interpret("test.rinha", source)
+        .unwrap();
+    (result, output.take())
+}
+
+#[test]
+fn programs_are_formatted_canonically() {
+    let source = r#"// Adds things up.
+let sum = fn (n, total) => { if (n == 0) { total } else { sum(n - 1, total + n) } }; // tail recursive
+
+
+let pair = ("a\"b", (2));
+/* Parentheses on the left of an operator matter, since operators
+   nest to the right. */
+let f = fn (a, b) => (a - b) - 1
+print(f(first(pair) + "", second(pair) * (1 + 2)))
+let g = fn () => { let x = 1; x + 1 };
+if ((g() == 2) && true) { print(sum(10, 0)) } else { 0 }
+"#;
+
+    let formatted = format_program("test.rinha", source).unwrap();
+    assert_eq!(
+        formatted,
+        r#"// Adds things up.
+let sum = fn (n, total) => {
+  if (n == 0) {
+    total
+  } else {
+    sum(n - 1, total + n)
+  }
+}; // tail recursive
+
+let pair = ("a\"b", 2);
+/* Parentheses on the left of an operator matter, since operators
+   nest to the right. */
+let f = fn (a, b) => {
+  (a - b) - 1
+};
+print(f(first(pair) + "", second(pair) * (1 + 2)));
+let g = fn () => {
+  let x = 1;
+  x + 1
+};
+if ((g() == 2) && true) {
+  print(sum(10, 0))
+} else {
+  0
+}
+"#
+    );
+}
+
+#[test]
+fn formatting_keeps_what_programs_do_and_settles() {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/official");
+
+    let programs = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "rinha")
+        });
+
+    for path in programs {
+        let source = fs::read_to_string(&path).unwrap();
+
+        let formatted = format_program("test.rinha", &source).unwrap();
+        assert_eq!(run(&formatted), run(&source), "{}", path.display());
+        assert_eq!(
+            format_program("test.rinha", &formatted).unwrap(),
+            formatted,
+            "{}",
+            path.display()
+        );
+    }
+}
@@ -0,0 +1,334 @@
+use std::rc::Rc;
+
+use rvm::{
+    bytecode::Instruction,
+    chunk::Chunk,
+    function::Function,
+    gc::{self, Allocation, Heap},
+    native::NativeResult,
+    value::{FinalValue, ShortString, Tagged, Value},
+    vm::{MemoryLimitExceeded, RuntimeError, Vm},
+};
+
+#[test]
+fn unreachable_values_are_freed() {
+    let mut heap = Heap::new(16);
+
+    let first = heap.store(Value::String("first string".into()));
+    let second = heap.store(Value::String("second string".into()));
+    let tuple = heap.store(Value::Tuple(first, second));
+    heap.store(Value::String("third string".into()));
+
+    heap.collect([tuple]);
+
+    let stats = heap.stats();
+    assert_eq!(stats.collections, 1);
+    assert_eq!(stats.freed, 1);
+    assert_eq!(stats.live, 3);
+    assert_eq!(
+        heap.finalize(tuple),
+        FinalValue::Tuple(
+            Box::new(FinalValue::String("first string".to_owned())),
+            Box::new(FinalValue::String("second string".to_owned()))
+        )
+    );
+
+    let reused = heap.store(Value::String("fourth string".into()));
+    assert_eq!(heap.stats().live, 4);
+    assert_eq!(
+        heap.finalize(reused),
+        FinalValue::String("fourth string".to_owned())
+    );
+}
+
+#[test]
+fn list_elements_are_kept_alive() {
+    let mut heap = Heap::new(16);
+
+    let element = heap.store(Value::String("an element".into()));
+    let list = heap.store(Value::List(vec![element, Tagged::Integer(1)]));
+    heap.store(Value::String("garbage".into()));
+
+    heap.collect([list]);
+
+    assert_eq!(heap.stats().freed, 1);
+    assert_eq!(
+        heap.finalize(list),
+        FinalValue::List(vec![
+            FinalValue::String("an element".to_owned()),
+            FinalValue::Integer(1)
+        ])
+    );
+}
+
+#[test]
+fn integers_and_booleans_are_stored_inline() {
+    let mut heap = Heap::default();
+
+    assert_eq!(heap.store(Value::Integer(7)), Tagged::Integer(7));
+    assert_eq!(heap.store(Value::Bool(false)), Tagged::Bool(false));
+    assert_eq!(heap.stats().allocations, 0);
+}
+
+#[test]
+fn collection_keeps_live_values() {
+    let mut vm = Vm::new().with_gc_threshold(1);
+
+    let program = r#"
+        let build = fn (n, acc) => {
+            if (n == 0) { acc } else { build(n - 1, (n, acc)) }
+        };
+        let sum = fn (list) => {
+            if (list == 0) { 0 } else { first(list) + sum(second(list)) }
+        };
+        let adder = fn (x) => { fn (y) => { x + y } };
+        let add = adder(sum(build(50, 0)));
+        add(1)
+    "#;
+
+    let result = vm.interpret("test", program).unwrap();
+    assert_eq!(result, FinalValue::Integer(1276));
+    assert!(vm.gc_stats().collections > 0);
+}
+
+#[test]
+fn long_loops_run_in_bounded_memory() {
+    let mut vm = Vm::new().with_gc_threshold(1_000);
+
+    let program = r#"
+        let loop = fn (n, acc) => {
+            if (n == 0) { acc } else { loop(n - 1, acc + second((acc, n % 7))) }
+        };
+        loop(100000, 0)
+    "#;
+
+    vm.interpret("test", program).unwrap();
+
+    let stats = vm.gc_stats();
+    assert!(stats.collections > 0);
+    assert!(stats.live < 2_000, "{stats:?}");
+}
+
+#[test]
+fn arenas_free_a_run_at_once() {
+    let mut vm = Vm::new()
+        .with_gc_threshold(1)
+        .with_allocation(Allocation::Arena);
+    vm.register_suspendable_native("id", 1, |arguments| {
+        Ok(NativeResult::Ready(arguments[0].clone()))
+    });
+
+    let program = r#"
+        let loop = fn (n, acc) => {
+            if (n == 0) { acc } else { loop(n - 1, acc + second((acc, n % 7))) }
+        };
+        id(loop(1000, 0))
+    "#;
+
+    for _ in 0..2 {
+        assert_eq!(
+            vm.interpret("test", program).unwrap(),
+            FinalValue::Integer(3003)
+        );
+
+        let stats = vm.gc_stats();
+        assert_eq!(stats.collections, 0);
+        assert_eq!(stats.live, 1);
+        assert!(vm.globals().is_empty());
+    }
+    // Each run allocates a thousand tuples and the closure of `loop`.
+    assert_eq!(vm.gc_stats().freed, 2002);
+}
+
+#[test]
+fn program_strings_are_interned() {
+    let mut vm = Vm::new();
+
+    let program = r#"
+        let greet = fn (name) => { "hello " + name };
+        let same = "hello" == "hello";
+        (same, greet("world"))
+    "#;
+
+    let result = vm.interpret("test", program).unwrap();
+    assert_eq!(
+        result,
+        FinalValue::Tuple(
+            Box::new(FinalValue::Bool(true)),
+            Box::new(FinalValue::String("hello world".to_owned()))
+        )
+    );
+    // The closure, the concatenation and the tuple are allocated; the
+    // literals are loaded as symbols.
+    assert_eq!(vm.gc_stats().allocations, 3);
+}
+
+#[test]
+fn interned_and_allocated_strings_compare_by_contents() {
+    let mut vm = Vm::new();
+    let program = r#"
+        let append = fn (x) => { x + "b" };
+        "ab" == append("a")
+    "#;
+    let result = vm.interpret("test", program).unwrap();
+    assert_eq!(result, FinalValue::Bool(true));
+}
+
+#[test]
+fn short_strings_are_stored_inline() {
+    let mut heap = Heap::default();
+
+    let short = heap.store(Value::String("token".into()));
+    assert_eq!(short, Tagged::Short(ShortString::new("token").unwrap()));
+    assert_eq!(heap.text(&short), Some("token"));
+    assert_eq!(heap.stats().allocations, 0);
+
+    assert!(ShortString::new("sixsix").is_some());
+    assert!(ShortString::new("seven!!").is_none());
+    assert_eq!(ShortString::new("ção").unwrap().as_str(), "ção");
+
+    let long = heap.store_str("a longer string");
+    assert!(matches!(long, Tagged::Object(_)));
+    assert_eq!(heap.stats().allocations, 1);
+}
+
+#[test]
+fn short_concatenations_do_not_allocate() {
+    let mut vm = Vm::new();
+    let program = r#"
+        let join = fn (a, b) => { a + b };
+        (join("ab", "cd") == "abcd", join("x", 1))
+    "#;
+
+    let result = vm.interpret("test", program).unwrap();
+    assert_eq!(
+        result,
+        FinalValue::Tuple(
+            Box::new(FinalValue::Bool(true)),
+            Box::new(FinalValue::String("x1".to_owned()))
+        )
+    );
+    // Only the closure and the tuple live in the heap.
+    assert_eq!(vm.gc_stats().allocations, 2);
+}
+
+#[test]
+fn closures_display_their_names() {
+    let mut heap = Heap::default();
+
+    let mut function =
+        Function::script(Chunk::new(vec![Instruction::True, Instruction::Return(0)]));
+    let anonymous = heap.store(Value::Closure(Rc::new(function.clone()), Rc::from([])));
+    function.name = Some("fib".to_owned());
+    let named = heap.store(Value::Closure(Rc::new(function), Rc::from([])));
+
+    assert_eq!(heap.display(anonymous).to_string(), "<#closure>");
+    assert_eq!(heap.display(named).to_string(), "<#closure fib>");
+}
+
+#[test]
+fn lists_and_records_display_their_contents() {
+    let mut heap = Heap::default();
+
+    let list = heap.store(Value::List(vec![Tagged::Integer(1), Tagged::Bool(true)]));
+    let name = heap.store_str("rinha");
+    let record = heap.store(Value::Record(
+        [("name".into(), name), ("list".into(), list)].into(),
+    ));
+
+    assert_eq!(heap.display(list).to_string(), "[1, true]");
+    assert_eq!(
+        heap.display(record).to_string(),
+        "{list: [1, true], name: rinha}"
+    );
+}
+
+#[test]
+fn call_site_caches_do_not_outlive_collections() {
+    let mut vm = Vm::new().with_gc_threshold(100);
+
+    // Every iteration calls a fresh closure from the same call site,
+    // alternating between two functions, while collections free the old
+    // ones for their handles to be reused. The tuple keeps `make` from being
+    // memoized, which would keep every closure alive.
+    let program = r#"
+        let make = fn (pair) => {
+            let n = first(pair);
+            if (n % 2 == 0) { fn (y) => { y + n } } else { fn (y) => { y - n } }
+        };
+        let loop = fn (n, acc) => {
+            if (n == 0) { acc } else {
+                let f = make((n, n));
+                loop(n - 1, f(acc))
+            }
+        };
+        loop(2000, 0)
+    "#;
+
+    let result = vm.interpret("test", program).unwrap();
+    assert_eq!(result, FinalValue::Integer(1000));
+    assert!(vm.gc_stats().collections > 0);
+}
+
+#[test]
+fn the_heap_tracks_the_bytes_its_values_take() {
+    let mut heap = Heap::new(16);
+
+    let string = Value::String("a string that is not short".into());
+    let size = gc::size(&string);
+    assert!(size > "a string that is not short".len());
+
+    let kept = heap.store(string);
+    heap.store(Value::String("garbage that is not short".into()));
+    assert!(heap.stats().bytes > size);
+
+    heap.collect([kept]);
+    assert_eq!(heap.stats().bytes, size);
+
+    heap.collect([]);
+    assert_eq!(heap.stats().bytes, 0);
+}
+
+#[test]
+fn programs_keeping_too_much_alive_are_stopped() {
+    let program = r#"
+        let grow = fn (n, s) => { if (n == 0) { s } else { grow(n - 1, s + "0123456789") } };
+        let s = grow(2000, "");
+        0
+    "#;
+
+    let error = Vm::new()
+        .with_memory_limit(10_000)
+        .interpret("test", program)
+        .unwrap_err();
+    let error = &error.downcast_ref::<RuntimeError>().unwrap().error;
+    let exceeded = error.downcast_ref::<MemoryLimitExceeded>().unwrap();
+    assert_eq!(exceeded.limit, 10_000);
+    assert!(exceeded.used > 10_000);
+
+    // Every intermediate string is garbage once the next one is built, so
+    // only the last one counts.
+    assert_eq!(
+        Vm::new()
+            .with_memory_limit(100_000)
+            .interpret("test", program)
+            .unwrap(),
+        FinalValue::Integer(0)
+    );
+}
+
+#[test]
+fn the_memoization_table_is_emptied_before_giving_up() {
+    // Each `depth(n)` is memoized, which takes more than the limit.
+    let program = "
+        let depth = fn (n) => { if (n == 0) { 0 } else { 1 + depth(n - 1) } };
+        depth(2000)
+    ";
+
+    let mut vm = Vm::new().with_memory_limit(10_000);
+    assert_eq!(
+        vm.interpret("test", program).unwrap(),
+        FinalValue::Integer(2000)
+    );
+    assert!(vm.memory_used() <= 10_000);
+}
@@ -0,0 +1,145 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+use rvm::{imports::imported_files, printing::Captured, value::FinalValue, vm::Vm};
+
+/// Writes `files` into a fresh directory, returning its path.
+fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let directory = env::temp_dir().join(format!("rvm-imports-{name}-{}", process::id()));
+    for (path, contents) in files {
+        let path = directory.join(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, contents).unwrap();
+    }
+    directory
+}
+
+fn interpret(path: &Path) -> (anyhow::Result<FinalValue>, String) {
+    let output = Captured::default();
+    let mut vm = Vm::new().with_output(output.clone());
+    let source = fs::read_to_string(path).unwrap();
+    let result = vm.interpret(&path.to_string_lossy(), &source);
+    let printed = String::from_utf8(output.take()).unwrap();
+    (result, printed)
+}
+
+#[test]
+fn imported_lets_run_before_the_program() {
+    let directory = project(
+        "lets",
+        &[
+            (
+                "main.rinha",
+                "import \"lib/math.rinha\";\nimport \"lib/util.rinha\";\nprint(twice(square, 3));\ncube(2)",
+            ),
+            (
+                "lib/math.rinha",
+                "import \"util.rinha\";\nlet square = fn (x) => { x * x };\nlet cube = fn (x) => { x * square(x) };\n",
+            ),
+            (
+                "lib/util.rinha",
+                "let twice = fn (f, x) => { f(f(x)) };\nprint(\"util\");\n0",
+            ),
+        ],
+    );
+
+    let (result, printed) = interpret(&directory.join("main.rinha"));
+    assert_eq!(result.unwrap(), FinalValue::Integer(8));
+    // util.rinha runs once, though two files import it.
+    assert_eq!(printed, "util\n81\n");
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn import_cycles_are_reported() {
+    let directory = project(
+        "cycle",
+        &[
+            ("main.rinha", "import \"a.rinha\";\n1"),
+            ("a.rinha", "import \"b.rinha\";\nlet a = 1;"),
+            ("b.rinha", "import \"a.rinha\";\nlet b = 2;"),
+        ],
+    );
+
+    let (result, _) = interpret(&directory.join("main.rinha"));
+    let canonical = fs::canonicalize(&directory).unwrap();
+    let a = canonical.join("a.rinha");
+    let b = canonical.join("b.rinha");
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        format!(
+            "Import cycle: {} → {} → {}.",
+            a.display(),
+            b.display(),
+            a.display()
+        )
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn errors_name_the_imported_file() {
+    let directory = project(
+        "errors",
+        &[
+            (
+                "main.rinha",
+                "import \"broken.rinha\";\nimport \"arity.rinha\";\n1",
+            ),
+            ("broken.rinha", "let broken = fn (x) => { x + };"),
+            ("arity.rinha", "let f = fn (x) => { x };\nlet y = f(1, 2);"),
+        ],
+    );
+    let main = directory.join("main.rinha");
+
+    let (result, _) = interpret(&main);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        format!(
+            "Could not parse {}, imported at {}:0..21.",
+            directory.join("broken.rinha").display(),
+            main.display()
+        )
+    );
+
+    fs::write(&main, "import \"arity.rinha\";\n1").unwrap();
+    let (result, _) = interpret(&main);
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        format!(
+            "Function f takes 1 argument but is called with 2 at {}:33..40.",
+            directory.join("arity.rinha").display()
+        )
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
+
+#[test]
+fn imported_files_are_listed_in_the_order_they_are_found() {
+    let main = "import \"b.rinha\";\nimport \"a.rinha\";\n1";
+    let directory = project(
+        "order",
+        &[
+            ("main.rinha", main),
+            ("b.rinha", "import \"c.rinha\";\nlet b = 2;"),
+            ("a.rinha", "import \"b.rinha\";\nlet a = 1;"),
+            ("c.rinha", "let c = 3;"),
+        ],
+    );
+
+    let files = imported_files(&directory.join("main.rinha").to_string_lossy(), main).unwrap();
+    let canonical = fs::canonicalize(&directory).unwrap();
+    let paths: Vec<PathBuf> = files.into_iter().map(|(path, _)| path).collect();
+    assert_eq!(
+        paths,
+        ["b.rinha", "a.rinha", "c.rinha"].map(|file| canonical.join(file))
+    );
+
+    fs::remove_dir_all(directory).unwrap();
+}
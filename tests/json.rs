@@ -0,0 +1,241 @@
+use rvm::{ast, value::FinalValue, vm::Vm};
+
+fn interpret_fixture(name: &str) -> FinalValue {
+    let path = format!("{}/tests/{name}", env!("CARGO_MANIFEST_DIR"));
+    let json = std::fs::read_to_string(path).unwrap();
+    Vm::new().interpret_json(&json).unwrap()
+}
+
+#[test]
+fn runs_spec_json_asts() {
+    assert_eq!(interpret_fixture("fib.json"), FinalValue::Integer(55));
+    assert_eq!(interpret_fixture("sum.json"), FinalValue::Integer(15));
+    assert_eq!(
+        interpret_fixture("combination.json"),
+        FinalValue::Integer(45)
+    );
+}
+
+#[test]
+fn parses_every_term_kind() {
+    let json = r#"{
+        "name": "all.rinha",
+        "expression": {
+            "kind": "Let",
+            "name": { "text": "pair", "location": { "start": 0, "end": 0, "filename": "all.rinha" } },
+            "value": {
+                "kind": "Function",
+                "parameters": [
+                    { "text": "x", "location": { "start": 0, "end": 0, "filename": "all.rinha" } }
+                ],
+                "value": {
+                    "kind": "Tuple",
+                    "first": {
+                        "kind": "Binary",
+                        "lhs": { "kind": "Var", "text": "x", "location": { "start": 0, "end": 0, "filename": "all.rinha" } },
+                        "op": "Mul",
+                        "rhs": { "kind": "Int", "value": 2, "location": { "start": 0, "end": 0, "filename": "all.rinha" } },
+                        "location": { "start": 0, "end": 0, "filename": "all.rinha" }
+                    },
+                    "second": { "kind": "Str", "value": "two", "location": { "start": 0, "end": 0, "filename": "all.rinha" } },
+                    "location": { "start": 0, "end": 0, "filename": "all.rinha" }
+                },
+                "location": { "start": 0, "end": 0, "filename": "all.rinha" }
+            },
+            "next": {
+                "kind": "If",
+                "condition": { "kind": "Bool", "value": true, "location": { "start": 0, "end": 0, "filename": "all.rinha" } },
+                "then": {
+                    "kind": "Print",
+                    "value": {
+                        "kind": "First",
+                        "value": {
+                            "kind": "Call",
+                            "callee": { "kind": "Var", "text": "pair", "location": { "start": 0, "end": 0, "filename": "all.rinha" } },
+                            "arguments": [
+                                { "kind": "Int", "value": 21, "location": { "start": 0, "end": 0, "filename": "all.rinha" } }
+                            ],
+                            "location": { "start": 0, "end": 0, "filename": "all.rinha" }
+                        },
+                        "location": { "start": 0, "end": 0, "filename": "all.rinha" }
+                    },
+                    "location": { "start": 0, "end": 0, "filename": "all.rinha" }
+                },
+                "otherwise": {
+                    "kind": "Second",
+                    "value": { "kind": "Var", "text": "pair", "location": { "start": 0, "end": 0, "filename": "all.rinha" } },
+                    "location": { "start": 0, "end": 0, "filename": "all.rinha" }
+                },
+                "location": { "start": 0, "end": 0, "filename": "all.rinha" }
+            },
+            "location": { "start": 0, "end": 0, "filename": "all.rinha" }
+        },
+        "location": { "start": 0, "end": 0, "filename": "all.rinha" }
+    }"#;
+
+    let file: ast::File = serde_json::from_str(json).unwrap();
+    assert_eq!(file.name, "all.rinha");
+    assert!(matches!(file.expression, ast::Term::Let { .. }));
+
+    assert_eq!(
+        Vm::new().interpret_json(json).unwrap(),
+        FinalValue::Integer(42)
+    );
+}
+
+#[test]
+fn rejects_unknown_term_kinds() {
+    let json = r#"{
+        "name": "bad.rinha",
+        "expression": { "kind": "Loop", "location": { "start": 0, "end": 0, "filename": "bad.rinha" } },
+        "location": { "start": 0, "end": 0, "filename": "bad.rinha" }
+    }"#;
+
+    assert!(Vm::new().interpret_json(json).is_err());
+}
+
+#[test]
+fn parsed_sources_round_trip_through_json() {
+    let source = r#"
+        let fib = fn (n) => { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+        let pair = (first((fib(10), "x")), second((true, "done")));
+        print(pair)
+    "#;
+
+    let file = ast::File::parse("fib.rinha", source).unwrap();
+    let json = serde_json::to_string_pretty(&file).unwrap();
+
+    let parsed: ast::File = serde_json::from_str(&json).unwrap();
+    assert_eq!(parsed, file);
+
+    assert_eq!(
+        Vm::new().interpret_json(&json).unwrap(),
+        Vm::new().interpret("fib.rinha", source).unwrap()
+    );
+}
+
+#[test]
+fn sequences_round_trip_through_json() {
+    let source = "print(1); let x = 2; print(x); x + 1";
+
+    let file = ast::File::parse("sequence.rinha", source).unwrap();
+    assert!(matches!(file.expression, ast::Term::Sequence { .. }));
+
+    let json = serde_json::to_string(&file).unwrap();
+    assert_eq!(serde_json::from_str::<ast::File>(&json).unwrap(), file);
+    assert_eq!(
+        Vm::new().interpret_json(&json).unwrap(),
+        Vm::new().interpret("sequence.rinha", source).unwrap()
+    );
+}
+
+#[test]
+fn ifs_may_leave_out_otherwise() {
+    let location = r#""location": { "start": 0, "end": 0, "filename": "if.rinha" }"#;
+    let branch = |condition: bool| {
+        format!(
+            r#"{{ "kind": "If",
+                "condition": {{ "kind": "Bool", "value": {condition}, {location} }},
+                "then": {{ "kind": "Int", "value": 1, {location} }},
+                {location} }}"#
+        )
+    };
+    let json = format!(
+        r#"{{ "name": "if.rinha", "expression": {{ "kind": "Tuple",
+            "first": {}, "second": {}, {location} }}, {location} }}"#,
+        branch(true),
+        branch(false)
+    );
+
+    assert_eq!(
+        Vm::new().interpret_json(&json).unwrap(),
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(1)),
+            Box::new(FinalValue::Bool(false))
+        )
+    );
+}
+
+#[test]
+fn syntax_errors_are_not_emitted() {
+    assert!(ast::File::parse("bad.rinha", "let x = ;").is_err());
+}
+
+#[test]
+fn runs_unary_terms() {
+    let unary = |op: &str, value: &str| {
+        format!(
+            r#"{{ "kind": "Unary", "op": "{op}", "value": {value}, "location": {{ "start": 0, "end": 0, "filename": "unary.rinha" }} }}"#
+        )
+    };
+    let int = r#"{ "kind": "Int", "value": 7, "location": { "start": 0, "end": 0, "filename": "unary.rinha" } }"#;
+    let bool = r#"{ "kind": "Bool", "value": false, "location": { "start": 0, "end": 0, "filename": "unary.rinha" } }"#;
+    let program = |expression: &str| {
+        format!(
+            r#"{{ "name": "unary.rinha", "expression": {expression}, "location": {{ "start": 0, "end": 0, "filename": "unary.rinha" }} }}"#
+        )
+    };
+
+    assert_eq!(
+        Vm::new()
+            .interpret_json(&program(&unary("Neg", &unary("Neg", int))))
+            .unwrap(),
+        FinalValue::Integer(7)
+    );
+    assert_eq!(
+        Vm::new()
+            .interpret_json(&program(&unary("Neg", int)))
+            .unwrap(),
+        FinalValue::Integer(-7)
+    );
+    assert_eq!(
+        Vm::new()
+            .interpret_json(&program(&unary("Not", bool)))
+            .unwrap(),
+        FinalValue::Bool(true)
+    );
+    assert_eq!(
+        Vm::new()
+            .interpret_json(&program(&unary("Not", int)))
+            .unwrap_err()
+            .to_string(),
+        "Operand must be a boolean."
+    );
+    assert_eq!(
+        Vm::new()
+            .interpret_json(&program(&unary("Neg", bool)))
+            .unwrap_err()
+            .to_string(),
+        "Operand must be an integer."
+    );
+}
+
+#[test]
+fn unary_terms_round_trip_through_the_parser_ast() {
+    let json = r#"{
+        "kind": "Unary",
+        "op": "Not",
+        "value": { "kind": "Var", "text": "x", "location": { "start": 1, "end": 2, "filename": "unary.rinha" } },
+        "location": { "start": 0, "end": 2, "filename": "unary.rinha" }
+    }"#;
+
+    let term: ast::Term = serde_json::from_str(json).unwrap();
+    let parsed = rinha::ast::Term::from(term.clone());
+    assert_eq!(ast::Term::try_from(parsed).unwrap(), term);
+}
+
+#[test]
+fn big_integers_are_strings_beyond_what_json_numbers_hold() {
+    let json = |digits: &str| {
+        serde_json::to_string(&FinalValue::BigInteger(digits.parse().unwrap())).unwrap()
+    };
+
+    assert_eq!(json("9007199254740991"), "9007199254740991");
+    assert_eq!(json("-9007199254740991"), "-9007199254740991");
+    assert_eq!(json("9007199254740992"), "\"9007199254740992\"");
+    assert_eq!(json("-9007199254740992"), "\"-9007199254740992\"");
+    assert_eq!(
+        json("15511210043330985984000000"),
+        "\"15511210043330985984000000\""
+    );
+}
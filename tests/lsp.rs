@@ -0,0 +1,159 @@
+use std::io::{BufRead, Read};
+
+use rvm::lsp::LspServer;
+use serde_json::{json, Value};
+
+const URI: &str = "file:///work/main%20file.rinha";
+
+const PROGRAM: &str = "let double = fn (n) => {
+  n * 2
+};
+let x = double(1, 2);
+double(x)
+";
+
+fn frame(message: Value) -> String {
+    let body = message.to_string();
+    format!("Content-Length: {}\r\n\r\n{body}", body.len())
+}
+
+fn read_messages(mut output: &[u8]) -> Vec<Value> {
+    let mut messages = Vec::new();
+
+    loop {
+        let mut header = String::new();
+        if output.read_line(&mut header).unwrap() == 0 {
+            return messages;
+        }
+        let length: usize = header
+            .trim()
+            .strip_prefix("Content-Length: ")
+            .unwrap()
+            .parse()
+            .unwrap();
+        output.read_line(&mut String::new()).unwrap();
+
+        let mut body = vec![0; length];
+        output.read_exact(&mut body).unwrap();
+        messages.push(serde_json::from_slice(&body).unwrap());
+    }
+}
+
+fn at(line: u64, character: u64) -> Value {
+    json!({ "textDocument": { "uri": URI }, "position": { "line": line, "character": character } })
+}
+
+fn range(start: (u64, u64), end: (u64, u64)) -> Value {
+    json!({
+        "start": { "line": start.0, "character": start.1 },
+        "end": { "line": end.0, "character": end.1 },
+    })
+}
+
+#[test]
+fn a_session_reports_diagnostics_hovers_definitions_and_symbols() {
+    let document = json!({ "uri": URI });
+    let messages = [
+        (Some(1), "initialize", json!({ "capabilities": {} })),
+        (None, "initialized", json!({})),
+        (
+            None,
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": URI, "languageId": "rinha", "version": 1, "text": PROGRAM } }),
+        ),
+        (Some(2), "textDocument/hover", at(3, 10)),
+        (Some(3), "textDocument/definition", at(4, 7)),
+        (
+            Some(4),
+            "textDocument/documentSymbol",
+            json!({ "textDocument": document }),
+        ),
+        (Some(5), "textDocument/hover", at(1, 2)),
+        (
+            None,
+            "textDocument/didChange",
+            json!({
+                "textDocument": { "uri": URI, "version": 2 },
+                "contentChanges": [{ "text": PROGRAM.replace("(1, 2)", "(1)") }],
+            }),
+        ),
+        (
+            Some(6),
+            "textDocument/formatting",
+            json!({ "textDocument": document }),
+        ),
+        (Some(7), "shutdown", Value::Null),
+        (None, "exit", Value::Null),
+    ];
+    let input: String = messages
+        .iter()
+        .map(|(id, method, params)| {
+            let mut message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+            if let Some(id) = id {
+                message["id"] = json!(id);
+            }
+            frame(message)
+        })
+        .collect();
+
+    let mut output = Vec::new();
+    LspServer::new(&mut output).serve(input.as_bytes()).unwrap();
+
+    let messages = read_messages(&output);
+    let response = |id: u64| messages.iter().find(|m| m["id"] == id).unwrap();
+    let diagnostics: Vec<&Value> = messages
+        .iter()
+        .filter(|m| m["method"] == "textDocument/publishDiagnostics")
+        .map(|m| &m["params"]["diagnostics"])
+        .collect();
+
+    assert_eq!(response(1)["result"]["capabilities"]["hoverProvider"], true);
+    assert_eq!(
+        diagnostics,
+        [
+            &json!([{
+                "range": range((3, 8), (3, 20)),
+                "severity": 1,
+                "source": "rvm",
+                "message": "Function double takes 1 argument but is called with 2",
+            }]),
+            &json!([]),
+        ]
+    );
+
+    assert_eq!(
+        response(2)["result"],
+        json!({
+            "contents": { "kind": "plaintext", "value": "double: fn (n)" },
+            "range": range((3, 8), (3, 14)),
+        })
+    );
+    assert_eq!(
+        response(3)["result"],
+        json!({ "uri": URI, "range": range((3, 4), (3, 5)) })
+    );
+    assert_eq!(
+        response(4)["result"],
+        json!([
+            {
+                "name": "double",
+                "kind": 12,
+                "detail": "fn (n)",
+                "range": range((0, 0), (2, 1)),
+                "selectionRange": range((0, 4), (0, 10)),
+            },
+            {
+                "name": "x",
+                "kind": 13,
+                "range": range((3, 0), (3, 20)),
+                "selectionRange": range((3, 4), (3, 5)),
+            },
+        ])
+    );
+    assert_eq!(
+        response(5)["result"]["contents"]["value"],
+        "n: parameter of double"
+    );
+    assert_eq!(response(6)["error"]["code"], -32601);
+    assert_eq!(response(7)["result"], Value::Null);
+}
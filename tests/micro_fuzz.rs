@@ -0,0 +1,416 @@
+use std::{
+    panic::{catch_unwind, AssertUnwindSafe},
+    rc::Rc,
+};
+
+use rvm::{
+    bytecode::Instruction,
+    chunk::Chunk,
+    function::{Function, Local},
+    value::{FinalValue, Value},
+    vm::Vm,
+};
+
+const FUEL: u64 = 10_000;
+const IDENTIFIERS: [&str; 3] = ["a", "b", "c"];
+
+/// A tiny xorshift generator, so failures can be reproduced from the seed alone.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        Self(seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) | 1)
+    }
+
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+struct Generator {
+    rng: Rng,
+    constants: u32,
+    arities: Vec<u16>,
+}
+
+impl Generator {
+    fn expression(&mut self, depth: usize, locals: u16, bytecode: &mut Vec<Instruction>) {
+        if depth == 0 {
+            return self.leaf(locals, bytecode);
+        }
+
+        match self.rng.below(9) {
+            0 | 1 => self.leaf(locals, bytecode),
+            2 => {
+                self.expression(depth - 1, locals, bytecode);
+                self.expression(depth - 1, locals, bytecode);
+                let operators = [
+                    Instruction::Add,
+                    Instruction::Sub,
+                    Instruction::Mul,
+                    Instruction::Div,
+                    Instruction::Rem,
+                    Instruction::Eq,
+                    Instruction::Neq,
+                    Instruction::Gt,
+                    Instruction::Lt,
+                    Instruction::Gte,
+                    Instruction::Lte,
+                    Instruction::And,
+                    Instruction::Or,
+                    Instruction::Tuple,
+                ];
+                let operator = self.rng.below(operators.len() as u64) as usize;
+                bytecode.push(operators[operator].clone());
+            }
+            3 => {
+                self.expression(depth - 1, locals, bytecode);
+                let accessor = match self.rng.below(3) {
+                    0 => Instruction::First,
+                    1 => Instruction::Second,
+                    _ => Instruction::Print,
+                };
+                bytecode.push(accessor);
+            }
+            4 => {
+                self.expression(depth - 1, locals, bytecode);
+                bytecode.push(Instruction::If(0));
+                let if_address = bytecode.len() - 1;
+
+                self.expression(depth - 1, locals, bytecode);
+                bytecode.push(Instruction::Jump(0));
+                let jump_address = bytecode.len() - 1;
+                bytecode[if_address] = Instruction::If((jump_address - if_address) as u32);
+
+                self.expression(depth - 1, locals, bytecode);
+                let after_address = bytecode.len() - 1;
+                bytecode[jump_address] = Instruction::Jump((after_address - jump_address) as u32);
+            }
+            5 => {
+                self.expression(depth - 1, locals, bytecode);
+                let identifier = self.rng.below(IDENTIFIERS.len() as u64) as u32;
+                bytecode.push(Instruction::GlobalSet(identifier));
+                self.expression(depth - 1, locals, bytecode);
+            }
+            6 if !self.arities.is_empty() => {
+                let function = self.rng.below(self.arities.len() as u64) as usize;
+                bytecode.push(Instruction::Closure(function as u32));
+
+                let arity = self.arities[function];
+                for _ in 0..arity {
+                    self.expression(depth - 1, locals, bytecode);
+                }
+
+                bytecode.push(Instruction::Call(arity));
+            }
+            7 => {
+                // Deep tuples and long branches that push jump offsets far apart.
+                self.leaf(locals, bytecode);
+                for _ in 0..self.rng.below(1_000) {
+                    self.leaf(locals, bytecode);
+                    bytecode.push(Instruction::Tuple);
+                }
+            }
+            _ => {
+                self.expression(depth - 1, locals, bytecode);
+                bytecode.push(Instruction::Constant(0));
+                bytecode.push(Instruction::Eq);
+            }
+        }
+    }
+
+    /// Generates an expression in tail position, which may end in a tail call.
+    fn body(&mut self, depth: usize, locals: u16, bytecode: &mut Vec<Instruction>) {
+        if self.arities.is_empty() || self.rng.below(3) != 0 {
+            return self.expression(depth, locals, bytecode);
+        }
+
+        let function = self.rng.below(self.arities.len() as u64) as usize;
+        bytecode.push(Instruction::Closure(function as u32));
+
+        let arity = self.arities[function];
+        for _ in 0..arity {
+            self.expression(depth.saturating_sub(1), locals, bytecode);
+        }
+
+        bytecode.push(Instruction::TailCall(arity));
+    }
+
+    fn leaf(&mut self, locals: u16, bytecode: &mut Vec<Instruction>) {
+        let instruction = match self.rng.below(6) {
+            0 => Instruction::True,
+            1 => Instruction::False,
+            2 => Instruction::GlobalGet(self.rng.below(IDENTIFIERS.len() as u64) as u32),
+            3 if locals > 0 => Instruction::LocalGet(self.rng.below(locals as u64) as u16, 0),
+            4 if !self.arities.is_empty() => {
+                Instruction::Closure(self.rng.below(self.arities.len() as u64) as u32)
+            }
+            _ => Instruction::Constant(self.rng.below(self.constants as u64) as u32),
+        };
+
+        bytecode.push(instruction);
+    }
+
+    fn random_instruction(&mut self) -> Instruction {
+        let small = |rng: &mut Rng| rng.below(4) as u16;
+
+        match self.rng.below(30) {
+            0 => Instruction::Constant(small(&mut self.rng).into()),
+            1 => Instruction::True,
+            2 => Instruction::False,
+            3 => Instruction::Add,
+            4 => Instruction::Sub,
+            5 => Instruction::Mul,
+            6 => Instruction::Div,
+            7 => Instruction::Rem,
+            8 => Instruction::Eq,
+            9 => Instruction::Neq,
+            10 => Instruction::Gt,
+            11 => Instruction::Lt,
+            12 => Instruction::Gte,
+            13 => Instruction::Lte,
+            14 => Instruction::And,
+            15 => Instruction::Or,
+            16 => Instruction::Tuple,
+            17 => Instruction::First,
+            18 => Instruction::Second,
+            19 => Instruction::Print,
+            20 => Instruction::GlobalGet(small(&mut self.rng).into()),
+            21 => Instruction::GlobalSet(small(&mut self.rng).into()),
+            22 => Instruction::LocalGet(small(&mut self.rng), 0),
+            23 => Instruction::If(self.rng.below(4) as u32),
+            24 => Instruction::Jump(self.rng.below(4) as u32),
+            25 => Instruction::Closure(small(&mut self.rng).into()),
+            26 => Instruction::Call(small(&mut self.rng)),
+            27 => Instruction::TailCall(small(&mut self.rng)),
+            28 => Instruction::Dup,
+            _ => Instruction::Return(small(&mut self.rng)),
+        }
+    }
+}
+
+fn prepare(vm: &mut Vm, generator: &mut Generator, depth: usize) {
+    let constants = [
+        Value::Integer(0),
+        Value::Integer(-1),
+        Value::Integer(i32::MAX),
+        Value::Integer(i32::MIN),
+        Value::String("s".into()),
+    ];
+    for constant in constants {
+        vm.create_constant(constant).unwrap();
+    }
+    for identifier in IDENTIFIERS {
+        vm.create_identifier(identifier.to_owned()).unwrap();
+    }
+
+    let functions = generator.rng.below(4) as u16;
+    generator.arities = (0..functions)
+        .map(|_| generator.rng.below(3) as u16)
+        .collect();
+
+    for index in 0..functions {
+        let arity = generator.arities[index as usize];
+
+        let mut bytecode = Vec::new();
+        generator.body(depth, arity, &mut bytecode);
+        bytecode.push(Instruction::Return(arity));
+
+        vm.functions.push(Rc::new(Function {
+            arity,
+            chunk: Chunk::new(bytecode),
+            captured: Vec::new(),
+            index: index.into(),
+            locals: (0..arity)
+                .map(|parameter| Local {
+                    name: format!("p{parameter}"),
+                })
+                .collect(),
+            name: None,
+            max_stack: 0,
+        }));
+    }
+}
+
+fn run_checked(seed: u64, bytecode: &[Instruction], vm: &mut Vm) {
+    let outcome = catch_unwind(AssertUnwindSafe(|| vm.run_bytecode(bytecode)));
+
+    match outcome {
+        Ok(Ok(_)) => {}
+        Ok(Err(error)) => assert!(
+            !error.to_string().starts_with("Stack imbalance"),
+            "seed {seed}: {error}"
+        ),
+        Err(_) => panic!("seed {seed}: the VM panicked on verified bytecode {bytecode:?}"),
+    }
+}
+
+#[test]
+fn structured_bytecode_never_panics() {
+    for seed in 0..500 {
+        let mut generator = Generator {
+            rng: Rng::new(seed),
+            constants: 5,
+            arities: Vec::new(),
+        };
+
+        // A tiny threshold makes collections happen mid-run, where a missing
+        // root would show up as a dangling handle.
+        let mut vm = Vm::new().with_fuel(FUEL).with_gc_threshold(8);
+        prepare(&mut vm, &mut generator, 4);
+
+        let mut bytecode = Vec::new();
+        generator.body(6, 0, &mut bytecode);
+        bytecode.push(Instruction::Return(0));
+
+        vm.verify(&bytecode)
+            .unwrap_or_else(|error| panic!("seed {seed}: generated invalid bytecode: {error}"));
+
+        run_checked(seed, &bytecode, &mut vm);
+    }
+}
+
+#[test]
+fn random_verified_bytecode_never_panics() {
+    let mut accepted = 0;
+
+    for seed in 0..20_000 {
+        let mut generator = Generator {
+            rng: Rng::new(seed),
+            constants: 5,
+            arities: Vec::new(),
+        };
+
+        let mut vm = Vm::new().with_fuel(FUEL);
+        prepare(&mut vm, &mut generator, 2);
+
+        let length = generator.rng.below(8) + 1;
+        let mut bytecode: Vec<Instruction> = (0..length)
+            .map(|_| generator.random_instruction())
+            .collect();
+        bytecode.push(Instruction::Return(0));
+
+        if vm.verify(&bytecode).is_err() {
+            continue;
+        }
+
+        accepted += 1;
+        run_checked(seed, &bytecode, &mut vm);
+    }
+
+    assert!(accepted > 0, "The verifier rejected every random sequence.");
+}
+
+#[test]
+fn empty_frames_and_extreme_jumps() {
+    let mut bytecode = vec![Instruction::False, Instruction::If(u16::MAX as u32 + 1)];
+    bytecode.extend((0..=u16::MAX).map(|_| Instruction::Jump(0)));
+    bytecode.extend([Instruction::GlobalGet(0), Instruction::Return(0)]);
+
+    let mut vm = Vm::new().with_fuel(FUEL);
+    vm.create_identifier("missing".to_owned()).unwrap();
+
+    vm.verify(&bytecode).unwrap();
+    assert!(vm.run_bytecode(&bytecode).is_err());
+}
+
+#[test]
+fn unverified_stack_underflows_are_errors() {
+    let programs = [
+        vec![Instruction::Pop],
+        vec![Instruction::True, Instruction::Slide(2)],
+        vec![Instruction::Call(1)],
+        vec![Instruction::True, Instruction::TailCall(4)],
+        vec![Instruction::Return(0)],
+    ];
+
+    for bytecode in programs {
+        let mut vm = Vm::new().with_fuel(FUEL);
+        let outcome = catch_unwind(AssertUnwindSafe(|| vm.run_bytecode(&bytecode)));
+        assert!(matches!(outcome, Ok(Err(_))), "{bytecode:?}");
+    }
+}
+
+#[test]
+fn verifier_rejects_malformed_bytecode() {
+    let vm = Vm::new();
+
+    assert!(vm
+        .verify(&[Instruction::Add, Instruction::Return(0)])
+        .is_err());
+    assert!(vm.verify(&[Instruction::True]).is_err());
+    assert!(vm
+        .verify(&[Instruction::Jump(5), Instruction::Return(0)])
+        .is_err());
+    assert!(vm
+        .verify(&[Instruction::Constant(0), Instruction::Return(0)])
+        .is_err());
+    assert!(vm
+        .verify(&[
+            Instruction::True,
+            Instruction::True,
+            Instruction::TailCall(0),
+            Instruction::Tuple,
+            Instruction::Return(0),
+        ])
+        .is_err());
+    assert!(vm
+        .verify(&[
+            Instruction::True,
+            Instruction::If(1),
+            Instruction::True,
+            Instruction::True,
+            Instruction::Return(0),
+        ])
+        .is_err());
+}
+
+#[test]
+fn functions_know_the_most_values_their_frame_holds() {
+    let mut vm = Vm::new();
+    vm.interpret("test", "let f = fn (a, b) => { (a, (b, a + b)) }; f(1, 2)")
+        .unwrap();
+
+    // The two arguments, then a, b, a and b before the addition.
+    assert_eq!(vm.functions[0].max_stack, 6);
+}
+
+#[test]
+fn returns_discard_every_local_of_the_frame() {
+    let mut vm = Vm::new();
+    // Returns with a local left under its result, which `Return(0)` does not
+    // account for.
+    vm.functions.push(Rc::new(Function {
+        arity: 0,
+        chunk: Chunk::new(vec![
+            Instruction::True,
+            Instruction::False,
+            Instruction::Return(0),
+        ]),
+        captured: Vec::new(),
+        index: 0,
+        locals: Vec::new(),
+        name: None,
+        max_stack: 0,
+    }));
+
+    let result = vm
+        .run_bytecode(&[
+            Instruction::True,
+            Instruction::Closure(0),
+            Instruction::Call(0),
+            Instruction::Tuple,
+            Instruction::Return(0),
+        ])
+        .unwrap();
+    assert_eq!(
+        result,
+        FinalValue::Tuple(
+            Box::new(FinalValue::Bool(true)),
+            Box::new(FinalValue::Bool(false))
+        )
+    );
+}
@@ -0,0 +1,162 @@
+use std::{fs, io};
+
+use rvm::{
+    native::{NativeRegistry, NativeResult},
+    printing::Captured,
+    replay::ReplayLog,
+    sandbox::{Effects, SandboxPolicy},
+    value::FinalValue,
+    vm::Vm,
+};
+
+fn registry() -> NativeRegistry {
+    let mut registry = NativeRegistry::standard();
+    registry
+        .register("text.shout", 1, |arguments| match arguments {
+            [FinalValue::String(s)] => {
+                Ok(NativeResult::Ready(FinalValue::String(s.to_uppercase())))
+            }
+            _ => anyhow::bail!("text.shout expects a string."),
+        })
+        .unwrap();
+    registry
+}
+
+#[test]
+fn natives_are_denied_by_default() {
+    let mut vm = Vm::new().with_natives(&registry(), &SandboxPolicy::new());
+    assert!(vm.interpret("test", "math/random(10)").is_err());
+}
+
+#[test]
+fn allowed_namespaces_are_callable() {
+    let policy = SandboxPolicy::new().allow("math").allow("text");
+    let mut vm = Vm::new().with_natives(&registry(), &policy);
+
+    let result = vm
+        .interpret(
+            "test",
+            r#"let r = math/random(10); (r < 10, text/shout("hi"))"#,
+        )
+        .unwrap();
+    assert_eq!(
+        result,
+        FinalValue::Tuple(
+            Box::new(FinalValue::Bool(true)),
+            Box::new(FinalValue::String("HI".to_owned()))
+        )
+    );
+}
+
+#[test]
+fn only_allowed_namespaces_are_installed() {
+    let path = std::env::temp_dir().join("rvm-natives-test.txt");
+    fs::write(&path, "contents").unwrap();
+    let program = format!(r#"io/read_file("{}")"#, path.display());
+
+    let policy = SandboxPolicy::new().allow("math");
+    let mut vm = Vm::new().with_natives(&registry(), &policy);
+    assert!(vm.interpret("test", &program).is_err());
+
+    let policy = SandboxPolicy::new().allow("io");
+    let mut vm = Vm::new().with_natives(&registry(), &policy);
+    assert_eq!(
+        vm.interpret("test", &program).unwrap(),
+        FinalValue::String("contents".to_owned())
+    );
+}
+
+#[test]
+fn native_names_must_be_qualified_and_unique() {
+    let mut registry = registry();
+    let native = |_: &[FinalValue]| Ok(NativeResult::Ready(FinalValue::Bool(true)));
+
+    assert!(registry.register("unqualified", 0, native).is_err());
+    assert!(registry.register("text.shout", 0, native).is_err());
+    assert!(registry.register("text.whisper", 0, native).is_ok());
+}
+
+#[test]
+fn embedders_can_register_host_functions() {
+    let mut vm = Vm::new();
+    vm.register_native("clock", 0, |_| Ok(FinalValue::Integer(42)));
+    vm.register_native("add", 2, |arguments| match arguments {
+        [FinalValue::Integer(a), FinalValue::Integer(b)] => Ok(FinalValue::Integer(a + b)),
+        _ => anyhow::bail!("add expects two integers."),
+    });
+
+    assert_eq!(
+        vm.interpret("test", "add(clock(), 1)").unwrap(),
+        FinalValue::Integer(43)
+    );
+    assert_eq!(
+        vm.interpret("test", r#"add("a", 1)"#)
+            .unwrap_err()
+            .to_string(),
+        "add expects two integers."
+    );
+}
+
+#[test]
+fn pure_profiles_keep_effects_from_the_host() {
+    let program = r#"let _ = print("hello"); (read_line(), 42)"#;
+    let vm = |effects: Effects, output: Captured| {
+        Vm::new()
+            .with_effects(effects)
+            .with_output(output)
+            .with_reader(io::Cursor::new("input\n"))
+    };
+
+    let output = Captured::default();
+    let error = vm(Effects::Denied, output.clone())
+        .interpret("test", program)
+        .unwrap_err();
+    assert!(error.to_string().contains("Printing is not allowed."));
+    let error = vm(Effects::Denied, output.clone())
+        .interpret("test", "read_int()")
+        .unwrap_err();
+    assert!(error.to_string().contains("Reading input is not allowed."));
+
+    let nothing_read = FinalValue::Tuple(
+        Box::new(FinalValue::String(String::new())),
+        Box::new(FinalValue::Integer(42)),
+    );
+    let mut ignoring = vm(Effects::Ignored, output.clone());
+    assert_eq!(ignoring.interpret("test", program).unwrap(), nothing_read);
+    assert!(ignoring.printed().is_empty());
+
+    let mut capturing = vm(Effects::Captured, output.clone());
+    assert_eq!(capturing.interpret("test", program).unwrap(), nothing_read);
+    assert_eq!(capturing.printed(), ["hello"]);
+
+    assert!(output.take().is_empty());
+}
+
+#[test]
+fn recorded_runs_replay_exactly() {
+    let program = "let line = read_line(); (line, (math/random(1000000), math/random(1000000)))";
+    let policy = SandboxPolicy::new().allow("math");
+
+    let mut recording = Vm::new()
+        .with_natives(&registry(), &policy)
+        .with_reader(io::Cursor::new("first\n"))
+        .with_recording();
+    let recorded = recording.interpret("test", program).unwrap();
+    let log = recording.take_recording().unwrap();
+    assert_eq!(log.events.len(), 3);
+
+    let log = ReplayLog::from_bytes(&log.to_bytes()).unwrap();
+    let mut replaying = Vm::new()
+        .with_natives(&registry(), &policy)
+        .with_reader(io::Cursor::new("second\n"))
+        .with_replay(log.clone());
+    assert_eq!(replaying.interpret("test", program).unwrap(), recorded);
+
+    let mut diverging = Vm::new()
+        .with_natives(&registry(), &policy)
+        .with_replay(log);
+    let error = diverging.interpret("test", "math/random(10)").unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("where the run called math.random"));
+}
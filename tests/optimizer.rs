@@ -0,0 +1,513 @@
+use rvm::{
+    artifact::CompiledProgram,
+    bytecode::Instruction,
+    optimizer::{eliminate_dead_code, fuse, peephole, propagate_ranges, Pass, Passes},
+    value::{FinalValue, Value},
+    vm::{Vm, COMPILE_OPTIONS},
+};
+
+#[test]
+fn true_condition_keeps_only_then_branch() {
+    let bytecode = vec![
+        Instruction::True,
+        Instruction::If(2),
+        Instruction::Constant(0),
+        Instruction::Jump(1),
+        Instruction::Constant(1),
+        Instruction::Return(0),
+    ];
+
+    assert_eq!(
+        eliminate_dead_code(&bytecode),
+        vec![Instruction::Constant(0), Instruction::Return(0)]
+    );
+}
+
+#[test]
+fn false_condition_keeps_only_else_branch() {
+    let bytecode = vec![
+        Instruction::False,
+        Instruction::If(2),
+        Instruction::Constant(0),
+        Instruction::Jump(1),
+        Instruction::Constant(1),
+        Instruction::Return(0),
+    ];
+
+    assert_eq!(
+        eliminate_dead_code(&bytecode),
+        vec![Instruction::Constant(1), Instruction::Return(0)]
+    );
+}
+
+#[test]
+fn unknown_condition_is_untouched() {
+    let bytecode = vec![
+        Instruction::GlobalGet(0),
+        Instruction::If(2),
+        Instruction::Constant(0),
+        Instruction::Jump(1),
+        Instruction::Constant(1),
+        Instruction::Return(0),
+    ];
+
+    assert_eq!(eliminate_dead_code(&bytecode), bytecode);
+}
+
+#[test]
+fn code_after_return_is_dropped() {
+    let bytecode = vec![
+        Instruction::Constant(0),
+        Instruction::Return(0),
+        Instruction::Constant(1),
+        Instruction::Print,
+    ];
+
+    assert_eq!(
+        eliminate_dead_code(&bytecode),
+        vec![Instruction::Constant(0), Instruction::Return(0)]
+    );
+}
+
+#[test]
+fn jumps_are_rewritten_around_removed_code() {
+    let bytecode = vec![
+        Instruction::GlobalGet(0),
+        Instruction::If(5),
+        Instruction::True,
+        Instruction::If(1),
+        Instruction::Constant(0),
+        Instruction::Constant(1),
+        Instruction::Jump(1),
+        Instruction::Constant(2),
+        Instruction::Return(0),
+    ];
+
+    assert_eq!(
+        eliminate_dead_code(&bytecode),
+        vec![
+            Instruction::GlobalGet(0),
+            Instruction::If(3),
+            Instruction::Constant(0),
+            Instruction::Constant(1),
+            Instruction::Jump(1),
+            Instruction::Constant(2),
+            Instruction::Return(0),
+        ]
+    );
+}
+
+#[test]
+fn constant_arithmetic_is_precomputed() {
+    let mut vm = Vm::new();
+    let two = vm.create_constant(Value::Integer(2)).unwrap();
+    let three = vm.create_constant(Value::Integer(3)).unwrap();
+
+    let bytecode = vec![
+        Instruction::Constant(two),
+        Instruction::Constant(three),
+        Instruction::Add,
+        Instruction::Constant(two),
+        Instruction::Mul,
+        Instruction::Return(0),
+    ];
+
+    let optimized = peephole(&bytecode, &mut vm).unwrap();
+    let ten = vm.create_constant(Value::Integer(10)).unwrap();
+
+    assert_eq!(
+        optimized,
+        vec![Instruction::Constant(ten), Instruction::Return(0)]
+    );
+}
+
+#[test]
+fn constant_comparison_becomes_boolean() {
+    let mut vm = Vm::new();
+    let one = vm.create_constant(Value::Integer(1)).unwrap();
+    let two = vm.create_constant(Value::Integer(2)).unwrap();
+
+    let bytecode = vec![
+        Instruction::Constant(one),
+        Instruction::Constant(two),
+        Instruction::Lt,
+        Instruction::Return(0),
+    ];
+
+    assert_eq!(
+        peephole(&bytecode, &mut vm).unwrap(),
+        vec![Instruction::True, Instruction::Return(0)]
+    );
+}
+
+#[test]
+fn failing_constant_operations_are_kept() {
+    let mut vm = Vm::new();
+    let one = vm.create_constant(Value::Integer(1)).unwrap();
+    let zero = vm.create_constant(Value::Integer(0)).unwrap();
+
+    let bytecode = vec![
+        Instruction::Constant(one),
+        Instruction::Constant(zero),
+        Instruction::Div,
+        Instruction::Return(0),
+    ];
+
+    assert_eq!(peephole(&bytecode, &mut vm).unwrap(), bytecode);
+}
+
+#[test]
+fn literal_conditions_are_resolved() {
+    let mut vm = Vm::new();
+
+    let bytecode = vec![
+        Instruction::True,
+        Instruction::If(1),
+        Instruction::GlobalGet(0),
+        Instruction::False,
+        Instruction::If(1),
+        Instruction::GlobalGet(1),
+        Instruction::Return(0),
+    ];
+
+    assert_eq!(
+        peephole(&bytecode, &mut vm).unwrap(),
+        vec![
+            Instruction::GlobalGet(0),
+            Instruction::Jump(1),
+            Instruction::GlobalGet(1),
+            Instruction::Return(0),
+        ]
+    );
+}
+
+#[test]
+fn double_jumps_are_collapsed() {
+    let mut vm = Vm::new();
+
+    let bytecode = vec![
+        Instruction::GlobalGet(0),
+        Instruction::If(1),
+        Instruction::Jump(1),
+        Instruction::Jump(1),
+        Instruction::GlobalGet(1),
+        Instruction::Return(0),
+    ];
+
+    assert_eq!(
+        peephole(&bytecode, &mut vm).unwrap(),
+        vec![
+            Instruction::GlobalGet(0),
+            Instruction::If(3),
+            Instruction::Jump(1),
+            Instruction::Jump(1),
+            Instruction::GlobalGet(1),
+            Instruction::Return(0),
+        ]
+    );
+}
+
+#[test]
+fn reading_a_just_set_global_duplicates_it() {
+    let mut vm = Vm::new();
+
+    let bytecode = vec![
+        Instruction::True,
+        Instruction::GlobalSet(0),
+        Instruction::GlobalGet(0),
+        Instruction::Return(0),
+    ];
+
+    assert_eq!(
+        peephole(&bytecode, &mut vm).unwrap(),
+        vec![
+            Instruction::True,
+            Instruction::Dup,
+            Instruction::GlobalSet(0),
+            Instruction::Return(0),
+        ]
+    );
+}
+
+#[test]
+fn locals_tested_for_equality_become_constants() {
+    let mut vm = Vm::new();
+    let zero = vm.create_constant(Value::Integer(0)).unwrap();
+    let one = vm.create_constant(Value::Integer(1)).unwrap();
+
+    // if (n == 0) { n + 1 } else { n }
+    let bytecode = vec![
+        Instruction::LocalGet(0, 0),
+        Instruction::Constant(zero),
+        Instruction::Eq,
+        Instruction::If(4),
+        Instruction::LocalGet(0, 0),
+        Instruction::Constant(one),
+        Instruction::Add,
+        Instruction::Jump(1),
+        Instruction::LocalGet(0, 0),
+        Instruction::Return(1),
+    ];
+
+    let mut expected = bytecode.clone();
+    expected[4] = Instruction::Constant(zero);
+    assert_eq!(propagate_ranges(&bytecode, 1, &mut vm).unwrap(), expected);
+}
+
+#[test]
+fn comparisons_decided_by_ranges_are_resolved() {
+    let mut vm = Vm::new();
+    let one = vm.create_constant(Value::Integer(1)).unwrap();
+    let two = vm.create_constant(Value::Integer(2)).unwrap();
+
+    // if (n < 2) { n } else { if (1 > n) { 1 } else { n } }
+    let bytecode = vec![
+        Instruction::LocalGet(0, 0),
+        Instruction::Constant(two),
+        Instruction::Lt,
+        Instruction::If(2),
+        Instruction::LocalGet(0, 0),
+        Instruction::Jump(7),
+        Instruction::Constant(one),
+        Instruction::LocalGet(0, 0),
+        Instruction::Gt,
+        Instruction::If(2),
+        Instruction::Constant(one),
+        Instruction::Jump(1),
+        Instruction::LocalGet(0, 0),
+        Instruction::Return(1),
+    ];
+
+    let propagated = propagate_ranges(&bytecode, 1, &mut vm).unwrap();
+    assert_eq!(propagated[6], Instruction::False);
+
+    assert_eq!(
+        eliminate_dead_code(&propagated),
+        vec![
+            Instruction::LocalGet(0, 0),
+            Instruction::Constant(two),
+            Instruction::Lt,
+            Instruction::If(2),
+            Instruction::LocalGet(0, 0),
+            Instruction::Jump(1),
+            Instruction::LocalGet(0, 0),
+            Instruction::Return(1),
+        ]
+    );
+}
+
+#[test]
+fn overflowing_arithmetic_is_not_bounded() {
+    let mut vm = Vm::new();
+    let zero = vm.create_constant(Value::Integer(0)).unwrap();
+    let one = vm.create_constant(Value::Integer(1)).unwrap();
+    let max = vm.create_constant(Value::Integer(i32::MAX)).unwrap();
+
+    // let x = 2147483647 + 1; if (x > 0) { 1 } else { 0 }
+    let bytecode = vec![
+        Instruction::Constant(max),
+        Instruction::Constant(one),
+        Instruction::Add,
+        Instruction::LocalGet(0, 0),
+        Instruction::Constant(zero),
+        Instruction::Gt,
+        Instruction::If(2),
+        Instruction::Constant(one),
+        Instruction::Jump(1),
+        Instruction::Constant(zero),
+        Instruction::Slide(1),
+        Instruction::Return(0),
+    ];
+
+    assert_eq!(propagate_ranges(&bytecode, 0, &mut vm).unwrap(), bytecode);
+}
+
+#[test]
+fn hot_sequences_are_fused() {
+    // let count = fn (n, acc) => { if (n < 1) { acc } else { count(n - 1, acc + 1) } }
+    let bytecode = vec![
+        Instruction::LocalGet(0, 0),
+        Instruction::Constant(1),
+        Instruction::Lt,
+        Instruction::If(2),
+        Instruction::LocalGet(1, 1),
+        Instruction::Return(2),
+        Instruction::GlobalGet(2),
+        Instruction::LocalGet(0, 0),
+        Instruction::Constant(1),
+        Instruction::Sub,
+        Instruction::LocalGet(1, 1),
+        Instruction::Constant(1),
+        Instruction::Add,
+        Instruction::TailCall(2),
+        Instruction::Return(2),
+    ];
+
+    assert_eq!(
+        fuse(&bytecode),
+        vec![
+            Instruction::LocalGet(0, 0),
+            Instruction::ConstantLt(1),
+            Instruction::If(2),
+            Instruction::LocalGet(1, 1),
+            Instruction::Return(2),
+            Instruction::GlobalGet(2),
+            Instruction::LocalGetConstantSub(0, 0, 1),
+            Instruction::LocalGetConstantAdd(1, 1, 1),
+            Instruction::TailCall(2),
+            Instruction::Return(2),
+        ]
+    );
+}
+
+#[test]
+fn sequences_jumped_into_are_not_fused() {
+    // n == if (n) { 0 } else { 1 }, where the else branch falls into the
+    // comparison and the then branch jumps to it.
+    let bytecode = vec![
+        Instruction::LocalGet(0, 0),
+        Instruction::LocalGet(0, 0),
+        Instruction::If(2),
+        Instruction::Constant(0),
+        Instruction::Jump(1),
+        Instruction::Constant(1),
+        Instruction::Eq,
+        Instruction::Return(1),
+    ];
+
+    assert_eq!(fuse(&bytecode), bytecode);
+}
+
+#[test]
+fn passes_can_be_disabled() {
+    assert_eq!(Passes::default().names(), COMPILE_OPTIONS);
+    assert_eq!(Passes::level(Passes::MAX_LEVEL).unwrap(), Passes::default());
+    assert!(Passes::level(0).unwrap().names().is_empty());
+    assert!(!Passes::level(1).unwrap().contains(Pass::Ranges));
+    assert!(!Passes::level(1).unwrap().contains(Pass::Fusion));
+    assert!(Passes::level(3).is_err());
+    assert!(Pass::from_name("inlining").is_err());
+
+    let source = "let f = fn (n) => { if (n == 0) { 0 } else { f(n - 1) } }; f(1 + 2)";
+    let compile = |passes: Passes| {
+        let program = Vm::new()
+            .with_passes(passes.clone())
+            .compile_program("test", source)
+            .unwrap();
+        assert_eq!(program.metadata.options, passes.names());
+        program
+    };
+
+    // `f` calls itself in tail position, which loops in place.
+    let loops = |program: &CompiledProgram| {
+        program.functions[0]
+            .bytecode
+            .iter()
+            .any(|instruction| matches!(instruction, Instruction::JumpBack(_)))
+    };
+
+    let optimized = compile(Passes::default());
+    assert!(loops(&optimized));
+    assert!(!optimized.script.contains(&Instruction::Add));
+
+    let unoptimized = compile(Passes::level(0).unwrap());
+    assert!(!loops(&unoptimized));
+    assert!(unoptimized.script.contains(&Instruction::Add));
+
+    let without_tail_calls = compile(Passes::default().without(Pass::TailCalls));
+    assert!(!loops(&without_tail_calls));
+    assert_eq!(
+        Vm::new()
+            .with_passes(Passes::level(0).unwrap())
+            .interpret("test", source)
+            .unwrap(),
+        FinalValue::Integer(0)
+    );
+}
+
+fn calls(bytecode: &[Instruction]) -> usize {
+    bytecode
+        .iter()
+        .filter(|instruction| {
+            matches!(
+                instruction,
+                Instruction::Call(_) | Instruction::TailCall(_) | Instruction::LocalGetTailCall(..)
+            )
+        })
+        .count()
+}
+
+#[test]
+fn small_leaf_functions_are_inlined() {
+    let cases = [
+        (
+            "let sq = fn (x) => { x * x }; let f = fn (n) => { sq(n) + sq(n + 1) }; f(3)",
+            25,
+        ),
+        (
+            "let f = fn (n) => { let h = fn (a, b) => { let s = a + b; s * s }; h(n, 1) + h(2, n) }; f(3)",
+            41,
+        ),
+        (
+            "let clamp = fn (x) => { if (x < 0) { 0 } else { x } }; let f = fn (n) => { clamp(n) + clamp(n - 10) }; f(15)",
+            20,
+        ),
+    ];
+
+    for (program, expected) in cases {
+        let compiled = Vm::new().compile_program("test", program).unwrap();
+        assert_eq!(calls(&compiled.functions[1].bytecode), 0, "{program}");
+
+        for passes in [Passes::default(), Passes::default().without(Pass::Inline)] {
+            assert_eq!(
+                Vm::new()
+                    .with_passes(passes)
+                    .interpret("test", program)
+                    .unwrap(),
+                FinalValue::Integer(expected),
+                "{program}"
+            );
+        }
+    }
+}
+
+#[test]
+fn only_known_pure_leaf_functions_are_inlined() {
+    let programs = [
+        // Calls itself.
+        "let h = fn (x) => { if (x == 0) { 0 } else { h(x - 1) } }; let f = fn (n) => { h(n) }; f(1)",
+        // Has an effect.
+        "let h = fn (x) => { print(x) }; let f = fn (n) => { h(n) }; f(1)",
+        // Captures a variable.
+        "let f = fn (n) => { let h = fn (x) => { x + n }; h(1) }; f(1)",
+        // Is not known until it runs.
+        "let f = fn (h, n) => { h(n) }; f(fn (x) => { x }, 1)",
+        // Is too big.
+        "let h = fn (x) => { (x, (x, (x, (x, (x, (x, (x, (x, x)))))))) }; let f = fn (n) => { h(n) }; f(1)",
+    ];
+
+    for program in programs {
+        let compiled = Vm::new().compile_program("test", program).unwrap();
+        let f = compiled
+            .functions
+            .iter()
+            .find(|function| function.name.as_deref() == Some("f"))
+            .unwrap();
+        assert_eq!(calls(&f.bytecode), 1, "{program}");
+    }
+}
+
+#[test]
+fn inlining_stays_within_a_budget() {
+    let calls_to_inc = vec!["inc(n)"; 100].join(" + ");
+    let program =
+        format!("let inc = fn (x) => {{ x + 1 }}; let f = fn (n) => {{ {calls_to_inc} }}; f(1)");
+
+    let compiled = Vm::new().compile_program("test", &program).unwrap();
+    let remaining = calls(&compiled.functions[1].bytecode);
+    assert!(remaining > 0 && remaining < 100, "{remaining}");
+    assert_eq!(
+        Vm::new().interpret("test", &program).unwrap(),
+        FinalValue::Integer(200)
+    );
+}
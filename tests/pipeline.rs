@@ -0,0 +1,124 @@
+use std::{
+    fs,
+    io::{self},
+    path::{Path, PathBuf},
+    thread,
+};
+
+use rvm::{
+    artifact::CompiledProgram, ast::File, interp::Interpreter, native::NativeRegistry,
+    optimizer::Passes, printing::Captured, sandbox::SandboxPolicy, value::FinalValue, vm::Vm,
+};
+
+/// What a run printed and how it ended. Errors only have to happen on both
+/// sides, not to read the same.
+#[derive(Debug, PartialEq)]
+struct Run {
+    lines: Vec<String>,
+    result: Option<FinalValue>,
+}
+
+/// The programs in `tests/conformance`.
+fn corpus() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance");
+    let mut programs: Vec<PathBuf> = fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "rinha")
+        })
+        .collect();
+    programs.sort();
+    programs
+}
+
+/// Runs `source` on the AST interpreter, on a thread with a stack large
+/// enough for the corpus' deepest calls.
+fn reference(name: &str, source: &str) -> Run {
+    let file = File::parse(name, source).unwrap();
+
+    thread::Builder::new()
+        .stack_size(512 * 1024 * 1024)
+        .spawn(move || {
+            let mut interpreter = Interpreter::new()
+                .with_steps(u64::MAX)
+                .with_call_depth(usize::MAX);
+            let result = interpreter.run(&file);
+            Run {
+                lines: interpreter.output().to_vec(),
+                result: result.ok(),
+            }
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+/// Runs `source` with everything turned on: every optimizer pass, a round
+/// trip through a compressed artifact, the standard natives behind a
+/// sandbox, a garbage collector that runs all the time and a small
+/// memoization table with every result it serves checked against a fresh
+/// call.
+fn maximal(name: &str, source: &str) -> Run {
+    let passes = Passes::level(Passes::MAX_LEVEL).unwrap();
+    let program = Vm::new()
+        .with_passes(passes)
+        .compile_program(name, source)
+        .unwrap();
+
+    #[cfg(feature = "zstd")]
+    let bytes = program.to_compressed_bytes(3).unwrap();
+    #[cfg(not(feature = "zstd"))]
+    let bytes = program.to_bytes().unwrap();
+    let program = CompiledProgram::from_bytes(&bytes).unwrap();
+
+    let output = Captured::default();
+    let mut vm = Vm::new()
+        .with_natives(
+            &NativeRegistry::standard(),
+            &SandboxPolicy::new().allow("math"),
+        )
+        .with_gc_threshold(16)
+        .with_memo_capacity(64)
+        .with_memo_verification(100)
+        .with_output(output.clone())
+        .with_reader(io::empty());
+
+    let result = vm.interpret_program(&program);
+    assert!(
+        vm.memo_mismatches().is_empty(),
+        "{name} memoized unsound results: {:?}",
+        vm.memo_mismatches()
+    );
+
+    let printed = String::from_utf8(output.take()).unwrap();
+    Run {
+        lines: printed.lines().map(str::to_owned).collect(),
+        result: result.ok(),
+    }
+}
+
+#[test]
+fn the_corpus_runs_the_same_with_every_feature_enabled() {
+    let programs = corpus();
+    assert!(!programs.is_empty());
+
+    for path in programs {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let source = fs::read_to_string(&path).unwrap();
+
+        let expected = reference(&name, &source);
+        assert!(!expected.lines.is_empty(), "{name} prints nothing.");
+        assert_eq!(maximal(&name, &source), expected, "{name}");
+    }
+}
+
+#[test]
+fn the_corpus_has_a_failing_program() {
+    let source = fs::read_to_string(
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/conformance/errors.rinha"),
+    )
+    .unwrap();
+    assert_eq!(reference("errors.rinha", &source).result, None);
+}
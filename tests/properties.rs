@@ -0,0 +1,182 @@
+use proptest::prelude::*;
+
+use rvm::{
+    ast::{BinaryOp, File, Location, Term},
+    optimizer::Passes,
+    value::FinalValue,
+    vm::Vm,
+};
+
+const OPERATORS: [BinaryOp; 13] = [
+    BinaryOp::Add,
+    BinaryOp::Sub,
+    BinaryOp::Mul,
+    BinaryOp::Div,
+    BinaryOp::Rem,
+    BinaryOp::Eq,
+    BinaryOp::Neq,
+    BinaryOp::Lt,
+    BinaryOp::Gt,
+    BinaryOp::Lte,
+    BinaryOp::Gte,
+    BinaryOp::And,
+    BinaryOp::Or,
+];
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Int(i32),
+    Bool(bool),
+    Str(String),
+    Binary(Box<Expr>, BinaryOp, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Val {
+    Int(i32),
+    Bool(bool),
+    Str(String),
+}
+
+fn integer() -> impl Strategy<Value = i32> {
+    prop_oneof![any::<i32>(), -3..=3, Just(i32::MIN), Just(i32::MAX)]
+}
+
+fn expression() -> impl Strategy<Value = Expr> {
+    let leaf = prop_oneof![
+        4 => integer().prop_map(Expr::Int),
+        2 => any::<bool>().prop_map(Expr::Bool),
+        // Short strings are stored inline and longer ones on the heap.
+        1 => "[a-z]{0,24}".prop_map(Expr::Str),
+    ];
+
+    leaf.prop_recursive(5, 48, 3, |inner| {
+        prop_oneof![
+            4 => (inner.clone(), 0..OPERATORS.len(), inner.clone())
+                .prop_map(|(lhs, op, rhs)| Expr::Binary(Box::new(lhs), OPERATORS[op], Box::new(rhs))),
+            1 => (inner.clone(), inner.clone(), inner)
+                .prop_map(|(condition, then, otherwise)| {
+                    Expr::If(Box::new(condition), Box::new(then), Box::new(otherwise))
+                }),
+        ]
+    })
+}
+
+/// What the expression evaluates to by the language's rules, or `None` when
+/// it must fail.
+fn evaluate(expr: &Expr) -> Option<Val> {
+    Some(match expr {
+        Expr::Int(i) => Val::Int(*i),
+        Expr::Bool(b) => Val::Bool(*b),
+        Expr::Str(s) => Val::Str(s.clone()),
+        Expr::If(condition, then, otherwise) => match evaluate(condition)? {
+            Val::Bool(true) => evaluate(then)?,
+            Val::Bool(false) => evaluate(otherwise)?,
+            _ => return None,
+        },
+        Expr::Binary(lhs, op, rhs) => {
+            let (lhs, rhs) = (evaluate(lhs)?, evaluate(rhs)?);
+
+            match (op, lhs, rhs) {
+                (BinaryOp::Eq, lhs, rhs) => Val::Bool(lhs == rhs),
+                (BinaryOp::Neq, lhs, rhs) => Val::Bool(lhs != rhs),
+                (BinaryOp::Add, Val::Int(lhs), Val::Int(rhs)) => Val::Int(lhs.wrapping_add(rhs)),
+                (BinaryOp::Add, Val::Str(lhs), Val::Int(rhs)) => Val::Str(format!("{lhs}{rhs}")),
+                (BinaryOp::Add, Val::Int(lhs), Val::Str(rhs)) => Val::Str(format!("{lhs}{rhs}")),
+                (BinaryOp::Add, Val::Str(lhs), Val::Str(rhs)) => Val::Str(lhs + &rhs),
+                (BinaryOp::Sub, Val::Int(lhs), Val::Int(rhs)) => Val::Int(lhs.wrapping_sub(rhs)),
+                (BinaryOp::Mul, Val::Int(lhs), Val::Int(rhs)) => Val::Int(lhs.wrapping_mul(rhs)),
+                (BinaryOp::Div | BinaryOp::Rem, Val::Int(_), Val::Int(0)) => return None,
+                (BinaryOp::Div, Val::Int(lhs), Val::Int(rhs)) => Val::Int(lhs.wrapping_div(rhs)),
+                (BinaryOp::Rem, Val::Int(lhs), Val::Int(rhs)) => Val::Int(lhs.wrapping_rem(rhs)),
+                (BinaryOp::Lt, Val::Int(lhs), Val::Int(rhs)) => Val::Bool(lhs < rhs),
+                (BinaryOp::Gt, Val::Int(lhs), Val::Int(rhs)) => Val::Bool(lhs > rhs),
+                (BinaryOp::Lte, Val::Int(lhs), Val::Int(rhs)) => Val::Bool(lhs <= rhs),
+                (BinaryOp::Gte, Val::Int(lhs), Val::Int(rhs)) => Val::Bool(lhs >= rhs),
+                (BinaryOp::Lt, Val::Str(lhs), Val::Str(rhs)) => Val::Bool(lhs < rhs),
+                (BinaryOp::Gt, Val::Str(lhs), Val::Str(rhs)) => Val::Bool(lhs > rhs),
+                (BinaryOp::Lte, Val::Str(lhs), Val::Str(rhs)) => Val::Bool(lhs <= rhs),
+                (BinaryOp::Gte, Val::Str(lhs), Val::Str(rhs)) => Val::Bool(lhs >= rhs),
+                (BinaryOp::And, Val::Bool(lhs), Val::Bool(rhs)) => Val::Bool(lhs && rhs),
+                (BinaryOp::Or, Val::Bool(lhs), Val::Bool(rhs)) => Val::Bool(lhs || rhs),
+                _ => return None,
+            }
+        }
+    })
+}
+
+fn term(expr: &Expr) -> Term {
+    let location = Location::default();
+
+    match expr {
+        Expr::Int(value) => Term::Int {
+            value: *value,
+            location,
+        },
+        Expr::Bool(value) => Term::Bool {
+            value: *value,
+            location,
+        },
+        Expr::Str(value) => Term::Str {
+            value: value.clone(),
+            location,
+        },
+        Expr::Binary(lhs, op, rhs) => Term::Binary {
+            lhs: Box::new(term(lhs)),
+            op: *op,
+            rhs: Box::new(term(rhs)),
+            location,
+        },
+        Expr::If(condition, then, otherwise) => Term::If {
+            condition: Box::new(term(condition)),
+            then: Box::new(term(then)),
+            otherwise: Box::new(term(otherwise)),
+            location,
+        },
+    }
+}
+
+fn json(expr: &Expr) -> String {
+    let file = File {
+        name: "property.rinha".to_owned(),
+        expression: term(expr),
+        location: Location::default(),
+    };
+    serde_json::to_string(&file).unwrap()
+}
+
+fn final_value(value: Val) -> FinalValue {
+    match value {
+        Val::Int(i) => FinalValue::Integer(i),
+        Val::Bool(b) => FinalValue::Bool(b),
+        Val::Str(s) => FinalValue::String(s),
+    }
+}
+
+/// Checks the VM against [`evaluate`] at every optimization level, so
+/// constant folding is held to the same rules as the interpreter loop.
+fn check(expr: &Expr) -> Result<(), TestCaseError> {
+    let expected = evaluate(expr).map(final_value);
+    let json = json(expr);
+
+    for level in 0..=Passes::MAX_LEVEL {
+        let mut vm = Vm::new().with_passes(Passes::level(level).unwrap());
+        let actual = vm.interpret_json(&json).ok();
+        prop_assert_eq!(&actual, &expected, "at level {}", level);
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn operators_on_integers_follow_rust(lhs in integer(), op in 0..OPERATORS.len(), rhs in integer()) {
+        check(&Expr::Binary(Box::new(Expr::Int(lhs)), OPERATORS[op], Box::new(Expr::Int(rhs))))?;
+    }
+
+    #[test]
+    fn expression_trees_evaluate_as_in_rust(expr in expression()) {
+        check(&expr)?;
+    }
+}
@@ -0,0 +1,105 @@
+use rvm::{
+    debugger::Breakpoints,
+    native::NativeResult,
+    optimizer::Passes,
+    value::FinalValue,
+    vm::{Execution, Vm},
+};
+
+fn vm_with_fetch() -> Vm {
+    let mut vm = Vm::new();
+    vm.register_suspendable_native("fetch", 1, |_| Ok(NativeResult::Pending));
+    vm
+}
+
+#[test]
+fn suspended_runs_continue_in_another_vm() {
+    let program = r#"
+        let fib = fn (n) => { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };
+        let greet = fn (name) => { "hello there, " + name };
+        let pair = (greet("world"), fib(20));
+        let add = fn (x) => { fn (y) => { x + y } };
+        let plus = add(fetch(first(pair)));
+        (pair, plus(fib(20)))
+    "#;
+
+    let mut vm = vm_with_fetch();
+    let Execution::Suspended(token) = vm.start("test", program).unwrap() else {
+        panic!("Expected fetch to suspend.");
+    };
+    let bytes = vm.snapshot().unwrap();
+    drop(vm);
+
+    let mut restored = vm_with_fetch();
+    restored.restore(&bytes).unwrap();
+    assert_eq!(restored.suspension(), Some(&token));
+    assert_eq!(
+        token.arguments,
+        [FinalValue::String("hello there, world".to_owned())]
+    );
+
+    let result = restored
+        .resume_with(&token, FinalValue::Integer(1))
+        .unwrap();
+    let pair = FinalValue::Tuple(
+        Box::new(FinalValue::String("hello there, world".to_owned())),
+        Box::new(FinalValue::Integer(6765)),
+    );
+    assert_eq!(
+        result,
+        Execution::Finished(FinalValue::Tuple(
+            Box::new(pair),
+            Box::new(FinalValue::Integer(6766))
+        ))
+    );
+    // fib(20) comes from the restored memoization table.
+    assert!(restored.stats().instructions < 100);
+}
+
+#[test]
+fn paused_runs_continue_without_the_debugger() {
+    let program = "let f = fn (x) => {\n  x * 2\n};\nf(20) + 2\n";
+    let mut breakpoints = Breakpoints::new(program);
+    breakpoints.add_line(2);
+
+    let mut vm = Vm::new()
+        .with_passes(Passes::level(0).unwrap())
+        .with_breakpoints(breakpoints);
+    assert_eq!(vm.start("test", program).unwrap(), Execution::Paused);
+    let bytes = vm.snapshot().unwrap();
+
+    let mut restored = Vm::new();
+    restored.restore(&bytes).unwrap();
+    let frames = restored.frames();
+    assert_eq!(frames[0].function, "f");
+    assert_eq!(frames[0].slots[0].1, FinalValue::Integer(20));
+    assert_eq!(frames[0].offset, Some(program.find("x * 2").unwrap()));
+
+    assert_eq!(
+        restored.resume().unwrap(),
+        Execution::Finished(FinalValue::Integer(42))
+    );
+    assert_eq!(
+        vm.resume().unwrap(),
+        Execution::Finished(FinalValue::Integer(42))
+    );
+}
+
+#[test]
+fn restoring_checks_the_snapshot() {
+    let mut vm = vm_with_fetch();
+    assert!(vm.snapshot().is_err());
+
+    vm.start("test", "fetch(1) + 1").unwrap();
+    let bytes = vm.snapshot().unwrap();
+
+    let error = Vm::new().restore(&bytes).unwrap_err();
+    assert!(error.to_string().contains("native fetch"));
+
+    assert!(vm_with_fetch().restore(&bytes[..bytes.len() - 1]).is_err());
+    assert!(vm_with_fetch().restore(b"RVMC").is_err());
+
+    let mut used = vm_with_fetch();
+    used.interpret("test", "1").unwrap();
+    assert!(used.restore(&bytes).is_err());
+}
@@ -0,0 +1,140 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use rvm::{
+    printing::Captured,
+    vm::{Vm, VmStats},
+};
+
+/// The most a stress program may use of each resource. They are set a bit
+/// above what the programs need today, so a change that makes any of them
+/// noticeably worse fails here.
+struct Budget {
+    instructions: u64,
+    peak_stack_depth: usize,
+    peak_frame_depth: usize,
+    allocations: u64,
+}
+
+/// The programs in `tests/stress`, each next to a `.out` file with what it
+/// prints.
+const PROGRAMS: &[(&str, Budget)] = &[
+    (
+        "ackermann",
+        Budget {
+            instructions: 180_000,
+            peak_stack_depth: 800,
+            peak_frame_depth: 160,
+            allocations: 10,
+        },
+    ),
+    (
+        "deep_closures",
+        Budget {
+            instructions: 45_000,
+            peak_stack_depth: 4_000,
+            peak_frame_depth: 1_100,
+            allocations: 1_300,
+        },
+    ),
+    (
+        // Tail calls must keep the frames from piling up.
+        "mutual_recursion",
+        Budget {
+            instructions: 2_000_000,
+            peak_stack_depth: 16,
+            peak_frame_depth: 4,
+            allocations: 10,
+        },
+    ),
+    (
+        "string_building",
+        Budget {
+            instructions: 70_000,
+            peak_stack_depth: 16,
+            peak_frame_depth: 4,
+            allocations: 5_000,
+        },
+    ),
+    (
+        "tuple_lists",
+        Budget {
+            instructions: 85_000,
+            peak_stack_depth: 5_000,
+            peak_frame_depth: 1_100,
+            allocations: 4_000,
+        },
+    ),
+];
+
+fn stress_directory() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/stress")
+}
+
+fn run(name: &str) -> (String, VmStats) {
+    let path = stress_directory().join(format!("{name}.rinha"));
+    let source = fs::read_to_string(&path).unwrap();
+
+    let output = Captured::default();
+    let mut vm = Vm::new().with_output(output.clone());
+    vm.interpret(&path.to_string_lossy(), &source)
+        .unwrap_or_else(|error| panic!("{name}: {error}"));
+
+    let printed = String::from_utf8(output.take()).unwrap();
+    (printed, vm.stats())
+}
+
+#[test]
+fn stress_programs_print_what_they_should() {
+    for (name, _) in PROGRAMS {
+        let expected = fs::read_to_string(stress_directory().join(format!("{name}.out"))).unwrap();
+        let (printed, _) = run(name);
+
+        assert_eq!(printed, expected, "{name}");
+    }
+}
+
+#[test]
+fn stress_programs_stay_within_their_budgets() {
+    for (name, budget) in PROGRAMS {
+        let (_, stats) = run(name);
+
+        let checks = [
+            ("instructions", stats.instructions, budget.instructions),
+            (
+                "peak stack depth",
+                stats.peak_stack_depth as u64,
+                budget.peak_stack_depth as u64,
+            ),
+            (
+                "peak frame depth",
+                stats.peak_frame_depth as u64,
+                budget.peak_frame_depth as u64,
+            ),
+            ("allocations", stats.allocations, budget.allocations),
+        ];
+
+        for (resource, used, limit) in checks {
+            assert!(
+                used <= limit,
+                "{name} used {used} {resource}, over its budget of {limit}."
+            );
+        }
+    }
+}
+
+#[test]
+fn every_stress_program_is_listed() {
+    for entry in fs::read_dir(stress_directory()).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_stem().unwrap().to_string_lossy();
+
+        assert!(
+            PROGRAMS.iter().any(|(listed, _)| *listed == name),
+            "{} has no budget.",
+            path.display()
+        );
+    }
+}
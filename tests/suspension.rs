@@ -0,0 +1,138 @@
+use rvm::{
+    native::{NativeResult, SuspensionToken},
+    scheduler::Scheduler,
+    value::FinalValue,
+    vm::{Execution, Vm},
+};
+
+fn vm_with_fetch() -> Vm {
+    let mut vm = Vm::new();
+    vm.register_suspendable_native("fetch", 1, |_| Ok(NativeResult::Pending));
+    vm.register_suspendable_native("double", 1, |arguments| match arguments {
+        [FinalValue::Integer(i)] => Ok(NativeResult::Ready(FinalValue::Integer(i * 2))),
+        _ => anyhow::bail!("double expects an integer."),
+    });
+    vm
+}
+
+#[test]
+fn ready_natives_do_not_suspend() {
+    let mut vm = vm_with_fetch();
+    let result = vm.interpret("test", "double(20) + 2").unwrap();
+    assert_eq!(result, FinalValue::Integer(42));
+}
+
+#[test]
+fn pending_native_suspends_and_resumes() {
+    let mut vm = vm_with_fetch();
+
+    let program = r#"
+        let f = fn (x) => { fetch(x) + 1 };
+        let a = f(10);
+        let b = fetch("second");
+        (a, b)
+    "#;
+
+    let Execution::Suspended(first) = vm.start("test", program).unwrap() else {
+        panic!("Expected the first fetch to suspend.");
+    };
+    assert_eq!(first.native, "fetch");
+    assert_eq!(first.arguments, vec![FinalValue::Integer(10)]);
+
+    let Execution::Suspended(second) = vm.resume_with(&first, FinalValue::Integer(41)).unwrap()
+    else {
+        panic!("Expected the second fetch to suspend.");
+    };
+    assert_ne!(first.id, second.id);
+    assert_eq!(
+        second.arguments,
+        vec![FinalValue::String("second".to_owned())]
+    );
+
+    let result = vm.resume_with(&second, FinalValue::Bool(true)).unwrap();
+    assert_eq!(
+        result,
+        Execution::Finished(FinalValue::Tuple(
+            Box::new(FinalValue::Integer(42)),
+            Box::new(FinalValue::Bool(true))
+        ))
+    );
+}
+
+#[test]
+fn resuming_without_suspension_fails() {
+    let mut vm = vm_with_fetch();
+    let token = SuspensionToken {
+        id: 1,
+        native: "fetch".to_owned(),
+        arguments: Vec::new(),
+    };
+    assert!(vm.resume_with(&token, FinalValue::Integer(1)).is_err());
+
+    vm.start("test", "1 + 1").unwrap();
+    assert!(vm.resume_with(&token, FinalValue::Integer(1)).is_err());
+}
+
+#[test]
+fn resuming_with_a_stale_token_fails() {
+    let mut vm = vm_with_fetch();
+
+    let Execution::Suspended(first) = vm.start("test", "fetch(1) + fetch(2)").unwrap() else {
+        panic!("Expected the first fetch to suspend.");
+    };
+    let Execution::Suspended(second) = vm.resume_with(&first, FinalValue::Integer(1)).unwrap()
+    else {
+        panic!("Expected the second fetch to suspend.");
+    };
+
+    assert!(vm.resume_with(&first, FinalValue::Integer(2)).is_err());
+    assert_eq!(vm.suspension(), Some(&second));
+    assert_eq!(
+        vm.resume_with(&second, FinalValue::Integer(2)).unwrap(),
+        Execution::Finished(FinalValue::Integer(3))
+    );
+}
+
+#[test]
+fn interpret_rejects_suspension() {
+    let mut vm = vm_with_fetch();
+    assert!(vm.interpret("test", "fetch(1)").is_err());
+}
+
+#[test]
+fn scheduler_drives_many_programs() {
+    let mut scheduler = Scheduler::new();
+
+    let programs: Vec<_> = (1..=3)
+        .map(|n| {
+            let program = format!("let x = fetch({n}); x + fetch(x)");
+            scheduler.spawn(vm_with_fetch(), "test", &program)
+        })
+        .collect();
+    let finished = scheduler.spawn(vm_with_fetch(), "test", "double(21)");
+
+    assert_eq!(scheduler.pending().count(), 3);
+    assert_eq!(
+        scheduler.take_result(finished).unwrap().unwrap(),
+        FinalValue::Integer(42)
+    );
+
+    let mut resumed = 0;
+    while let Some(pending) = scheduler.next_pending() {
+        let [FinalValue::Integer(argument)] = pending.token.arguments[..] else {
+            panic!("fetch takes a single integer.");
+        };
+        scheduler
+            .resume_with(&pending, FinalValue::Integer(argument * 10))
+            .unwrap();
+        resumed += 1;
+    }
+
+    assert_eq!(resumed, 6);
+    assert!(scheduler.is_idle());
+
+    for (n, program) in (1..=3).zip(programs) {
+        let result = scheduler.take_result(program).unwrap().unwrap();
+        assert_eq!(result, FinalValue::Integer(n * 10 + n * 100));
+    }
+}
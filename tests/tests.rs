@@ -1,8 +1,23 @@
 use anyhow::Result;
+use std::io;
 
-use rvm::{value::FinalValue, vm::Vm};
+use rvm::{
+    arithmetic::Overflow,
+    call_frame::ELIDED_CALLS,
+    optimizer::{Pass, Passes},
+    printing::{Captured, PrintResult, Printing},
+    source_map::LineIndex,
+    value::FinalValue,
+    vm::{MemoMismatch, RuntimeError, Vm, DEFAULT_MAX_TUPLE_DEPTH},
+};
 
-fn compile_and_assert(program: &str, assert: impl Fn(Result<FinalValue>) -> ()) {
+/// A VM that keeps every call, for tests looking at the calls themselves,
+/// which inlining would otherwise remove.
+fn without_inlining() -> Vm {
+    Vm::new().with_passes(Passes::default().without(Pass::Inline))
+}
+
+fn compile_and_assert(program: &str, assert: impl Fn(Result<FinalValue>)) {
     let mut vm = Vm::new();
     let result = vm.interpret("test", program);
     assert(result);
@@ -72,6 +87,37 @@ fn lt_works() {
     });
 }
 
+#[test]
+fn strings_are_ordered_by_code_point() {
+    let program = r#"
+        let compare = fn (a, b) => (a < b, (a <= b, (a > b, a >= b)));
+        (compare("abc", "abd"), (compare("ab", "abc"), (compare("Z", "a"), compare("é", "z"))))
+    "#;
+    let expected = Vm::new()
+        .interpret(
+            "test",
+            "((true, (true, (false, false))), ((true, (true, (false, false))), \
+             ((true, (true, (false, false))), (false, (false, (true, true))))))",
+        )
+        .unwrap();
+
+    // Folded at compile time and compared at runtime alike.
+    for level in [0, Passes::MAX_LEVEL] {
+        let mut vm = Vm::new().with_passes(Passes::level(level).unwrap());
+        assert_eq!(vm.interpret("test", program).unwrap(), expected);
+    }
+
+    compile_and_assert(r#""b" >= "b""#, |result| {
+        assert_eq!(result.unwrap(), FinalValue::Bool(true));
+    });
+    compile_and_assert(r#"let one = 1; "a" < one"#, |result| {
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Operands must be both integers or both strings."
+        );
+    });
+}
+
 #[test]
 fn gte_works() {
     compile_and_assert("2 >= 1", |result| {
@@ -422,3 +468,978 @@ fn closure_capturing_nested() {
         |result| assert_eq!(result.unwrap(), FinalValue::String("value".to_owned())),
     )
 }
+
+#[test]
+fn constant_conditions_skip_dead_branch() {
+    compile_and_assert(
+        r#"
+        if (false) {
+            print(first(1))
+        } else {
+            if (true) {
+                42
+            } else {
+                print(second(1))
+            }
+        }
+    "#,
+        |result| {
+            assert_eq!(result.unwrap(), FinalValue::Integer(42));
+        },
+    );
+}
+
+#[test]
+fn top_level_tail_call() {
+    compile_and_assert(
+        r#"
+            let id = fn (x) => { x };
+            id(42)
+        "#,
+        |result| assert_eq!(result.unwrap(), FinalValue::Integer(42)),
+    )
+}
+
+#[test]
+fn call_inside_first_is_not_a_tail_call() {
+    compile_and_assert(
+        r#"
+            let pair = fn (x) => { (x, x + 1) };
+            let f = fn (x) => { second(pair(x)) };
+            f(41)
+        "#,
+        |result| assert_eq!(result.unwrap(), FinalValue::Integer(42)),
+    )
+}
+
+#[test]
+fn integer_overflow_wraps() {
+    compile_and_assert("2147483647 + 1", |result| {
+        assert_eq!(result.unwrap(), FinalValue::Integer(i32::MIN));
+    })
+}
+
+fn overflowing(overflow: Overflow, program: &str) -> Result<FinalValue> {
+    Vm::new().with_overflow(overflow).interpret("test", program)
+}
+
+#[test]
+fn integer_overflow_saturates() {
+    let max = FinalValue::Integer(i32::MAX);
+    let min = FinalValue::Integer(i32::MIN);
+    assert_eq!(
+        overflowing(Overflow::Saturating, "2147483647 + 1").unwrap(),
+        max
+    );
+    assert_eq!(
+        overflowing(Overflow::Saturating, "let x = 0 - 2147483647; x - 2").unwrap(),
+        min
+    );
+    assert_eq!(
+        overflowing(
+            Overflow::Saturating,
+            "let f = fn (x) => { x * 2 }; f(0 - 2147483647)"
+        )
+        .unwrap(),
+        min
+    );
+    assert_eq!(
+        overflowing(Overflow::Saturating, "2147483646 + 1").unwrap(),
+        max
+    );
+}
+
+#[test]
+fn integer_overflow_can_be_an_error() {
+    assert_eq!(
+        overflowing(Overflow::Checked, "2147483647 + 1")
+            .unwrap_err()
+            .to_string(),
+        "Integer overflow in 2147483647 + 1."
+    );
+    assert!(overflowing(
+        Overflow::Checked,
+        "let f = fn (x) => { x + 1 }; f(2147483647)"
+    )
+    .is_err());
+    assert!(overflowing(Overflow::Checked, "let x = 0 - 2147483647; x - 2").is_err());
+    assert!(overflowing(Overflow::Checked, "65536 * 65536").is_err());
+    assert_eq!(
+        overflowing(Overflow::Checked, "2147483646 + 1").unwrap(),
+        FinalValue::Integer(i32::MAX)
+    );
+}
+
+#[test]
+fn integer_overflow_can_be_promoted() {
+    let big = |digits: &str| FinalValue::BigInteger(digits.parse().unwrap());
+    assert_eq!(
+        overflowing(Overflow::Promoting, "2147483647 + 1").unwrap(),
+        big("2147483648")
+    );
+    assert_eq!(
+        overflowing(
+            Overflow::Promoting,
+            "let fact = fn (n) => { if (n < 2) { 1 } else { n * fact(n - 1) } }; fact(25)"
+        )
+        .unwrap(),
+        big("15511210043330985984000000")
+    );
+    assert_eq!(
+        overflowing(
+            Overflow::Promoting,
+            "let x = 2147483647 * 2147483647; (x / 2147483647, (x > 2147483647, (0 - x) % 10))"
+        )
+        .unwrap(),
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(i32::MAX)),
+            Box::new(FinalValue::Tuple(
+                Box::new(FinalValue::Bool(true)),
+                Box::new(FinalValue::Integer(-9)),
+            )),
+        )
+    );
+
+    // Beyond 2^53 - 1, JSON readers would round numbers.
+    let factorial = overflowing(
+        Overflow::Promoting,
+        "let fact = fn (n) => { if (n < 2) { 1 } else { n * fact(n - 1) } }; (fact(18), fact(19))",
+    )
+    .unwrap();
+    assert_eq!(
+        serde_json::to_string(&factorial).unwrap(),
+        r#"[6402373705728000,"121645100408832000"]"#
+    );
+
+    let output = Captured::default();
+    let result = Vm::new()
+        .with_overflow(Overflow::Promoting)
+        .with_output(output.clone())
+        .interpret("test", r#"print("big: " + (65536 * 65536))"#)
+        .unwrap();
+    assert_eq!(result, FinalValue::String("big: 4294967296".to_string()));
+    assert_eq!(output.take(), b"big: 4294967296\n");
+}
+
+#[test]
+fn dividing_the_smallest_integer_by_minus_one_overflows() {
+    let program = "let min = (0 - 2147483647) - 1; let minus_one = 0 - 1;";
+    let divide = |overflow: Overflow| overflowing(overflow, &format!("{program} min / minus_one"));
+    let remainder =
+        |overflow: Overflow| overflowing(overflow, &format!("{program} min % minus_one"));
+
+    assert_eq!(
+        divide(Overflow::Wrapping).unwrap(),
+        FinalValue::Integer(i32::MIN)
+    );
+    assert_eq!(
+        divide(Overflow::Saturating).unwrap(),
+        FinalValue::Integer(i32::MAX)
+    );
+    assert_eq!(
+        divide(Overflow::Checked).unwrap_err().to_string(),
+        "Integer overflow in -2147483648 / -1."
+    );
+    assert_eq!(
+        divide(Overflow::Promoting).unwrap(),
+        FinalValue::BigInteger(2147483648u32.into())
+    );
+
+    for overflow in [
+        Overflow::Wrapping,
+        Overflow::Saturating,
+        Overflow::Promoting,
+    ] {
+        assert_eq!(remainder(overflow).unwrap(), FinalValue::Integer(0));
+    }
+    assert_eq!(
+        remainder(Overflow::Checked).unwrap_err().to_string(),
+        "Integer overflow in -2147483648 % -1."
+    );
+}
+
+#[test]
+fn constant_expressions_are_folded() {
+    compile_and_assert(
+        r#"
+        let x = 1 + 2 * 3;
+        if (x == 7) { x } else { 0 }
+    "#,
+        |result| {
+            assert_eq!(result.unwrap(), FinalValue::Integer(7));
+        },
+    );
+}
+
+#[test]
+fn one_vm_runs_several_programs() {
+    let mut vm = Vm::new();
+
+    let first = vm.interpret("first", "let x = 5; let f = fn (n) => { n + x }; f(1)");
+    assert_eq!(first.unwrap(), FinalValue::Integer(6));
+
+    let second = vm.interpret("second", "let x = 7; let f = fn (n) => { n * x }; f(1)");
+    assert_eq!(second.unwrap(), FinalValue::Integer(7));
+
+    let third = vm.interpret("third", "f(2) + x");
+    assert_eq!(third.unwrap(), FinalValue::Integer(21));
+}
+
+#[test]
+fn globals_are_listed_in_definition_order() {
+    let mut vm = Vm::new();
+    vm.interpret(
+        "test",
+        r#"let zeta = 1; let alpha = "a"; let mid = (zeta, alpha); let zeta = 2; mid"#,
+    )
+    .unwrap();
+
+    let globals = vm.globals();
+    let names: Vec<&str> = globals.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, ["zeta", "alpha", "mid"]);
+    // The second `zeta` only shadows the global for the rest of the program.
+    assert_eq!(globals[0].1, FinalValue::Integer(1));
+}
+
+#[test]
+fn reset_vms_forget_previous_programs() {
+    let mut vm = Vm::new().with_gc_threshold(1);
+
+    vm.interpret("first", "let x = (1, 2); let f = fn (n) => { n }; f(5)")
+        .unwrap();
+    vm.reset();
+
+    assert!(vm.globals().is_empty());
+    assert_eq!(vm.gc_stats().live, 0);
+    assert!(vm.interpret("second", "x").is_err());
+
+    let program = Vm::new().compile_program("third", "40 + 2").unwrap();
+    vm.reset();
+    assert_eq!(
+        vm.interpret_program(&program).unwrap(),
+        FinalValue::Integer(42)
+    );
+}
+
+#[test]
+fn closures_are_equal_only_to_themselves() {
+    let program = r#"
+        let f = fn (x) => { x };
+        let g = fn (x) => { x };
+        let make = fn () => { fn (x) => { x } };
+        let adder = fn (n) => { fn (x) => { x + n } };
+        let alias = f;
+        let with_f = (f, 1);
+        let with_alias = (alias, 1);
+        let with_g = (g, 1);
+        let pairs = (with_f == with_alias, (with_f == with_g, adder(1) == adder(1)));
+        ((f == alias, (f == g, make() == make())), pairs)
+    "#;
+    let expected = Vm::new()
+        .interpret("test", "((true, (false, false)), (true, (false, false)))")
+        .unwrap();
+
+    // Memoizing `adder` would hand out the same closure twice.
+    for mut vm in [Vm::new(), Vm::new().with_memo_capacity(0)] {
+        assert_eq!(vm.interpret("test", program).unwrap(), expected);
+    }
+}
+
+#[test]
+fn opcode_histogram_counts_executed_instructions() {
+    let mut vm = without_inlining().with_opcode_histogram();
+    vm.interpret("test", "let f = fn (n) => { n + 1 }; f(1) + f(2)")
+        .unwrap();
+
+    let histogram = vm.opcode_histogram().unwrap();
+    let count = |name: &str| {
+        histogram
+            .iter()
+            .find(|(opcode, _)| *opcode == name)
+            .map(|(_, count)| *count)
+    };
+
+    assert_eq!(count("Call"), Some(2));
+    assert_eq!(count("Closure"), Some(1));
+    assert_eq!(count("Return"), Some(3));
+    assert_eq!(count("Tuple"), None);
+    assert!(histogram.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+
+    assert!(Vm::new().opcode_histogram().is_none());
+}
+
+#[test]
+fn direct_calls_with_the_wrong_arity_fail_to_compile() {
+    let error = Vm::new()
+        .interpret(
+            "test.rinha",
+            "let f = fn (a) => { a }; if (false) { f(1, 2) } else { 0 }",
+        )
+        .unwrap_err();
+    assert!(error.to_string().contains("test.rinha:"), "{error}");
+
+    let nested = "let g = fn (x) => { let h = fn (a, b) => { a + b }; h(x) }; 0";
+    assert!(Vm::new().interpret("test", nested).is_err());
+
+    // Bindings that disagree leave the arity to be checked when the call runs.
+    let rebound = "
+        let f = fn (a) => { a };
+        let x = if (true) { 0 } else { let f = fn (a, b) => { a }; 0 };
+        f(1)
+    ";
+    assert_eq!(
+        Vm::new().interpret("test", rebound).unwrap(),
+        FinalValue::Integer(1)
+    );
+
+    let shadowed = "let f = fn (a) => { a }; let g = fn (f) => { f(1, 2) }; 0";
+    assert_eq!(
+        Vm::new().interpret("test", shadowed).unwrap(),
+        FinalValue::Integer(0)
+    );
+}
+
+#[test]
+fn stack_traces_show_elided_tail_calls() {
+    let program = |n: i32| {
+        format!(
+            "
+            let f = fn (n) => {{ if (n == 0) {{ first(1) }} else {{ g(n - 1) }} }};
+            let g = fn (n) => {{ f(n) }};
+            let main = fn () => {{ 1 + f({n}) }};
+            main()
+            "
+        )
+    };
+
+    let error = Vm::new().interpret("test", &program(3)).unwrap_err();
+    let trace = &error.downcast_ref::<RuntimeError>().unwrap().trace;
+
+    let functions: Vec<&str> = trace.frames.iter().map(|f| f.function.as_str()).collect();
+    assert_eq!(functions, ["f", "main"]);
+    assert_eq!(trace.frames[0].elided_count, 6);
+    assert_eq!(trace.frames[0].elided, ["f", "g", "f", "g", "f", "g"]);
+    assert_eq!(trace.frames[1].elided, ["<script>"]);
+    assert!(error
+        .to_string()
+        .contains("… 6 tail calls elided via f → g → f → g → f → g …"));
+
+    let error = Vm::new().interpret("test", &program(50)).unwrap_err();
+    let trace = &error.downcast_ref::<RuntimeError>().unwrap().trace;
+    assert_eq!(trace.frames[0].elided_count, 100);
+    assert_eq!(trace.frames[0].elided.len(), ELIDED_CALLS);
+}
+
+#[test]
+fn functions_calling_themselves_in_tail_position_loop_in_place() {
+    let program = "
+        let count = fn (n, acc) => { if (n == 0) { acc } else { count(n - 1, acc + 1) } };
+        count(100000, 0)
+    ";
+
+    let mut vm = Vm::new().with_opcode_histogram();
+    assert_eq!(
+        vm.interpret("test", program).unwrap(),
+        FinalValue::Integer(100000)
+    );
+
+    // The script tail calls `count`, which then never pushes a frame.
+    let stats = vm.stats();
+    assert_eq!(stats.peak_frame_depth, 1);
+    let executed = |name: &str| {
+        stats
+            .opcodes
+            .iter()
+            .find(|(opcode, _)| *opcode == name)
+            .map_or(0, |(_, count)| *count)
+    };
+    assert_eq!(executed("JumpBack"), 100000);
+    assert_eq!(executed("TailCall"), 1);
+
+    // Loops still show up in stack traces as the calls they replace.
+    let error = Vm::new()
+        .interpret(
+            "test",
+            "let f = fn (n) => { if (n == 0) { 1 / 0 } else { f(n - 1) } }; f(3)",
+        )
+        .unwrap_err();
+    let trace = &error.downcast_ref::<RuntimeError>().unwrap().trace;
+    assert_eq!(trace.frames[0].elided, ["<script>", "f", "f", "f"]);
+
+    // A local of the same name is some other function.
+    let shadowed = without_inlining()
+        .compile_program(
+            "test",
+            "let f = fn (n) => { let f = fn (m) => { m }; f(n) }; f(1)",
+        )
+        .unwrap();
+    assert!(shadowed
+        .functions
+        .iter()
+        .all(|function| !function.bytecode.iter().any(|i| i.name() == "JumpBack")));
+}
+
+#[test]
+fn stack_traces_point_at_the_failing_expressions() {
+    let program = "let f = fn (x) => {\n  x / 0\n};\nprint(f(3))\n";
+
+    let error = without_inlining().interpret("test", program).unwrap_err();
+    let trace = &error.downcast_ref::<RuntimeError>().unwrap().trace;
+
+    let lines = LineIndex::new(program);
+    let positions: Vec<(usize, usize)> = trace
+        .frames
+        .iter()
+        .map(|frame| {
+            let offset = frame.offset.unwrap();
+            (lines.line_of(offset), lines.column_of(offset))
+        })
+        .collect();
+    assert_eq!(positions, [(2, 3), (4, 7)]);
+}
+
+#[test]
+fn let_bindings_are_lexically_scoped() {
+    let cases = [
+        ("let x = 1; let y = (let x = 2; x + 1); x + y", 4),
+        ("let f = fn (x) => { let x = x + 1; x * 2 }; f(3)", 8),
+        ("let f = fn (a) => { 1 + (let b = a * 2; b) + a }; f(5)", 16),
+        (
+            "let x = 1; let f = fn () => { x }; let x = 2; f() * 10 + x",
+            12,
+        ),
+        (
+            "let make = fn (x) => { let x = x + 10; fn () => { x } }; let get = make(1); get()",
+            11,
+        ),
+        (
+            "let f = fn (n) => { if (n > 0) { let m = n * 2; m } else { 0 - n } }; f(3) + f(0 - 4)",
+            10,
+        ),
+    ];
+
+    for (program, expected) in cases {
+        assert_eq!(
+            Vm::new().interpret("test", program).unwrap(),
+            FinalValue::Integer(expected),
+            "{program}"
+        );
+    }
+}
+
+#[test]
+fn let_bindings_in_branches_do_not_leak() {
+    use serde_json::json;
+
+    let cases = [
+        (
+            "let x = 1; let r = if (true) { let x = 2; x } else { let y = 3; y }; (r, x)",
+            json!([2, 1]),
+        ),
+        (
+            "let x = 1; let y = if (false) { let x = 5; x } else { x * 10 }; (x, y)",
+            json!([1, 10]),
+        ),
+        (
+            "let f = fn (b) => { let x = 1; let y = if (b) { let x = 2; x } else { x }; (x, y) }; (f(true), f(false))",
+            json!([[1, 2], [1, 1]]),
+        ),
+        (
+            "let f = fn (n) => { let r = if (n > 0) { let m = n; let m = m * 2; m } else { n }; r + n }; (f(3), f(0 - 3))",
+            json!([9, -6]),
+        ),
+    ];
+
+    for (program, expected) in cases {
+        let value = Vm::new().interpret("test", program).unwrap();
+        assert_eq!(serde_json::to_value(&value).unwrap(), expected, "{program}");
+    }
+
+    let error = Vm::new()
+        .interpret(
+            "test",
+            "let f = fn (b) => { if (b) { let m = 5; m } else { m } }; f(false)",
+        )
+        .unwrap_err();
+    assert!(error.to_string().contains("Unknown variable m"), "{error}");
+}
+
+#[test]
+fn tail_calls_drop_let_bindings() {
+    let program = "
+        let sum = fn (n, total) => {
+            if (n == 0) { total } else { let next = n - 1; sum(next, total + n) }
+        };
+        sum(10000, 0)
+    ";
+
+    assert_eq!(
+        Vm::new().interpret("test", program).unwrap(),
+        FinalValue::Integer(50005000)
+    );
+}
+
+#[test]
+fn returns_drop_let_bindings() {
+    let program = "
+        let pair = fn (n) => { let a = n + 1; let b = a * 2; (a, b) };
+        let sum = fn (n) => {
+            if (n == 0) { 0 } else { let p = pair(n); first(p) + second(p) + sum(n - 1) }
+        };
+        (sum(100), pair(1))
+    ";
+
+    assert_eq!(
+        Vm::new().interpret("test", program).unwrap(),
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(15450)),
+            Box::new(FinalValue::Tuple(
+                Box::new(FinalValue::Integer(2)),
+                Box::new(FinalValue::Integer(4))
+            ))
+        )
+    );
+}
+
+#[test]
+fn tuple_nesting_is_capped() {
+    let program = |depth: i32| {
+        format!(
+            "
+            let build = fn (n, tuple) => {{ if (n == 0) {{ tuple }} else {{ build(n - 1, (n, tuple)) }} }};
+            let a = build({depth}, 0);
+            let b = build({depth}, 0);
+            if (a == b) {{ a }} else {{ 0 }}
+            "
+        )
+    };
+
+    let within = Vm::new().interpret("test", &program(DEFAULT_MAX_TUPLE_DEPTH as i32));
+    assert!(matches!(within.unwrap(), FinalValue::Tuple(_, _)));
+
+    let error = Vm::new()
+        .with_max_tuple_depth(100)
+        .interpret("test", &program(101))
+        .unwrap_err();
+    assert!(error.to_string().contains("depth 101"), "{error}");
+}
+
+#[test]
+fn recursion_is_only_as_deep_as_the_call_depth_limit() {
+    let program = r#"
+        let sum = fn (n) => if (n == 0) { 0 } else { n + sum(n - 1) };
+        let fib = fn (n) => if (n < 2) { n } else { fib(n - 1) + fib(n - 2) };
+        (sum(500000), fib(32))
+    "#;
+
+    let result = Vm::new().interpret("test", program).unwrap();
+    assert_eq!(
+        result,
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(446198416)),
+            Box::new(FinalValue::Integer(2178309))
+        )
+    );
+
+    let error = without_inlining()
+        .with_max_call_depth(1000)
+        .interpret("test", "let f = fn (n) => 1 + f(n + 1); f(0)")
+        .unwrap_err();
+    let error = error.downcast::<RuntimeError>().unwrap();
+    assert_eq!(
+        error.error.to_string(),
+        "Call depth exceeds the limit of 1000 calls."
+    );
+    assert_eq!(error.trace.frames.len(), 1000);
+}
+
+#[test]
+fn programs_may_use_more_constants_than_sixteen_bits_index() {
+    // Two calls of 35,000 arguments each, every one a different constant.
+    let arguments = |start: usize| {
+        (start..start + 35_000)
+            .map(|i| (i + 100_000).to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let parameters = (0..35_000)
+        .map(|i| format!("p{i}"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let program = format!(
+        "let last = fn ({parameters}) => p34999;\n(last({}), last({}))",
+        arguments(0),
+        arguments(35_000)
+    );
+
+    let mut vm = Vm::new();
+    let compiled = vm.compile_program("test", &program).unwrap();
+    assert!(compiled.constants.len() > u16::MAX as usize);
+    assert_eq!(
+        Vm::new().interpret_program(&compiled).unwrap(),
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(134_999)),
+            Box::new(FinalValue::Integer(169_999))
+        )
+    );
+}
+
+#[test]
+fn calls_take_at_most_as_many_arguments_as_sixteen_bits_count() {
+    let arguments = vec!["0"; u16::MAX as usize + 1].join(", ");
+    let error = Vm::new()
+        .interpret("test", &format!("let f = fn (x) => x;\nf({arguments})"))
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Cannot call a function with more than 65535 arguments."
+    );
+}
+
+#[test]
+fn arguments_are_bound_as_globals() {
+    let arguments = ["30".to_owned(), "name".to_owned()];
+    let mut vm = Vm::new().with_arguments(&arguments);
+
+    let result = vm.interpret("test", "(argc, (arg0 + 1, arg1))").unwrap();
+    assert_eq!(
+        result,
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(2)),
+            Box::new(FinalValue::Tuple(
+                Box::new(FinalValue::Integer(31)),
+                Box::new(FinalValue::String("name".to_owned()))
+            ))
+        )
+    );
+
+    let mut vm = Vm::new();
+    vm.define_global("limit", &FinalValue::Integer(3)).unwrap();
+    assert_eq!(
+        vm.interpret("test", "limit * 2").unwrap(),
+        FinalValue::Integer(6)
+    );
+    assert!(vm.define_global("f", &FinalValue::Closure).is_err());
+}
+
+#[test]
+fn functions_can_be_called_from_rust() {
+    let output = Captured::default();
+    let mut vm = Vm::new().with_output(output.clone());
+
+    vm.interpret(
+        "test",
+        "let double = fn (n) => { print(n * 2) }; let _ = double(1); 0",
+    )
+    .unwrap();
+    assert_eq!(
+        vm.call_function("double", &[FinalValue::Integer(21)])
+            .unwrap(),
+        FinalValue::Integer(42)
+    );
+    assert_eq!(output.take(), b"2\n42\n");
+
+    assert!(vm.call_function("missing", &[]).is_err());
+    assert!(vm
+        .call_function("double", &[FinalValue::Integer(1), FinalValue::Integer(2)])
+        .is_err());
+}
+
+#[test]
+fn print_can_leave_out_newlines_and_return_what_it_printed() {
+    let program = r#"let x = print(1) + print(2); print((x, "!"))"#;
+
+    let output = Captured::default();
+    let mut vm = Vm::new().with_output(output.clone());
+    assert!(vm.interpret("test", program).is_ok());
+    assert_eq!(output.take(), b"1\n2\n(3, !)\n");
+
+    let output = Captured::default();
+    let mut vm = Vm::new()
+        .with_output(output.clone())
+        .with_printing(Printing {
+            newline: false,
+            result: PrintResult::Text,
+        });
+    assert_eq!(
+        vm.interpret("test", program).unwrap(),
+        FinalValue::String("(12, !)".to_owned())
+    );
+    assert_eq!(output.take(), b"12(12, !)");
+}
+
+#[test]
+fn top_level_expressions_run_in_sequence() {
+    let output = Captured::default();
+    let mut vm = Vm::new().with_output(output.clone());
+
+    let program = r#"
+        print("a;b"); // a comment; with a semicolon
+        let x = 2;
+        print(x);
+        let double = fn (n) => { n * x };
+        print(double(3));
+        double(4);
+    "#;
+    assert_eq!(
+        vm.interpret("test", program).unwrap(),
+        FinalValue::Integer(8)
+    );
+    assert_eq!(output.take(), b"a;b\n2\n6\n");
+
+    assert!(vm.interpret("test", "print(1); print(2 +); 3").is_err());
+}
+
+#[test]
+fn top_level_statements_may_be_separated_by_lines() {
+    let output = Captured::default();
+    let mut vm = Vm::new().with_output(output.clone());
+
+    let program = "
+        let a = 1
+        let b = a +
+          print(2) // continues the line above
+        let f = fn (n) => {
+          if (n < 1) { 0 }
+          else { n * b }
+        }
+        print(a)
+        f(2);
+    ";
+    assert_eq!(
+        vm.interpret("test", program).unwrap(),
+        FinalValue::Integer(6)
+    );
+    assert_eq!(output.take(), b"2\n1\n");
+
+    assert!(vm.interpret("test", "let x = 1\n").is_err());
+}
+
+#[test]
+fn input_is_read_from_the_reader() {
+    let input = io::Cursor::new("Ada\r\n 41 \nnot a number\n");
+    let mut vm = Vm::new().with_reader(input);
+
+    let result = vm
+        .interpret("test", "let name = read_line(); (name, read_int() + 1)")
+        .unwrap();
+    assert_eq!(
+        result,
+        FinalValue::Tuple(
+            Box::new(FinalValue::String("Ada".to_owned())),
+            Box::new(FinalValue::Integer(42))
+        )
+    );
+    assert!(vm.interpret("test", "read_int()").is_err());
+    assert_eq!(
+        vm.interpret("test", "read_line()").unwrap(),
+        FinalValue::String(String::new())
+    );
+
+    let shadowed = "let read_line = fn (x) => { x + 1 }; read_line(1)";
+    compile_and_assert(shadowed, |result| {
+        assert_eq!(result.unwrap(), FinalValue::Integer(2));
+    });
+    let captured =
+        "let f = fn () => { let read_int = fn () => { 7 }; fn () => { read_int() } }; f()()";
+    compile_and_assert(captured, |result| {
+        assert_eq!(result.unwrap(), FinalValue::Integer(7));
+    });
+    compile_and_assert("read_line(1)", |result| {
+        assert!(result.is_err());
+    });
+}
+
+#[test]
+fn results_serialize_to_json() {
+    let mut vm = Vm::new();
+    let result = vm
+        .interpret("test", r#"(1, (true, ("text", fn () => { 0 })))"#)
+        .unwrap();
+
+    assert_eq!(
+        serde_json::to_value(&result).unwrap(),
+        serde_json::json!([1, [true, ["text", "<#closure>"]]])
+    );
+    assert!(vm.instructions_executed() > 0);
+}
+
+#[test]
+fn stats_count_what_the_vm_did() {
+    let mut vm = Vm::new().with_opcode_histogram();
+    vm.interpret(
+        "test",
+        "let fib = fn (n) => { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; (fib(10), 0)",
+    )
+    .unwrap();
+
+    let stats = vm.stats();
+    assert_eq!(stats.instructions, vm.instructions_executed());
+    assert_eq!(
+        stats.opcodes.iter().map(|(_, count)| count).sum::<u64>(),
+        stats.instructions
+    );
+    // Memoized calls return at once, so only the leftmost chain of calls
+    // is ever on the stack.
+    assert_eq!(stats.peak_frame_depth, 11);
+    assert!(stats.memoization_misses >= 11);
+    assert!(stats.memoization_hits > 0);
+    assert!(stats.peak_stack_depth > 10);
+    assert_eq!(stats.allocations, 2);
+}
+
+#[test]
+fn calls_are_memoized_by_all_their_arguments() {
+    let combination = "
+        let combination = fn (n, k) => {
+            if (k == 0) { 1 } else {
+                if (k == n) { 1 } else { combination(n - 1, k - 1) + combination(n - 1, k) }
+            }
+        };
+        (combination(30, 12), 0)
+    ";
+    let mut vm = Vm::new();
+    assert_eq!(
+        vm.interpret("test", combination).unwrap(),
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(86493225)),
+            Box::new(FinalValue::Integer(0))
+        )
+    );
+    assert!(vm.stats().memoization_misses < 1000);
+
+    // Strings are known by what they hold, wherever they come from, but
+    // tuples live in the heap and are only known by their handle, which
+    // keeps calls out of the table.
+    let mut vm = without_inlining();
+    vm.interpret(
+        "test",
+        r#"
+            let f = fn (x, n) => { (x, n) };
+            let join = fn (a, b) => { a + b };
+            let a = f("short", 1);
+            let b = f("short", 1);
+            let made = f(join("sho", "rt"), 1);
+            let c = f("a long string", 1);
+            let d = f("a long string", 1);
+            let e = f((1, 2), 1);
+            (f((1, 2), 1), 0)
+        "#,
+    )
+    .unwrap();
+    assert_eq!(vm.stats().memoization_hits, 3);
+    assert_eq!(vm.stats().memoization_misses, 3);
+}
+
+#[test]
+fn memo_capacity_bounds_the_table() {
+    let fib =
+        "let fib = fn (n) => { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; (fib(25), 0)";
+    let expected = FinalValue::Tuple(
+        Box::new(FinalValue::Integer(75025)),
+        Box::new(FinalValue::Integer(0)),
+    );
+
+    // The most recent results are the ones the recursion asks for next.
+    let mut vm = Vm::new().with_memo_capacity(4).with_memo_verification(100);
+    assert_eq!(vm.interpret("test", fib).unwrap(), expected);
+    let stats = vm.stats();
+    assert!(stats.memoization_hits > 0);
+    assert_eq!(stats.memoization_evictions, stats.memoization_misses - 4);
+    assert!(vm.memo_mismatches().is_empty());
+
+    let mut vm = Vm::new().with_memo_capacity(0);
+    assert_eq!(vm.interpret("test", fib).unwrap(), expected);
+    let stats = vm.stats();
+    assert_eq!(stats.memoization_hits + stats.memoization_misses, 0);
+}
+
+#[test]
+fn memo_verification_flags_unsound_entries() {
+    let fib = "let fib = fn (n) => { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)";
+    let mut vm = Vm::new().with_memo_verification(100);
+    assert_eq!(vm.interpret("test", fib).unwrap(), FinalValue::Integer(610));
+    assert!(vm.stats().memoization_checks > 0);
+    assert!(vm.memo_mismatches().is_empty());
+
+    // Closures that captured variables are not memoized at all.
+    let closures = "let make = fn (x) => { fn (y) => { x + y } }; (make(10)(1), make(20)(1))";
+    let mut vm = Vm::new().with_memo_verification(100);
+    assert_eq!(
+        vm.interpret("test", closures).unwrap(),
+        FinalValue::Tuple(
+            Box::new(FinalValue::Integer(11)),
+            Box::new(FinalValue::Integer(21))
+        )
+    );
+    assert!(vm.memo_mismatches().is_empty());
+
+    // Globals are looked up when functions run, so redefining one in a later
+    // program leaves stale entries behind.
+    let mut vm = Vm::new().with_memo_verification(100);
+    vm.interpret("test", "let k = 10; let f = fn (n) => { n + k }; f(1) + 0")
+        .unwrap();
+    assert_eq!(
+        vm.interpret("test", "let k = 20; f(1) + 0").unwrap(),
+        FinalValue::Integer(21)
+    );
+    assert_eq!(
+        vm.memo_mismatches(),
+        [MemoMismatch {
+            function: "f".to_owned(),
+            arguments: vec![FinalValue::Integer(1)],
+            memoized: FinalValue::Integer(11),
+            computed: FinalValue::Integer(21),
+        }]
+    );
+}
+
+#[test]
+fn profiles_attribute_instructions_to_functions() {
+    let mut vm = without_inlining().with_profiler(1);
+    vm.interpret(
+        "test",
+        "let double = fn (n) => { n * 2 }; let twice = fn (n) => { double(double(n)) + 0 }; twice(1) + 0",
+    )
+    .unwrap();
+
+    let mut profile = Vec::new();
+    vm.write_profile(&mut profile).unwrap();
+    let profile = String::from_utf8(profile).unwrap();
+
+    let mut total = 0;
+    for line in profile.lines() {
+        let (stack, count) = line.rsplit_once(' ').unwrap();
+        assert!(stack.starts_with("<script>"), "{line}");
+        total += count.parse::<u64>().unwrap();
+    }
+    assert_eq!(total, vm.instructions_executed());
+    assert!(profile.contains("<script>;twice#1;double#0 "));
+
+    assert!(Vm::new().write_profile(&mut Vec::new()).is_err());
+}
+
+#[test]
+fn call_sites_cache_the_closure_they_call() {
+    let mut vm = Vm::new();
+    let program = "
+        let inc = fn (x) => { x + 1 };
+        let dec = fn (x) => { x - 1 };
+        let apply = fn (f, x) => { f(x) };
+        let loop = fn (n, acc) => {
+            if (n == 0) { acc } else { loop(n - 1, apply(if (n % 3 == 0) { dec } else { inc }, acc)) }
+        };
+        loop(300, 0)
+    ";
+
+    // The call in `apply` switches closures, so its cache is replaced
+    // whenever the other one shows up.
+    assert_eq!(
+        vm.interpret("test", program).unwrap(),
+        FinalValue::Integer(100)
+    );
+
+    let stats = vm.stats();
+    assert!(stats.inline_cache_hits >= 300, "{stats:?}");
+    assert!(stats.inline_cache_misses >= 200, "{stats:?}");
+}
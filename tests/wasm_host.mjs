@@ -0,0 +1,43 @@
+// Runs a module emitted by `rvm compile --target wasm` under Node.js, with
+// standard input and output as the program's input and output.
+import { readFileSync, writeSync } from "node:fs";
+
+const module = await WebAssembly.compile(readFileSync(process.argv[2]));
+const input = readFileSync(0);
+const decoder = new TextDecoder();
+let position = 0;
+let line = new Uint8Array();
+let memory;
+
+const text = (address, length) =>
+  decoder.decode(new Uint8Array(memory.buffer, address, length));
+
+const { exports } = await WebAssembly.instantiate(module, {
+  rinha: {
+    print(address, length) {
+      writeSync(1, text(address, length) + "\n");
+    },
+    fail(address, length) {
+      writeSync(2, `Error: ${text(address, length)}\n`);
+      process.exit(1);
+    },
+    read_line() {
+      let end = input.indexOf(10, position);
+      if (end === -1) {
+        end = input.length;
+      }
+      line = input.subarray(position, end);
+      position = Math.min(end + 1, input.length);
+      if (line.length > 0 && line[line.length - 1] === 13) {
+        line = line.subarray(0, line.length - 1);
+      }
+      return line.length;
+    },
+    take_line(address) {
+      new Uint8Array(memory.buffer).set(line, address);
+    },
+  },
+});
+
+memory = exports.memory;
+exports.run();